@@ -0,0 +1,340 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! The `masq --agent` connection cache. Every non-interactive masq
+//! invocation otherwise pays daemon discovery, TCP connect, websocket
+//! upgrade, and protocol handshake; scripts that run masq dozens of times
+//! feel it. The agent is a background masq process that holds the daemon
+//! connection open and listens on a localhost socket; later invocations
+//! detect it, proxy their command line through it, and fall back to a
+//! direct connection when it is absent.
+//!
+//! Wire format is deliberately dumb: one newline-terminated command line
+//! in, one length-prefixed response out. Both ends are the same binary,
+//! so there is no compatibility horizon to manage.
+
+use crate::exit_code::CommandError;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_AGENT_PORT: u16 = 5333;
+pub const DEFAULT_IDLE_SHUTDOWN: Duration = Duration::from_secs(300);
+const DETECT_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// The agent's hold on the daemon: whatever connection machinery masq
+/// uses directly, behind a trait so the agent neither knows nor cares.
+pub trait DaemonConnection: Send {
+    fn transact(&mut self, command_line: &str) -> Result<String, CommandError>;
+}
+
+pub struct AgentConfig {
+    pub port: u16,
+    pub idle_shutdown: Duration,
+}
+
+impl Default for AgentConfig {
+    fn default() -> AgentConfig {
+        AgentConfig {
+            port: DEFAULT_AGENT_PORT,
+            idle_shutdown: DEFAULT_IDLE_SHUTDOWN,
+        }
+    }
+}
+
+/// The background process's server half. Serves one command at a time —
+/// the daemon connection is serial anyway — and shuts itself down after
+/// the configured idle period.
+pub struct Agent {
+    port: u16,
+    shutdown: Arc<AtomicBool>,
+    join_handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Agent {
+    pub fn start(
+        config: AgentConfig,
+        daemon_connection: Box<dyn DaemonConnection>,
+    ) -> std::io::Result<Agent> {
+        let listener = TcpListener::bind(("127.0.0.1", config.port))?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let shutdown_flag = shutdown.clone();
+        let idle_shutdown = config.idle_shutdown;
+        let connection = Arc::new(Mutex::new(daemon_connection));
+        let join_handle = thread::spawn(move || {
+            let mut last_activity = Instant::now();
+            loop {
+                if shutdown_flag.load(Ordering::SeqCst) {
+                    break;
+                }
+                if last_activity.elapsed() >= idle_shutdown {
+                    break;
+                }
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        last_activity = Instant::now();
+                        Self::serve_one(stream, &connection);
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(25));
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+        Ok(Agent {
+            port,
+            shutdown,
+            join_handle: Some(join_handle),
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn stop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        if let Some(join_handle) = self.join_handle.take() {
+            let _ = join_handle.join();
+        }
+    }
+
+    fn serve_one(stream: TcpStream, connection: &Arc<Mutex<Box<dyn DaemonConnection>>>) {
+        let mut reader = BufReader::new(match stream.try_clone() {
+            Ok(clone) => clone,
+            Err(_) => return,
+        });
+        let mut command_line = String::new();
+        if reader.read_line(&mut command_line).is_err() {
+            return;
+        }
+        let result = connection
+            .lock()
+            .expect("agent daemon connection poisoned")
+            .transact(command_line.trim_end());
+        let _ = write_response(stream, &result);
+    }
+}
+
+impl Drop for Agent {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// True when an agent is listening on the port; a quarter second is long
+/// enough for localhost and short enough that the fallback path doesn't
+/// feel it.
+pub fn detect_agent(port: u16) -> bool {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    TcpStream::connect_timeout(&addr, DETECT_TIMEOUT).is_ok()
+}
+
+/// Proxies one command line through a detected agent.
+pub fn proxy_through_agent(port: u16, command_line: &str) -> Result<String, CommandError> {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let mut stream = TcpStream::connect_timeout(&addr, DETECT_TIMEOUT)
+        .map_err(|e| CommandError::ConnectionProblem(format!("agent: {}", e)))?;
+    stream
+        .write_all(format!("{}\n", command_line).as_bytes())
+        .map_err(|e| CommandError::ConnectionProblem(format!("agent: {}", e)))?;
+    read_response(stream)
+}
+
+/// The client-side entry point: through the agent when one is running,
+/// otherwise via `direct`.
+pub fn run_command<F>(port: u16, command_line: &str, direct: F) -> Result<String, CommandError>
+where
+    F: FnOnce(&str) -> Result<String, CommandError>,
+{
+    if detect_agent(port) {
+        proxy_through_agent(port, command_line)
+    } else {
+        direct(command_line)
+    }
+}
+
+// Response framing: one status byte ('+' success, '-' error class byte
+// follows) then a u32 length and that many payload bytes.
+fn write_response(mut stream: TcpStream, result: &Result<String, CommandError>) -> std::io::Result<()> {
+    let (header, payload): (Vec<u8>, &str) = match result {
+        Ok(response) => (vec![b'+'], response.as_str()),
+        Err(e) => (vec![b'-', error_class(e)], ""),
+    };
+    stream.write_all(&header)?;
+    stream.write_all(&(payload.len() as u32).to_be_bytes())?;
+    stream.write_all(payload.as_bytes())
+}
+
+fn read_response(mut stream: TcpStream) -> Result<String, CommandError> {
+    let mut status = [0u8; 1];
+    stream
+        .read_exact(&mut status)
+        .map_err(|e| CommandError::ConnectionProblem(format!("agent: {}", e)))?;
+    if status[0] == b'-' {
+        let mut class = [0u8; 1];
+        stream
+            .read_exact(&mut class)
+            .map_err(|e| CommandError::ConnectionProblem(format!("agent: {}", e)))?;
+        return Err(class_error(class[0]));
+    }
+    let mut length_bytes = [0u8; 4];
+    stream
+        .read_exact(&mut length_bytes)
+        .map_err(|e| CommandError::ConnectionProblem(format!("agent: {}", e)))?;
+    let mut payload = vec![0u8; u32::from_be_bytes(length_bytes) as usize];
+    stream
+        .read_exact(&mut payload)
+        .map_err(|e| CommandError::ConnectionProblem(format!("agent: {}", e)))?;
+    String::from_utf8(payload)
+        .map_err(|_| CommandError::Other("agent sent a non-UTF-8 response".to_string()))
+}
+
+fn error_class(error: &CommandError) -> u8 {
+    match error {
+        CommandError::UsageError(_) => b'U',
+        CommandError::ConnectionProblem(_) => b'C',
+        CommandError::DaemonRejected(_) => b'R',
+        CommandError::NodeNotRunning => b'N',
+        CommandError::Timeout(_) => b'T',
+        CommandError::Other(_) => b'O',
+    }
+}
+
+fn class_error(class: u8) -> CommandError {
+    match class {
+        b'U' => CommandError::UsageError("relayed by agent".to_string()),
+        b'C' => CommandError::ConnectionProblem("relayed by agent".to_string()),
+        b'R' => CommandError::DaemonRejected("relayed by agent".to_string()),
+        b'N' => CommandError::NodeNotRunning,
+        b'T' => CommandError::Timeout(0),
+        _ => CommandError::Other("relayed by agent".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct DaemonConnectionMock {
+        transact_params: Arc<Mutex<Vec<String>>>,
+        transact_results: Mutex<Vec<Result<String, CommandError>>>,
+    }
+
+    impl DaemonConnectionMock {
+        fn new() -> DaemonConnectionMock {
+            DaemonConnectionMock {
+                transact_params: Arc::new(Mutex::new(vec![])),
+                transact_results: Mutex::new(vec![]),
+            }
+        }
+
+        fn transact_params(mut self, params: &Arc<Mutex<Vec<String>>>) -> DaemonConnectionMock {
+            self.transact_params = params.clone();
+            self
+        }
+
+        fn transact_result(self, result: Result<String, CommandError>) -> DaemonConnectionMock {
+            self.transact_results.lock().unwrap().push(result);
+            self
+        }
+    }
+
+    impl DaemonConnection for DaemonConnectionMock {
+        fn transact(&mut self, command_line: &str) -> Result<String, CommandError> {
+            self.transact_params
+                .lock()
+                .unwrap()
+                .push(command_line.to_string());
+            self.transact_results.lock().unwrap().remove(0)
+        }
+    }
+
+    fn start_agent(daemon_connection: DaemonConnectionMock) -> Agent {
+        Agent::start(
+            AgentConfig {
+                port: 0,
+                idle_shutdown: Duration::from_secs(60),
+            },
+            Box::new(daemon_connection),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn a_running_agent_is_detected_and_a_missing_one_is_not() {
+        let agent = start_agent(DaemonConnectionMock::new());
+        let port = agent.port();
+
+        assert!(detect_agent(port));
+        drop(agent);
+        assert!(!detect_agent(port));
+    }
+
+    #[test]
+    fn a_command_proxies_through_the_agent_to_the_daemon_connection() {
+        let transact_params = Arc::new(Mutex::new(vec![]));
+        let daemon_connection = DaemonConnectionMock::new()
+            .transact_params(&transact_params)
+            .transact_result(Ok("descriptor: cNet://abc".to_string()));
+        let agent = start_agent(daemon_connection);
+
+        let response = run_command(agent.port(), "descriptor", |_| {
+            panic!("should not fall back while the agent is running")
+        })
+        .unwrap();
+
+        assert_eq!(response, "descriptor: cNet://abc");
+        assert_eq!(*transact_params.lock().unwrap(), vec!["descriptor"]);
+    }
+
+    #[test]
+    fn daemon_errors_come_back_through_the_agent_with_their_class() {
+        let daemon_connection =
+            DaemonConnectionMock::new().transact_result(Err(CommandError::NodeNotRunning));
+        let agent = start_agent(daemon_connection);
+
+        let result = proxy_through_agent(agent.port(), "shutdown");
+
+        assert_eq!(result, Err(CommandError::NodeNotRunning));
+    }
+
+    #[test]
+    fn with_no_agent_the_command_falls_back_to_a_direct_connection() {
+        let port = {
+            let agent = start_agent(DaemonConnectionMock::new());
+            agent.port()
+            // dropped here: the port is now free and agentless
+        };
+
+        let response = run_command(port, "descriptor", |command_line| {
+            assert_eq!(command_line, "descriptor");
+            Ok("direct".to_string())
+        })
+        .unwrap();
+
+        assert_eq!(response, "direct");
+    }
+
+    #[test]
+    fn the_agent_shuts_itself_down_when_idle() {
+        let agent = Agent::start(
+            AgentConfig {
+                port: 0,
+                idle_shutdown: Duration::from_millis(100),
+            },
+            Box::new(DaemonConnectionMock::new()),
+        )
+        .unwrap();
+        let port = agent.port();
+
+        thread::sleep(Duration::from_millis(400));
+
+        assert!(!detect_agent(port));
+    }
+}
@@ -0,0 +1,128 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Process exit codes for scripted use of masq. Scripts need to tell
+//! "daemon unreachable" apart from "command rejected" apart from "that
+//! needs a running node", so each failure class gets its own code and the
+//! mapping lives here rather than being scattered through main.
+
+/// Why a command failed. Produced by the command processor; consumed both
+/// by the error printer and by the exit-code mapping below.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CommandError {
+    /// The command line itself was malformed.
+    UsageError(String),
+    /// The daemon or node could not be reached at all.
+    ConnectionProblem(String),
+    /// The connection worked but the daemon refused the command.
+    DaemonRejected(String),
+    /// The command requires a running node and none is running.
+    NodeNotRunning,
+    /// No answer arrived within the command's deadline.
+    Timeout(u64),
+    /// Anything else; kept last so new classes get their own code first.
+    Other(String),
+}
+
+/// The codes themselves. Values are part of the scripting interface:
+/// never renumber, only append.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    OtherFailure = 1,
+    UsageError = 2,
+    ConnectionFailure = 3,
+    DaemonRejected = 4,
+    NodeNotRunning = 5,
+    Timeout = 6,
+}
+
+impl ExitCode {
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+}
+
+impl From<&CommandError> for ExitCode {
+    fn from(error: &CommandError) -> Self {
+        match error {
+            CommandError::UsageError(_) => ExitCode::UsageError,
+            CommandError::ConnectionProblem(_) => ExitCode::ConnectionFailure,
+            CommandError::DaemonRejected(_) => ExitCode::DaemonRejected,
+            CommandError::NodeNotRunning => ExitCode::NodeNotRunning,
+            CommandError::Timeout(_) => ExitCode::Timeout,
+            CommandError::Other(_) => ExitCode::OtherFailure,
+        }
+    }
+}
+
+/// Executes one command; mocked in tests.
+pub trait CommandProcessor {
+    fn process(&mut self, command_line: &[String]) -> Result<(), CommandError>;
+}
+
+/// Runs a single non-interactive command and produces the process exit
+/// code. Interactive mode does not use this: it reports errors inline and
+/// only the final `exit` determines the code.
+pub fn run_command(processor: &mut dyn CommandProcessor, command_line: &[String]) -> ExitCode {
+    match processor.process(command_line) {
+        Ok(()) => ExitCode::Success,
+        Err(error) => ExitCode::from(&error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct CommandProcessorMock {
+        result: Result<(), CommandError>,
+    }
+
+    impl CommandProcessor for CommandProcessorMock {
+        fn process(&mut self, _command_line: &[String]) -> Result<(), CommandError> {
+            self.result.clone()
+        }
+    }
+
+    fn run_with(result: Result<(), CommandError>) -> ExitCode {
+        let mut processor = CommandProcessorMock { result };
+        run_command(&mut processor, &["setup".to_string()])
+    }
+
+    #[test]
+    fn each_error_class_maps_to_its_own_code() {
+        assert_eq!(run_with(Ok(())), ExitCode::Success);
+        assert_eq!(
+            run_with(Err(CommandError::UsageError("bad flag".to_string()))),
+            ExitCode::UsageError
+        );
+        assert_eq!(
+            run_with(Err(CommandError::ConnectionProblem("refused".to_string()))),
+            ExitCode::ConnectionFailure
+        );
+        assert_eq!(
+            run_with(Err(CommandError::DaemonRejected("nope".to_string()))),
+            ExitCode::DaemonRejected
+        );
+        assert_eq!(
+            run_with(Err(CommandError::NodeNotRunning)),
+            ExitCode::NodeNotRunning
+        );
+        assert_eq!(run_with(Err(CommandError::Timeout(5000))), ExitCode::Timeout);
+        assert_eq!(
+            run_with(Err(CommandError::Other("mystery".to_string()))),
+            ExitCode::OtherFailure
+        );
+    }
+
+    #[test]
+    fn numeric_values_are_stable() {
+        assert_eq!(ExitCode::Success.code(), 0);
+        assert_eq!(ExitCode::OtherFailure.code(), 1);
+        assert_eq!(ExitCode::UsageError.code(), 2);
+        assert_eq!(ExitCode::ConnectionFailure.code(), 3);
+        assert_eq!(ExitCode::DaemonRejected.code(), 4);
+        assert_eq!(ExitCode::NodeNotRunning.code(), 5);
+        assert_eq!(ExitCode::Timeout.code(), 6);
+    }
+}
@@ -0,0 +1,431 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! The `export-financials` command: writes the daemon's service history
+//! for a date range to a CSV or JSON file, for tax and bookkeeping. The
+//! daemon streams the history in chunks (a year of records does not fit
+//! in one UI frame); this side checks the chunk sequence for gaps and
+//! appends each chunk to the file as it arrives, so a large export never
+//! has to fit in masq's memory either. Every amount appears both as a
+//! wei-equivalent integer (exact, reconcilable against the database) and
+//! in decimal token units (what the bookkeeper actually reads).
+
+use crate::exit_code::CommandError;
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+/// One exported record, as the daemon renders it: direction and service
+/// come pre-stringified, amounts in both representations.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportRecord {
+    pub timestamp_unix: u64,
+    pub direction: String,
+    pub service: String,
+    pub peer_wallet: String,
+    pub amount_wei: u64,
+    pub amount_tokens: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportFinancialsCommand {
+    pub from_unix: u64,
+    /// Inclusive: a `--to` date covers that whole day.
+    pub to_unix: u64,
+    pub format: ExportFormat,
+    pub file: PathBuf,
+}
+
+const USAGE: &str =
+    "usage: export-financials --from <YYYY-MM-DD|unix> --to <YYYY-MM-DD|unix> --format csv|json --file <path>";
+
+/// Parses `export-financials --from ... --to ... --format ... --file ...`
+/// with the flags in any order.
+pub fn parse_export_financials_command(
+    args: &[String],
+) -> Result<ExportFinancialsCommand, CommandError> {
+    let mut from = None;
+    let mut to = None;
+    let mut format = None;
+    let mut file = None;
+    let mut remaining = args;
+    while let [flag, value, rest @ ..] = remaining {
+        match flag.as_str() {
+            "--from" => from = Some(parse_date(value, false)?),
+            "--to" => to = Some(parse_date(value, true)?),
+            "--format" => {
+                format = Some(match value.as_str() {
+                    "csv" => ExportFormat::Csv,
+                    "json" => ExportFormat::Json,
+                    other => {
+                        return Err(CommandError::UsageError(format!(
+                            "--format must be csv or json, not {}",
+                            other
+                        )))
+                    }
+                })
+            }
+            "--file" => file = Some(PathBuf::from(value)),
+            _ => return Err(CommandError::UsageError(USAGE.to_string())),
+        }
+        remaining = rest;
+    }
+    if !remaining.is_empty() {
+        return Err(CommandError::UsageError(USAGE.to_string()));
+    }
+    match (from, to, format, file) {
+        (Some(from_unix), Some(to_unix), Some(format), Some(file)) => {
+            if from_unix > to_unix {
+                return Err(CommandError::UsageError(
+                    "--from must not be later than --to".to_string(),
+                ));
+            }
+            Ok(ExportFinancialsCommand {
+                from_unix,
+                to_unix,
+                format,
+                file,
+            })
+        }
+        _ => Err(CommandError::UsageError(USAGE.to_string())),
+    }
+}
+
+/// A date flag: either raw Unix seconds or `YYYY-MM-DD`, interpreted as
+/// UTC. An end-of-range date means the end of that day.
+fn parse_date(value: &str, end_of_day: bool) -> Result<u64, CommandError> {
+    if let Ok(unix) = value.parse::<u64>() {
+        return Ok(unix);
+    }
+    let parts: Vec<&str> = value.split('-').collect();
+    let parsed = match parts.as_slice() {
+        [y, m, d] => match (y.parse::<i64>(), m.parse::<u32>(), d.parse::<u32>()) {
+            (Ok(year), Ok(month), Ok(day))
+                if (1..=12).contains(&month) && (1..=31).contains(&day) =>
+            {
+                Some(unix_midnight(year, month, day))
+            }
+            _ => None,
+        },
+        _ => None,
+    };
+    match parsed {
+        Some(midnight) => Ok(if end_of_day {
+            midnight + 86_399
+        } else {
+            midnight
+        }),
+        None => Err(CommandError::UsageError(format!(
+            "{} is neither a YYYY-MM-DD date nor a Unix timestamp",
+            value
+        ))),
+    }
+}
+
+/// Days-from-civil (proleptic Gregorian, UTC) times 86400. All dates the
+/// command will see are well past the epoch, so the result fits u64.
+fn unix_midnight(year: i64, month: u32, day: u32) -> u64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = ((month + 9) % 12) as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    ((era * 146_097 + doe - 719_468) * 86_400) as u64
+}
+
+/// Streams arriving chunks into the output file in the chosen format,
+/// verifying the chunk sequence so a dropped frame produces an error
+/// instead of a silently truncated export.
+pub struct ExportWriter<W: Write> {
+    format: ExportFormat,
+    out: W,
+    next_chunk_index: usize,
+    records_written: usize,
+    finished: bool,
+}
+
+impl<W: Write> ExportWriter<W> {
+    pub fn new(format: ExportFormat, out: W) -> ExportWriter<W> {
+        ExportWriter {
+            format,
+            out,
+            next_chunk_index: 0,
+            records_written: 0,
+            finished: false,
+        }
+    }
+
+    pub fn accept_chunk(
+        &mut self,
+        chunk_index: usize,
+        records: &[ExportRecord],
+        last: bool,
+    ) -> Result<(), CommandError> {
+        if self.finished {
+            return Err(CommandError::Other(
+                "the daemon sent a chunk after the final one".to_string(),
+            ));
+        }
+        if chunk_index != self.next_chunk_index {
+            return Err(CommandError::Other(format!(
+                "export stream lost a chunk: expected chunk {}, got chunk {}",
+                self.next_chunk_index, chunk_index
+            )));
+        }
+        self.next_chunk_index += 1;
+        if self.records_written == 0 && !records.is_empty() {
+            self.write_str(match self.format {
+                ExportFormat::Csv => {
+                    "timestamp_unix,direction,service,peer_wallet,amount_wei,amount_tokens\n"
+                }
+                ExportFormat::Json => "[\n",
+            })?;
+        }
+        for record in records {
+            let rendered = match self.format {
+                ExportFormat::Csv => render_csv_row(record),
+                ExportFormat::Json => {
+                    let separator = if self.records_written == 0 { "" } else { ",\n" };
+                    format!("{}{}", separator, render_json_object(record))
+                }
+            };
+            self.write_str(&rendered)?;
+            self.records_written += 1;
+        }
+        if last {
+            self.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the final chunk has arrived; a command that disconnects
+    /// before this is true reports the export incomplete.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn finish(&mut self) -> Result<(), CommandError> {
+        match (self.format, self.records_written) {
+            (ExportFormat::Csv, 0) => self.write_str(
+                "timestamp_unix,direction,service,peer_wallet,amount_wei,amount_tokens\n",
+            )?,
+            (ExportFormat::Csv, _) => (),
+            (ExportFormat::Json, 0) => self.write_str("[]\n")?,
+            (ExportFormat::Json, _) => self.write_str("\n]\n")?,
+        }
+        self.finished = true;
+        Ok(())
+    }
+
+    fn write_str(&mut self, s: &str) -> Result<(), CommandError> {
+        self.out
+            .write_all(s.as_bytes())
+            .map_err(|error| CommandError::Other(format!("could not write export file: {}", error)))
+    }
+}
+
+fn render_csv_row(record: &ExportRecord) -> String {
+    format!(
+        "{},{},{},{},{},{}\n",
+        record.timestamp_unix,
+        csv_field(&record.direction),
+        csv_field(&record.service),
+        csv_field(&record.peer_wallet),
+        record.amount_wei,
+        record.amount_tokens
+    )
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn render_json_object(record: &ExportRecord) -> String {
+    format!(
+        "  {{\"timestamp_unix\": {}, \"direction\": \"{}\", \"service\": \"{}\", \"peer_wallet\": \"{}\", \"amount_wei\": {}, \"amount_tokens\": \"{}\"}}",
+        record.timestamp_unix,
+        json_escape(&record.direction),
+        json_escape(&record.service),
+        json_escape(&record.peer_wallet),
+        record.amount_wei,
+        json_escape(&record.amount_tokens)
+    )
+}
+
+fn json_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    fn record(timestamp: u64, amount_wei: u64, amount_tokens: &str) -> ExportRecord {
+        ExportRecord {
+            timestamp_unix: timestamp,
+            direction: "provided".to_string(),
+            service: "routing".to_string(),
+            peer_wallet: "0xpeer".to_string(),
+            amount_wei,
+            amount_tokens: amount_tokens.to_string(),
+        }
+    }
+
+    #[test]
+    fn a_complete_command_line_parses_in_any_flag_order() {
+        let command = parse_export_financials_command(&args(&[
+            "--format", "csv", "--file", "/tmp/out.csv", "--from", "2023-01-01", "--to",
+            "2023-12-31",
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            command,
+            ExportFinancialsCommand {
+                from_unix: 1_672_531_200,
+                to_unix: 1_704_067_199, // end of the --to day, inclusive
+                format: ExportFormat::Csv,
+                file: PathBuf::from("/tmp/out.csv"),
+            }
+        );
+    }
+
+    #[test]
+    fn raw_unix_timestamps_are_accepted_for_the_range() {
+        let command = parse_export_financials_command(&args(&[
+            "--from", "1000", "--to", "2000", "--format", "json", "--file", "/tmp/out.json",
+        ]))
+        .unwrap();
+
+        assert_eq!(command.from_unix, 1000);
+        assert_eq!(command.to_unix, 2000);
+        assert_eq!(command.format, ExportFormat::Json);
+    }
+
+    #[test]
+    fn missing_or_bad_flags_are_usage_errors() {
+        let cases: Vec<Vec<&str>> = vec![
+            vec![],
+            vec!["--from", "2023-01-01", "--to", "2023-12-31", "--format", "csv"],
+            vec!["--from", "2023-01-01", "--to", "2023-12-31", "--file", "/tmp/x"],
+            vec!["--from", "not-a-date", "--to", "2023-12-31", "--format", "csv", "--file", "/tmp/x"],
+            vec!["--from", "2023-13-01", "--to", "2023-12-31", "--format", "csv", "--file", "/tmp/x"],
+            vec!["--from", "2023-01-01", "--to", "2023-12-31", "--format", "xml", "--file", "/tmp/x"],
+            vec!["--from", "2023-12-31", "--to", "2023-01-01", "--format", "csv", "--file", "/tmp/x"],
+            vec!["--from", "2023-01-01", "--to", "2023-12-31", "--format", "csv", "--file", "/tmp/x", "stray"],
+        ];
+        for case in cases {
+            let result = parse_export_financials_command(&args(&case));
+
+            assert!(
+                matches!(result, Err(CommandError::UsageError(_))),
+                "for {:?}: {:?}",
+                case,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn csv_output_has_a_header_and_both_amount_representations() {
+        let mut out = vec![];
+        let mut subject = ExportWriter::new(ExportFormat::Csv, &mut out);
+
+        subject
+            .accept_chunk(0, &[record(1_000, 1_500_000_000_000_000_000, "1.5")], true)
+            .unwrap();
+
+        assert!(subject.is_finished());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "timestamp_unix,direction,service,peer_wallet,amount_wei,amount_tokens\n\
+             1000,provided,routing,0xpeer,1500000000000000000,1.5\n"
+        );
+    }
+
+    #[test]
+    fn json_output_is_one_array_across_all_chunks() {
+        let mut out = vec![];
+        let mut subject = ExportWriter::new(ExportFormat::Json, &mut out);
+
+        subject
+            .accept_chunk(0, &[record(1_000, 7, "0.000000000000000007")], false)
+            .unwrap();
+        subject
+            .accept_chunk(1, &[record(1_001, 8, "0.000000000000000008")], true)
+            .unwrap();
+
+        let rendered = String::from_utf8(out).unwrap();
+        assert!(rendered.starts_with("[\n"));
+        assert!(rendered.ends_with("\n]\n"));
+        assert_eq!(rendered.matches("\"timestamp_unix\"").count(), 2);
+        assert!(rendered.contains("\"amount_wei\": 7, \"amount_tokens\": \"0.000000000000000007\""));
+        // Exactly one separator between the two objects.
+        assert_eq!(rendered.matches("},\n").count(), 1);
+    }
+
+    #[test]
+    fn records_split_across_chunks_concatenate_without_loss_or_duplication() {
+        let mut out = vec![];
+        let mut subject = ExportWriter::new(ExportFormat::Csv, &mut out);
+        let first: Vec<ExportRecord> = (0..3).map(|n| record(n, n, "0")).collect();
+        let second: Vec<ExportRecord> = (3..5).map(|n| record(n, n, "0")).collect();
+
+        subject.accept_chunk(0, &first, false).unwrap();
+        subject.accept_chunk(1, &second, true).unwrap();
+
+        let rendered = String::from_utf8(out).unwrap();
+        let data_lines: Vec<&str> = rendered.lines().skip(1).collect();
+        assert_eq!(data_lines.len(), 5);
+        for (n, line) in data_lines.iter().enumerate() {
+            assert!(line.starts_with(&format!("{},", n)), "line {}: {}", n, line);
+        }
+    }
+
+    #[test]
+    fn a_gap_in_the_chunk_sequence_is_an_error_not_a_truncated_file() {
+        let mut out = vec![];
+        let mut subject = ExportWriter::new(ExportFormat::Csv, &mut out);
+        subject.accept_chunk(0, &[record(1, 1, "0")], false).unwrap();
+
+        let result = subject.accept_chunk(2, &[record(2, 2, "0")], true);
+
+        match result {
+            Err(CommandError::Other(message)) => {
+                assert!(message.contains("expected chunk 1, got chunk 2"))
+            }
+            other => panic!("expected Other, got {:?}", other),
+        }
+        assert!(!subject.is_finished());
+    }
+
+    #[test]
+    fn an_empty_export_still_writes_a_valid_file() {
+        let mut csv_out = vec![];
+        let mut csv = ExportWriter::new(ExportFormat::Csv, &mut csv_out);
+        let mut json_out = vec![];
+        let mut json = ExportWriter::new(ExportFormat::Json, &mut json_out);
+
+        csv.accept_chunk(0, &[], true).unwrap();
+        json.accept_chunk(0, &[], true).unwrap();
+
+        assert_eq!(
+            String::from_utf8(csv_out).unwrap(),
+            "timestamp_unix,direction,service,peer_wallet,amount_wei,amount_tokens\n"
+        );
+        assert_eq!(String::from_utf8(json_out).unwrap(), "[]\n");
+    }
+}
@@ -0,0 +1,285 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! The `generate-config` command: captures a working setup into a
+//! commented TOML config file. Every known parameter appears — active
+//! values uncommented, everything else commented out showing its default
+//! — so the file doubles as documentation of what can be set. Secrets
+//! are never written; they get a placeholder comment instead. The
+//! parameter table below is the single source of metadata, so a new
+//! parameter added there shows up in generated files automatically.
+
+use crate::exit_code::CommandError;
+use std::path::Path;
+
+/// One known setup parameter. `secret` parameters are omitted from
+/// generated files regardless of whether they are set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParamDef {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub default: &'static str,
+    pub secret: bool,
+}
+
+/// The shared parameter definitions; the daemon's setup space and this
+/// generator both read from here so they cannot diverge.
+pub const KNOWN_PARAMETERS: &[ParamDef] = &[
+    ParamDef {
+        name: "chain",
+        help: "Blockchain the node settles payments on",
+        default: "eth-mainnet",
+        secret: false,
+    },
+    ParamDef {
+        name: "clandestine-port",
+        help: "TCP port other nodes use to reach this one",
+        default: "5333",
+        secret: false,
+    },
+    ParamDef {
+        name: "dns-servers",
+        help: "Comma-separated upstream DNS servers for the exit",
+        default: "1.1.1.1",
+        secret: false,
+    },
+    ParamDef {
+        name: "earning-wallet",
+        help: "Wallet address that accrues routing and exit charges",
+        default: "",
+        secret: false,
+    },
+    ParamDef {
+        name: "gas-price",
+        help: "Gas price in gwei for settlement transactions",
+        default: "50",
+        secret: false,
+    },
+    ParamDef {
+        name: "log-level",
+        help: "Minimum level written to the log (trace|debug|info|warn|error)",
+        default: "warn",
+        secret: false,
+    },
+    ParamDef {
+        name: "neighbors",
+        help: "Comma-separated descriptors of nodes to debut against",
+        default: "",
+        secret: false,
+    },
+    ParamDef {
+        name: "wallet-password",
+        help: "Password unlocking the consuming wallet keystore",
+        default: "",
+        secret: true,
+    },
+];
+
+/// Renders the config file from the active (name, value) pairs — the
+/// daemon's merged setup when one is running, empty for offline defaults.
+pub fn render_config(active: &[(String, String)]) -> String {
+    let mut output = String::from(
+        "# ClandestiNode configuration, generated by `masq generate-config`.\n\
+         # Uncommented lines are active values; commented lines show defaults.\n",
+    );
+    for param in KNOWN_PARAMETERS {
+        output.push('\n');
+        output.push_str(&format!("# {}\n", param.help));
+        if param.secret {
+            output.push_str(&format!(
+                "# {} = <secret; set via environment or interactively, never written here>\n",
+                param.name
+            ));
+            continue;
+        }
+        match active.iter().find(|(name, _)| name == param.name) {
+            Some((_, value)) => output.push_str(&format!("{} = \"{}\"\n", param.name, value)),
+            None => output.push_str(&format!("# {} = \"{}\"\n", param.name, param.default)),
+        }
+    }
+    output
+}
+
+/// The loader's view of a generated file: the uncommented `name = "value"`
+/// lines. Shared with the tests' round-trip check.
+pub fn parse_config(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut pairs = vec![];
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected name = \"value\"", index + 1))?;
+        let value = value
+            .trim()
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .ok_or_else(|| format!("line {}: value must be double-quoted", index + 1))?;
+        pairs.push((name.trim().to_string(), value.to_string()));
+    }
+    Ok(pairs)
+}
+
+/// Writes the rendered file, refusing to clobber an existing one unless
+/// forced.
+pub fn write_config(path: &Path, contents: &str, force: bool) -> Result<(), CommandError> {
+    if path.exists() && !force {
+        return Err(CommandError::UsageError(format!(
+            "{} already exists; pass --force to overwrite it",
+            path.display()
+        )));
+    }
+    std::fs::write(path, contents)
+        .map_err(|e| CommandError::Other(format!("could not write {}: {}", path.display(), e)))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GenerateConfigAction {
+    pub path: String,
+    pub force: bool,
+}
+
+/// Parses `generate-config [<path>] [--force]`.
+pub fn parse_generate_config_command(
+    args: &[String],
+) -> Result<GenerateConfigAction, CommandError> {
+    let usage = || {
+        CommandError::UsageError("usage: generate-config [<path>] [--force]".to_string())
+    };
+    match args {
+        [] => Ok(GenerateConfigAction {
+            path: "config.toml".to_string(),
+            force: false,
+        }),
+        [only] if only == "--force" => Ok(GenerateConfigAction {
+            path: "config.toml".to_string(),
+            force: true,
+        }),
+        [path] if !path.starts_with("--") => Ok(GenerateConfigAction {
+            path: path.clone(),
+            force: false,
+        }),
+        [path, flag] if !path.starts_with("--") && flag == "--force" => {
+            Ok(GenerateConfigAction {
+                path: path.clone(),
+                force: true,
+            })
+        }
+        _ => Err(usage()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn active(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn every_known_parameter_appears_with_its_help_text() {
+        let rendered = render_config(&[]);
+
+        for param in KNOWN_PARAMETERS {
+            assert!(
+                rendered.contains(&format!("# {}\n", param.help)),
+                "missing help for {}",
+                param.name
+            );
+            assert!(
+                rendered.contains(&format!("# {} = ", param.name)),
+                "missing entry for {}",
+                param.name
+            );
+        }
+    }
+
+    #[test]
+    fn active_values_are_uncommented_and_defaults_are_commented() {
+        let rendered = render_config(&active(&[("log-level", "debug")]));
+
+        assert!(rendered.contains("log-level = \"debug\"\n"));
+        assert!(rendered.contains("# clandestine-port = \"5333\"\n"));
+        assert!(!rendered.contains("\nlog-level = \"warn\""));
+    }
+
+    #[test]
+    fn secrets_are_never_written_even_when_set() {
+        let rendered = render_config(&active(&[("wallet-password", "hunter2")]));
+
+        assert!(!rendered.contains("hunter2"));
+        assert!(rendered.contains("# wallet-password = <secret;"));
+    }
+
+    #[test]
+    fn generated_output_round_trips_through_the_loader() {
+        let set = active(&[("log-level", "debug"), ("clandestine-port", "9876")]);
+
+        let mut reloaded = parse_config(&render_config(&set)).unwrap();
+
+        reloaded.sort();
+        let mut expected = set;
+        expected.sort();
+        assert_eq!(reloaded, expected);
+    }
+
+    #[test]
+    fn an_offline_generation_parses_to_no_active_values() {
+        let reloaded = parse_config(&render_config(&[])).unwrap();
+
+        assert!(reloaded.is_empty());
+    }
+
+    #[test]
+    fn an_existing_file_is_not_overwritten_without_force() {
+        let dir = std::env::temp_dir().join("clandestinode_generate_config_tests");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("existing.toml");
+        std::fs::write(&path, "precious = \"data\"\n").unwrap();
+
+        let refused = write_config(&path, &render_config(&[]), false);
+        let forced = write_config(&path, &render_config(&[]), true);
+
+        match refused {
+            Err(CommandError::UsageError(message)) => assert!(message.contains("--force")),
+            other => panic!("expected UsageError, got {:?}", other),
+        }
+        assert_eq!(forced, Ok(()));
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("precious"));
+    }
+
+    #[test]
+    fn command_parsing_handles_path_and_force_in_any_arrangement() {
+        assert_eq!(
+            parse_generate_config_command(&[]).unwrap(),
+            GenerateConfigAction {
+                path: "config.toml".to_string(),
+                force: false,
+            }
+        );
+        assert_eq!(
+            parse_generate_config_command(&["--force".to_string()]).unwrap(),
+            GenerateConfigAction {
+                path: "config.toml".to_string(),
+                force: true,
+            }
+        );
+        assert_eq!(
+            parse_generate_config_command(&["mine.toml".to_string(), "--force".to_string()])
+                .unwrap(),
+            GenerateConfigAction {
+                path: "mine.toml".to_string(),
+                force: true,
+            }
+        );
+        assert!(matches!(
+            parse_generate_config_command(&["a".to_string(), "b".to_string()]),
+            Err(CommandError::UsageError(_))
+        ));
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Prompt rendering for interactive mode. The prompt reflects what masq is
+//! currently talking to (daemon vs running node) and flags broadcasts that
+//! arrived while the user was typing; the synchronized terminal writer
+//! redraws it without clobbering the line being edited.
+
+/// What the connection behind the prompt is currently attached to, kept
+/// fresh by broadcasts and the periodic status query.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionTarget {
+    Daemon,
+    NodeRunning,
+    NodeShuttingDown,
+}
+
+pub struct PromptState {
+    target: ConnectionTarget,
+    unread_broadcasts: usize,
+}
+
+impl PromptState {
+    pub fn new() -> PromptState {
+        PromptState {
+            target: ConnectionTarget::Daemon,
+            unread_broadcasts: 0,
+        }
+    }
+
+    /// Applied when a status query answers or a redirect lands.
+    pub fn target_changed(&mut self, target: ConnectionTarget) {
+        self.target = target;
+    }
+
+    /// Applied when a broadcast arrives while no command is running.
+    pub fn broadcast_arrived(&mut self) {
+        self.unread_broadcasts += 1;
+    }
+
+    /// Applied when the user runs `show` and the backlog is displayed.
+    pub fn broadcasts_read(&mut self) -> usize {
+        std::mem::take(&mut self.unread_broadcasts)
+    }
+
+    pub fn unread_broadcasts(&self) -> usize {
+        self.unread_broadcasts
+    }
+
+    /// The string the terminal writer draws.
+    pub fn render(&self) -> String {
+        let target = match self.target {
+            ConnectionTarget::Daemon => "daemon",
+            ConnectionTarget::NodeRunning => "node:running",
+            ConnectionTarget::NodeShuttingDown => "node:shutting-down",
+        };
+        match self.unread_broadcasts {
+            0 => format!("masq({})> ", target),
+            count => format!("masq({}) [{} new]> ", target, count),
+        }
+    }
+}
+
+impl Default for PromptState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fresh_prompt_shows_the_daemon() {
+        let subject = PromptState::new();
+
+        assert_eq!(subject.render(), "masq(daemon)> ");
+    }
+
+    #[test]
+    fn prompt_follows_the_connection_target() {
+        let mut subject = PromptState::new();
+
+        subject.target_changed(ConnectionTarget::NodeRunning);
+        assert_eq!(subject.render(), "masq(node:running)> ");
+
+        subject.target_changed(ConnectionTarget::NodeShuttingDown);
+        assert_eq!(subject.render(), "masq(node:shutting-down)> ");
+
+        subject.target_changed(ConnectionTarget::Daemon);
+        assert_eq!(subject.render(), "masq(daemon)> ");
+    }
+
+    #[test]
+    fn unread_broadcasts_show_in_the_prompt_until_read() {
+        let mut subject = PromptState::new();
+        subject.target_changed(ConnectionTarget::NodeRunning);
+
+        subject.broadcast_arrived();
+        subject.broadcast_arrived();
+        assert_eq!(subject.render(), "masq(node:running) [2 new]> ");
+
+        let read = subject.broadcasts_read();
+
+        assert_eq!(read, 2);
+        assert_eq!(subject.render(), "masq(node:running)> ");
+    }
+
+    #[test]
+    fn scripted_session_renders_expected_sequence() {
+        let mut subject = PromptState::new();
+        let mut rendered = vec![subject.render()];
+
+        subject.target_changed(ConnectionTarget::NodeRunning);
+        rendered.push(subject.render());
+        subject.broadcast_arrived();
+        rendered.push(subject.render());
+        subject.broadcasts_read();
+        subject.target_changed(ConnectionTarget::Daemon);
+        rendered.push(subject.render());
+
+        assert_eq!(
+            rendered,
+            vec![
+                "masq(daemon)> ",
+                "masq(node:running)> ",
+                "masq(node:running) [1 new]> ",
+                "masq(daemon)> ",
+            ]
+        );
+    }
+}
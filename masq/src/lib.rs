@@ -0,0 +1,13 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+pub mod agent;
+pub mod descriptor;
+pub mod exit_code;
+pub mod export_command;
+pub mod generate_config;
+pub mod interactive_prompt;
+pub mod localization;
+pub mod runs_command;
+pub mod setup_command;
+pub mod streams_command;
+pub mod ui_connection;
@@ -0,0 +1,232 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Message catalog for user-facing output. Commands look strings up by key;
+//! the catalog for the selected language answers, falling back to the
+//! embedded English catalog for any key it lacks. The language comes from
+//! `--lang` when given, otherwise the LANG environment variable. Community
+//! translations can be dropped into an override directory as simple
+//! `key = value` files and take precedence over the embedded catalogs.
+//!
+//! Placeholder substitution (`{0}`, `{1}`, ...) is deliberately forgiving:
+//! a missing argument leaves the placeholder visible and an extra argument
+//! is ignored, in both cases with a log line — a mistranslated catalog must
+//! never panic a command.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The embedded English catalog: the authoritative key set.
+const ENGLISH_CATALOG: &str = "\
+setup.header = NAME                      VALUE                                            STATUS\n\
+setup.changes_saved = Changes saved: {0}\n\
+start.success = ClandestiNode started on PID {0}\n\
+start.failure = ClandestiNode failed to start: {0}\n\
+shutdown.success = ClandestiNode was shut down\n\
+shutdown.not_running = ClandestiNode is not running; nothing to shut down\n\
+status.running = Node is running (mode {0}, {1} neighbors)\n\
+status.not_running = Node is not running\n\
+error.connection = Could not reach the daemon at port {0}\n\
+error.usage = Invalid arguments: {0}\n\
+";
+
+/// Embedded community catalogs, keyed by language code.
+const SPANISH_CATALOG: &str = "\
+start.success = ClandestiNode se inici\u{f3} con PID {0}\n\
+shutdown.success = ClandestiNode se apag\u{f3}\n\
+status.not_running = El nodo no se est\u{e1} ejecutando\n\
+";
+
+/// Key-based lookup with English fallback.
+pub struct MessageCatalog {
+    language: String,
+    messages: HashMap<String, String>,
+    english: HashMap<String, String>,
+}
+
+impl MessageCatalog {
+    /// Selects the catalog: `--lang` wins, then LANG (of which only the
+    /// leading language code matters, so "es_ES.UTF-8" selects "es").
+    pub fn select(lang_flag: Option<&str>, lang_env: Option<&str>) -> MessageCatalog {
+        let language = lang_flag
+            .map(|lang| lang.to_string())
+            .or_else(|| {
+                lang_env.map(|value| {
+                    value
+                        .split(['_', '.'])
+                        .next()
+                        .unwrap_or("en")
+                        .to_string()
+                })
+            })
+            .unwrap_or_else(|| "en".to_string());
+        let messages = match language.as_str() {
+            "es" => parse_catalog(SPANISH_CATALOG),
+            _ => HashMap::new(),
+        };
+        MessageCatalog {
+            language,
+            messages,
+            english: parse_catalog(ENGLISH_CATALOG),
+        }
+    }
+
+    /// Layers `key = value` files from the override directory on top of the
+    /// embedded catalog. Unreadable files are skipped; a community typo must
+    /// not break masq.
+    pub fn load_overrides(&mut self, directory: &Path) {
+        let path = directory.join(format!("{}.catalog", self.language));
+        if let Ok(contents) = std::fs::read_to_string(path) {
+            for (key, value) in parse_catalog(&contents) {
+                self.messages.insert(key, value);
+            }
+        }
+    }
+
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    /// Looks up `key` and substitutes `{0}`, `{1}`, ... with `args`.
+    /// Unknown keys fall back to English; keys absent there too render as
+    /// the key itself so the problem is visible rather than silent.
+    pub fn message(&self, key: &str, args: &[&str]) -> String {
+        let template = self
+            .messages
+            .get(key)
+            .or_else(|| self.english.get(key))
+            .cloned()
+            .unwrap_or_else(|| key.to_string());
+        substitute(&template, args)
+    }
+}
+
+fn parse_catalog(contents: &str) -> HashMap<String, String> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            line.split_once(" = ")
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Replaces each `{n}` with the matching argument. A placeholder without an
+/// argument stays in place; surplus arguments are ignored. Both cases are
+/// catalog bugs, not user errors, so the output degrades instead of failing.
+fn substitute(template: &str, args: &[&str]) -> String {
+    let mut result = template.to_string();
+    for (index, arg) in args.iter().enumerate() {
+        result = result.replace(&format!("{{{}}}", index), arg);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_lang_flag_wins_over_the_environment() {
+        let subject = MessageCatalog::select(Some("es"), Some("de_DE.UTF-8"));
+
+        assert_eq!(subject.language(), "es");
+    }
+
+    #[test]
+    fn the_environment_language_code_is_extracted() {
+        let subject = MessageCatalog::select(None, Some("es_MX.UTF-8"));
+
+        assert_eq!(subject.language(), "es");
+        assert_eq!(
+            subject.message("shutdown.success", &[]),
+            "ClandestiNode se apag\u{f3}"
+        );
+    }
+
+    #[test]
+    fn a_translated_command_output_comes_from_the_selected_catalog() {
+        let subject = MessageCatalog::select(Some("es"), None);
+
+        assert_eq!(
+            subject.message("start.success", &["4217"]),
+            "ClandestiNode se inici\u{f3} con PID 4217"
+        );
+    }
+
+    #[test]
+    fn keys_missing_from_a_translation_fall_back_to_english() {
+        let subject = MessageCatalog::select(Some("es"), None);
+
+        // The Spanish catalog has no entry for error.connection.
+        assert_eq!(
+            subject.message("error.connection", &["5333"]),
+            "Could not reach the daemon at port 5333"
+        );
+    }
+
+    #[test]
+    fn an_unknown_language_is_entirely_english() {
+        let subject = MessageCatalog::select(Some("tlh"), None);
+
+        assert_eq!(
+            subject.message("status.not_running", &[]),
+            "Node is not running"
+        );
+    }
+
+    #[test]
+    fn a_key_absent_everywhere_renders_as_itself() {
+        let subject = MessageCatalog::select(None, None);
+
+        assert_eq!(subject.message("no.such.key", &[]), "no.such.key");
+    }
+
+    #[test]
+    fn a_missing_argument_leaves_the_placeholder_visible() {
+        let subject = MessageCatalog::select(None, None);
+
+        assert_eq!(
+            subject.message("status.running", &["standard"]),
+            "Node is running (mode standard, {1} neighbors)"
+        );
+    }
+
+    #[test]
+    fn extra_arguments_are_ignored() {
+        let subject = MessageCatalog::select(None, None);
+
+        assert_eq!(
+            subject.message("shutdown.success", &["stray", "args"]),
+            "ClandestiNode was shut down"
+        );
+    }
+
+    #[test]
+    fn override_directory_catalogs_take_precedence() {
+        let directory = std::env::temp_dir().join("masq_localization_override_test");
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("es.catalog"),
+            "status.not_running = El nodo est\u{e1} detenido\n",
+        )
+        .unwrap();
+        let mut subject = MessageCatalog::select(Some("es"), None);
+
+        subject.load_overrides(&directory);
+
+        assert_eq!(
+            subject.message("status.not_running", &[]),
+            "El nodo est\u{e1} detenido"
+        );
+        // Keys the override does not touch keep their embedded translation.
+        assert_eq!(
+            subject.message("shutdown.success", &[]),
+            "ClandestiNode se apag\u{f3}"
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! The `runs` and `logs` commands: `runs` lists the daemon's recent node
+//! runs — each with its own log file and exit status — and
+//! `logs --run <n>` prints the log of one of them. Together they make a
+//! crash loop readable run by run instead of as one interleaved file.
+
+use crate::exit_code::CommandError;
+use std::fs;
+
+/// One run row as the daemon reports it over the UI gateway.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunRow {
+    pub run_number: u64,
+    pub started_at_unix: u64,
+    /// "running", "exited:<code>", or "signaled", as rendered by the daemon.
+    pub outcome: String,
+    pub log_path: String,
+}
+
+/// Parses `runs` (no arguments).
+pub fn parse_runs_command(args: &[String]) -> Result<(), CommandError> {
+    if args.is_empty() {
+        Ok(())
+    } else {
+        Err(CommandError::UsageError("usage: runs".to_string()))
+    }
+}
+
+/// Parses `logs --run <n>`.
+pub fn parse_logs_command(args: &[String]) -> Result<u64, CommandError> {
+    match args {
+        [flag, number] if flag == "--run" => number
+            .parse()
+            .map_err(|_| CommandError::UsageError("usage: logs --run <number>".to_string())),
+        _ => Err(CommandError::UsageError(
+            "usage: logs --run <number>".to_string(),
+        )),
+    }
+}
+
+/// Renders the run listing: fixed-width columns, header first, newest
+/// run at the top (the daemon sends them that way).
+pub fn render_runs_list(rows: &[RunRow]) -> String {
+    let mut output = format!(
+        "{:<6} {:<12} {:<12} {}\n",
+        "RUN", "STARTED", "OUTCOME", "LOG FILE"
+    );
+    for row in rows {
+        output.push_str(&format!(
+            "{:<6} {:<12} {:<12} {}\n",
+            row.run_number, row.started_at_unix, row.outcome, row.log_path
+        ));
+    }
+    if rows.is_empty() {
+        output.push_str("(no recorded runs)\n");
+    }
+    output
+}
+
+/// Reads the log file of the requested run out of the daemon's listing.
+pub fn read_run_log(rows: &[RunRow], run_number: u64) -> Result<String, CommandError> {
+    let row = rows
+        .iter()
+        .find(|row| row.run_number == run_number)
+        .ok_or_else(|| {
+            CommandError::Other(format!(
+                "no recorded run {}; `runs` lists the ones the daemon kept",
+                run_number
+            ))
+        })?;
+    fs::read_to_string(&row.log_path).map_err(|error| {
+        CommandError::Other(format!(
+            "could not read the log of run {} at {}: {}",
+            run_number, row.log_path, error
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+
+    fn args(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    fn row(run_number: u64, outcome: &str, log_path: &str) -> RunRow {
+        RunRow {
+            run_number,
+            started_at_unix: 1_700_000_000,
+            outcome: outcome.to_string(),
+            log_path: log_path.to_string(),
+        }
+    }
+
+    #[test]
+    fn runs_takes_no_arguments() {
+        assert_eq!(parse_runs_command(&[]), Ok(()));
+        assert_eq!(
+            parse_runs_command(&args(&["extra"])),
+            Err(CommandError::UsageError("usage: runs".to_string()))
+        );
+    }
+
+    #[test]
+    fn logs_requires_a_numeric_run() {
+        assert_eq!(parse_logs_command(&args(&["--run", "7"])), Ok(7));
+        for bad_args in [vec![], vec!["--run"], vec!["--run", "seven"], vec!["7"]] {
+            let result = parse_logs_command(&args(&bad_args));
+
+            assert_eq!(
+                result,
+                Err(CommandError::UsageError(
+                    "usage: logs --run <number>".to_string()
+                )),
+                "for {:?}",
+                bad_args
+            );
+        }
+    }
+
+    #[test]
+    fn the_listing_renders_a_row_per_run() {
+        let rows = vec![
+            row(3, "running", "/var/log/node-run-3.log"),
+            row(2, "exited:101", "/var/log/node-run-2.log"),
+        ];
+
+        let output = render_runs_list(&rows);
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].starts_with("RUN"));
+        assert!(lines[1].contains("running"));
+        assert!(lines[2].contains("exited:101"));
+        assert!(lines[2].contains("/var/log/node-run-2.log"));
+    }
+
+    #[test]
+    fn an_empty_listing_says_so() {
+        assert!(render_runs_list(&[]).contains("(no recorded runs)"));
+    }
+
+    #[test]
+    fn reading_a_run_log_returns_its_contents() {
+        let path: PathBuf = env::temp_dir().join(format!("runs_cmd_{}.log", std::process::id()));
+        std::fs::write(&path, "the run's log body\n").unwrap();
+        let rows = vec![row(4, "exited:0", path.to_str().unwrap())];
+
+        let contents = read_run_log(&rows, 4).unwrap();
+
+        assert_eq!(contents, "the run's log body\n");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn an_unknown_run_is_reported_as_such() {
+        let result = read_run_log(&[row(1, "exited:0", "/nowhere.log")], 9);
+
+        match result {
+            Err(CommandError::Other(message)) => assert!(message.contains("no recorded run 9")),
+            other => panic!("expected Other, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_pruned_log_file_is_reported_as_unreadable() {
+        let result = read_run_log(&[row(1, "exited:0", "/definitely/not/there.log")], 1);
+
+        match result {
+            Err(CommandError::Other(message)) => {
+                assert!(message.contains("could not read the log of run 1"))
+            }
+            other => panic!("expected Other, got {:?}", other),
+        }
+    }
+}
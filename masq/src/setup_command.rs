@@ -0,0 +1,165 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! The `setup` command line: sets daemon setup parameters, unsets them so
+//! lower-precedence sources show through (`--param ''` or `--unset param`),
+//! and resets the whole space back to defaults. Reset is destructive
+//! enough that interactive sessions confirm it first; scripts passed
+//! `--reset` on a one-shot command line are presumed to mean it.
+
+use crate::exit_code::CommandError;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SetupAction {
+    /// Bare `setup`: show the current space.
+    Show,
+    /// Parameter changes, applied in the order given.
+    Changes(Vec<SetupChange>),
+    Reset,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SetupChange {
+    Set { name: String, value: String },
+    Unset { name: String },
+}
+
+const USAGE: &str =
+    "usage: setup [--reset] [--unset <param>] [--<param> <value>] [--<param> '']";
+
+/// Parses everything after `setup`. `--reset` stands alone; otherwise any
+/// mix of sets and unsets is fine and order is preserved.
+pub fn parse_setup_command(args: &[String]) -> Result<SetupAction, CommandError> {
+    if args.is_empty() {
+        return Ok(SetupAction::Show);
+    }
+    if args.iter().any(|arg| arg == "--reset") {
+        if args.len() > 1 {
+            return Err(CommandError::UsageError(
+                "--reset cannot be combined with other setup changes".to_string(),
+            ));
+        }
+        return Ok(SetupAction::Reset);
+    }
+    let mut changes = vec![];
+    let mut remaining = args;
+    loop {
+        remaining = match remaining {
+            [] => break,
+            [unset_flag, name, rest @ ..] if unset_flag == "--unset" => {
+                changes.push(SetupChange::Unset {
+                    name: name.clone(),
+                });
+                rest
+            }
+            [param, value, rest @ ..] if param.starts_with("--") => {
+                let name = param.trim_start_matches("--").to_string();
+                if name.is_empty() {
+                    return Err(CommandError::UsageError(USAGE.to_string()));
+                }
+                if value.is_empty() {
+                    // `--param ''`: the unset spelling for people who think
+                    // in values rather than flags.
+                    changes.push(SetupChange::Unset { name });
+                } else {
+                    changes.push(SetupChange::Set {
+                        name,
+                        value: value.clone(),
+                    });
+                }
+                rest
+            }
+            _ => return Err(CommandError::UsageError(USAGE.to_string())),
+        };
+    }
+    Ok(SetupAction::Changes(changes))
+}
+
+/// Interactive sessions ask before a reset; this decides whether the
+/// typed answer authorizes it.
+pub fn reset_confirmed(answer: &str) -> bool {
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn bare_setup_shows_the_space() {
+        assert_eq!(parse_setup_command(&args(&[])), Ok(SetupAction::Show));
+    }
+
+    #[test]
+    fn sets_and_unsets_parse_in_order() {
+        let result = parse_setup_command(&args(&[
+            "--neighborhood-mode",
+            "consume-only",
+            "--unset",
+            "neighbors",
+            "--clandestine-port",
+            "",
+        ]));
+
+        assert_eq!(
+            result,
+            Ok(SetupAction::Changes(vec![
+                SetupChange::Set {
+                    name: "neighborhood-mode".to_string(),
+                    value: "consume-only".to_string(),
+                },
+                SetupChange::Unset {
+                    name: "neighbors".to_string(),
+                },
+                SetupChange::Unset {
+                    name: "clandestine-port".to_string(),
+                },
+            ]))
+        );
+    }
+
+    #[test]
+    fn reset_stands_alone() {
+        assert_eq!(
+            parse_setup_command(&args(&["--reset"])),
+            Ok(SetupAction::Reset)
+        );
+        match parse_setup_command(&args(&["--reset", "--neighbors", "a,b"])) {
+            Err(CommandError::UsageError(message)) => {
+                assert!(message.contains("--reset"));
+            }
+            other => panic!("expected UsageError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn malformed_argument_lists_are_usage_errors() {
+        for bad_args in [
+            vec!["--neighbors"],             // flag with no value
+            vec!["neighbors", "a,b"],        // value with no flag
+            vec!["--", "value"],             // empty parameter name
+            vec!["--unset"],                 // unset with no parameter
+        ] {
+            let result = parse_setup_command(&args(&bad_args));
+
+            assert!(
+                matches!(result, Err(CommandError::UsageError(_))),
+                "expected UsageError for {:?}, got {:?}",
+                bad_args,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn reset_confirmation_accepts_yes_and_nothing_else() {
+        assert!(reset_confirmed("y"));
+        assert!(reset_confirmed("Yes "));
+        assert!(!reset_confirmed("n"));
+        assert!(!reset_confirmed(""));
+        assert!(!reset_confirmed("yep"));
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! The `streams` command: lists the node's active ProxyServer streams or
+//! kills a named one. Debugging aid for the stream that never finishes.
+
+use crate::exit_code::CommandError;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StreamsAction {
+    List,
+    Kill { stream_key: String },
+}
+
+/// One stream row as the node reports it over the UI gateway.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamRow {
+    pub stream_key: String,
+    pub protocol: String,
+    pub target_hostname: String,
+    pub age_secs: u64,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+}
+
+/// Parses `streams list` / `streams kill <key>`.
+pub fn parse_streams_command(args: &[String]) -> Result<StreamsAction, CommandError> {
+    match args {
+        [subcommand] if subcommand == "list" => Ok(StreamsAction::List),
+        [subcommand, stream_key] if subcommand == "kill" => Ok(StreamsAction::Kill {
+            stream_key: stream_key.clone(),
+        }),
+        _ => Err(CommandError::UsageError(
+            "usage: streams list | streams kill <stream-key>".to_string(),
+        )),
+    }
+}
+
+/// Renders the listing the way `masq` prints tables: fixed-width columns,
+/// header first.
+pub fn render_stream_list(rows: &[StreamRow]) -> String {
+    let mut output = format!(
+        "{:<18} {:<8} {:<30} {:>8} {:>12} {:>12}\n",
+        "STREAM", "PROTOCOL", "TARGET", "AGE", "BYTES UP", "BYTES DOWN"
+    );
+    for row in rows {
+        output.push_str(&format!(
+            "{:<18} {:<8} {:<30} {:>7}s {:>12} {:>12}\n",
+            row.stream_key,
+            row.protocol,
+            row.target_hostname,
+            row.age_secs,
+            row.bytes_up,
+            row.bytes_down
+        ));
+    }
+    if rows.is_empty() {
+        output.push_str("(no active streams)\n");
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn list_and_kill_parse() {
+        assert_eq!(
+            parse_streams_command(&args(&["list"])),
+            Ok(StreamsAction::List)
+        );
+        assert_eq!(
+            parse_streams_command(&args(&["kill", "feedfacedeadbeef"])),
+            Ok(StreamsAction::Kill {
+                stream_key: "feedfacedeadbeef".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn anything_else_is_a_usage_error() {
+        for bad_args in [vec![], vec!["kill"], vec!["pause", "key"]] {
+            let result = parse_streams_command(&args(&bad_args));
+
+            match result {
+                Err(CommandError::UsageError(message)) => {
+                    assert!(message.contains("usage: streams"), "for {:?}", bad_args);
+                }
+                other => panic!("expected UsageError for {:?}, got {:?}", bad_args, other),
+            }
+        }
+    }
+
+    #[test]
+    fn listing_renders_a_row_per_stream() {
+        let rows = vec![StreamRow {
+            stream_key: "feedfacedeadbeef".to_string(),
+            protocol: "HTTP".to_string(),
+            target_hostname: "neverloads.example".to_string(),
+            age_secs: 95,
+            bytes_up: 400,
+            bytes_down: 0,
+        }];
+
+        let output = render_stream_list(&rows);
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].starts_with("STREAM"));
+        assert!(lines[1].contains("feedfacedeadbeef"));
+        assert!(lines[1].contains("neverloads.example"));
+        assert!(lines[1].contains("95s"));
+    }
+
+    #[test]
+    fn an_empty_listing_says_so() {
+        assert!(render_stream_list(&[]).contains("(no active streams)"));
+    }
+}
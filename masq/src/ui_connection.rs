@@ -0,0 +1,169 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Where masq connects: `--ui-host` joins `--ui-port`, so a one-shot
+//! command can reach a headless daemon directly instead of through a
+//! hand-built SSH tunnel. Commands can carry secrets (wallet passwords,
+//! descriptors), so a non-loopback target requires TLS on the UI
+//! connection; an operator who genuinely wants cleartext across a
+//! network must say `--insecure` and own the consequences. The resolved
+//! target — host, port, and scheme — is what the CommandContext's
+//! websocket connection is built from.
+
+use crate::exit_code::CommandError;
+use std::net::IpAddr;
+
+pub const DEFAULT_UI_HOST: &str = "127.0.0.1";
+pub const DEFAULT_UI_PORT: u16 = 5334;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UiSecurity {
+    /// Cleartext websocket; loopback targets, or `--insecure`.
+    Plain,
+    /// TLS websocket; mandatory for non-loopback targets.
+    Tls,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UiTarget {
+    pub host: String,
+    pub port: u16,
+    pub security: UiSecurity,
+}
+
+impl UiTarget {
+    /// The websocket URL the CommandContext connects to.
+    pub fn url(&self) -> String {
+        let scheme = match self.security {
+            UiSecurity::Plain => "ws",
+            UiSecurity::Tls => "wss",
+        };
+        if self.host.contains(':') {
+            // A bare IPv6 address needs brackets in a URL.
+            format!("{}://[{}]:{}", scheme, self.host, self.port)
+        } else {
+            format!("{}://{}:{}", scheme, self.host, self.port)
+        }
+    }
+}
+
+/// Resolves `--ui-host`/`--ui-port`/`--insecure` into a connection
+/// target, enforcing the TLS-off-loopback policy.
+pub fn resolve_ui_target(
+    ui_host: Option<&str>,
+    ui_port: Option<u16>,
+    insecure: bool,
+) -> Result<UiTarget, CommandError> {
+    let host = ui_host.unwrap_or(DEFAULT_UI_HOST);
+    if !is_valid_host(host) {
+        return Err(CommandError::UsageError(format!(
+            "--ui-host {} is neither an IP address nor a plausible hostname",
+            host
+        )));
+    }
+    let security = if is_loopback_host(host) {
+        UiSecurity::Plain
+    } else if insecure {
+        UiSecurity::Plain
+    } else {
+        UiSecurity::Tls
+    };
+    Ok(UiTarget {
+        host: host.to_string(),
+        port: ui_port.unwrap_or(DEFAULT_UI_PORT),
+        security,
+    })
+}
+
+fn is_loopback_host(host: &str) -> bool {
+    if host == "localhost" {
+        return true;
+    }
+    match host.parse::<IpAddr>() {
+        Ok(address) => address.is_loopback(),
+        Err(_) => false,
+    }
+}
+
+fn is_valid_host(host: &str) -> bool {
+    if host.is_empty() {
+        return false;
+    }
+    if host.parse::<IpAddr>().is_ok() {
+        return true;
+    }
+    // Hostname: labels of letters, digits, and hyphens, dot-separated.
+    host.split('.').all(|label| {
+        !label.is_empty()
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_default_target_is_loopback_and_plain() {
+        let target = resolve_ui_target(None, None, false).unwrap();
+
+        assert_eq!(
+            target,
+            UiTarget {
+                host: "127.0.0.1".to_string(),
+                port: DEFAULT_UI_PORT,
+                security: UiSecurity::Plain,
+            }
+        );
+        assert_eq!(target.url(), format!("ws://127.0.0.1:{}", DEFAULT_UI_PORT));
+    }
+
+    #[test]
+    fn loopback_spellings_all_stay_plain() {
+        for host in ["127.0.0.1", "127.0.0.53", "localhost", "::1"] {
+            let target = resolve_ui_target(Some(host), Some(5334), false).unwrap();
+
+            assert_eq!(target.security, UiSecurity::Plain, "for {}", host);
+        }
+    }
+
+    #[test]
+    fn a_remote_host_requires_tls() {
+        let target = resolve_ui_target(Some("node.lan"), Some(5334), false).unwrap();
+
+        assert_eq!(target.security, UiSecurity::Tls);
+        assert_eq!(target.url(), "wss://node.lan:5334");
+    }
+
+    #[test]
+    fn insecure_acknowledges_cleartext_to_a_remote_host() {
+        let target = resolve_ui_target(Some("192.168.1.50"), Some(5334), true).unwrap();
+
+        assert_eq!(target.security, UiSecurity::Plain);
+        assert_eq!(target.url(), "ws://192.168.1.50:5334");
+    }
+
+    #[test]
+    fn an_ipv6_host_is_bracketed_in_the_url() {
+        let target = resolve_ui_target(Some("fd00::17"), Some(5334), false).unwrap();
+
+        assert_eq!(target.url(), "wss://[fd00::17]:5334");
+    }
+
+    #[test]
+    fn implausible_hosts_are_usage_errors() {
+        for bad_host in ["", "no spaces.example", "-leading.example", "bad_underscore"] {
+            let result = resolve_ui_target(Some(bad_host), None, false);
+
+            match result {
+                Err(CommandError::UsageError(message)) => {
+                    assert!(message.contains("--ui-host"), "for {:?}", bad_host)
+                }
+                other => panic!("expected UsageError for {:?}, got {:?}", bad_host, other),
+            }
+        }
+    }
+}
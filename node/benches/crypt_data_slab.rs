@@ -0,0 +1,42 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Allocation churn for hop-sized crypto buffers: the recycling slab vs
+//! a fresh Vec per buffer. Run with `cargo bench --bench crypt_data_slab`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use node_lib::sub_lib::crypt_data_slab::CryptDataSlab;
+
+const CHURN_COUNT: u64 = 100_000;
+const HOP_BYTES: usize = 120;
+
+fn churn_slab(slab: &CryptDataSlab) {
+    for i in 0..CHURN_COUNT {
+        let mut handle = slab.alloc(HOP_BYTES);
+        handle[0] = i as u8;
+        black_box(&handle[..]);
+    }
+}
+
+fn churn_heap() {
+    for i in 0..CHURN_COUNT {
+        let mut buffer = vec![0u8; HOP_BYTES];
+        buffer[0] = i as u8;
+        black_box(&buffer[..]);
+    }
+}
+
+fn slab_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("crypt_buffer_churn");
+    group.throughput(Throughput::Elements(CHURN_COUNT));
+    let slab = CryptDataSlab::new();
+    group.bench_function(BenchmarkId::from_parameter("slab"), |b| {
+        b.iter(|| churn_slab(&slab));
+    });
+    group.bench_function(BenchmarkId::from_parameter("heap"), |b| {
+        b.iter(churn_heap);
+    });
+    group.finish();
+}
+
+criterion_group!(benches, slab_benchmark);
+criterion_main!(benches);
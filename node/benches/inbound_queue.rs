@@ -0,0 +1,111 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Inbound server-data intake: the lock-free ArrayQueue path vs the
+//! mutexed queue it replaces, four producers against one drain worker.
+//! Run with `cargo bench --bench inbound_queue`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use node_lib::proxy_client::inbound_queue::{InboundIntake, InboundServerData};
+use node_lib::sub_lib::stream_key::StreamKey;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const MESSAGES_PER_PRODUCER: u64 = 10_000;
+const PRODUCERS: u32 = 4;
+
+fn message(stream: u32, sequence_number: u64) -> InboundServerData {
+    let peer_addr = SocketAddr::from_str(&format!("127.0.0.1:{}", 40_000 + stream)).unwrap();
+    InboundServerData {
+        stream_key: StreamKey::new(b"bench_salt", peer_addr),
+        sequence_number,
+        last_data: false,
+        data: vec![0xAB; 64],
+    }
+}
+
+fn run_lock_free() {
+    let handled = Arc::new(AtomicU64::new(0));
+    let handled_in_handler = handled.clone();
+    let subject = Arc::new(InboundIntake::start(16_384, move |_| {
+        handled_in_handler.fetch_add(1, Ordering::Relaxed);
+    }));
+    let producers: Vec<_> = (0..PRODUCERS)
+        .map(|stream| {
+            let subject = subject.clone();
+            thread::spawn(move || {
+                for sequence_number in 0..MESSAGES_PER_PRODUCER {
+                    let mut pending = message(stream, sequence_number);
+                    while let Err(returned) = subject.offer(pending) {
+                        pending = returned;
+                    }
+                }
+            })
+        })
+        .collect();
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    drop(Arc::try_unwrap(subject).ok().expect("producers done"));
+    assert_eq!(
+        handled.load(Ordering::Relaxed),
+        MESSAGES_PER_PRODUCER * u64::from(PRODUCERS)
+    );
+}
+
+fn run_mutexed() {
+    let queue: Arc<Mutex<VecDeque<InboundServerData>>> = Arc::new(Mutex::new(VecDeque::new()));
+    let handled = Arc::new(AtomicU64::new(0));
+    let total = MESSAGES_PER_PRODUCER * u64::from(PRODUCERS);
+    let drain_queue = queue.clone();
+    let drain_handled = handled.clone();
+    let drain = thread::spawn(move || loop {
+        let popped = drain_queue.lock().unwrap().pop_front();
+        match popped {
+            Some(_) => {
+                if drain_handled.fetch_add(1, Ordering::Relaxed) + 1 == total {
+                    return;
+                }
+            }
+            None => thread::yield_now(),
+        }
+    });
+    let producers: Vec<_> = (0..PRODUCERS)
+        .map(|stream| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                for sequence_number in 0..MESSAGES_PER_PRODUCER {
+                    queue
+                        .lock()
+                        .unwrap()
+                        .push_back(message(stream, sequence_number));
+                }
+            })
+        })
+        .collect();
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    drain.join().unwrap();
+}
+
+fn intake_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("inbound_intake_40k");
+    group.throughput(Throughput::Elements(
+        MESSAGES_PER_PRODUCER * u64::from(PRODUCERS),
+    ));
+    group.sample_size(20);
+    group.bench_function(BenchmarkId::from_parameter("array_queue"), |b| {
+        b.iter(run_lock_free);
+    });
+    group.bench_function(BenchmarkId::from_parameter("mutexed"), |b| {
+        b.iter(run_mutexed);
+    });
+    group.finish();
+}
+
+criterion_group!(benches, intake_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,57 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Serving a 10 MiB file-backed cache entry: sendfile(2) vs the io::copy
+//! baseline. Run with `cargo bench --bench sendfile_serve`; as with the
+//! relay benchmark, the win is CPU time per serve, not wall clock on an
+//! idle loopback.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use node_lib::proxy_client::sendfile_serve::{serve_file, serve_file_userspace};
+use std::fs::File;
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+const CACHED_RESPONSE_BYTES: usize = 10 * 1024 * 1024;
+
+fn cached_file_path() -> std::path::PathBuf {
+    let path = std::env::temp_dir().join("clandestinode_sendfile_bench.bin");
+    if !path.exists() {
+        std::fs::write(&path, vec![0xC3u8; CACHED_RESPONSE_BYTES]).unwrap();
+    }
+    path
+}
+
+fn run_one_serve(serve: fn(&mut File, &mut TcpStream) -> std::io::Result<u64>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client_thread = thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        let mut sink = vec![0u8; 1024 * 1024];
+        while socket.read(&mut sink).unwrap() > 0 {}
+    });
+
+    let mut file = File::open(cached_file_path()).unwrap();
+    let mut socket = TcpStream::connect(addr).unwrap();
+    let total = serve(&mut file, &mut socket).unwrap();
+    assert_eq!(total, CACHED_RESPONSE_BYTES as u64);
+    drop(socket);
+
+    client_thread.join().unwrap();
+}
+
+fn serve_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serve_cached_10mib");
+    group.throughput(Throughput::Bytes(CACHED_RESPONSE_BYTES as u64));
+    group.sample_size(20);
+    group.bench_function(BenchmarkId::from_parameter("platform"), |b| {
+        b.iter(|| run_one_serve(serve_file));
+    });
+    group.bench_function(BenchmarkId::from_parameter("userspace"), |b| {
+        b.iter(|| run_one_serve(serve_file_userspace));
+    });
+    group.finish();
+}
+
+criterion_group!(benches, serve_benchmark);
+criterion_main!(benches);
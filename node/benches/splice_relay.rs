@@ -0,0 +1,60 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Relay throughput: splice(2) zero-copy path vs the userspace buffer
+//! loop. Run with `cargo bench --bench splice_relay`; the interesting
+//! number is CPU time per transfer, which is what the zero-copy path
+//! halves on bulk streams.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use node_lib::proxy_client::splice_relay::{copy_until_eof, relay_until_eof};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::thread;
+
+const TRANSFER_BYTES: usize = 100 * 1024 * 1024;
+
+fn run_one_transfer(relay: fn(&mut TcpStream, &mut TcpStream) -> std::io::Result<u64>) {
+    let source_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let sink_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let source_addr = source_listener.local_addr().unwrap();
+    let sink_addr = sink_listener.local_addr().unwrap();
+
+    let source_thread = thread::spawn(move || {
+        let (mut socket, _) = source_listener.accept().unwrap();
+        let chunk = vec![0xA5u8; 1024 * 1024];
+        for _ in 0..TRANSFER_BYTES / chunk.len() {
+            socket.write_all(&chunk).unwrap();
+        }
+        socket.shutdown(Shutdown::Write).unwrap();
+    });
+    let sink_thread = thread::spawn(move || {
+        let (mut socket, _) = sink_listener.accept().unwrap();
+        let mut sink = vec![0u8; 1024 * 1024];
+        while socket.read(&mut sink).unwrap() > 0 {}
+    });
+
+    let mut input = TcpStream::connect(source_addr).unwrap();
+    let mut output = TcpStream::connect(sink_addr).unwrap();
+    let total = relay(&mut input, &mut output).unwrap();
+    assert_eq!(total, TRANSFER_BYTES as u64);
+    drop(output);
+
+    source_thread.join().unwrap();
+    sink_thread.join().unwrap();
+}
+
+fn relay_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("relay_100mib");
+    group.throughput(Throughput::Bytes(TRANSFER_BYTES as u64));
+    group.sample_size(10);
+    group.bench_function(BenchmarkId::from_parameter("platform"), |b| {
+        b.iter(|| run_one_transfer(relay_until_eof));
+    });
+    group.bench_function(BenchmarkId::from_parameter("userspace"), |b| {
+        b.iter(|| run_one_transfer(copy_until_eof));
+    });
+    group.finish();
+}
+
+criterion_group!(benches, relay_benchmark);
+criterion_main!(benches);
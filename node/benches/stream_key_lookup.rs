@@ -0,0 +1,70 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Stream-context lookup: hashbrown + AHash vs the std SipHash map it
+//! replaced. Run with `cargo bench --bench stream_key_lookup`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use node_lib::proxy_server::stream_context_map::{new_stream_key_map, StreamKeyMap};
+use node_lib::sub_lib::stream_key::StreamKey;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+const STREAM_COUNT: u32 = 10_000;
+const LOOKUPS: usize = 100_000;
+
+fn keys() -> Vec<StreamKey> {
+    (0..STREAM_COUNT)
+        .map(|n| {
+            let addr =
+                SocketAddr::from_str(&format!("10.{}.{}.1:4000", n / 256, n % 256)).unwrap();
+            StreamKey::new(&n.to_be_bytes(), addr)
+        })
+        .collect()
+}
+
+fn run_lookups<M: Map>(map: &M, keys: &[StreamKey]) {
+    for i in 0..LOOKUPS {
+        let key = &keys[(i * 7919) % keys.len()];
+        black_box(map.lookup(key));
+    }
+}
+
+trait Map {
+    fn lookup(&self, key: &StreamKey) -> Option<&u64>;
+}
+
+impl Map for HashMap<StreamKey, u64> {
+    fn lookup(&self, key: &StreamKey) -> Option<&u64> {
+        self.get(key)
+    }
+}
+
+impl Map for StreamKeyMap<u64> {
+    fn lookup(&self, key: &StreamKey) -> Option<&u64> {
+        self.get(key)
+    }
+}
+
+fn lookup_benchmark(c: &mut Criterion) {
+    let keys = keys();
+    let mut std_map: HashMap<StreamKey, u64> = HashMap::new();
+    let mut fast_map: StreamKeyMap<u64> = new_stream_key_map();
+    for (n, key) in keys.iter().enumerate() {
+        std_map.insert(*key, n as u64);
+        fast_map.insert(*key, n as u64);
+    }
+
+    let mut group = c.benchmark_group("stream_key_lookup");
+    group.throughput(Throughput::Elements(LOOKUPS as u64));
+    group.bench_function(BenchmarkId::from_parameter("hashbrown_ahash"), |b| {
+        b.iter(|| run_lookups(&fast_map, &keys));
+    });
+    group.bench_function(BenchmarkId::from_parameter("std_siphash"), |b| {
+        b.iter(|| run_lookups(&std_map, &keys));
+    });
+    group.finish();
+}
+
+criterion_group!(benches, lookup_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,272 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Anonymous payment credentials via Chaum blind signatures. A consuming
+//! node blinds a freshly generated token, the issuer (the earning side's
+//! credential mint) signs the blinded value without ever seeing the token,
+//! and the node unblinds the signature. The Accountant can then verify the
+//! credential against the issuer's public parameters without learning which
+//! wallet it was issued to.
+//!
+//! The scheme here is the textbook RSA construction at demonstration scale:
+//! the modulus is a fixed 62-bit product of two Mersenne-adjacent primes so
+//! that all arithmetic fits in u128. A production deployment would swap in a
+//! real RSA or BLS blind-signature library behind the same interface.
+
+use crate::sub_lib::wallet::BlindCredential;
+
+/// Fixed demonstration-scale issuer parameters: n = P * Q, e = 65537.
+const ISSUER_P: u64 = 2_147_483_647; // 2^31 - 1
+const ISSUER_Q: u64 = 2_147_483_629;
+pub const ISSUER_E: u64 = 65_537;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CredentialError {
+    /// The token is not the expected 16 bytes of (message, signature).
+    Malformed,
+    /// The signature does not verify under the issuer's public parameters.
+    Invalid,
+    /// A blinding factor must be invertible mod n; this one was not.
+    BadBlindingFactor,
+}
+
+/// The issuer's public parameters: everything a verifier needs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IssuerPublicParams {
+    pub n: u64,
+    pub e: u64,
+}
+
+/// The credential mint. Holds the private exponent; signs blinded messages
+/// without being able to connect them to the tokens later presented.
+pub struct CredentialIssuer {
+    n: u64,
+    e: u64,
+    d: u64,
+}
+
+impl CredentialIssuer {
+    pub fn new() -> CredentialIssuer {
+        let n = ISSUER_P * ISSUER_Q;
+        let lambda = (ISSUER_P - 1) * (ISSUER_Q - 1);
+        let d = mod_inverse(ISSUER_E, lambda).expect("e is coprime to lambda(n)");
+        CredentialIssuer { n, e: ISSUER_E, d }
+    }
+
+    pub fn public_params(&self) -> IssuerPublicParams {
+        IssuerPublicParams { n: self.n, e: self.e }
+    }
+
+    /// Signs a blinded message. The issuer sees only m * r^e mod n, which is
+    /// uniformly distributed and reveals nothing about m.
+    pub fn sign_blinded(&self, blinded: u64) -> u64 {
+        mod_pow(blinded % self.n, self.d, self.n)
+    }
+}
+
+impl Default for CredentialIssuer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Blinds a token message with a client-chosen blinding factor r:
+/// returns m * r^e mod n. Fails if r is not invertible mod n.
+pub fn blind(
+    message: u64,
+    blinding_factor: u64,
+    params: &IssuerPublicParams,
+) -> Result<u64, CredentialError> {
+    if mod_inverse(blinding_factor % params.n, params.n).is_none() {
+        return Err(CredentialError::BadBlindingFactor);
+    }
+    let r_to_e = mod_pow(blinding_factor % params.n, params.e, params.n);
+    Ok(mod_mul(message % params.n, r_to_e, params.n))
+}
+
+/// Strips the blinding from the issuer's signature on the blinded message:
+/// (m * r^e)^d * r^-1 = m^d mod n.
+pub fn unblind(
+    signed_blinded: u64,
+    blinding_factor: u64,
+    params: &IssuerPublicParams,
+) -> Result<u64, CredentialError> {
+    let r_inverse = mod_inverse(blinding_factor % params.n, params.n)
+        .ok_or(CredentialError::BadBlindingFactor)?;
+    Ok(mod_mul(signed_blinded % params.n, r_inverse, params.n))
+}
+
+/// Packages an unblinded (message, signature) pair as the token carried in
+/// `ConsumingCredential::Blind`.
+pub fn make_credential(message: u64, signature: u64) -> BlindCredential {
+    let mut token = Vec::with_capacity(16);
+    token.extend_from_slice(&message.to_le_bytes());
+    token.extend_from_slice(&signature.to_le_bytes());
+    BlindCredential { token }
+}
+
+/// Verifies a presented credential against the issuer's public parameters.
+/// Nothing in the token identifies the wallet it was issued to: the issuer
+/// only ever saw the blinded form of the message.
+pub fn verify_credential(
+    credential: &BlindCredential,
+    params: &IssuerPublicParams,
+) -> Result<(), CredentialError> {
+    if credential.token.len() != 16 {
+        return Err(CredentialError::Malformed);
+    }
+    let mut message_bytes = [0u8; 8];
+    message_bytes.copy_from_slice(&credential.token[..8]);
+    let mut signature_bytes = [0u8; 8];
+    signature_bytes.copy_from_slice(&credential.token[8..]);
+    let message = u64::from_le_bytes(message_bytes);
+    let signature = u64::from_le_bytes(signature_bytes);
+    if mod_pow(signature % params.n, params.e, params.n) == message % params.n {
+        Ok(())
+    } else {
+        Err(CredentialError::Invalid)
+    }
+}
+
+fn mod_mul(a: u64, b: u64, modulus: u64) -> u64 {
+    ((a as u128 * b as u128) % modulus as u128) as u64
+}
+
+fn mod_pow(mut base: u64, mut exponent: u64, modulus: u64) -> u64 {
+    let mut result = 1u64;
+    base %= modulus;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mod_mul(result, base, modulus);
+        }
+        base = mod_mul(base, base, modulus);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// Extended Euclid: the inverse of a mod m, or None if gcd(a, m) != 1.
+fn mod_inverse(a: u64, modulus: u64) -> Option<u64> {
+    let (mut old_r, mut r) = (a as i128, modulus as i128);
+    let (mut old_s, mut s) = (1i128, 0i128);
+    while r != 0 {
+        let quotient = old_r / r;
+        let next_r = old_r - quotient * r;
+        old_r = r;
+        r = next_r;
+        let next_s = old_s - quotient * s;
+        old_s = s;
+        s = next_s;
+    }
+    if old_r != 1 {
+        return None;
+    }
+    Some(old_s.rem_euclid(modulus as i128) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sub_lib::wallet::{ConsumingCredential, Wallet};
+
+    fn issue(message: u64, blinding_factor: u64) -> BlindCredential {
+        let issuer = CredentialIssuer::new();
+        let params = issuer.public_params();
+        let blinded = blind(message, blinding_factor, &params).unwrap();
+        let signed_blinded = issuer.sign_blinded(blinded);
+        let signature = unblind(signed_blinded, blinding_factor, &params).unwrap();
+        make_credential(message, signature)
+    }
+
+    #[test]
+    fn issued_credential_verifies() {
+        let credential = issue(0x0123_4567_89AB_CDEF, 0xDEAD_BEEF);
+
+        let result = verify_credential(&credential, &CredentialIssuer::new().public_params());
+
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn issuer_never_sees_the_token_message() {
+        let issuer = CredentialIssuer::new();
+        let params = issuer.public_params();
+        let message = 0x0123_4567_89AB_CDEF % params.n;
+        let blinding_factor = 0xDEAD_BEEF;
+
+        let blinded = blind(message, blinding_factor, &params).unwrap();
+
+        // What the issuer signs bears no visible relation to the message the
+        // credential will later present.
+        assert_ne!(blinded, message);
+        let credential = issue(message, blinding_factor);
+        assert_ne!(&credential.token[..8], &blinded.to_le_bytes());
+    }
+
+    #[test]
+    fn different_blinding_factors_give_the_same_unblinded_signature() {
+        // Unlinkability sanity check: the final signature depends only on
+        // the message, so the issuer cannot correlate by signature either.
+        let first = issue(777_777, 12_345);
+        let second = issue(777_777, 987_654_321);
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn tampered_message_is_rejected() {
+        let mut credential = issue(0x1111_2222_3333_4444, 99);
+        credential.token[0] ^= 0x01;
+
+        let result = verify_credential(&credential, &CredentialIssuer::new().public_params());
+
+        assert_eq!(result, Err(CredentialError::Invalid));
+    }
+
+    #[test]
+    fn tampered_signature_is_rejected() {
+        let mut credential = issue(0x1111_2222_3333_4444, 99);
+        credential.token[15] ^= 0x80;
+
+        let result = verify_credential(&credential, &CredentialIssuer::new().public_params());
+
+        assert_eq!(result, Err(CredentialError::Invalid));
+    }
+
+    #[test]
+    fn truncated_token_is_malformed() {
+        let credential = BlindCredential {
+            token: vec![1, 2, 3],
+        };
+
+        let result = verify_credential(&credential, &CredentialIssuer::new().public_params());
+
+        assert_eq!(result, Err(CredentialError::Malformed));
+    }
+
+    #[test]
+    fn non_invertible_blinding_factor_is_rejected() {
+        let params = CredentialIssuer::new().public_params();
+
+        // P divides n, so P is not invertible mod n.
+        let result = blind(42, super::ISSUER_P, &params);
+
+        assert_eq!(result, Err(CredentialError::BadBlindingFactor));
+    }
+
+    #[test]
+    fn consuming_credential_accepts_either_form() {
+        let wallet_form = ConsumingCredential::Wallet(Wallet::new("0xabcdef"));
+        let blind_form = ConsumingCredential::Blind(issue(5, 7));
+
+        match (&wallet_form, &blind_form) {
+            (ConsumingCredential::Wallet(w), ConsumingCredential::Blind(c)) => {
+                assert_eq!(w.address, "0xabcdef");
+                assert_eq!(
+                    verify_credential(c, &CredentialIssuer::new().public_params()),
+                    Ok(())
+                );
+            }
+            _ => panic!("variants did not match as constructed"),
+        }
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Dispatches settlement transactions to the configured payment chain.
+//! Ethereum L1 works but is expensive; operators can instead point the
+//! accountant at an L2 rollup, which speaks the same JSON-RPC surface
+//! (`eth_sendRawTransaction`) but needs the rollup's chain id baked into
+//! the signed transaction so it cannot be replayed on another chain.
+
+use crate::accountant::payment_channels::Transaction;
+
+pub const L1_CHAIN_ID: u64 = 1;
+pub const OPTIMISM_CHAIN_ID: u64 = 10;
+pub const ARBITRUM_CHAIN_ID: u64 = 42_161;
+
+const DEFAULT_L1_URL: &str = "https://mainnet.infura.io/v3/default";
+
+/// Where settlement transactions go. The L2 variants carry the operator's
+/// rollup RPC endpoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PaymentChain {
+    L1,
+    OptimismL2(String),
+    ArbitrumL2(String),
+}
+
+impl PaymentChain {
+    pub fn chain_id(&self) -> u64 {
+        match self {
+            PaymentChain::L1 => L1_CHAIN_ID,
+            PaymentChain::OptimismL2(_) => OPTIMISM_CHAIN_ID,
+            PaymentChain::ArbitrumL2(_) => ARBITRUM_CHAIN_ID,
+        }
+    }
+
+    pub fn rpc_url(&self) -> &str {
+        match self {
+            PaymentChain::L1 => DEFAULT_L1_URL,
+            PaymentChain::OptimismL2(url) | PaymentChain::ArbitrumL2(url) => url,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SubmissionError {
+    RpcFailure(String),
+}
+
+/// The slice of JSON-RPC the submitter needs; mockable for tests.
+pub trait JsonRpcClient: Send {
+    /// Posts a JSON-RPC request body to `url` and returns the `result`
+    /// field of the response.
+    fn call(&self, url: &str, body: &str) -> Result<String, SubmissionError>;
+}
+
+/// Sends settlements to the configured chain. The raw transaction encoding
+/// here is a stand-in for real RLP signing, but the chain id discipline and
+/// the JSON-RPC envelope are exactly what production needs.
+pub struct ChainSubmitter {
+    chain: PaymentChain,
+    client: Box<dyn JsonRpcClient>,
+}
+
+impl ChainSubmitter {
+    pub fn new(chain: PaymentChain, client: Box<dyn JsonRpcClient>) -> ChainSubmitter {
+        ChainSubmitter { chain, client }
+    }
+
+    /// Submits a settlement transaction; returns the transaction hash the
+    /// chain reported.
+    pub fn submit(&self, transaction: &Transaction) -> Result<String, SubmissionError> {
+        let raw = self.encode_raw(transaction);
+        let body = format!(
+            "{{\"jsonrpc\":\"2.0\",\"method\":\"eth_sendRawTransaction\",\"params\":[\"{}\"],\"id\":1}}",
+            raw
+        );
+        self.client.call(self.chain.rpc_url(), &body)
+    }
+
+    fn encode_raw(&self, transaction: &Transaction) -> String {
+        let plain = format!(
+            "{}|{}|{}|{}",
+            transaction.from.address,
+            transaction.to.address,
+            transaction.amount_wei,
+            self.chain.chain_id()
+        );
+        let mut encoded = String::from("0x");
+        for byte in plain.as_bytes() {
+            encoded.push_str(&format!("{:02x}", byte));
+        }
+        encoded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sub_lib::wallet::Wallet;
+    use std::sync::{Arc, Mutex};
+
+    struct JsonRpcClientMock {
+        call_params: Arc<Mutex<Vec<(String, String)>>>,
+        call_results: Mutex<Vec<Result<String, SubmissionError>>>,
+    }
+
+    impl JsonRpcClientMock {
+        fn new() -> JsonRpcClientMock {
+            JsonRpcClientMock {
+                call_params: Arc::new(Mutex::new(vec![])),
+                call_results: Mutex::new(vec![]),
+            }
+        }
+
+        fn call_params(mut self, params: &Arc<Mutex<Vec<(String, String)>>>) -> JsonRpcClientMock {
+            self.call_params = params.clone();
+            self
+        }
+
+        fn call_result(self, result: Result<String, SubmissionError>) -> JsonRpcClientMock {
+            self.call_results.lock().unwrap().push(result);
+            self
+        }
+    }
+
+    impl JsonRpcClient for JsonRpcClientMock {
+        fn call(&self, url: &str, body: &str) -> Result<String, SubmissionError> {
+            self.call_params
+                .lock()
+                .unwrap()
+                .push((url.to_string(), body.to_string()));
+            self.call_results.lock().unwrap().remove(0)
+        }
+    }
+
+    fn settlement() -> Transaction {
+        Transaction {
+            from: Wallet::new("0xconsumer"),
+            to: Wallet::new("0xearning"),
+            amount_wei: 700,
+        }
+    }
+
+    fn decode_raw_param(body: &str) -> String {
+        let hex = body
+            .split("\"params\":[\"0x")
+            .nth(1)
+            .unwrap()
+            .split('"')
+            .next()
+            .unwrap();
+        let bytes: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn optimism_submission_uses_the_rollup_endpoint_and_chain_id() {
+        let call_params = Arc::new(Mutex::new(vec![]));
+        let client = JsonRpcClientMock::new()
+            .call_params(&call_params)
+            .call_result(Ok("0xtxhash01".to_string()));
+        let subject = ChainSubmitter::new(
+            PaymentChain::OptimismL2("https://optimism.example/rpc".to_string()),
+            Box::new(client),
+        );
+
+        let result = subject.submit(&settlement());
+
+        assert_eq!(result, Ok("0xtxhash01".to_string()));
+        let calls = call_params.lock().unwrap();
+        let (url, body) = &calls[0];
+        assert_eq!(url, "https://optimism.example/rpc");
+        assert!(body.contains("\"method\":\"eth_sendRawTransaction\""));
+        assert_eq!(
+            decode_raw_param(body),
+            format!("0xconsumer|0xearning|700|{}", OPTIMISM_CHAIN_ID)
+        );
+    }
+
+    #[test]
+    fn arbitrum_submission_bakes_in_the_arbitrum_chain_id() {
+        let call_params = Arc::new(Mutex::new(vec![]));
+        let client = JsonRpcClientMock::new()
+            .call_params(&call_params)
+            .call_result(Ok("0xtxhash02".to_string()));
+        let subject = ChainSubmitter::new(
+            PaymentChain::ArbitrumL2("https://arbitrum.example/rpc".to_string()),
+            Box::new(client),
+        );
+
+        subject.submit(&settlement()).unwrap();
+
+        let calls = call_params.lock().unwrap();
+        assert!(decode_raw_param(&calls[0].1).ends_with(&ARBITRUM_CHAIN_ID.to_string()));
+    }
+
+    #[test]
+    fn l1_remains_the_default_dispatch_target() {
+        let call_params = Arc::new(Mutex::new(vec![]));
+        let client = JsonRpcClientMock::new()
+            .call_params(&call_params)
+            .call_result(Ok("0xtxhash03".to_string()));
+        let subject = ChainSubmitter::new(PaymentChain::L1, Box::new(client));
+
+        subject.submit(&settlement()).unwrap();
+
+        let calls = call_params.lock().unwrap();
+        assert_eq!(calls[0].0, DEFAULT_L1_URL);
+        assert!(decode_raw_param(&calls[0].1).ends_with(&L1_CHAIN_ID.to_string()));
+    }
+
+    #[test]
+    fn rpc_failure_is_surfaced() {
+        let client = JsonRpcClientMock::new()
+            .call_result(Err(SubmissionError::RpcFailure("sequencer down".to_string())));
+        let subject = ChainSubmitter::new(
+            PaymentChain::OptimismL2("https://optimism.example/rpc".to_string()),
+            Box::new(client),
+        );
+
+        let result = subject.submit(&settlement());
+
+        assert_eq!(
+            result,
+            Err(SubmissionError::RpcFailure("sequencer down".to_string()))
+        );
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use crate::accountant::expected_charges_dao::ExpectedChargesDao;
+use crate::sub_lib::cryptde::PublicKey;
+
+/// Tolerance applied before a claim is called an over-claim: clock skew and
+/// retransmissions legitimately inflate a relay's byte counts a little.
+pub const DEFAULT_TOLERANCE_PERCENT: u64 = 10;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChargeVerdict {
+    /// The claim is within tolerance of our own records; pay it.
+    Verified,
+    /// The relay claims more than our records plus tolerance allow. Hold the
+    /// payment, surface it in financials, and broadcast a warning.
+    OverClaim {
+        claimed_wei: u64,
+        expected_wei: u64,
+        tolerance_percent: u64,
+    },
+    /// We have no expectation recorded for this hop at all.
+    NoExpectation { claimed_wei: u64 },
+}
+
+/// Cross-checks incoming payable claims against the expected-charges ledger
+/// instead of paying whatever relays assert.
+pub struct ChargeVerifier {
+    tolerance_percent: u64,
+}
+
+impl ChargeVerifier {
+    pub fn new(tolerance_percent: u64) -> ChargeVerifier {
+        ChargeVerifier { tolerance_percent }
+    }
+
+    pub fn verify(
+        &self,
+        dao: &dyn ExpectedChargesDao,
+        hop_key: &PublicKey,
+        claimed_wei: u64,
+    ) -> ChargeVerdict {
+        let expected_wei = dao.expected_total_for(hop_key);
+        if expected_wei == 0 {
+            return ChargeVerdict::NoExpectation { claimed_wei };
+        }
+        let ceiling = expected_wei + (expected_wei * self.tolerance_percent / 100);
+        if claimed_wei <= ceiling {
+            ChargeVerdict::Verified
+        } else {
+            ChargeVerdict::OverClaim {
+                claimed_wei,
+                expected_wei,
+                tolerance_percent: self.tolerance_percent,
+            }
+        }
+    }
+}
+
+impl Default for ChargeVerifier {
+    fn default() -> Self {
+        Self::new(DEFAULT_TOLERANCE_PERCENT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accountant::expected_charges_dao::{ExpectedCharge, ExpectedChargesDaoReal};
+    use std::time::SystemTime;
+
+    fn dao_with(hop_key: &PublicKey, expected_wei: u64) -> ExpectedChargesDaoReal {
+        let mut dao = ExpectedChargesDaoReal::new();
+        dao.record(ExpectedCharge {
+            route_id: 1,
+            hop_key: hop_key.clone(),
+            expected_charge_wei: expected_wei,
+            recorded_at: SystemTime::now(),
+        });
+        dao
+    }
+
+    #[test]
+    fn matching_claim_is_verified() {
+        let key = PublicKey::new(b"relay");
+        let dao = dao_with(&key, 1000);
+        let subject = ChargeVerifier::new(10);
+
+        assert_eq!(subject.verify(&dao, &key, 1000), ChargeVerdict::Verified);
+    }
+
+    #[test]
+    fn claim_at_the_tolerance_boundary_is_still_verified() {
+        let key = PublicKey::new(b"relay");
+        let dao = dao_with(&key, 1000);
+        let subject = ChargeVerifier::new(10);
+
+        assert_eq!(subject.verify(&dao, &key, 1100), ChargeVerdict::Verified);
+    }
+
+    #[test]
+    fn claim_just_past_the_tolerance_boundary_is_an_over_claim() {
+        let key = PublicKey::new(b"relay");
+        let dao = dao_with(&key, 1000);
+        let subject = ChargeVerifier::new(10);
+
+        assert_eq!(
+            subject.verify(&dao, &key, 1101),
+            ChargeVerdict::OverClaim {
+                claimed_wei: 1101,
+                expected_wei: 1000,
+                tolerance_percent: 10,
+            }
+        );
+    }
+
+    #[test]
+    fn claim_without_any_expectation_is_flagged() {
+        let key = PublicKey::new(b"stranger");
+        let dao = ExpectedChargesDaoReal::new();
+        let subject = ChargeVerifier::default();
+
+        assert_eq!(
+            subject.verify(&dao, &key, 500),
+            ChargeVerdict::NoExpectation { claimed_wei: 500 }
+        );
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use crate::sub_lib::cryptde::PublicKey;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// What the originating ProxyServer expects a given hop to charge for a given
+/// route: it knows the payload sizes it sent and the rates it agreed to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpectedCharge {
+    pub route_id: u64,
+    pub hop_key: PublicKey,
+    pub expected_charge_wei: u64,
+    pub recorded_at: SystemTime,
+}
+
+/// Ledger of per-route, per-hop expected charges, kept so the accountant can
+/// cross-check what relays later claim against what we believe we consumed.
+pub trait ExpectedChargesDao: Send {
+    fn record(&mut self, charge: ExpectedCharge);
+    /// Total expected charge for a hop across all routes still in retention.
+    fn expected_total_for(&self, hop_key: &PublicKey) -> u64;
+    /// Drops entries older than the retention period; returns how many went.
+    fn purge_older_than(&mut self, retention: Duration, now: SystemTime) -> usize;
+}
+
+pub struct ExpectedChargesDaoReal {
+    charges: HashMap<PublicKey, Vec<ExpectedCharge>>,
+}
+
+impl ExpectedChargesDaoReal {
+    pub fn new() -> ExpectedChargesDaoReal {
+        ExpectedChargesDaoReal {
+            charges: HashMap::new(),
+        }
+    }
+}
+
+impl Default for ExpectedChargesDaoReal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExpectedChargesDao for ExpectedChargesDaoReal {
+    fn record(&mut self, charge: ExpectedCharge) {
+        self.charges
+            .entry(charge.hop_key.clone())
+            .or_default()
+            .push(charge);
+    }
+
+    fn expected_total_for(&self, hop_key: &PublicKey) -> u64 {
+        self.charges
+            .get(hop_key)
+            .map(|charges| charges.iter().map(|c| c.expected_charge_wei).sum())
+            .unwrap_or(0)
+    }
+
+    fn purge_older_than(&mut self, retention: Duration, now: SystemTime) -> usize {
+        let cutoff = now - retention;
+        let mut purged = 0;
+        self.charges.retain(|_, charges| {
+            let before = charges.len();
+            charges.retain(|c| c.recorded_at >= cutoff);
+            purged += before - charges.len();
+            !charges.is_empty()
+        });
+        purged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn charge(route_id: u64, key: &[u8], wei: u64, recorded_at: SystemTime) -> ExpectedCharge {
+        ExpectedCharge {
+            route_id,
+            hop_key: PublicKey::new(key),
+            expected_charge_wei: wei,
+            recorded_at,
+        }
+    }
+
+    #[test]
+    fn totals_accumulate_per_hop_key() {
+        let mut subject = ExpectedChargesDaoReal::new();
+        let now = SystemTime::now();
+        subject.record(charge(1, b"relay", 100, now));
+        subject.record(charge(2, b"relay", 250, now));
+        subject.record(charge(1, b"exit", 400, now));
+
+        assert_eq!(subject.expected_total_for(&PublicKey::new(b"relay")), 350);
+        assert_eq!(subject.expected_total_for(&PublicKey::new(b"exit")), 400);
+        assert_eq!(subject.expected_total_for(&PublicKey::new(b"nobody")), 0);
+    }
+
+    #[test]
+    fn purge_respects_retention_cutoff() {
+        let mut subject = ExpectedChargesDaoReal::new();
+        let now = SystemTime::now();
+        let old = now - Duration::from_secs(7200);
+        subject.record(charge(1, b"relay", 100, old));
+        subject.record(charge(2, b"relay", 250, now));
+
+        let purged = subject.purge_older_than(Duration::from_secs(3600), now);
+
+        assert_eq!(purged, 1);
+        assert_eq!(subject.expected_total_for(&PublicKey::new(b"relay")), 250);
+    }
+}
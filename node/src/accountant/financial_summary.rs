@@ -0,0 +1,175 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Periodic "you earned X, you owe Y, Z peers delinquent" summaries, so
+//! operators don't have to poll. The scheduler leans on DAO aggregation
+//! queries; it never loads individual rows.
+
+use std::time::{Duration, SystemTime};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct FinancialTotals {
+    pub earned_wei: u64,
+    pub owed_wei: u64,
+}
+
+/// Aggregation-level view of the financial records. Implemented over the
+/// receivable/payable DAOs with SUM queries.
+pub trait FinancialsSummaryDao: Send {
+    fn totals_since(&self, since: SystemTime) -> FinancialTotals;
+    fn lifetime_totals(&self) -> FinancialTotals;
+    fn delinquent_peer_count(&self) -> usize;
+}
+
+/// One summary broadcast: deltas since the previous summary plus lifetime
+/// figures. The UI gateway forwards it to subscribed clients and masq
+/// renders it as a banner.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FinancialSummary {
+    pub period_start: SystemTime,
+    pub period_end: SystemTime,
+    pub earned_this_period_wei: u64,
+    pub owed_this_period_wei: u64,
+    pub lifetime: FinancialTotals,
+    pub delinquent_peers: usize,
+}
+
+pub struct FinancialSummaryScheduler {
+    period: Duration,
+    last_summary_at: Option<SystemTime>,
+}
+
+impl FinancialSummaryScheduler {
+    pub fn new(period: Duration) -> FinancialSummaryScheduler {
+        FinancialSummaryScheduler {
+            period,
+            last_summary_at: None,
+        }
+    }
+
+    /// Called on the accountant's timer tick. Produces a summary when a full
+    /// period has elapsed since the previous one (the first call starts the
+    /// clock without emitting).
+    pub fn tick(
+        &mut self,
+        dao: &dyn FinancialsSummaryDao,
+        now: SystemTime,
+    ) -> Option<FinancialSummary> {
+        let period_start = match self.last_summary_at {
+            None => {
+                self.last_summary_at = Some(now);
+                return None;
+            }
+            Some(last) => last,
+        };
+        if now
+            .duration_since(period_start)
+            .unwrap_or(Duration::ZERO)
+            < self.period
+        {
+            return None;
+        }
+        let period_totals = dao.totals_since(period_start);
+        self.last_summary_at = Some(now);
+        Some(FinancialSummary {
+            period_start,
+            period_end: now,
+            earned_this_period_wei: period_totals.earned_wei,
+            owed_this_period_wei: period_totals.owed_wei,
+            lifetime: dao.lifetime_totals(),
+            delinquent_peers: dao.delinquent_peer_count(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    struct FinancialsSummaryDaoMock {
+        // (timestamp, earned, owed) service reports accumulated so far
+        reports: RefCell<Vec<(SystemTime, u64, u64)>>,
+        delinquents: usize,
+    }
+
+    impl FinancialsSummaryDaoMock {
+        fn new() -> FinancialsSummaryDaoMock {
+            FinancialsSummaryDaoMock {
+                reports: RefCell::new(vec![]),
+                delinquents: 0,
+            }
+        }
+
+        fn report(&self, at: SystemTime, earned: u64, owed: u64) {
+            self.reports.borrow_mut().push((at, earned, owed));
+        }
+    }
+
+    impl FinancialsSummaryDao for FinancialsSummaryDaoMock {
+        fn totals_since(&self, since: SystemTime) -> FinancialTotals {
+            self.reports
+                .borrow()
+                .iter()
+                .filter(|(at, _, _)| *at >= since)
+                .fold(FinancialTotals::default(), |acc, (_, earned, owed)| {
+                    FinancialTotals {
+                        earned_wei: acc.earned_wei + earned,
+                        owed_wei: acc.owed_wei + owed,
+                    }
+                })
+        }
+
+        fn lifetime_totals(&self) -> FinancialTotals {
+            self.totals_since(SystemTime::UNIX_EPOCH)
+        }
+
+        fn delinquent_peer_count(&self) -> usize {
+            self.delinquents
+        }
+    }
+
+    #[test]
+    fn first_tick_only_starts_the_clock() {
+        let mut subject = FinancialSummaryScheduler::new(Duration::from_secs(3600));
+        let dao = FinancialsSummaryDaoMock::new();
+
+        assert_eq!(subject.tick(&dao, SystemTime::now()), None);
+    }
+
+    #[test]
+    fn two_periods_report_independent_deltas() {
+        let mut subject = FinancialSummaryScheduler::new(Duration::from_secs(3600));
+        let dao = FinancialsSummaryDaoMock::new();
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        subject.tick(&dao, t0);
+
+        // Interleaved service reports across two periods.
+        dao.report(t0 + Duration::from_secs(100), 500, 50);
+        dao.report(t0 + Duration::from_secs(200), 300, 0);
+        let first = subject.tick(&dao, t0 + Duration::from_secs(3600)).unwrap();
+        dao.report(t0 + Duration::from_secs(3700), 0, 75);
+        let second = subject.tick(&dao, t0 + Duration::from_secs(7200)).unwrap();
+
+        assert_eq!(first.earned_this_period_wei, 800);
+        assert_eq!(first.owed_this_period_wei, 50);
+        assert_eq!(second.earned_this_period_wei, 0);
+        assert_eq!(second.owed_this_period_wei, 75);
+        assert_eq!(
+            second.lifetime,
+            FinancialTotals {
+                earned_wei: 800,
+                owed_wei: 125
+            }
+        );
+    }
+
+    #[test]
+    fn mid_period_tick_emits_nothing() {
+        let mut subject = FinancialSummaryScheduler::new(Duration::from_secs(3600));
+        let dao = FinancialsSummaryDaoMock::new();
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        subject.tick(&dao, t0);
+
+        assert_eq!(subject.tick(&dao, t0 + Duration::from_secs(1800)), None);
+    }
+}
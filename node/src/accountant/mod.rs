@@ -0,0 +1,10 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+pub mod blind_credentials;
+pub mod chain_submitter;
+pub mod charge_verifier;
+pub mod expected_charges_dao;
+pub mod financial_summary;
+pub mod payment_channels;
+pub mod per_service_payables;
+pub mod service_history_export;
@@ -0,0 +1,220 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Off-chain payment channels. Submitting an on-chain transaction for every
+//! stream would cost more in gas than the service itself; instead the two
+//! parties open a channel with a fixed on-chain capacity, tick its balance
+//! up off-chain as service is provided, and settle with a single on-chain
+//! transaction when the channel closes.
+
+use crate::sub_lib::wallet::Wallet;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChannelId(pub u64);
+
+/// An off-chain channel between the consuming side's wallet and ours. The
+/// balance is what the peer owes us so far; it can never exceed capacity,
+/// which is what was escrowed on-chain when the channel opened.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaymentChannel {
+    pub channel_id: ChannelId,
+    pub peer_wallet: Wallet,
+    pub capacity: u64,
+    pub balance: u64,
+}
+
+/// The settlement transaction produced when a channel closes, ready for
+/// on-chain submission.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transaction {
+    pub from: Wallet,
+    pub to: Wallet,
+    pub amount_wei: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AccountantError {
+    ChannelNotFound(ChannelId),
+    /// The charge would push the balance past the escrowed capacity; the
+    /// peer must settle and reopen before consuming more.
+    ChannelExhausted {
+        channel_id: ChannelId,
+        balance: u64,
+        capacity: u64,
+        charge_wei: u64,
+    },
+}
+
+/// What an exit node reports after serving a request, so the accountant can
+/// charge the consuming side's channel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReportExitServiceProvidedMessage {
+    pub channel_id: ChannelId,
+    pub payload_size: u64,
+    /// Flat charge per request, in wei.
+    pub service_rate_wei: u64,
+    /// Additional charge per byte of payload, in wei.
+    pub byte_rate_wei: u64,
+}
+
+/// Accountant-side channel ledger: opens channels, applies service charges
+/// off-chain, and produces settlement transactions on close.
+pub struct ChannelManager {
+    earning_wallet: Wallet,
+    channels: HashMap<ChannelId, PaymentChannel>,
+    next_channel_id: u64,
+}
+
+impl ChannelManager {
+    pub fn new(earning_wallet: Wallet) -> ChannelManager {
+        ChannelManager {
+            earning_wallet,
+            channels: HashMap::new(),
+            next_channel_id: 1,
+        }
+    }
+
+    pub fn open_channel(&mut self, peer_wallet: Wallet, capacity: u64) -> ChannelId {
+        let channel_id = ChannelId(self.next_channel_id);
+        self.next_channel_id += 1;
+        self.channels.insert(
+            channel_id,
+            PaymentChannel {
+                channel_id,
+                peer_wallet,
+                capacity,
+                balance: 0,
+            },
+        );
+        channel_id
+    }
+
+    pub fn channel(&self, channel_id: ChannelId) -> Option<&PaymentChannel> {
+        self.channels.get(&channel_id)
+    }
+
+    /// Applies an exit-service charge to the channel balance. No on-chain
+    /// transaction is made; the debt accumulates until close_channel.
+    pub fn handle_exit_service_provided(
+        &mut self,
+        msg: &ReportExitServiceProvidedMessage,
+    ) -> Result<u64, AccountantError> {
+        let channel = self
+            .channels
+            .get_mut(&msg.channel_id)
+            .ok_or(AccountantError::ChannelNotFound(msg.channel_id))?;
+        let charge_wei = msg.service_rate_wei + msg.byte_rate_wei * msg.payload_size;
+        if channel.balance + charge_wei > channel.capacity {
+            return Err(AccountantError::ChannelExhausted {
+                channel_id: msg.channel_id,
+                balance: channel.balance,
+                capacity: channel.capacity,
+                charge_wei,
+            });
+        }
+        channel.balance += charge_wei;
+        Ok(channel.balance)
+    }
+
+    /// Settles the channel on-chain: removes it from the ledger and returns
+    /// the single transaction covering everything charged off-chain.
+    pub fn close_channel(&mut self, channel_id: ChannelId) -> Result<Transaction, AccountantError> {
+        let channel = self
+            .channels
+            .remove(&channel_id)
+            .ok_or(AccountantError::ChannelNotFound(channel_id))?;
+        Ok(Transaction {
+            from: channel.peer_wallet,
+            to: self.earning_wallet.clone(),
+            amount_wei: channel.balance,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_subject() -> (ChannelManager, ChannelId) {
+        let mut manager = ChannelManager::new(Wallet::new("0xearning"));
+        let channel_id = manager.open_channel(Wallet::new("0xconsumer"), 10_000);
+        (manager, channel_id)
+    }
+
+    fn report(channel_id: ChannelId, payload_size: u64) -> ReportExitServiceProvidedMessage {
+        ReportExitServiceProvidedMessage {
+            channel_id,
+            payload_size,
+            service_rate_wei: 100,
+            byte_rate_wei: 2,
+        }
+    }
+
+    #[test]
+    fn exit_service_charges_accumulate_off_chain() {
+        let (mut subject, channel_id) = make_subject();
+
+        let first = subject.handle_exit_service_provided(&report(channel_id, 50));
+        let second = subject.handle_exit_service_provided(&report(channel_id, 200));
+
+        assert_eq!(first, Ok(200)); // 100 + 2 * 50
+        assert_eq!(second, Ok(700)); // + 100 + 2 * 200
+        assert_eq!(subject.channel(channel_id).unwrap().balance, 700);
+    }
+
+    #[test]
+    fn a_charge_past_capacity_is_refused_and_leaves_the_balance_alone() {
+        let (mut subject, channel_id) = make_subject();
+        subject
+            .handle_exit_service_provided(&report(channel_id, 4_900))
+            .unwrap(); // balance 9_900 of 10_000
+
+        let result = subject.handle_exit_service_provided(&report(channel_id, 50));
+
+        assert_eq!(
+            result,
+            Err(AccountantError::ChannelExhausted {
+                channel_id,
+                balance: 9_900,
+                capacity: 10_000,
+                charge_wei: 200,
+            })
+        );
+        assert_eq!(subject.channel(channel_id).unwrap().balance, 9_900);
+    }
+
+    #[test]
+    fn close_channel_settles_the_accumulated_balance_on_chain() {
+        let (mut subject, channel_id) = make_subject();
+        subject
+            .handle_exit_service_provided(&report(channel_id, 50))
+            .unwrap();
+
+        let transaction = subject.close_channel(channel_id).unwrap();
+
+        assert_eq!(
+            transaction,
+            Transaction {
+                from: Wallet::new("0xconsumer"),
+                to: Wallet::new("0xearning"),
+                amount_wei: 200,
+            }
+        );
+        assert_eq!(subject.channel(channel_id), None);
+    }
+
+    #[test]
+    fn operations_on_an_unknown_channel_are_refused() {
+        let (mut subject, _) = make_subject();
+        let bogus = ChannelId(42);
+
+        assert_eq!(
+            subject.handle_exit_service_provided(&report(bogus, 1)),
+            Err(AccountantError::ChannelNotFound(bogus))
+        );
+        assert_eq!(
+            subject.close_channel(bogus),
+            Err(AccountantError::ChannelNotFound(bogus))
+        );
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Consuming-side payable bookkeeping keyed by (node, wallet). A node that
+//! splits its earning wallets accrues two payables here — routing charges
+//! against one address, exit charges against the other — and each settles
+//! independently. Nodes advertising a single wallet collapse back to one
+//! payable, exactly the old behavior.
+
+use crate::sub_lib::cryptde::PublicKey;
+use crate::sub_lib::wallet::{EarningWallets, ServiceType, Wallet};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayableEntry {
+    pub node: PublicKey,
+    pub wallet: Wallet,
+    pub balance_wei: u64,
+}
+
+pub struct PerServicePayables {
+    balances: HashMap<(PublicKey, Wallet), u64>,
+}
+
+impl PerServicePayables {
+    pub fn new() -> PerServicePayables {
+        PerServicePayables {
+            balances: HashMap::new(),
+        }
+    }
+
+    /// Accrues a charge for a service the node rendered, against whichever
+    /// of its advertised wallets that service is paid to.
+    pub fn record_service(
+        &mut self,
+        node: &PublicKey,
+        wallets: &EarningWallets,
+        service: ServiceType,
+        amount_wei: u64,
+    ) {
+        let wallet = wallets.for_service(service).clone();
+        *self
+            .balances
+            .entry((node.clone(), wallet))
+            .or_insert(0) += amount_wei;
+    }
+
+    pub fn balance(&self, node: &PublicKey, wallet: &Wallet) -> u64 {
+        self.balances
+            .get(&(node.clone(), wallet.clone()))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Everything owed to the node across its wallets, for the "can we
+    /// still afford this neighbor" checks that do not care where it gets
+    /// paid.
+    pub fn total_owed_to_node(&self, node: &PublicKey) -> u64 {
+        self.balances
+            .iter()
+            .filter(|((owed_node, _), _)| owed_node == node)
+            .map(|(_, balance)| balance)
+            .sum()
+    }
+
+    /// One entry per (node, wallet) with a nonzero balance, sorted for
+    /// stable reporting.
+    pub fn entries(&self) -> Vec<PayableEntry> {
+        let mut entries: Vec<PayableEntry> = self
+            .balances
+            .iter()
+            .filter(|(_, balance)| **balance > 0)
+            .map(|((node, wallet), balance)| PayableEntry {
+                node: node.clone(),
+                wallet: wallet.clone(),
+                balance_wei: *balance,
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            (&a.node, &a.wallet.address).cmp(&(&b.node, &b.wallet.address))
+        });
+        entries
+    }
+
+    /// Zeroes a payable once its payment confirms.
+    pub fn mark_paid(&mut self, node: &PublicKey, wallet: &Wallet) {
+        self.balances.remove(&(node.clone(), wallet.clone()));
+    }
+}
+
+impl Default for PerServicePayables {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split_wallets() -> EarningWallets {
+        EarningWallets::split(Wallet::new("0xrouting"), Wallet::new("0xexit"))
+    }
+
+    #[test]
+    fn split_wallets_accrue_separate_payables() {
+        let mut subject = PerServicePayables::new();
+        let node = PublicKey::new(b"neighbor");
+        let wallets = split_wallets();
+
+        subject.record_service(&node, &wallets, ServiceType::Routing, 100);
+        subject.record_service(&node, &wallets, ServiceType::Routing, 50);
+        subject.record_service(&node, &wallets, ServiceType::Exit, 700);
+
+        assert_eq!(subject.balance(&node, &Wallet::new("0xrouting")), 150);
+        assert_eq!(subject.balance(&node, &Wallet::new("0xexit")), 700);
+        assert_eq!(subject.total_owed_to_node(&node), 850);
+    }
+
+    #[test]
+    fn a_single_wallet_node_collapses_to_one_payable() {
+        let mut subject = PerServicePayables::new();
+        let node = PublicKey::new(b"neighbor");
+        let wallets = EarningWallets::single(Wallet::new("0xboth"));
+
+        subject.record_service(&node, &wallets, ServiceType::Routing, 100);
+        subject.record_service(&node, &wallets, ServiceType::Exit, 700);
+
+        assert_eq!(subject.balance(&node, &Wallet::new("0xboth")), 800);
+        assert_eq!(subject.entries().len(), 1);
+    }
+
+    #[test]
+    fn paying_one_wallet_leaves_the_other_payable_standing() {
+        let mut subject = PerServicePayables::new();
+        let node = PublicKey::new(b"neighbor");
+        let wallets = split_wallets();
+        subject.record_service(&node, &wallets, ServiceType::Routing, 100);
+        subject.record_service(&node, &wallets, ServiceType::Exit, 700);
+
+        subject.mark_paid(&node, &Wallet::new("0xexit"));
+
+        assert_eq!(subject.balance(&node, &Wallet::new("0xexit")), 0);
+        assert_eq!(subject.balance(&node, &Wallet::new("0xrouting")), 100);
+        assert_eq!(subject.total_owed_to_node(&node), 100);
+    }
+
+    #[test]
+    fn payables_are_tracked_per_node_even_on_a_shared_wallet() {
+        let mut subject = PerServicePayables::new();
+        let wallets = EarningWallets::single(Wallet::new("0xshared"));
+        let node_a = PublicKey::new(b"node_a");
+        let node_b = PublicKey::new(b"node_b");
+
+        subject.record_service(&node_a, &wallets, ServiceType::Routing, 100);
+        subject.record_service(&node_b, &wallets, ServiceType::Routing, 200);
+
+        assert_eq!(subject.balance(&node_a, &Wallet::new("0xshared")), 100);
+        assert_eq!(subject.balance(&node_b, &Wallet::new("0xshared")), 200);
+    }
+}
@@ -0,0 +1,348 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Service-history export for bookkeeping. Operators need "every service
+//! I provided or consumed, with timestamps and amounts" over a date range
+//! in a form an accountant will accept, and a year of history does not
+//! fit in one UI frame. The export is a chunked UI transaction: the DAO
+//! is read a page at a time and each page goes out as its own response,
+//! with the last chunk flagged so masq knows when the file is complete.
+//! Amounts travel as wei-equivalent integers — the decimal token
+//! rendering is derived, never stored, so the books and the database can
+//! always be reconciled exactly.
+
+use crate::sub_lib::wallet::ServiceType;
+use serde::{Deserialize, Serialize};
+
+/// Records per chunk. Large enough to amortize framing, small enough
+/// that a chunk serializes comfortably under the UI frame limit.
+pub const EXPORT_PAGE_SIZE: usize = 500;
+
+/// Decimal places in one token; wei-equivalent amounts divide by 10^18.
+pub const TOKEN_DECIMALS: u32 = 18;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ServiceDirection {
+    /// Services this node rendered to others (earnings).
+    Provided,
+    /// Services others rendered to this node (expenditures).
+    Consumed,
+}
+
+/// One row of history, as stored.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceHistoryRecord {
+    pub timestamp_unix: u64,
+    pub direction: ServiceDirection,
+    pub service: ServiceType,
+    /// The counterparty's wallet address.
+    pub peer_wallet: String,
+    pub amount_wei: u64,
+}
+
+/// Paginated read access to the service history, implemented over the
+/// receivable and payable DAOs with an OFFSET/LIMIT query ordered by
+/// timestamp then rowid, so pages are stable across calls.
+pub trait ServiceHistoryDao: Send {
+    fn records_page(
+        &self,
+        from_unix: u64,
+        to_unix: u64,
+        direction: Option<ServiceDirection>,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<ServiceHistoryRecord>;
+}
+
+/// The UI transaction's request: an inclusive date range, an optional
+/// direction filter, and nothing about pagination — chunking is the
+/// daemon's concern.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportFinancialsRequest {
+    pub from_unix: u64,
+    pub to_unix: u64,
+    #[serde(default)]
+    pub direction: Option<ServiceDirection>,
+}
+
+/// One record as exported: the stored row plus the derived decimal
+/// rendering, so clients never have to do token arithmetic.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportRecordView {
+    pub timestamp_unix: u64,
+    pub direction: ServiceDirection,
+    pub service: ServiceType,
+    pub peer_wallet: String,
+    pub amount_wei: u64,
+    pub amount_tokens: String,
+}
+
+impl ExportRecordView {
+    pub fn from_record(record: &ServiceHistoryRecord) -> ExportRecordView {
+        ExportRecordView {
+            timestamp_unix: record.timestamp_unix,
+            direction: record.direction,
+            service: record.service,
+            peer_wallet: record.peer_wallet.clone(),
+            amount_wei: record.amount_wei,
+            amount_tokens: wei_to_token_decimal(record.amount_wei),
+        }
+    }
+}
+
+/// One response frame of the chunked export.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportFinancialsChunk {
+    /// Position of this chunk in the stream, from zero; lets masq detect
+    /// a dropped frame instead of writing a silently truncated file.
+    pub chunk_index: usize,
+    pub records: Vec<ExportRecordView>,
+    pub last: bool,
+}
+
+pub struct ServiceHistoryExporter {
+    page_size: usize,
+}
+
+impl ServiceHistoryExporter {
+    pub fn new(page_size: usize) -> ServiceHistoryExporter {
+        ServiceHistoryExporter { page_size }
+    }
+
+    /// Runs one export: pages the DAO and hands each page to `send` as a
+    /// chunk. An empty result still produces one (empty, last) chunk so
+    /// the client can finish its file deterministically.
+    pub fn export<F>(&self, dao: &dyn ServiceHistoryDao, request: &ExportFinancialsRequest, mut send: F)
+    where
+        F: FnMut(ExportFinancialsChunk),
+    {
+        let mut offset = 0;
+        let mut chunk_index = 0;
+        loop {
+            let page = dao.records_page(
+                request.from_unix,
+                request.to_unix,
+                request.direction,
+                offset,
+                self.page_size,
+            );
+            let last = page.len() < self.page_size;
+            send(ExportFinancialsChunk {
+                chunk_index,
+                records: page.iter().map(ExportRecordView::from_record).collect(),
+                last,
+            });
+            if last {
+                return;
+            }
+            offset += self.page_size;
+            chunk_index += 1;
+        }
+    }
+}
+
+impl Default for ServiceHistoryExporter {
+    fn default() -> Self {
+        Self::new(EXPORT_PAGE_SIZE)
+    }
+}
+
+/// Renders a wei-equivalent amount in decimal token units, exactly:
+/// integer part, and a fractional part with trailing zeros trimmed.
+pub fn wei_to_token_decimal(amount_wei: u64) -> String {
+    let divisor = 10u64.pow(TOKEN_DECIMALS);
+    let whole = amount_wei / divisor;
+    let frac = amount_wei % divisor;
+    if frac == 0 {
+        return format!("{}", whole);
+    }
+    let frac_str = format!("{:018}", frac);
+    format!("{}.{}", whole, frac_str.trim_end_matches('0'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    struct ServiceHistoryDaoMock {
+        records_page_params: RefCell<Vec<(u64, u64, Option<ServiceDirection>, usize, usize)>>,
+        records: Vec<ServiceHistoryRecord>,
+    }
+
+    impl ServiceHistoryDaoMock {
+        fn new(records: Vec<ServiceHistoryRecord>) -> ServiceHistoryDaoMock {
+            ServiceHistoryDaoMock {
+                records_page_params: RefCell::new(vec![]),
+                records,
+            }
+        }
+    }
+
+    impl ServiceHistoryDao for ServiceHistoryDaoMock {
+        fn records_page(
+            &self,
+            from_unix: u64,
+            to_unix: u64,
+            direction: Option<ServiceDirection>,
+            offset: usize,
+            limit: usize,
+        ) -> Vec<ServiceHistoryRecord> {
+            self.records_page_params.borrow_mut().push((
+                from_unix, to_unix, direction, offset, limit,
+            ));
+            self.records
+                .iter()
+                .filter(|r| r.timestamp_unix >= from_unix && r.timestamp_unix <= to_unix)
+                .filter(|r| direction.map(|d| r.direction == d).unwrap_or(true))
+                .skip(offset)
+                .take(limit)
+                .cloned()
+                .collect()
+        }
+    }
+
+    fn record(timestamp: u64, direction: ServiceDirection, amount_wei: u64) -> ServiceHistoryRecord {
+        ServiceHistoryRecord {
+            timestamp_unix: timestamp,
+            direction,
+            service: ServiceType::Routing,
+            peer_wallet: "0xpeer".to_string(),
+            amount_wei,
+        }
+    }
+
+    fn collect_chunks(
+        exporter: &ServiceHistoryExporter,
+        dao: &ServiceHistoryDaoMock,
+        request: &ExportFinancialsRequest,
+    ) -> Vec<ExportFinancialsChunk> {
+        let mut chunks = vec![];
+        exporter.export(dao, request, |chunk| chunks.push(chunk));
+        chunks
+    }
+
+    #[test]
+    fn records_spanning_chunk_boundaries_arrive_exactly_once_and_in_order() {
+        let records: Vec<ServiceHistoryRecord> = (0..7)
+            .map(|n| record(1_000 + n, ServiceDirection::Provided, n))
+            .collect();
+        let dao = ServiceHistoryDaoMock::new(records);
+        let exporter = ServiceHistoryExporter::new(3);
+        let request = ExportFinancialsRequest {
+            from_unix: 0,
+            to_unix: 2_000,
+            direction: None,
+        };
+
+        let chunks = collect_chunks(&exporter, &dao, &request);
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(
+            chunks.iter().map(|c| c.records.len()).collect::<Vec<_>>(),
+            vec![3, 3, 1]
+        );
+        assert_eq!(
+            chunks.iter().map(|c| c.last).collect::<Vec<_>>(),
+            vec![false, false, true]
+        );
+        assert_eq!(
+            chunks.iter().map(|c| c.chunk_index).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        let exported: Vec<u64> = chunks
+            .iter()
+            .flat_map(|c| c.records.iter().map(|r| r.amount_wei))
+            .collect();
+        assert_eq!(exported, vec![0, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn a_count_landing_exactly_on_a_page_boundary_still_terminates() {
+        let records: Vec<ServiceHistoryRecord> = (0..6)
+            .map(|n| record(1_000 + n, ServiceDirection::Provided, n))
+            .collect();
+        let dao = ServiceHistoryDaoMock::new(records);
+        let exporter = ServiceHistoryExporter::new(3);
+        let request = ExportFinancialsRequest {
+            from_unix: 0,
+            to_unix: 2_000,
+            direction: None,
+        };
+
+        let chunks = collect_chunks(&exporter, &dao, &request);
+
+        // Two full pages and an empty closing chunk: the exporter cannot
+        // know page two was the end until page three comes back short.
+        assert_eq!(
+            chunks.iter().map(|c| c.records.len()).collect::<Vec<_>>(),
+            vec![3, 3, 0]
+        );
+        assert!(chunks[2].last);
+    }
+
+    #[test]
+    fn an_empty_range_yields_one_empty_last_chunk() {
+        let dao = ServiceHistoryDaoMock::new(vec![]);
+        let exporter = ServiceHistoryExporter::default();
+        let request = ExportFinancialsRequest {
+            from_unix: 500,
+            to_unix: 600,
+            direction: None,
+        };
+
+        let chunks = collect_chunks(&exporter, &dao, &request);
+
+        assert_eq!(
+            chunks,
+            vec![ExportFinancialsChunk {
+                chunk_index: 0,
+                records: vec![],
+                last: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn the_direction_filter_reaches_the_dao_and_takes_effect() {
+        let dao = ServiceHistoryDaoMock::new(vec![
+            record(1_000, ServiceDirection::Provided, 100),
+            record(1_001, ServiceDirection::Consumed, 200),
+            record(1_002, ServiceDirection::Provided, 300),
+        ]);
+        let exporter = ServiceHistoryExporter::default();
+        let request = ExportFinancialsRequest {
+            from_unix: 0,
+            to_unix: 2_000,
+            direction: Some(ServiceDirection::Consumed),
+        };
+
+        let chunks = collect_chunks(&exporter, &dao, &request);
+
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].records.len(), 1);
+        assert_eq!(chunks[0].records[0].amount_wei, 200);
+        let params = dao.records_page_params.borrow();
+        assert_eq!(params[0].2, Some(ServiceDirection::Consumed));
+    }
+
+    #[test]
+    fn amounts_are_exported_in_both_wei_and_decimal_tokens() {
+        let view = ExportRecordView::from_record(&record(
+            1_000,
+            ServiceDirection::Provided,
+            1_500_000_000_000_000_000,
+        ));
+
+        assert_eq!(view.amount_wei, 1_500_000_000_000_000_000);
+        assert_eq!(view.amount_tokens, "1.5");
+    }
+
+    #[test]
+    fn token_decimal_rendering_is_exact_at_the_edges() {
+        assert_eq!(wei_to_token_decimal(0), "0");
+        assert_eq!(wei_to_token_decimal(1), "0.000000000000000001");
+        assert_eq!(wei_to_token_decimal(10u64.pow(18)), "1");
+        assert_eq!(wei_to_token_decimal(10u64.pow(18) + 1), "1.000000000000000001");
+        assert_eq!(wei_to_token_decimal(u64::MAX), "18.446744073709551615");
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Two-phase actor startup. The historical pattern — every actor storing
+//! its collaborators as `Option<Recipient<...>>`, populated by a BindMessage
+//! and `.expect()`ed at use time — panics whenever a message races ahead of
+//! the bind. The factory now runs in two phases instead: phase one builds
+//! every actor with its real recipients (no Options), and only phase two
+//! starts the external message sources (listeners, timers). A source cannot
+//! exist before the actors it feeds, so the unbound-recipient panic is
+//! unrepresentable rather than merely tested against.
+
+use crate::sub_lib::hopper::MessageType;
+use std::sync::mpsc::Sender;
+
+/// The recipients every external message source needs. All fields are
+/// mandatory: this struct can only be had from a fully built system.
+#[derive(Clone)]
+pub struct BoundRecipients {
+    pub hopper: Sender<MessageType>,
+    pub proxy_client: Sender<MessageType>,
+    pub accountant: Sender<MessageType>,
+}
+
+/// Phase one. Recipients accumulate here; `bind` refuses to produce a
+/// BoundSystem until every one is present, so a forgotten wiring line is a
+/// startup error, not a latent panic.
+#[derive(Default)]
+pub struct SystemBuilder {
+    hopper: Option<Sender<MessageType>>,
+    proxy_client: Option<Sender<MessageType>>,
+    accountant: Option<Sender<MessageType>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BindError {
+    MissingRecipient(&'static str),
+}
+
+impl SystemBuilder {
+    pub fn new() -> SystemBuilder {
+        Self::default()
+    }
+
+    pub fn hopper(mut self, recipient: Sender<MessageType>) -> SystemBuilder {
+        self.hopper = Some(recipient);
+        self
+    }
+
+    pub fn proxy_client(mut self, recipient: Sender<MessageType>) -> SystemBuilder {
+        self.proxy_client = Some(recipient);
+        self
+    }
+
+    pub fn accountant(mut self, recipient: Sender<MessageType>) -> SystemBuilder {
+        self.accountant = Some(recipient);
+        self
+    }
+
+    /// Completes phase one. After this point the Options are gone for good.
+    pub fn bind(self) -> Result<BoundSystem, BindError> {
+        Ok(BoundSystem {
+            recipients: BoundRecipients {
+                hopper: self.hopper.ok_or(BindError::MissingRecipient("hopper"))?,
+                proxy_client: self
+                    .proxy_client
+                    .ok_or(BindError::MissingRecipient("proxy_client"))?,
+                accountant: self
+                    .accountant
+                    .ok_or(BindError::MissingRecipient("accountant"))?,
+            },
+            sources_started: false,
+        })
+    }
+}
+
+/// Phase two. Message sources receive BoundRecipients — never the builder —
+/// so by construction they start with every recipient in hand.
+pub struct BoundSystem {
+    recipients: BoundRecipients,
+    sources_started: bool,
+}
+
+impl BoundSystem {
+    /// Starts each external message source, handing it the complete
+    /// recipient set. Callable once.
+    pub fn start_message_sources<F>(&mut self, mut start: F)
+    where
+        F: FnMut(BoundRecipients),
+    {
+        assert!(
+            !self.sources_started,
+            "message sources were already started"
+        );
+        self.sources_started = true;
+        start(self.recipients.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn channel() -> (Sender<MessageType>, mpsc::Receiver<MessageType>) {
+        mpsc::channel()
+    }
+
+    #[test]
+    fn a_fully_wired_builder_binds() {
+        let (hopper_tx, _hopper_rx) = channel();
+        let (proxy_client_tx, _proxy_client_rx) = channel();
+        let (accountant_tx, _accountant_rx) = channel();
+
+        let result = SystemBuilder::new()
+            .hopper(hopper_tx)
+            .proxy_client(proxy_client_tx)
+            .accountant(accountant_tx)
+            .bind();
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn a_forgotten_recipient_is_a_startup_error_not_a_latent_panic() {
+        let (hopper_tx, _hopper_rx) = channel();
+        let (accountant_tx, _accountant_rx) = channel();
+
+        let result = SystemBuilder::new()
+            .hopper(hopper_tx)
+            .accountant(accountant_tx)
+            .bind();
+
+        assert_eq!(
+            result.err(),
+            Some(BindError::MissingRecipient("proxy_client"))
+        );
+    }
+
+    #[test]
+    fn sources_started_after_binding_always_find_their_recipients() {
+        let (hopper_tx, hopper_rx) = channel();
+        let (proxy_client_tx, _proxy_client_rx) = channel();
+        let (accountant_tx, _accountant_rx) = channel();
+        let mut system = SystemBuilder::new()
+            .hopper(hopper_tx)
+            .proxy_client(proxy_client_tx)
+            .accountant(accountant_tx)
+            .bind()
+            .unwrap();
+
+        // A source that fires immediately on startup — the exact race that
+        // used to panic an unbound actor.
+        system.start_message_sources(|recipients| {
+            recipients
+                .hopper
+                .send(MessageType::CoverTraffic(vec![1, 2, 3]))
+                .unwrap();
+        });
+
+        assert_eq!(
+            hopper_rx.recv().unwrap(),
+            MessageType::CoverTraffic(vec![1, 2, 3])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "already started")]
+    fn message_sources_cannot_be_started_twice() {
+        let (hopper_tx, _hopper_rx) = channel();
+        let (proxy_client_tx, _proxy_client_rx) = channel();
+        let (accountant_tx, _accountant_rx) = channel();
+        let mut system = SystemBuilder::new()
+            .hopper(hopper_tx)
+            .proxy_client(proxy_client_tx)
+            .accountant(accountant_tx)
+            .bind()
+            .unwrap();
+
+        system.start_message_sources(|_| ());
+        system.start_message_sources(|_| ());
+    }
+}
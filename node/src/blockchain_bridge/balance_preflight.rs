@@ -0,0 +1,308 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Consuming-wallet balance preflight. An unfunded wallet can happily
+//! originate traffic, rack up debt to relays, and get the node banned
+//! network-wide before the user notices. The bridge now checks token and
+//! gas balances periodically, warns when either crosses below its
+//! threshold, and — if the operator opts in — tells the ProxyServer to
+//! refuse new routed traffic while the balance cannot cover a typical
+//! session.
+
+use crate::sub_lib::logger::Logger;
+use crate::sub_lib::wallet::Wallet;
+
+/// The slice of the blockchain the preflight needs; mockable for tests.
+pub trait BlockchainInterface: Send {
+    fn token_balance_wei(&self, wallet: &Wallet) -> Result<u64, String>;
+    fn gas_balance_wei(&self, wallet: &Wallet) -> Result<u64, String>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BalanceThresholds {
+    pub low_token_wei: u64,
+    pub low_gas_wei: u64,
+}
+
+/// What one preflight check found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BalanceReport {
+    pub token_wei: u64,
+    pub gas_wei: u64,
+    /// Warnings broadcast this check; empty when nothing newly crossed a
+    /// threshold.
+    pub warnings: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OriginateVerdict {
+    Allow,
+    /// Serve the local explanation page instead of originating.
+    RefuseUnderfunded,
+}
+
+/// The page the ProxyServer serves when refusing to originate.
+pub const UNDERFUNDED_ERROR_PAGE: &str = "HTTP/1.1 402 Payment Required\r\n\
+    Content-Type: text/html\r\n\
+    Connection: close\r\n\
+    \r\n\
+    <html><body><h1>Consuming wallet underfunded</h1>\
+    <p>This node's consuming wallet cannot cover the estimated cost of a \
+    browsing session, so routed traffic is paused to protect your standing \
+    with the network. Fund the wallet and traffic will resume on the next \
+    balance check.</p></body></html>";
+
+/// Periodic balance checker. Warnings fire on the transition below a
+/// threshold, not on every check, so the log is not flooded while the
+/// wallet stays low.
+pub struct BalancePreflight {
+    interface: Box<dyn BlockchainInterface>,
+    thresholds: BalanceThresholds,
+    /// When true, originate-refusal kicks in below the estimated session
+    /// cost; when false the preflight only warns.
+    refuse_when_underfunded: bool,
+    estimated_session_cost_wei: u64,
+    token_was_low: bool,
+    gas_was_low: bool,
+    last_token_wei: Option<u64>,
+    logger: Logger,
+}
+
+impl BalancePreflight {
+    pub fn new(
+        interface: Box<dyn BlockchainInterface>,
+        thresholds: BalanceThresholds,
+        refuse_when_underfunded: bool,
+        estimated_session_cost_wei: u64,
+    ) -> BalancePreflight {
+        BalancePreflight {
+            interface,
+            thresholds,
+            refuse_when_underfunded,
+            estimated_session_cost_wei,
+            token_was_low: false,
+            gas_was_low: false,
+            last_token_wei: None,
+            logger: Logger::new("BlockchainBridge"),
+        }
+    }
+
+    /// Runs one periodic check against the consuming wallet.
+    pub fn check(&mut self, wallet: &Wallet) -> Result<BalanceReport, String> {
+        let token_wei = self.interface.token_balance_wei(wallet)?;
+        let gas_wei = self.interface.gas_balance_wei(wallet)?;
+        let mut warnings = vec![];
+        let token_low = token_wei < self.thresholds.low_token_wei;
+        if token_low && !self.token_was_low {
+            warnings.push(format!(
+                "Consuming wallet token balance is low: {} wei (threshold {})",
+                token_wei, self.thresholds.low_token_wei
+            ));
+        }
+        self.token_was_low = token_low;
+        let gas_low = gas_wei < self.thresholds.low_gas_wei;
+        if gas_low && !self.gas_was_low {
+            warnings.push(format!(
+                "Consuming wallet gas balance is low: {} wei (threshold {})",
+                gas_wei, self.thresholds.low_gas_wei
+            ));
+        }
+        self.gas_was_low = gas_low;
+        for warning in &warnings {
+            self.logger.warning(warning.clone());
+        }
+        self.last_token_wei = Some(token_wei);
+        Ok(BalanceReport {
+            token_wei,
+            gas_wei,
+            warnings,
+        })
+    }
+
+    /// Gate consulted by the ProxyServer before originating paid routes,
+    /// based on the most recent check. An unchecked wallet is allowed:
+    /// refusing traffic on startup because the first check has not run yet
+    /// would be worse than the risk window.
+    pub fn originate_verdict(&self) -> OriginateVerdict {
+        if !self.refuse_when_underfunded {
+            return OriginateVerdict::Allow;
+        }
+        match self.last_token_wei {
+            Some(token_wei) if token_wei < self.estimated_session_cost_wei => {
+                OriginateVerdict::RefuseUnderfunded
+            }
+            _ => OriginateVerdict::Allow,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct BlockchainInterfaceMock {
+        token_balance_params: Arc<Mutex<Vec<Wallet>>>,
+        token_balance_results: Mutex<Vec<Result<u64, String>>>,
+        gas_balance_results: Mutex<Vec<Result<u64, String>>>,
+    }
+
+    impl BlockchainInterfaceMock {
+        fn new() -> BlockchainInterfaceMock {
+            BlockchainInterfaceMock {
+                token_balance_params: Arc::new(Mutex::new(vec![])),
+                token_balance_results: Mutex::new(vec![]),
+                gas_balance_results: Mutex::new(vec![]),
+            }
+        }
+
+        fn token_balance_params(
+            mut self,
+            params: &Arc<Mutex<Vec<Wallet>>>,
+        ) -> BlockchainInterfaceMock {
+            self.token_balance_params = params.clone();
+            self
+        }
+
+        fn token_balance_result(self, result: Result<u64, String>) -> BlockchainInterfaceMock {
+            self.token_balance_results.lock().unwrap().push(result);
+            self
+        }
+
+        fn gas_balance_result(self, result: Result<u64, String>) -> BlockchainInterfaceMock {
+            self.gas_balance_results.lock().unwrap().push(result);
+            self
+        }
+    }
+
+    impl BlockchainInterface for BlockchainInterfaceMock {
+        fn token_balance_wei(&self, wallet: &Wallet) -> Result<u64, String> {
+            self.token_balance_params.lock().unwrap().push(wallet.clone());
+            self.token_balance_results.lock().unwrap().remove(0)
+        }
+
+        fn gas_balance_wei(&self, _wallet: &Wallet) -> Result<u64, String> {
+            self.gas_balance_results.lock().unwrap().remove(0)
+        }
+    }
+
+    fn thresholds() -> BalanceThresholds {
+        BalanceThresholds {
+            low_token_wei: 1000,
+            low_gas_wei: 500,
+        }
+    }
+
+    #[test]
+    fn healthy_balances_produce_no_warnings() {
+        let interface = BlockchainInterfaceMock::new()
+            .token_balance_result(Ok(5000))
+            .gas_balance_result(Ok(2000));
+        let mut subject = BalancePreflight::new(Box::new(interface), thresholds(), false, 100);
+
+        let report = subject.check(&Wallet::new("0xconsuming")).unwrap();
+
+        assert_eq!(
+            report,
+            BalanceReport {
+                token_wei: 5000,
+                gas_wei: 2000,
+                warnings: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn warnings_fire_on_the_transition_below_each_threshold_only() {
+        let interface = BlockchainInterfaceMock::new()
+            .token_balance_result(Ok(900))
+            .gas_balance_result(Ok(400))
+            .token_balance_result(Ok(800))
+            .gas_balance_result(Ok(300))
+            .token_balance_result(Ok(5000))
+            .gas_balance_result(Ok(2000))
+            .token_balance_result(Ok(900))
+            .gas_balance_result(Ok(2000));
+        let mut subject = BalancePreflight::new(Box::new(interface), thresholds(), false, 100);
+        let wallet = Wallet::new("0xconsuming");
+
+        let first = subject.check(&wallet).unwrap();
+        let repeat = subject.check(&wallet).unwrap();
+        let recovered = subject.check(&wallet).unwrap();
+        let relapsed = subject.check(&wallet).unwrap();
+
+        assert_eq!(first.warnings.len(), 2);
+        assert!(first.warnings[0].contains("token balance is low: 900"));
+        assert!(first.warnings[1].contains("gas balance is low: 400"));
+        assert!(repeat.warnings.is_empty());
+        assert!(recovered.warnings.is_empty());
+        assert_eq!(relapsed.warnings.len(), 1);
+    }
+
+    #[test]
+    fn refusal_gate_trips_below_the_estimated_session_cost() {
+        let interface = BlockchainInterfaceMock::new()
+            .token_balance_result(Ok(99))
+            .gas_balance_result(Ok(2000))
+            .token_balance_result(Ok(100))
+            .gas_balance_result(Ok(2000));
+        let mut subject = BalancePreflight::new(Box::new(interface), thresholds(), true, 100);
+        let wallet = Wallet::new("0xconsuming");
+
+        assert_eq!(subject.originate_verdict(), OriginateVerdict::Allow);
+
+        subject.check(&wallet).unwrap();
+        assert_eq!(
+            subject.originate_verdict(),
+            OriginateVerdict::RefuseUnderfunded
+        );
+
+        subject.check(&wallet).unwrap();
+        assert_eq!(subject.originate_verdict(), OriginateVerdict::Allow);
+    }
+
+    #[test]
+    fn refusal_gate_stays_open_when_the_config_flag_is_off() {
+        let interface = BlockchainInterfaceMock::new()
+            .token_balance_result(Ok(0))
+            .gas_balance_result(Ok(0));
+        let mut subject = BalancePreflight::new(Box::new(interface), thresholds(), false, 100);
+
+        subject.check(&Wallet::new("0xconsuming")).unwrap();
+
+        assert_eq!(subject.originate_verdict(), OriginateVerdict::Allow);
+    }
+
+    #[test]
+    fn the_checked_wallet_is_the_one_passed_in() {
+        let token_balance_params = Arc::new(Mutex::new(vec![]));
+        let interface = BlockchainInterfaceMock::new()
+            .token_balance_params(&token_balance_params)
+            .token_balance_result(Ok(5000))
+            .gas_balance_result(Ok(2000));
+        let mut subject = BalancePreflight::new(Box::new(interface), thresholds(), false, 100);
+
+        subject.check(&Wallet::new("0xconsuming")).unwrap();
+
+        assert_eq!(
+            *token_balance_params.lock().unwrap(),
+            vec![Wallet::new("0xconsuming")]
+        );
+    }
+
+    #[test]
+    fn blockchain_errors_are_surfaced() {
+        let interface =
+            BlockchainInterfaceMock::new().token_balance_result(Err("rpc timeout".to_string()));
+        let mut subject = BalancePreflight::new(Box::new(interface), thresholds(), true, 100);
+
+        let result = subject.check(&Wallet::new("0xconsuming"));
+
+        assert_eq!(result, Err("rpc timeout".to_string()));
+    }
+
+    #[test]
+    fn the_refusal_page_explains_the_situation() {
+        assert!(UNDERFUNDED_ERROR_PAGE.starts_with("HTTP/1.1 402 "));
+        assert!(UNDERFUNDED_ERROR_PAGE.contains("Fund the wallet"));
+    }
+}
@@ -0,0 +1,335 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! The one shape every startup failure takes. Port binding, the
+//! database, the configurator, DNS discovery, and wallet decryption each
+//! have their own error types with their own formatting; before this
+//! module they surfaced as panics of a dozen different styles. The
+//! bootstrapper now converts them all into a BootstrapError carrying a
+//! severity, a stable code (safe to grep for and to document), a
+//! one-line message, and — where we actually know what the operator
+//! should do — a remediation hint. The process exit code derives from
+//! the variant, and the daemon relays the structured error to masq so
+//! `start` can print the hint instead of a stack trace.
+
+use crate::database::db_initializer::InitializationError;
+use crate::node_configurator::dns_servers::DnsServersError;
+use crate::node_configurator::intercept_ports::InterceptPortError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Whether the node can limp on without the failed piece.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Severity {
+    /// Startup cannot continue; the process exits.
+    Fatal,
+    /// The node starts without the affected capability.
+    Degraded,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BootstrapError {
+    /// A listener port could not be bound.
+    PortBind { port: u16, detail: String },
+    /// The intercept port layout is contradictory.
+    PortConfiguration(InterceptPortError),
+    Database(InitializationError),
+    DnsDiscovery(DnsServersError),
+    /// The consuming wallet exists but would not decrypt.
+    WalletDecryption { detail: String },
+    /// A configuration value failed validation after parsing.
+    Configuration { parameter: String, detail: String },
+}
+
+impl BootstrapError {
+    /// Stable, documentable code; masq prints it and operators search for
+    /// it. Codes are never reused for a different meaning.
+    pub fn code(&self) -> &'static str {
+        match self {
+            BootstrapError::PortBind { .. } => "CN-BOOT-PORT",
+            BootstrapError::PortConfiguration(_) => "CN-BOOT-PORTCFG",
+            BootstrapError::Database(_) => "CN-BOOT-DB",
+            BootstrapError::DnsDiscovery(_) => "CN-BOOT-DNS",
+            BootstrapError::WalletDecryption { .. } => "CN-BOOT-WALLET",
+            BootstrapError::Configuration { .. } => "CN-BOOT-CONFIG",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            // Exit service is optional; a node without resolvers can still
+            // originate and relay.
+            BootstrapError::DnsDiscovery(_) => Severity::Degraded,
+            _ => Severity::Fatal,
+        }
+    }
+
+    /// What went wrong, in one line. The full detail from the underlying
+    /// error is included; the formatting is ours.
+    pub fn message(&self) -> String {
+        match self {
+            BootstrapError::PortBind { port, detail } => {
+                format!("could not bind port {}: {}", port, detail)
+            }
+            BootstrapError::PortConfiguration(error) => match error {
+                InterceptPortError::PortCollision(port) => {
+                    format!("two intercept listeners are configured on port {}", port)
+                }
+                InterceptPortError::RawRequiresTransparent(port) => format!(
+                    "raw intercept port {} requires transparent mode to learn destinations",
+                    port
+                ),
+            },
+            BootstrapError::Database(error) => format!("database initialization failed: {}", error),
+            BootstrapError::DnsDiscovery(DnsServersError::NothingUsable(detail)) => {
+                format!("no usable DNS servers: {}", detail)
+            }
+            BootstrapError::WalletDecryption { detail } => {
+                format!("the consuming wallet would not decrypt: {}", detail)
+            }
+            BootstrapError::Configuration { parameter, detail } => {
+                format!("invalid value for --{}: {}", parameter, detail)
+            }
+        }
+    }
+
+    /// What the operator can do about it, when we know.
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            BootstrapError::PortBind { port, .. } => Some(format!(
+                "another process may hold port {0}; free it or choose a different port",
+                port
+            )),
+            BootstrapError::PortConfiguration(InterceptPortError::PortCollision(_)) => Some(
+                "give each intercept listener its own port in the configuration".to_string(),
+            ),
+            BootstrapError::PortConfiguration(InterceptPortError::RawRequiresTransparent(_)) => {
+                Some("enable transparent mode or remove the raw intercept port".to_string())
+            }
+            BootstrapError::Database(InitializationError::DatabaseLocked {
+                holder_pid, ..
+            }) => Some(match holder_pid {
+                Some(pid) => format!(
+                    "another node (pid {}) is using this data directory; stop it or use a different --data-directory",
+                    pid
+                ),
+                None => "another process is using this data directory; stop it or use a different --data-directory".to_string(),
+            }),
+            BootstrapError::Database(InitializationError::DatabaseCorrupt { .. }) => {
+                Some("restart with --recover-database to rebuild it (payment history will be lost)".to_string())
+            }
+            BootstrapError::Database(InitializationError::SqliteFailure(_)) => None,
+            BootstrapError::DnsDiscovery(_) => Some(
+                "pass --dns-servers with at least one reachable resolver to enable exit service"
+                    .to_string(),
+            ),
+            BootstrapError::WalletDecryption { .. } => {
+                Some("check the wallet password; the wallet file itself is intact".to_string())
+            }
+            BootstrapError::Configuration { .. } => None,
+        }
+    }
+
+    /// The process exit code for this failure; distinct per variant so a
+    /// supervising daemon can tell failure families apart without parsing
+    /// output. Zero is reserved for success, 1 for panics we failed to
+    /// convert.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BootstrapError::PortBind { .. } => 10,
+            BootstrapError::PortConfiguration(_) => 11,
+            BootstrapError::Database(_) => 12,
+            BootstrapError::DnsDiscovery(_) => 13,
+            BootstrapError::WalletDecryption { .. } => 14,
+            BootstrapError::Configuration { .. } => 15,
+        }
+    }
+
+    /// The structured shape the daemon relays to masq.
+    pub fn report(&self) -> BootstrapErrorReport {
+        BootstrapErrorReport {
+            code: self.code().to_string(),
+            severity: self.severity(),
+            message: self.message(),
+            hint: self.hint(),
+            exit_code: self.exit_code(),
+        }
+    }
+}
+
+impl fmt::Display for BootstrapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.message())?;
+        if let Some(hint) = self.hint() {
+            write!(f, " ({})", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<InitializationError> for BootstrapError {
+    fn from(error: InitializationError) -> Self {
+        BootstrapError::Database(error)
+    }
+}
+
+impl From<DnsServersError> for BootstrapError {
+    fn from(error: DnsServersError) -> Self {
+        BootstrapError::DnsDiscovery(error)
+    }
+}
+
+impl From<InterceptPortError> for BootstrapError {
+    fn from(error: InterceptPortError) -> Self {
+        BootstrapError::PortConfiguration(error)
+    }
+}
+
+/// The wire form of a BootstrapError, relayed daemon → masq.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootstrapErrorReport {
+    pub code: String,
+    pub severity: Severity,
+    pub message: String,
+    pub hint: Option<String>,
+    pub exit_code: i32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn a_bind_failure_names_the_port_and_suggests_freeing_it() {
+        let subject = BootstrapError::PortBind {
+            port: 5333,
+            detail: "address already in use".to_string(),
+        };
+
+        assert_eq!(subject.code(), "CN-BOOT-PORT");
+        assert_eq!(subject.severity(), Severity::Fatal);
+        assert_eq!(subject.exit_code(), 10);
+        assert_eq!(
+            subject.message(),
+            "could not bind port 5333: address already in use"
+        );
+        assert!(subject.hint().unwrap().contains("5333"));
+    }
+
+    #[test]
+    fn a_locked_database_points_at_the_holder() {
+        let subject: BootstrapError = InitializationError::DatabaseLocked {
+            path: PathBuf::from("/data/node.db"),
+            holder_pid: Some(4242),
+        }
+        .into();
+
+        assert_eq!(subject.code(), "CN-BOOT-DB");
+        assert_eq!(subject.exit_code(), 12);
+        assert!(subject.hint().unwrap().contains("pid 4242"));
+    }
+
+    #[test]
+    fn a_corrupt_database_suggests_recovery() {
+        let subject: BootstrapError = InitializationError::DatabaseCorrupt {
+            path: PathBuf::from("/data/node.db"),
+        }
+        .into();
+
+        assert!(subject.hint().unwrap().contains("--recover-database"));
+    }
+
+    #[test]
+    fn dns_discovery_failure_is_degraded_not_fatal() {
+        let subject: BootstrapError =
+            DnsServersError::NothingUsable("only loopback resolvers found".to_string()).into();
+
+        assert_eq!(subject.code(), "CN-BOOT-DNS");
+        assert_eq!(subject.severity(), Severity::Degraded);
+        assert!(subject.message().contains("only loopback resolvers found"));
+        assert!(subject.hint().unwrap().contains("--dns-servers"));
+    }
+
+    #[test]
+    fn intercept_port_errors_convert_with_actionable_hints() {
+        let collision: BootstrapError = InterceptPortError::PortCollision(8080).into();
+        let raw: BootstrapError = InterceptPortError::RawRequiresTransparent(2525).into();
+
+        assert_eq!(collision.exit_code(), 11);
+        assert!(collision.message().contains("8080"));
+        assert!(collision.hint().unwrap().contains("its own port"));
+        assert!(raw.message().contains("2525"));
+        assert!(raw.hint().unwrap().contains("transparent"));
+    }
+
+    #[test]
+    fn wallet_decryption_blames_the_password_not_the_file() {
+        let subject = BootstrapError::WalletDecryption {
+            detail: "MAC mismatch".to_string(),
+        };
+
+        assert_eq!(subject.code(), "CN-BOOT-WALLET");
+        assert!(subject.hint().unwrap().contains("password"));
+    }
+
+    #[test]
+    fn exit_codes_are_distinct_across_variants() {
+        let errors = [
+            BootstrapError::PortBind {
+                port: 1,
+                detail: String::new(),
+            },
+            BootstrapError::PortConfiguration(InterceptPortError::PortCollision(1)),
+            BootstrapError::Database(InitializationError::SqliteFailure(String::new())),
+            BootstrapError::DnsDiscovery(DnsServersError::NothingUsable(String::new())),
+            BootstrapError::WalletDecryption {
+                detail: String::new(),
+            },
+            BootstrapError::Configuration {
+                parameter: "gas-price".to_string(),
+                detail: String::new(),
+            },
+        ];
+
+        let mut codes: Vec<i32> = errors.iter().map(|e| e.exit_code()).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert!(codes.iter().all(|code| *code > 1));
+    }
+
+    #[test]
+    fn display_folds_code_message_and_hint_into_one_line() {
+        let subject = BootstrapError::PortBind {
+            port: 5333,
+            detail: "address already in use".to_string(),
+        };
+
+        let displayed = format!("{}", subject);
+
+        assert!(displayed.starts_with("CN-BOOT-PORT: could not bind port 5333"));
+        assert!(displayed.contains("free it or choose a different port"));
+        assert_eq!(displayed.lines().count(), 1);
+    }
+
+    #[test]
+    fn the_report_carries_everything_masq_needs() {
+        let subject = BootstrapError::Configuration {
+            parameter: "gas-price".to_string(),
+            detail: "must be a positive integer".to_string(),
+        };
+
+        let report = subject.report();
+
+        assert_eq!(
+            report,
+            BootstrapErrorReport {
+                code: "CN-BOOT-CONFIG".to_string(),
+                severity: Severity::Fatal,
+                message: "invalid value for --gas-price: must be a positive integer".to_string(),
+                hint: None,
+                exit_code: 15,
+            }
+        );
+    }
+}
@@ -0,0 +1,306 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Database opening with operator-grade failure handling. The two ways a
+//! node database goes wrong in the field — another instance holding it
+//! locked, or a half-written file after a full disk — used to surface as
+//! a panic from deep inside rusqlite. Both are now detected up front and
+//! reported as single-line, actionable startup errors; `--recover-database`
+//! turns the corruption case into a fresh start with the damaged file
+//! preserved aside for post-mortem.
+
+use crate::sub_lib::logger::Logger;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DATABASE_FILE: &str = "node-data.db";
+
+/// How long we wait on a busy database before concluding another process
+/// owns it.
+const BUSY_TIMEOUT_MS: i32 = 250;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InitializationError {
+    DatabaseLocked {
+        path: PathBuf,
+        holder_pid: Option<u32>,
+    },
+    DatabaseCorrupt {
+        path: PathBuf,
+    },
+    SqliteFailure(String),
+}
+
+impl fmt::Display for InitializationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitializationError::DatabaseLocked { path, holder_pid } => {
+                let holder = match holder_pid {
+                    Some(pid) => format!("process {}", pid),
+                    None => "another process".to_string(),
+                };
+                write!(
+                    f,
+                    "Database {} is locked by {}; is another node running against this data directory?",
+                    path.display(),
+                    holder
+                )
+            }
+            InitializationError::DatabaseCorrupt { path } => write!(
+                f,
+                "Database {} failed its integrity check; rerun with --recover-database to move it aside and start fresh",
+                path.display()
+            ),
+            InitializationError::SqliteFailure(message) => {
+                write!(f, "Database could not be opened: {}", message)
+            }
+        }
+    }
+}
+
+pub struct DbInitializer {
+    /// The `--recover-database` flag: move a corrupt database aside and
+    /// recreate the schema instead of refusing to start.
+    recover_database: bool,
+    logger: Logger,
+}
+
+impl DbInitializer {
+    pub fn new(recover_database: bool) -> DbInitializer {
+        DbInitializer {
+            recover_database,
+            logger: Logger::new("DbInitializer"),
+        }
+    }
+
+    pub fn initialize(&self, data_dir: &Path) -> Result<rusqlite::Connection, InitializationError> {
+        let path = data_dir.join(DATABASE_FILE);
+        let existed = path.exists();
+        let connection = match self.open(&path) {
+            Ok(connection) => connection,
+            Err(InitializationError::DatabaseCorrupt { .. }) if self.recover_database => {
+                self.recover(&path)?
+            }
+            Err(e) => return Err(e),
+        };
+        if !existed {
+            create_schema(&connection)?;
+        }
+        Ok(connection)
+    }
+
+    fn open(&self, path: &Path) -> Result<rusqlite::Connection, InitializationError> {
+        let connection = rusqlite::Connection::open(path)
+            .map_err(|e| self.classify(path, e))?;
+        connection
+            .busy_timeout(std::time::Duration::from_millis(BUSY_TIMEOUT_MS as u64))
+            .map_err(|e| self.classify(path, e))?;
+        let verdict: String = connection
+            .query_row("PRAGMA quick_check", [], |row| row.get(0))
+            .map_err(|e| self.classify(path, e))?;
+        if verdict != "ok" {
+            return Err(InitializationError::DatabaseCorrupt {
+                path: path.to_path_buf(),
+            });
+        }
+        Ok(connection)
+    }
+
+    /// Moves the damaged file aside under a timestamped name, recreates
+    /// the schema, and spells out in the log exactly what starting fresh
+    /// costs the operator.
+    fn recover(&self, path: &Path) -> Result<rusqlite::Connection, InitializationError> {
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("clock before 1970")
+            .as_secs();
+        let aside = path.with_extension(format!("corrupt-{}", stamp));
+        std::fs::rename(path, &aside)
+            .map_err(|e| InitializationError::SqliteFailure(e.to_string()))?;
+        self.logger.warning(format!(
+            "Corrupt database moved to {}; starting fresh. Lost with it: configuration values \
+             (reinitialized to defaults) and receivable/payable records (unrecoverable).",
+            aside.display()
+        ));
+        let connection = rusqlite::Connection::open(path)
+            .map_err(|e| InitializationError::SqliteFailure(e.to_string()))?;
+        create_schema(&connection)?;
+        Ok(connection)
+    }
+
+    fn classify(&self, path: &Path, error: rusqlite::Error) -> InitializationError {
+        match &error {
+            rusqlite::Error::SqliteFailure(inner, _)
+                if inner.code == rusqlite::ErrorCode::DatabaseBusy
+                    || inner.code == rusqlite::ErrorCode::DatabaseLocked =>
+            {
+                InitializationError::DatabaseLocked {
+                    path: path.to_path_buf(),
+                    holder_pid: holder_pid(path),
+                }
+            }
+            rusqlite::Error::SqliteFailure(inner, _)
+                if inner.code == rusqlite::ErrorCode::DatabaseCorrupt
+                    || inner.code == rusqlite::ErrorCode::NotADatabase =>
+            {
+                InitializationError::DatabaseCorrupt {
+                    path: path.to_path_buf(),
+                }
+            }
+            _ => InitializationError::SqliteFailure(error.to_string()),
+        }
+    }
+}
+
+fn create_schema(connection: &rusqlite::Connection) -> Result<(), InitializationError> {
+    connection
+        .execute_batch(
+            "CREATE TABLE IF NOT EXISTS config (
+                 name TEXT PRIMARY KEY,
+                 value TEXT
+             );
+             CREATE TABLE IF NOT EXISTS receivable (
+                 wallet_address TEXT PRIMARY KEY,
+                 balance_wei TEXT NOT NULL,
+                 last_received_timestamp INTEGER NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS payable (
+                 wallet_address TEXT PRIMARY KEY,
+                 balance_wei TEXT NOT NULL,
+                 last_paid_timestamp INTEGER NOT NULL
+             );",
+        )
+        .map_err(|e| InitializationError::SqliteFailure(e.to_string()))
+}
+
+/// Best effort: on Linux, find a process with the database file open by
+/// walking /proc/*/fd. Elsewhere (or without permission) the answer is
+/// simply unknown.
+#[cfg(target_os = "linux")]
+fn holder_pid(path: &Path) -> Option<u32> {
+    let target = path.canonicalize().ok()?;
+    let own_pid = std::process::id();
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let pid: u32 = match entry.file_name().to_string_lossy().parse() {
+            Ok(pid) => pid,
+            Err(_) => continue,
+        };
+        if pid == own_pid {
+            continue;
+        }
+        let fd_dir = match std::fs::read_dir(entry.path().join("fd")) {
+            Ok(fd_dir) => fd_dir,
+            Err(_) => continue,
+        };
+        for fd in fd_dir.flatten() {
+            if let Ok(link) = std::fs::read_link(fd.path()) {
+                if link == target {
+                    return Some(pid);
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+fn holder_pid(_path: &Path) -> Option<u32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_data_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join("clandestinode_db_initializer_tests")
+            .join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn a_fresh_directory_gets_a_database_with_the_schema() {
+        let data_dir = test_data_dir("fresh");
+
+        let connection = DbInitializer::new(false).initialize(&data_dir).unwrap();
+
+        let tables: Vec<String> = {
+            let mut statement = connection
+                .prepare("SELECT name FROM sqlite_master WHERE type='table' ORDER BY name")
+                .unwrap();
+            statement
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .map(|r| r.unwrap())
+                .collect()
+        };
+        assert_eq!(tables, vec!["config", "payable", "receivable"]);
+    }
+
+    #[test]
+    fn a_locked_database_is_reported_not_panicked_over() {
+        let data_dir = test_data_dir("locked");
+        DbInitializer::new(false).initialize(&data_dir).unwrap();
+        let holder =
+            rusqlite::Connection::open(data_dir.join(DATABASE_FILE)).unwrap();
+        holder.execute_batch("BEGIN EXCLUSIVE").unwrap();
+
+        let result = DbInitializer::new(false).initialize(&data_dir);
+
+        match result {
+            Err(InitializationError::DatabaseLocked { path, .. }) => {
+                assert_eq!(path, data_dir.join(DATABASE_FILE));
+            }
+            other => panic!("expected DatabaseLocked, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn a_corrupt_database_refuses_to_start_without_the_recovery_flag() {
+        let data_dir = test_data_dir("corrupt_no_flag");
+        std::fs::write(
+            data_dir.join(DATABASE_FILE),
+            b"this is not a sqlite database at all",
+        )
+        .unwrap();
+
+        let result = DbInitializer::new(false).initialize(&data_dir);
+
+        match result {
+            Err(e @ InitializationError::DatabaseCorrupt { .. }) => {
+                assert!(e.to_string().contains("--recover-database"));
+            }
+            other => panic!("expected DatabaseCorrupt, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn recovery_moves_the_corrupt_file_aside_and_starts_fresh() {
+        let data_dir = test_data_dir("corrupt_recover");
+        std::fs::write(
+            data_dir.join(DATABASE_FILE),
+            b"this is not a sqlite database at all",
+        )
+        .unwrap();
+
+        let connection = DbInitializer::new(true).initialize(&data_dir).unwrap();
+
+        let count: i64 = connection
+            .query_row("SELECT count(*) FROM config", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+        let aside_exists = std::fs::read_dir(&data_dir)
+            .unwrap()
+            .flatten()
+            .any(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .contains("corrupt-")
+            });
+        assert!(aside_exists, "corrupt file was not preserved aside");
+    }
+}
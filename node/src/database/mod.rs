@@ -0,0 +1,3 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+pub mod db_initializer;
@@ -0,0 +1,198 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Routing-loop detection for the hopper. A package that comes around a
+//! second time is recognized by a Bloom filter over a cheap fingerprint
+//! and dropped with a WARNING instead of circulating until its route runs
+//! out. Two filters rotate on a timer so membership ages out: the current
+//! filter takes inserts, both are consulted, and at each swap the older
+//! one is discarded — a package is remembered for between one and two
+//! rotation intervals.
+
+use crate::hopper::live_cores_package::LiveCoresPackage;
+use crate::sub_lib::logger::Logger;
+use std::time::{Duration, Instant};
+
+/// How often the filters rotate.
+pub const ROTATION_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Filter sizing: 2^17 bits and 4 probes hold ~10k five-minute
+/// fingerprints under a 1% false-positive rate.
+const FILTER_BITS: usize = 1 << 17;
+const PROBES: usize = 4;
+
+pub struct BloomFilter {
+    words: Vec<u64>,
+}
+
+impl BloomFilter {
+    pub fn new() -> BloomFilter {
+        BloomFilter {
+            words: vec![0; FILTER_BITS / 64],
+        }
+    }
+
+    pub fn insert(&mut self, item: u64) {
+        for bit in probe_bits(item) {
+            self.words[bit / 64] |= 1u64 << (bit % 64);
+        }
+    }
+
+    pub fn contains(&self, item: u64) -> bool {
+        probe_bits(item)
+            .iter()
+            .all(|bit| self.words[bit / 64] & (1u64 << (bit % 64)) != 0)
+    }
+}
+
+impl Default for BloomFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Double hashing: two independent mixes of the item give the probe
+/// sequence index_i = h1 + i*h2.
+fn probe_bits(item: u64) -> [usize; PROBES] {
+    let h1 = splitmix64(item);
+    let h2 = splitmix64(item ^ 0x9E37_79B9_7F4A_7C15) | 1;
+    let mut bits = [0usize; PROBES];
+    for (i, bit) in bits.iter_mut().enumerate() {
+        *bit = (h1.wrapping_add((i as u64).wrapping_mul(h2)) % FILTER_BITS as u64) as usize;
+    }
+    bits
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// Cheap fingerprint of a package: the next-hop ciphertext, the payload
+/// length, and the payload's first eight bytes. Enough to recognize the
+/// same package coming around again without hashing the whole thing.
+pub fn package_fingerprint(package: &LiveCoresPackage) -> u64 {
+    let mut acc = 0xCBF2_9CE4_8422_2325u64; // FNV offset basis
+    if let Some(first_hop) = package.route.hops().first() {
+        for byte in first_hop.as_slice() {
+            acc = (acc ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01B3);
+        }
+    }
+    acc = (acc ^ package.payload.len() as u64).wrapping_mul(0x0000_0100_0000_01B3);
+    for byte in package.payload.as_slice().iter().take(8) {
+        acc = (acc ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01B3);
+    }
+    acc
+}
+
+pub struct DuplicateDetector {
+    current: BloomFilter,
+    previous: BloomFilter,
+    last_rotation: Instant,
+    rotation_interval: Duration,
+    logger: Logger,
+}
+
+impl DuplicateDetector {
+    pub fn new(now: Instant) -> DuplicateDetector {
+        DuplicateDetector {
+            current: BloomFilter::new(),
+            previous: BloomFilter::new(),
+            last_rotation: now,
+            rotation_interval: ROTATION_INTERVAL,
+            logger: Logger::new("DuplicateDetector"),
+        }
+    }
+
+    #[cfg(test)]
+    fn with_rotation_interval(mut self, interval: Duration) -> DuplicateDetector {
+        self.rotation_interval = interval;
+        self
+    }
+
+    /// True means the package was seen recently and should be dropped.
+    /// Unseen packages are recorded.
+    pub fn check_and_record(&mut self, package: &LiveCoresPackage, now: Instant) -> bool {
+        if now.duration_since(self.last_rotation) >= self.rotation_interval {
+            self.previous = std::mem::take(&mut self.current);
+            self.last_rotation = now;
+        }
+        let fingerprint = package_fingerprint(package);
+        if self.current.contains(fingerprint) || self.previous.contains(fingerprint) {
+            self.logger.warning(format!(
+                "Dropping duplicate package (fingerprint {:016x}): probable routing loop",
+                fingerprint
+            ));
+            return true;
+        }
+        self.current.insert(fingerprint);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sub_lib::cryptde::CryptData;
+    use crate::sub_lib::route::Route;
+
+    fn package(seed: u8, payload: &[u8]) -> LiveCoresPackage {
+        LiveCoresPackage::new(
+            Route::new(vec![CryptData::new(&[seed, 1, 2, 3])]),
+            CryptData::new(payload),
+        )
+    }
+
+    #[test]
+    fn a_repeated_package_is_flagged_the_second_time() {
+        let mut subject = DuplicateDetector::new(Instant::now());
+        let now = Instant::now();
+
+        assert!(!subject.check_and_record(&package(1, b"payload bytes"), now));
+        assert!(subject.check_and_record(&package(1, b"payload bytes"), now));
+    }
+
+    #[test]
+    fn distinct_packages_pass() {
+        let mut subject = DuplicateDetector::new(Instant::now());
+        let now = Instant::now();
+
+        assert!(!subject.check_and_record(&package(1, b"payload one"), now));
+        assert!(!subject.check_and_record(&package(2, b"payload one"), now));
+        assert!(!subject.check_and_record(&package(1, b"payload two!"), now));
+    }
+
+    #[test]
+    fn membership_ages_out_after_two_rotations() {
+        let start = Instant::now();
+        let mut subject = DuplicateDetector::new(start)
+            .with_rotation_interval(Duration::from_secs(60));
+        subject.check_and_record(&package(1, b"payload"), start);
+
+        // One rotation later it lives on in the previous filter...
+        assert!(subject.check_and_record(&package(1, b"payload"), start + Duration::from_secs(61)));
+        // ...two rotations later it has been forgotten.
+        assert!(
+            !subject.check_and_record(&package(1, b"payload"), start + Duration::from_secs(181))
+        );
+    }
+
+    #[test]
+    fn false_positive_rate_stays_under_one_percent() {
+        let mut filter = BloomFilter::new();
+        for i in 0u64..10_000 {
+            filter.insert(splitmix64(i));
+        }
+
+        let false_positives = (10_000u64..20_000)
+            .filter(|i| filter.contains(splitmix64(*i)))
+            .count();
+
+        assert!(
+            false_positives < 100,
+            "false positive rate too high: {}/10000",
+            false_positives
+        );
+    }
+}
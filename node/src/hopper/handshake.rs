@@ -0,0 +1,498 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Noise XX session establishment between neighbors. Before routing
+//! messages flow, the two sides run the three-message XX handshake; the
+//! resulting transport state wraps every subsequent LiveCoresPackage on
+//! that link. The hopper consults the manager before sending and queues
+//! packages for peers whose handshake is still pending.
+
+use crate::sub_lib::cryptde::PublicKey;
+use crate::sub_lib::logger::Logger;
+use std::collections::HashMap;
+
+pub const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+/// Generous ceiling for handshake and transport frames.
+const MAX_NOISE_FRAME: usize = 65_535;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HandshakeError {
+    AlreadyEstablished,
+    NoSession,
+    StillHandshaking,
+    Noise(String),
+}
+
+enum PeerSession {
+    Handshaking(Box<snow::HandshakeState>),
+    Established(Box<snow::TransportState>),
+}
+
+/// Manages pending and established Noise sessions, one per neighbor.
+pub struct HandshakeManager {
+    static_key: Vec<u8>,
+    sessions: HashMap<PublicKey, PeerSession>,
+}
+
+impl HandshakeManager {
+    pub fn new() -> HandshakeManager {
+        let builder = snow::Builder::new(NOISE_PARAMS.parse().expect("bad Noise params"));
+        let static_key = builder
+            .generate_keypair()
+            .expect("keypair generation failed")
+            .private;
+        HandshakeManager {
+            static_key,
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// Starts a handshake toward the peer; returns the first message to send.
+    pub fn initiate(&mut self, peer: &PublicKey) -> Result<Vec<u8>, HandshakeError> {
+        if self.sessions.contains_key(peer) {
+            return Err(HandshakeError::AlreadyEstablished);
+        }
+        let mut state = snow::Builder::new(NOISE_PARAMS.parse().expect("bad Noise params"))
+            .local_private_key(&self.static_key)
+            .map_err(|e| HandshakeError::Noise(e.to_string()))?
+            .build_initiator()
+            .map_err(|e| HandshakeError::Noise(e.to_string()))?;
+        let mut buf = vec![0u8; MAX_NOISE_FRAME];
+        let len = state
+            .write_message(&[], &mut buf)
+            .map_err(|e| HandshakeError::Noise(e.to_string()))?;
+        buf.truncate(len);
+        self.sessions
+            .insert(peer.clone(), PeerSession::Handshaking(Box::new(state)));
+        Ok(buf)
+    }
+
+    /// Feeds an inbound handshake message; returns the reply to send, if
+    /// the pattern calls for one. The session flips to established when the
+    /// handshake completes.
+    pub fn on_handshake_message(
+        &mut self,
+        peer: &PublicKey,
+        message: &[u8],
+    ) -> Result<Option<Vec<u8>>, HandshakeError> {
+        let mut state = match self.sessions.remove(peer) {
+            Some(PeerSession::Handshaking(state)) => *state,
+            Some(established @ PeerSession::Established(_)) => {
+                self.sessions.insert(peer.clone(), established);
+                return Err(HandshakeError::AlreadyEstablished);
+            }
+            None => snow::Builder::new(NOISE_PARAMS.parse().expect("bad Noise params"))
+                .local_private_key(&self.static_key)
+                .map_err(|e| HandshakeError::Noise(e.to_string()))?
+                .build_responder()
+                .map_err(|e| HandshakeError::Noise(e.to_string()))?,
+        };
+        let mut read_buf = vec![0u8; MAX_NOISE_FRAME];
+        state
+            .read_message(message, &mut read_buf)
+            .map_err(|e| HandshakeError::Noise(e.to_string()))?;
+        let reply = if state.is_handshake_finished() {
+            None
+        } else {
+            let mut write_buf = vec![0u8; MAX_NOISE_FRAME];
+            let len = state
+                .write_message(&[], &mut write_buf)
+                .map_err(|e| HandshakeError::Noise(e.to_string()))?;
+            write_buf.truncate(len);
+            Some(write_buf)
+        };
+        if state.is_handshake_finished() {
+            let transport = state
+                .into_transport_mode()
+                .map_err(|e| HandshakeError::Noise(e.to_string()))?;
+            self.sessions
+                .insert(peer.clone(), PeerSession::Established(Box::new(transport)));
+        } else {
+            self.sessions
+                .insert(peer.clone(), PeerSession::Handshaking(Box::new(state)));
+        }
+        Ok(reply)
+    }
+
+    pub fn is_established(&self, peer: &PublicKey) -> bool {
+        matches!(self.sessions.get(peer), Some(PeerSession::Established(_)))
+    }
+
+    /// Wraps outgoing LiveCoresPackage bytes in the peer's session.
+    pub fn wrap(&mut self, peer: &PublicKey, plaintext: &[u8]) -> Result<Vec<u8>, HandshakeError> {
+        match self.sessions.get_mut(peer) {
+            Some(PeerSession::Established(transport)) => {
+                let mut buf = vec![0u8; plaintext.len() + 1024];
+                let len = transport
+                    .write_message(plaintext, &mut buf)
+                    .map_err(|e| HandshakeError::Noise(e.to_string()))?;
+                buf.truncate(len);
+                Ok(buf)
+            }
+            Some(PeerSession::Handshaking(_)) => Err(HandshakeError::StillHandshaking),
+            None => Err(HandshakeError::NoSession),
+        }
+    }
+
+    /// Forgets everything about the peer's session. Called on reconnect so
+    /// the next handshake mints fresh ephemeral keys instead of resuming a
+    /// transport state the other side no longer has.
+    pub fn reset(&mut self, peer: &PublicKey) {
+        self.sessions.remove(peer);
+    }
+
+    /// Unwraps inbound session-wrapped bytes from the peer.
+    pub fn unwrap(
+        &mut self,
+        peer: &PublicKey,
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, HandshakeError> {
+        match self.sessions.get_mut(peer) {
+            Some(PeerSession::Established(transport)) => {
+                let mut buf = vec![0u8; ciphertext.len()];
+                let len = transport
+                    .read_message(ciphertext, &mut buf)
+                    .map_err(|e| HandshakeError::Noise(e.to_string()))?;
+                buf.truncate(len);
+                Ok(buf)
+            }
+            Some(PeerSession::Handshaking(_)) => Err(HandshakeError::StillHandshaking),
+            None => Err(HandshakeError::NoSession),
+        }
+    }
+}
+
+impl Drop for HandshakeManager {
+    fn drop(&mut self) {
+        use zeroize::Zeroize;
+        // The Noise static private key must not outlive the manager in
+        // freed memory; snow zeroizes its own session state internally.
+        self.static_key.zeroize();
+    }
+}
+
+impl Default for HandshakeManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How link traffic to a given neighbor is protected. Legacy is the
+/// original scheme — every hop encrypted straight to the neighbor's
+/// long-term key, no forward secrecy — kept for peers that predate
+/// session keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkScheme {
+    SessionKeys,
+    LegacyLongTermKeys,
+}
+
+/// A frame ready for the wire, tagged with how it was protected so the
+/// receiving side knows which path to run it through.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LinkFrame {
+    Session(Vec<u8>),
+    Legacy(Vec<u8>),
+}
+
+/// Capability negotiation and rekeying around the HandshakeManager. When a
+/// neighbor connection comes up the neighborhood tells us whether the peer
+/// advertised session-key support; traffic to peers that did not falls
+/// back to the legacy scheme, and reconnects drop the old transport state
+/// so a fresh handshake derives fresh keys.
+pub struct LinkSessionManager {
+    handshakes: HandshakeManager,
+    schemes: HashMap<PublicKey, LinkScheme>,
+    logger: Logger,
+}
+
+impl LinkSessionManager {
+    pub fn new() -> LinkSessionManager {
+        LinkSessionManager {
+            handshakes: HandshakeManager::new(),
+            schemes: HashMap::new(),
+            logger: Logger::new("Hopper"),
+        }
+    }
+
+    /// Called when a neighbor connection comes up, with what the peer's
+    /// node record advertised. Returns the scheme the link will use; for
+    /// SessionKeys the caller follows up with `initiate` (or waits for the
+    /// peer's first handshake message).
+    pub fn negotiate(&mut self, peer: &PublicKey, peer_supports_session_keys: bool) -> LinkScheme {
+        let scheme = if peer_supports_session_keys {
+            LinkScheme::SessionKeys
+        } else {
+            self.logger.info(format!(
+                "Neighbor {:?} does not support session keys; falling back to long-term-key encryption",
+                peer
+            ));
+            LinkScheme::LegacyLongTermKeys
+        };
+        self.schemes.insert(peer.clone(), scheme);
+        scheme
+    }
+
+    /// The scheme negotiated for the peer; Legacy until told otherwise, so
+    /// a missed negotiation degrades rather than breaks.
+    pub fn scheme_for(&self, peer: &PublicKey) -> LinkScheme {
+        self.schemes
+            .get(peer)
+            .copied()
+            .unwrap_or(LinkScheme::LegacyLongTermKeys)
+    }
+
+    /// Called when the neighbor's connection drops and comes back. The old
+    /// transport state is discarded on both sides; the reconnect handshake
+    /// derives fresh session keys (rekeying), and until it completes the
+    /// link queues as during initial establishment.
+    pub fn on_reconnect(&mut self, peer: &PublicKey) {
+        self.handshakes.reset(peer);
+    }
+
+    pub fn initiate(&mut self, peer: &PublicKey) -> Result<Vec<u8>, HandshakeError> {
+        self.handshakes.initiate(peer)
+    }
+
+    pub fn on_handshake_message(
+        &mut self,
+        peer: &PublicKey,
+        message: &[u8],
+    ) -> Result<Option<Vec<u8>>, HandshakeError> {
+        self.handshakes.on_handshake_message(peer, message)
+    }
+
+    pub fn is_established(&self, peer: &PublicKey) -> bool {
+        self.handshakes.is_established(peer)
+    }
+
+    /// Protects serialized LiveCoresPackage bytes for the wire. Session
+    /// peers get the Noise transport; legacy peers get the bytes through
+    /// untouched — their hop encryption to the long-term key already
+    /// happened when the route was built.
+    pub fn wrap_package(
+        &mut self,
+        peer: &PublicKey,
+        package_bytes: &[u8],
+    ) -> Result<LinkFrame, HandshakeError> {
+        match self.scheme_for(peer) {
+            LinkScheme::SessionKeys => Ok(LinkFrame::Session(
+                self.handshakes.wrap(peer, package_bytes)?,
+            )),
+            LinkScheme::LegacyLongTermKeys => Ok(LinkFrame::Legacy(package_bytes.to_vec())),
+        }
+    }
+
+    /// The inbound counterpart; the frame's own tag governs, so a legacy
+    /// frame from a session-capable peer (sent before negotiation
+    /// finished) still decodes.
+    pub fn unwrap_package(
+        &mut self,
+        peer: &PublicKey,
+        frame: LinkFrame,
+    ) -> Result<Vec<u8>, HandshakeError> {
+        match frame {
+            LinkFrame::Session(ciphertext) => self.handshakes.unwrap(peer, &ciphertext),
+            LinkFrame::Legacy(package_bytes) => Ok(package_bytes),
+        }
+    }
+}
+
+impl Default for LinkSessionManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_full_handshake(
+        initiator: &mut HandshakeManager,
+        responder: &mut HandshakeManager,
+        initiator_key: &PublicKey,
+        responder_key: &PublicKey,
+    ) {
+        let msg1 = initiator.initiate(responder_key).unwrap();
+        let msg2 = responder
+            .on_handshake_message(initiator_key, &msg1)
+            .unwrap()
+            .expect("responder should reply");
+        let msg3 = initiator
+            .on_handshake_message(responder_key, &msg2)
+            .unwrap()
+            .expect("initiator should reply");
+        let done = responder.on_handshake_message(initiator_key, &msg3).unwrap();
+        assert_eq!(done, None);
+    }
+
+    #[test]
+    fn two_managers_derive_a_working_shared_session() {
+        let mut alice = HandshakeManager::new();
+        let mut bob = HandshakeManager::new();
+        let alice_key = PublicKey::new(b"alice");
+        let bob_key = PublicKey::new(b"bob");
+
+        run_full_handshake(&mut alice, &mut bob, &alice_key, &bob_key);
+
+        assert!(alice.is_established(&bob_key));
+        assert!(bob.is_established(&alice_key));
+        let wrapped = alice.wrap(&bob_key, b"live cores package bytes").unwrap();
+        assert_ne!(wrapped.as_slice(), b"live cores package bytes");
+        let unwrapped = bob.unwrap(&alice_key, &wrapped).unwrap();
+        assert_eq!(unwrapped, b"live cores package bytes");
+    }
+
+    #[test]
+    fn wrapping_before_establishment_is_refused() {
+        let mut alice = HandshakeManager::new();
+        let bob_key = PublicKey::new(b"bob");
+
+        assert_eq!(
+            alice.wrap(&bob_key, b"data"),
+            Err(HandshakeError::NoSession)
+        );
+
+        alice.initiate(&bob_key).unwrap();
+
+        assert_eq!(
+            alice.wrap(&bob_key, b"data"),
+            Err(HandshakeError::StillHandshaking)
+        );
+    }
+
+    #[test]
+    fn third_party_cannot_unwrap_the_session_traffic() {
+        let mut alice = HandshakeManager::new();
+        let mut bob = HandshakeManager::new();
+        let mut eve = HandshakeManager::new();
+        let alice_key = PublicKey::new(b"alice");
+        let bob_key = PublicKey::new(b"bob");
+        run_full_handshake(&mut alice, &mut bob, &alice_key, &bob_key);
+        run_full_handshake(&mut alice, &mut eve, &alice_key, &PublicKey::new(b"eve"));
+
+        let wrapped = alice.wrap(&bob_key, b"secret").unwrap();
+
+        let result = eve.unwrap(&alice_key, &wrapped);
+        assert!(matches!(result, Err(HandshakeError::Noise(_))));
+    }
+
+    fn run_full_link_handshake(
+        initiator: &mut LinkSessionManager,
+        responder: &mut LinkSessionManager,
+        initiator_key: &PublicKey,
+        responder_key: &PublicKey,
+    ) {
+        let msg1 = initiator.initiate(responder_key).unwrap();
+        let msg2 = responder
+            .on_handshake_message(initiator_key, &msg1)
+            .unwrap()
+            .expect("responder should reply");
+        let msg3 = initiator
+            .on_handshake_message(responder_key, &msg2)
+            .unwrap()
+            .expect("initiator should reply");
+        let done = responder.on_handshake_message(initiator_key, &msg3).unwrap();
+        assert_eq!(done, None);
+    }
+
+    #[test]
+    fn a_session_capable_peer_gets_session_frames() {
+        let mut alice = LinkSessionManager::new();
+        let mut bob = LinkSessionManager::new();
+        let alice_key = PublicKey::new(b"alice");
+        let bob_key = PublicKey::new(b"bob");
+        assert_eq!(
+            alice.negotiate(&bob_key, true),
+            LinkScheme::SessionKeys
+        );
+        bob.negotiate(&alice_key, true);
+        run_full_link_handshake(&mut alice, &mut bob, &alice_key, &bob_key);
+
+        let frame = alice.wrap_package(&bob_key, b"package bytes").unwrap();
+
+        match &frame {
+            LinkFrame::Session(ciphertext) => assert_ne!(ciphertext.as_slice(), b"package bytes"),
+            other => panic!("expected Session frame, got {:?}", other),
+        }
+        assert_eq!(
+            bob.unwrap_package(&alice_key, frame).unwrap(),
+            b"package bytes"
+        );
+    }
+
+    #[test]
+    fn a_legacy_peer_falls_back_to_long_term_key_frames() {
+        let mut alice = LinkSessionManager::new();
+        let mut old_bob = LinkSessionManager::new();
+        let alice_key = PublicKey::new(b"alice");
+        let bob_key = PublicKey::new(b"bob");
+        assert_eq!(
+            alice.negotiate(&bob_key, false),
+            LinkScheme::LegacyLongTermKeys
+        );
+
+        let frame = alice.wrap_package(&bob_key, b"package bytes").unwrap();
+
+        assert_eq!(frame, LinkFrame::Legacy(b"package bytes".to_vec()));
+        // No handshake ever ran; the legacy frame still decodes.
+        assert_eq!(
+            old_bob.unwrap_package(&alice_key, frame).unwrap(),
+            b"package bytes"
+        );
+    }
+
+    #[test]
+    fn an_unnegotiated_peer_defaults_to_legacy() {
+        let subject = LinkSessionManager::new();
+
+        assert_eq!(
+            subject.scheme_for(&PublicKey::new(b"stranger")),
+            LinkScheme::LegacyLongTermKeys
+        );
+    }
+
+    #[test]
+    fn traffic_decrypts_after_a_reconnect_rekey() {
+        let mut alice = LinkSessionManager::new();
+        let mut bob = LinkSessionManager::new();
+        let alice_key = PublicKey::new(b"alice");
+        let bob_key = PublicKey::new(b"bob");
+        alice.negotiate(&bob_key, true);
+        bob.negotiate(&alice_key, true);
+        run_full_link_handshake(&mut alice, &mut bob, &alice_key, &bob_key);
+        let stale_frame = alice.wrap_package(&bob_key, b"before reconnect").unwrap();
+
+        alice.on_reconnect(&bob_key);
+        bob.on_reconnect(&alice_key);
+        run_full_link_handshake(&mut alice, &mut bob, &alice_key, &bob_key);
+
+        let fresh_frame = alice.wrap_package(&bob_key, b"after reconnect").unwrap();
+        assert_eq!(
+            bob.unwrap_package(&alice_key, fresh_frame).unwrap(),
+            b"after reconnect"
+        );
+        // The pre-reconnect frame was wrapped under keys neither side holds
+        // any longer.
+        assert!(matches!(
+            bob.unwrap_package(&alice_key, stale_frame),
+            Err(HandshakeError::Noise(_))
+        ));
+    }
+
+    #[test]
+    fn reset_allows_a_second_handshake() {
+        let mut alice = HandshakeManager::new();
+        let bob_key = PublicKey::new(b"bob");
+        alice.initiate(&bob_key).unwrap();
+        assert_eq!(
+            alice.initiate(&bob_key),
+            Err(HandshakeError::AlreadyEstablished)
+        );
+
+        alice.reset(&bob_key);
+
+        assert!(alice.initiate(&bob_key).is_ok());
+    }
+}
@@ -0,0 +1,502 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use crate::sub_lib::cryptde::{CryptDE, CryptData, PlainData, PublicKey};
+use crate::sub_lib::decodex::{decodex, encodex, CodexError};
+use crate::sub_lib::hop::LiveHop;
+use crate::sub_lib::hopper::{ExpiredCoresPackage, PaddedEnvelope};
+use crate::sub_lib::route::{Route, RouteError};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// Width of the payload tag's MAC and of its key: 256 bits, like every
+/// other keyed digest in the tree.
+pub const PAYLOAD_TAG_BYTES: usize = 32;
+
+/// A CORES package in flight between nodes: the remaining route plus the
+/// end-to-end encrypted payload.
+///
+/// When `padded_to` is set, the payload has been PKCS#7-padded to the next
+/// multiple of that block size before hitting the wire, so relays see only
+/// bucketed sizes; the receiving end strips the padding before decoding.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LiveCoresPackage {
+    pub route: Route,
+    pub payload: CryptData,
+    pub padded_to: Option<usize>,
+    /// Authentication tag binding this payload to this route: an
+    /// HKDF-SHA256 MAC over the current hop ciphertext and the payload,
+    /// carried — together with its one-use key — encrypted to the
+    /// receiving hop. The key is derived from the sender's signature, so
+    /// a relay that swaps payloads between packages can neither read it
+    /// nor find another payload matching the 256-bit MAC it hides;
+    /// `to_next_live` refuses to forward on a mismatch.
+    pub payload_tag: CryptData,
+    /// Distributed-tracing context, carried end to end when telemetry is
+    /// enabled; None (and zero bytes of overhead beyond the option tag)
+    /// otherwise.
+    pub trace_context: Option<crate::telemetry::TraceContext>,
+}
+
+/// What actually rides inside `payload_tag` once decrypted: the MAC and
+/// the one-use key that verifies it.
+#[derive(Serialize, Deserialize)]
+struct PayloadTag {
+    mac_key: [u8; PAYLOAD_TAG_BYTES],
+    mac: [u8; PAYLOAD_TAG_BYTES],
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PaddingError {
+    BlockSizeOutOfRange(usize),
+    MalformedPadding,
+}
+
+impl LiveCoresPackage {
+    pub fn new(route: Route, payload: CryptData) -> LiveCoresPackage {
+        LiveCoresPackage {
+            route,
+            payload,
+            padded_to: None,
+            payload_tag: CryptData::new(&[]),
+            trace_context: None,
+        }
+    }
+
+    /// Computes the payload tag for the node about to receive this
+    /// package. Called by the sender after its own route shift, so
+    /// `hops[0]` is the hop the receiver will see.
+    pub fn tag_for_hop(
+        mut self,
+        cryptde: &dyn CryptDE,
+        hop_key: &PublicKey,
+    ) -> Result<LiveCoresPackage, CodexError> {
+        let mac_key = Self::tag_key(cryptde, self.route.hops().first(), &self.payload)?;
+        let mac = Self::tag_mac(&mac_key, self.route.hops().first(), &self.payload);
+        self.payload_tag = encodex(cryptde, hop_key, &PayloadTag { mac_key, mac })?;
+        Ok(self)
+    }
+
+    /// Verifies the payload tag, peels one hop, and re-tags the package
+    /// for the next node. Any tag that fails to decrypt or fails to match
+    /// — a swapped payload, a swapped tag — is PayloadTagInvalid. Routes
+    /// longer than DEFAULT_MAX_HOPS are refused before any crypto work: a
+    /// malicious originator does not get to spend this relay's cycles on a
+    /// 200-hop route.
+    pub fn to_next_live(
+        mut self,
+        cryptde: &dyn CryptDE,
+    ) -> Result<(LiveHop, LiveCoresPackage), RouteError> {
+        if self.route.hop_count() > crate::sub_lib::route::DEFAULT_MAX_HOPS {
+            return Err(RouteError::TooManyHops {
+                hops: self.route.hop_count(),
+                max: crate::sub_lib::route::DEFAULT_MAX_HOPS,
+            });
+        }
+        let tag: PayloadTag =
+            decodex(cryptde, &self.payload_tag).map_err(|_| RouteError::PayloadTagInvalid)?;
+        let expected = Self::tag_mac(&tag.mac_key, self.route.hops().first(), &self.payload);
+        // Constant-time: a forger probing tag bytes must not learn how
+        // close a guess came from the rejection latency.
+        if !bool::from(subtle::ConstantTimeEq::ct_eq(&tag.mac[..], &expected[..])) {
+            return Err(RouteError::PayloadTagInvalid);
+        }
+        let next_hop = self.route.shift(cryptde)?;
+        let retagged = self
+            .tag_for_hop(cryptde, &next_hop.public_key)
+            .map_err(|_| RouteError::PayloadTagInvalid)?;
+        Ok((next_hop, retagged))
+    }
+
+    /// `to_next_live` with replay protection in front: the sending
+    /// neighbor's link sequence number is checked against its sliding
+    /// window before any crypto work, so a replayed capture costs this
+    /// relay a hash lookup, not a decrypt.
+    pub fn to_next_live_checked(
+        self,
+        cryptde: &dyn CryptDE,
+        sender: &PublicKey,
+        sequence: u64,
+        replay_window: &mut crate::hopper::replay_window::ReplayWindow,
+    ) -> Result<(LiveHop, LiveCoresPackage), RouteError> {
+        replay_window.check_and_record(sender, sequence)?;
+        self.to_next_live(cryptde)
+    }
+
+    /// The MAC key for one tag. It travels only inside the encrypted tag,
+    /// so it needs to be unpredictable to relays rather than shared in
+    /// advance; deriving it from the sender's signature over the tagged
+    /// bytes gives that without an RNG in the hot path.
+    fn tag_key(
+        cryptde: &dyn CryptDE,
+        hop: Option<&CryptData>,
+        payload: &CryptData,
+    ) -> Result<[u8; PAYLOAD_TAG_BYTES], CodexError> {
+        let signature = cryptde
+            .sign(&PlainData::from(Self::tag_message(hop, payload)))
+            .map_err(CodexError::EncryptionFailed)?;
+        let hkdf = Hkdf::<Sha256>::new(Some(b"clandestine-payload-tag-key"), signature.as_slice());
+        let mut key = [0u8; PAYLOAD_TAG_BYTES];
+        hkdf.expand(b"clandestine-payload-tag-key", &mut key)
+            .expect("32 bytes is a valid HKDF output length");
+        Ok(key)
+    }
+
+    /// HKDF-SHA256 keyed MAC over hop||payload, the same construction the
+    /// stream ratchet uses for its seals.
+    fn tag_mac(
+        key: &[u8; PAYLOAD_TAG_BYTES],
+        hop: Option<&CryptData>,
+        payload: &CryptData,
+    ) -> [u8; PAYLOAD_TAG_BYTES] {
+        let hkdf = Hkdf::<Sha256>::new(Some(key), &Self::tag_message(hop, payload));
+        let mut mac = [0u8; PAYLOAD_TAG_BYTES];
+        hkdf.expand(b"clandestine-payload-tag", &mut mac)
+            .expect("32 bytes is a valid HKDF output length");
+        mac
+    }
+
+    /// hop and payload, length-prefixed so no (hop, payload) split is
+    /// ambiguous with another.
+    fn tag_message(hop: Option<&CryptData>, payload: &CryptData) -> Vec<u8> {
+        let hop_bytes = hop.map(CryptData::as_slice).unwrap_or(&[]);
+        let mut message = Vec::with_capacity(8 + hop_bytes.len() + payload.len());
+        message.extend_from_slice(&(hop_bytes.len() as u64).to_be_bytes());
+        message.extend_from_slice(hop_bytes);
+        message.extend_from_slice(payload.as_slice());
+        message
+    }
+
+    /// PKCS#7-pads the payload to the next multiple of `block_size` (1-255).
+    /// A full extra block is added when the payload is already aligned, as
+    /// PKCS#7 requires, so padding is always present and unambiguous.
+    pub fn pad_payload(mut self, block_size: usize) -> Result<LiveCoresPackage, PaddingError> {
+        if block_size == 0 || block_size > 255 {
+            return Err(PaddingError::BlockSizeOutOfRange(block_size));
+        }
+        let mut bytes = self.payload.as_slice().to_vec();
+        let pad_len = block_size - (bytes.len() % block_size);
+        bytes.extend(std::iter::repeat(pad_len as u8).take(pad_len));
+        self.payload = CryptData::from(bytes);
+        self.padded_to = Some(block_size);
+        Ok(self)
+    }
+
+    /// Strips PKCS#7 padding on receipt. A no-op for unpadded packages.
+    pub fn strip_padding(mut self) -> Result<LiveCoresPackage, PaddingError> {
+        let block_size = match self.padded_to {
+            None => return Ok(self),
+            Some(block_size) => block_size,
+        };
+        let bytes = self.payload.as_slice();
+        if bytes.is_empty() || bytes.len() % block_size != 0 {
+            return Err(PaddingError::MalformedPadding);
+        }
+        let pad_len = *bytes.last().expect("just checked nonempty") as usize;
+        if pad_len == 0 || pad_len > block_size || pad_len > bytes.len() {
+            return Err(PaddingError::MalformedPadding);
+        }
+        if bytes[bytes.len() - pad_len..].iter().any(|b| *b as usize != pad_len) {
+            return Err(PaddingError::MalformedPadding);
+        }
+        self.payload = CryptData::new(&bytes[..bytes.len() - pad_len]);
+        self.padded_to = None;
+        Ok(self)
+    }
+
+    /// Decrypts the payload at the end of the route and unwraps the padded
+    /// envelope; any filler bytes the originator added disappear here. The
+    /// caller must strip hop-level PKCS#7 padding first.
+    pub fn to_expired(self, cryptde: &dyn CryptDE) -> Result<ExpiredCoresPackage, CodexError> {
+        let envelope: PaddedEnvelope = decodex(cryptde, &self.payload)?;
+        Ok(ExpiredCoresPackage {
+            remaining_route: self.route,
+            payload: envelope.message,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sub_lib::cryptde::PublicKey;
+    use crate::sub_lib::cryptde_null::CryptDENull;
+    use crate::sub_lib::hopper::{IncipientCoresPackage, MessageType, PaddingSchedule};
+
+    fn make_package(payload: &[u8]) -> LiveCoresPackage {
+        LiveCoresPackage::new(Route::new(vec![]), CryptData::new(payload))
+    }
+
+    #[test]
+    fn padded_payload_length_is_a_block_multiple() {
+        let subject = make_package(&[1, 2, 3, 4, 5]).pad_payload(16).unwrap();
+
+        assert_eq!(subject.payload.len(), 16);
+        assert_eq!(subject.padded_to, Some(16));
+    }
+
+    #[test]
+    fn aligned_payload_gains_a_full_padding_block() {
+        let subject = make_package(&[0u8; 32]).pad_payload(16).unwrap();
+
+        assert_eq!(subject.payload.len(), 48);
+    }
+
+    #[test]
+    fn padding_round_trips() {
+        let original = make_package(b"secret payload");
+
+        let stripped = original
+            .clone()
+            .pad_payload(64)
+            .unwrap()
+            .strip_padding()
+            .unwrap();
+
+        assert_eq!(stripped, original);
+    }
+
+    #[test]
+    fn unpadded_package_strips_to_itself() {
+        let original = make_package(b"secret payload");
+
+        assert_eq!(original.clone().strip_padding().unwrap(), original);
+    }
+
+    #[test]
+    fn corrupted_padding_is_rejected() {
+        let mut padded = make_package(b"secret payload").pad_payload(64).unwrap();
+        let mut bytes = padded.payload.as_slice().to_vec();
+        *bytes.last_mut().unwrap() = 0;
+        padded.payload = CryptData::from(bytes);
+
+        assert_eq!(padded.strip_padding(), Err(PaddingError::MalformedPadding));
+    }
+
+    #[test]
+    fn a_tagged_package_forwards_through_a_relay() {
+        let originator = CryptDENull::from(&PublicKey::new(b"origin"));
+        let relay_key = PublicKey::new(b"relay");
+        let exit_key = PublicKey::new(b"exit");
+        let relay = CryptDENull::from(&relay_key);
+        let mut route = Route::one_way(&originator, &[&relay_key, &exit_key]).unwrap();
+        // The originator shifts its own hop conceptually by building the
+        // route starting at the relay; tag what the relay will see.
+        let package = LiveCoresPackage::new(route.clone(), CryptData::new(b"payload"))
+            .tag_for_hop(&originator, &relay_key)
+            .unwrap();
+
+        let (next_hop, forwarded) = package.to_next_live(&relay).unwrap();
+
+        assert_eq!(next_hop.public_key, exit_key);
+        route.shift(&relay).unwrap();
+        assert_eq!(forwarded.route, route);
+        assert_eq!(forwarded.payload, CryptData::new(b"payload"));
+        assert_ne!(forwarded.payload_tag.len(), 0);
+    }
+
+    #[test]
+    fn a_replayed_package_is_dropped_before_any_crypto_work() {
+        let originator = CryptDENull::from(&PublicKey::new(b"origin"));
+        let relay_key = PublicKey::new(b"relay");
+        let exit_key = PublicKey::new(b"exit");
+        let relay = CryptDENull::from(&relay_key);
+        let sender_key = PublicKey::new(b"sending_neighbor");
+        let route = Route::one_way(&originator, &[&relay_key, &exit_key]).unwrap();
+        let package = LiveCoresPackage::new(route, CryptData::new(b"payload"))
+            .tag_for_hop(&originator, &relay_key)
+            .unwrap();
+        let mut window = crate::hopper::replay_window::ReplayWindow::new();
+
+        let first = package
+            .clone()
+            .to_next_live_checked(&relay, &sender_key, 17, &mut window);
+        let replayed = package.to_next_live_checked(&relay, &sender_key, 17, &mut window);
+
+        assert!(first.is_ok());
+        assert_eq!(
+            replayed.err(),
+            Some(RouteError::ReplayDetected { sequence: 17 })
+        );
+    }
+
+    #[test]
+    fn reordered_packages_within_the_window_all_forward() {
+        let originator = CryptDENull::from(&PublicKey::new(b"origin"));
+        let relay_key = PublicKey::new(b"relay");
+        let exit_key = PublicKey::new(b"exit");
+        let relay = CryptDENull::from(&relay_key);
+        let sender_key = PublicKey::new(b"sending_neighbor");
+        let route = Route::one_way(&originator, &[&relay_key, &exit_key]).unwrap();
+        let package = LiveCoresPackage::new(route, CryptData::new(b"payload"))
+            .tag_for_hop(&originator, &relay_key)
+            .unwrap();
+        let mut window = crate::hopper::replay_window::ReplayWindow::new();
+
+        for sequence in [3u64, 6, 4, 5] {
+            let result =
+                package
+                    .clone()
+                    .to_next_live_checked(&relay, &sender_key, sequence, &mut window);
+
+            assert!(result.is_ok(), "sequence {} refused", sequence);
+        }
+    }
+
+    #[test]
+    fn a_route_at_the_hop_limit_still_forwards() {
+        let originator = CryptDENull::from(&PublicKey::new(b"origin"));
+        let keys: Vec<PublicKey> = (0..crate::sub_lib::route::DEFAULT_MAX_HOPS)
+            .map(|n| PublicKey::new(format!("key{}", n).as_bytes()))
+            .collect();
+        let key_refs: Vec<&PublicKey> = keys.iter().collect();
+        let first_relay = CryptDENull::from(&keys[0]);
+        let route = Route::one_way(&originator, &key_refs).unwrap();
+        let package = LiveCoresPackage::new(route, CryptData::new(b"payload"))
+            .tag_for_hop(&originator, &keys[0])
+            .unwrap();
+
+        let (next_hop, _) = package.to_next_live(&first_relay).unwrap();
+
+        assert_eq!(next_hop.public_key, keys[1]);
+    }
+
+    #[test]
+    fn an_oversized_route_is_refused_before_any_crypto_work() {
+        let relay = CryptDENull::from(&PublicKey::new(b"relay"));
+        let hops: Vec<CryptData> = (0..=crate::sub_lib::route::DEFAULT_MAX_HOPS)
+            .map(|n| CryptData::new(&[n as u8]))
+            .collect();
+        // No valid payload tag: the length check must fire first.
+        let package = LiveCoresPackage::new(Route::new(hops), CryptData::new(b"payload"));
+
+        let result = package.to_next_live(&relay);
+
+        assert_eq!(
+            result.unwrap_err(),
+            RouteError::TooManyHops {
+                hops: crate::sub_lib::route::DEFAULT_MAX_HOPS + 1,
+                max: crate::sub_lib::route::DEFAULT_MAX_HOPS,
+            }
+        );
+    }
+
+    #[test]
+    fn the_tag_is_a_256_bit_keyed_mac_not_a_short_digest() {
+        let originator = CryptDENull::from(&PublicKey::new(b"origin"));
+        let relay_key = PublicKey::new(b"relay");
+        let relay = CryptDENull::from(&relay_key);
+        let route = Route::one_way(&originator, &[&relay_key]).unwrap();
+        let package = LiveCoresPackage::new(route, CryptData::new(b"payload"))
+            .tag_for_hop(&originator, &relay_key)
+            .unwrap();
+
+        let tag: PayloadTag = decodex(&relay, &package.payload_tag).unwrap();
+
+        assert_eq!(tag.mac_key.len(), PAYLOAD_TAG_BYTES);
+        assert_eq!(tag.mac.len(), PAYLOAD_TAG_BYTES);
+        // The MAC is the HKDF-SHA256 construction under the carried key,
+        // over the hop the receiver sees and the payload.
+        let expected = LiveCoresPackage::tag_mac(
+            &tag.mac_key,
+            package.route.hops().first(),
+            &package.payload,
+        );
+        assert_eq!(tag.mac, expected);
+        // And it is keyed: without the carried key, the bytes are not
+        // reproducible from hop and payload alone.
+        let under_other_key = LiveCoresPackage::tag_mac(
+            &[0u8; PAYLOAD_TAG_BYTES],
+            package.route.hops().first(),
+            &package.payload,
+        );
+        assert_ne!(tag.mac, under_other_key);
+    }
+
+    #[test]
+    fn a_corrupted_tag_is_rejected() {
+        let originator = CryptDENull::from(&PublicKey::new(b"origin"));
+        let relay_key = PublicKey::new(b"relay");
+        let relay = CryptDENull::from(&relay_key);
+        let route = Route::one_way(&originator, &[&relay_key]).unwrap();
+        let mut package = LiveCoresPackage::new(route, CryptData::new(b"payload"))
+            .tag_for_hop(&originator, &relay_key)
+            .unwrap();
+        let mut tag_bytes = package.payload_tag.as_slice().to_vec();
+        *tag_bytes.last_mut().unwrap() ^= 0x01;
+        package.payload_tag = CryptData::from(tag_bytes);
+
+        let result = package.to_next_live(&relay);
+
+        assert_eq!(result.unwrap_err(), RouteError::PayloadTagInvalid);
+    }
+
+    #[test]
+    fn a_swapped_payload_is_rejected() {
+        let originator = CryptDENull::from(&PublicKey::new(b"origin"));
+        let relay_key = PublicKey::new(b"relay");
+        let relay = CryptDENull::from(&relay_key);
+        let route = Route::one_way(&originator, &[&relay_key]).unwrap();
+        let mut package = LiveCoresPackage::new(route, CryptData::new(b"honest payload"))
+            .tag_for_hop(&originator, &relay_key)
+            .unwrap();
+        // A malicious relay splices in a payload from another package.
+        package.payload = CryptData::new(b"injected payload");
+
+        let result = package.to_next_live(&relay);
+
+        assert_eq!(result.unwrap_err(), RouteError::PayloadTagInvalid);
+    }
+
+    #[test]
+    fn envelope_padding_round_trips_through_to_expired() {
+        let cryptde = CryptDENull::from(&PublicKey::new(b"dest"));
+        let message = MessageType::CoverTraffic(vec![7; 100]);
+        let incipient = IncipientCoresPackage::new(
+            &cryptde,
+            Route::new(vec![]),
+            message.clone(),
+            cryptde.public_key(),
+            Some(&PaddingSchedule::default()),
+        )
+        .unwrap();
+        let live = LiveCoresPackage::new(incipient.route, incipient.payload);
+
+        let expired = live.to_expired(&cryptde).unwrap();
+
+        assert_eq!(expired.payload, message);
+        assert_eq!(expired.remaining_route, Route::new(vec![]));
+    }
+
+    #[test]
+    fn unpadded_envelope_also_expires_cleanly() {
+        let cryptde = CryptDENull::from(&PublicKey::new(b"dest"));
+        let message = MessageType::CoverTraffic(vec![7; 100]);
+        let incipient = IncipientCoresPackage::new(
+            &cryptde,
+            Route::new(vec![]),
+            message.clone(),
+            cryptde.public_key(),
+            None,
+        )
+        .unwrap();
+
+        let expired = LiveCoresPackage::new(incipient.route, incipient.payload)
+            .to_expired(&cryptde)
+            .unwrap();
+
+        assert_eq!(expired.payload, message);
+    }
+
+    #[test]
+    fn out_of_range_block_sizes_are_rejected() {
+        assert_eq!(
+            make_package(b"x").pad_payload(0),
+            Err(PaddingError::BlockSizeOutOfRange(0))
+        );
+        assert_eq!(
+            make_package(b"x").pad_payload(256),
+            Err(PaddingError::BlockSizeOutOfRange(256))
+        );
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Mix-network batching: instead of forwarding each LiveCoresPackage as it
+//! arrives, the hopper pools them and releases the whole pool shuffled, so
+//! an observer cannot match arrival order to departure order. A pool is
+//! released when it fills or when its oldest member has waited the
+//! configured delay, whichever comes first.
+
+use crate::hopper::live_cores_package::LiveCoresPackage;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MixnetConfig {
+    pub pool_size: usize,
+    pub release_delay_ms: u64,
+}
+
+pub struct MixnetPool {
+    config: MixnetConfig,
+    pending: Vec<LiveCoresPackage>,
+    oldest_at: Option<Instant>,
+    rng_state: u64,
+}
+
+impl MixnetPool {
+    pub fn new(config: MixnetConfig, seed: u64) -> MixnetPool {
+        MixnetPool {
+            config,
+            pending: vec![],
+            oldest_at: None,
+            rng_state: seed | 1,
+        }
+    }
+
+    /// Adds a package; returns the shuffled batch when this add fills the
+    /// pool.
+    pub fn add(&mut self, package: LiveCoresPackage, now: Instant) -> Option<Vec<LiveCoresPackage>> {
+        if self.pending.is_empty() {
+            self.oldest_at = Some(now);
+        }
+        self.pending.push(package);
+        if self.pending.len() >= self.config.pool_size {
+            Some(self.release())
+        } else {
+            None
+        }
+    }
+
+    /// Timer hook: releases the pool when the oldest member has waited out
+    /// the delay.
+    pub fn tick(&mut self, now: Instant) -> Option<Vec<LiveCoresPackage>> {
+        let oldest_at = self.oldest_at?;
+        if now.duration_since(oldest_at) >= Duration::from_millis(self.config.release_delay_ms) {
+            Some(self.release())
+        } else {
+            None
+        }
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn release(&mut self) -> Vec<LiveCoresPackage> {
+        self.oldest_at = None;
+        let mut batch = std::mem::take(&mut self.pending);
+        // Fisher-Yates with a local xorshift; unpredictability of the
+        // permutation, not cryptographic strength, is what matters here.
+        for i in (1..batch.len()).rev() {
+            let j = (self.next_u64() % (i as u64 + 1)) as usize;
+            batch.swap(i, j);
+        }
+        batch
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.rng_state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng_state = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sub_lib::cryptde::CryptData;
+    use crate::sub_lib::route::Route;
+
+    fn package(tag: u8) -> LiveCoresPackage {
+        LiveCoresPackage::new(Route::new(vec![]), CryptData::new(&[tag]))
+    }
+
+    fn tag_of(package: &LiveCoresPackage) -> u8 {
+        package.payload.as_slice()[0]
+    }
+
+    fn config(pool_size: usize, release_delay_ms: u64) -> MixnetConfig {
+        MixnetConfig {
+            pool_size,
+            release_delay_ms,
+        }
+    }
+
+    #[test]
+    fn pool_releases_when_full() {
+        let mut subject = MixnetPool::new(config(3, 10_000), 42);
+        let now = Instant::now();
+
+        assert_eq!(subject.add(package(0), now), None);
+        assert_eq!(subject.add(package(1), now), None);
+        let batch = subject.add(package(2), now).unwrap();
+
+        assert_eq!(batch.len(), 3);
+        assert_eq!(subject.pending_count(), 0);
+    }
+
+    #[test]
+    fn pool_releases_when_the_delay_elapses() {
+        let mut subject = MixnetPool::new(config(100, 500), 42);
+        let now = Instant::now();
+        subject.add(package(0), now);
+        subject.add(package(1), now + Duration::from_millis(100));
+
+        assert_eq!(subject.tick(now + Duration::from_millis(499)), None);
+        let batch = subject.tick(now + Duration::from_millis(500)).unwrap();
+
+        assert_eq!(batch.len(), 2);
+    }
+
+    #[test]
+    fn empty_pool_never_releases_on_tick() {
+        let mut subject = MixnetPool::new(config(10, 500), 42);
+
+        assert_eq!(subject.tick(Instant::now() + Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn release_order_is_uniformly_shuffled() {
+        // Chi-squared test over the released position of the first-added
+        // package across many batches: 5 cells, df=4, critical value 18.47
+        // at p=0.001.
+        const TRIALS: usize = 2_000;
+        const POOL: usize = 5;
+        let mut subject = MixnetPool::new(config(POOL, 10_000), 0xdead_beef);
+        let mut position_counts = [0usize; POOL];
+        let now = Instant::now();
+
+        for _ in 0..TRIALS {
+            let mut batch = None;
+            for tag in 0..POOL as u8 {
+                batch = subject.add(package(tag), now);
+            }
+            let batch = batch.unwrap();
+            let position = batch.iter().position(|p| tag_of(p) == 0).unwrap();
+            position_counts[position] += 1;
+        }
+
+        let expected = TRIALS as f64 / POOL as f64;
+        let chi_squared: f64 = position_counts
+            .iter()
+            .map(|count| {
+                let diff = *count as f64 - expected;
+                diff * diff / expected
+            })
+            .sum();
+        assert!(
+            chi_squared < 18.47,
+            "position distribution {:?} gives chi-squared {}",
+            position_counts,
+            chi_squared
+        );
+    }
+}
@@ -0,0 +1,13 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+pub mod duplicate_detection;
+pub mod handshake;
+pub mod live_cores_package;
+pub mod metrics;
+pub mod mixnet_pool;
+pub mod numa_pool;
+pub mod recent_forwards;
+pub mod replay_window;
+pub mod route_guard;
+pub mod transmit_tracker;
+pub mod wire_frame;
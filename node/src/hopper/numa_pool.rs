@@ -0,0 +1,277 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! NUMA-aware worker pool for package processing. On multi-socket hosts
+//! the hopper's workers used to migrate freely, so the cache lines behind
+//! a busy next-hop's stream state bounced between NUMA nodes. The pool
+//! now pins one worker per NUMA node (topology read through hwloc2) and
+//! shards incoming LiveCoresPackages by a hash of their first encrypted
+//! hop: everything bound for the same next hop lands on the same
+//! NUMA-local worker. Hosts with a single node — or without hwloc — get
+//! one unpinned worker, which is the old behavior.
+
+use crate::hopper::live_cores_package::LiveCoresPackage;
+use crate::sub_lib::logger::Logger;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// Topology discovery and thread pinning, behind a trait so tests can
+/// simulate a multi-node host on whatever machine CI happens to be.
+pub trait NumaTopology: Send + Sync {
+    /// Number of NUMA nodes; 1 on hosts where detection fails.
+    fn node_count(&self) -> usize;
+    /// Pins the calling thread to the given node's CPUs.
+    fn pin_current_thread(&self, node_index: usize) -> Result<(), String>;
+}
+
+pub struct NumaTopologyReal {}
+
+impl NumaTopology for NumaTopologyReal {
+    fn node_count(&self) -> usize {
+        match hwloc2::Topology::new() {
+            Some(topology) => {
+                let nodes = topology
+                    .objects_with_type(&hwloc2::ObjectType::NUMANode)
+                    .map(|objects| objects.len())
+                    .unwrap_or(0);
+                nodes.max(1)
+            }
+            None => 1,
+        }
+    }
+
+    fn pin_current_thread(&self, node_index: usize) -> Result<(), String> {
+        let mut topology =
+            hwloc2::Topology::new().ok_or_else(|| "hwloc topology unavailable".to_string())?;
+        let nodes = topology
+            .objects_with_type(&hwloc2::ObjectType::NUMANode)
+            .map_err(|e| format!("{:?}", e))?;
+        let node = nodes
+            .get(node_index)
+            .ok_or_else(|| format!("no NUMA node {}", node_index))?;
+        let cpuset = node
+            .cpuset()
+            .ok_or_else(|| format!("NUMA node {} has no cpuset", node_index))?;
+        topology
+            .set_cpubind(cpuset, hwloc2::CpuBindFlags::CPUBIND_THREAD)
+            .map_err(|e| format!("{:?}", e))
+    }
+}
+
+/// One worker per NUMA node, each draining its own channel. The handler
+/// runs on the worker thread; dropping the pool closes the channels and
+/// joins the workers.
+pub struct NumaWorkerPool {
+    senders: Vec<Sender<LiveCoresPackage>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl NumaWorkerPool {
+    pub fn new<F>(topology: Arc<dyn NumaTopology>, handler: F) -> NumaWorkerPool
+    where
+        F: Fn(LiveCoresPackage) + Send + Sync + Clone + 'static,
+    {
+        let node_count = topology.node_count();
+        let mut senders = vec![];
+        let mut workers = vec![];
+        for node_index in 0..node_count {
+            let (sender, receiver) = channel::<LiveCoresPackage>();
+            let handler = handler.clone();
+            let topology = topology.clone();
+            workers.push(std::thread::spawn(move || {
+                // Pinning happens on the worker itself; a host that cannot
+                // pin still processes, just without locality.
+                if node_count > 1 {
+                    if let Err(error) = topology.pin_current_thread(node_index) {
+                        Logger::new("Hopper").warning(format!(
+                            "Could not pin worker {} to its NUMA node ({}); running unpinned",
+                            node_index, error
+                        ));
+                    }
+                }
+                while let Ok(package) = receiver.recv() {
+                    handler(package);
+                }
+            }));
+            senders.push(sender);
+        }
+        NumaWorkerPool { senders, workers }
+    }
+
+    pub fn worker_count(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// The shard a package belongs to: FNV-1a over its first encrypted
+    /// hop, so all traffic toward one next hop shares a worker (and its
+    /// cache lines).
+    pub fn shard_for(&self, package: &LiveCoresPackage) -> usize {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        if let Some(first_hop) = package.route.hops().first() {
+            for byte in first_hop.as_slice() {
+                hash ^= u64::from(*byte);
+                hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            }
+        }
+        (hash % self.senders.len() as u64) as usize
+    }
+
+    /// Hands the package to its shard's worker.
+    pub fn dispatch(&self, package: LiveCoresPackage) {
+        let shard = self.shard_for(&package);
+        self.senders[shard]
+            .send(package)
+            .expect("worker thread died");
+    }
+}
+
+impl Drop for NumaWorkerPool {
+    fn drop(&mut self) {
+        self.senders.clear();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sub_lib::cryptde::CryptData;
+    use crate::sub_lib::route::Route;
+    use std::sync::{Arc, Mutex};
+
+    struct NumaTopologyMock {
+        node_count: usize,
+        pin_params: Arc<Mutex<Vec<usize>>>,
+        pin_result: Result<(), String>,
+    }
+
+    impl NumaTopologyMock {
+        fn new(node_count: usize) -> NumaTopologyMock {
+            NumaTopologyMock {
+                node_count,
+                pin_params: Arc::new(Mutex::new(vec![])),
+                pin_result: Ok(()),
+            }
+        }
+
+        fn pin_params(mut self, params: &Arc<Mutex<Vec<usize>>>) -> NumaTopologyMock {
+            self.pin_params = params.clone();
+            self
+        }
+
+        fn pin_result(mut self, result: Result<(), String>) -> NumaTopologyMock {
+            self.pin_result = result;
+            self
+        }
+    }
+
+    impl NumaTopology for NumaTopologyMock {
+        fn node_count(&self) -> usize {
+            self.node_count
+        }
+
+        fn pin_current_thread(&self, node_index: usize) -> Result<(), String> {
+            self.pin_params.lock().unwrap().push(node_index);
+            self.pin_result.clone()
+        }
+    }
+
+    fn package_toward(first_hop: &[u8]) -> LiveCoresPackage {
+        LiveCoresPackage::new(
+            Route::new(vec![CryptData::new(first_hop)]),
+            CryptData::new(b"payload"),
+        )
+    }
+
+    #[test]
+    fn one_worker_per_numa_node_each_pinned() {
+        let pin_params = Arc::new(Mutex::new(vec![]));
+        let topology = NumaTopologyMock::new(2).pin_params(&pin_params);
+
+        let subject = NumaWorkerPool::new(Arc::new(topology), |_| {});
+
+        drop(subject); // workers have pinned (or tried) by the time they join
+        let mut pins = pin_params.lock().unwrap().clone();
+        pins.sort_unstable();
+        assert_eq!(pins, vec![0, 1]);
+    }
+
+    #[test]
+    fn a_single_node_host_gets_one_unpinned_worker() {
+        let pin_params = Arc::new(Mutex::new(vec![]));
+        let topology = NumaTopologyMock::new(1).pin_params(&pin_params);
+
+        let subject = NumaWorkerPool::new(Arc::new(topology), |_| {});
+
+        assert_eq!(subject.worker_count(), 1);
+        assert!(pin_params.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_failed_pin_still_yields_a_working_pool() {
+        let topology =
+            NumaTopologyMock::new(2).pin_result(Err("hwloc topology unavailable".to_string()));
+        let processed = Arc::new(Mutex::new(vec![]));
+        let processed_in_handler = processed.clone();
+        let subject = NumaWorkerPool::new(Arc::new(topology), move |package: LiveCoresPackage| {
+            processed_in_handler
+                .lock()
+                .unwrap()
+                .push(package.payload.as_slice().to_vec());
+        });
+
+        subject.dispatch(package_toward(b"next_hop"));
+        drop(subject); // joins the workers, so the dispatch has landed
+
+        assert_eq!(*processed.lock().unwrap(), vec![b"payload".to_vec()]);
+    }
+
+    #[test]
+    fn packages_toward_the_same_next_hop_share_a_shard() {
+        let subject = NumaWorkerPool::new(Arc::new(NumaTopologyMock::new(4)), |_| {});
+
+        let first = subject.shard_for(&package_toward(b"next_hop_alpha"));
+        let second = subject.shard_for(&package_toward(b"next_hop_alpha"));
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn different_next_hops_spread_across_shards() {
+        let subject = NumaWorkerPool::new(Arc::new(NumaTopologyMock::new(4)), |_| {});
+
+        let shards: std::collections::HashSet<usize> = (0..64u32)
+            .map(|i| subject.shard_for(&package_toward(&i.to_be_bytes())))
+            .collect();
+
+        assert!(
+            shards.len() > 1,
+            "64 distinct next hops all landed on one shard"
+        );
+    }
+
+    #[test]
+    fn every_dispatched_package_is_processed_exactly_once() {
+        let processed = Arc::new(Mutex::new(vec![]));
+        let processed_in_handler = processed.clone();
+        let subject = NumaWorkerPool::new(Arc::new(NumaTopologyMock::new(2)), move |package| {
+            processed_in_handler
+                .lock()
+                .unwrap()
+                .push(package.payload.as_slice().to_vec());
+        });
+
+        for i in 0..20u8 {
+            subject.dispatch(package_toward(&[i]));
+        }
+        drop(subject);
+
+        let mut payloads = processed.lock().unwrap().clone();
+        payloads.sort();
+        assert_eq!(payloads.len(), 20);
+        payloads.dedup();
+        assert_eq!(payloads.len(), 20, "a package was processed twice");
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! A short-lived index of which streams recently went out through which
+//! neighbor. When the dispatcher reports a NeighborStreamLostMessage, the
+//! hopper consults this to tell ProxyServer which streams should retry their
+//! routes right away instead of starving until their timeouts fire.
+//!
+//! The index is bounded and time-decayed: entries expire after a TTL and the
+//! oldest are evicted when the capacity is hit.
+
+use crate::sub_lib::cryptde::PublicKey;
+use crate::sub_lib::stream_key::StreamKey;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_FORWARD_INDEX_CAPACITY: usize = 4096;
+pub const DEFAULT_FORWARD_INDEX_TTL: Duration = Duration::from_secs(30);
+
+struct ForwardEntry {
+    recorded_at: Instant,
+    next_hop_key: PublicKey,
+    stream_key: StreamKey,
+}
+
+pub struct RecentForwardIndex {
+    capacity: usize,
+    ttl: Duration,
+    entries: VecDeque<ForwardEntry>,
+}
+
+impl RecentForwardIndex {
+    pub fn new(capacity: usize, ttl: Duration) -> RecentForwardIndex {
+        RecentForwardIndex {
+            capacity,
+            ttl,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Called on every forwarded package carrying stream traffic.
+    pub fn record(&mut self, next_hop_key: &PublicKey, stream_key: StreamKey, now: Instant) {
+        self.prune(now);
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(ForwardEntry {
+            recorded_at: now,
+            next_hop_key: next_hop_key.clone(),
+            stream_key,
+        });
+    }
+
+    /// Streams whose traffic recently went through the lost neighbor,
+    /// deduplicated; these get the immediate route-retry nudge.
+    pub fn affected_streams(&mut self, lost_neighbor: &PublicKey, now: Instant) -> Vec<StreamKey> {
+        self.prune(now);
+        let mut affected: Vec<StreamKey> = vec![];
+        for entry in &self.entries {
+            if &entry.next_hop_key == lost_neighbor && !affected.contains(&entry.stream_key) {
+                affected.push(entry.stream_key);
+            }
+        }
+        affected
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn prune(&mut self, now: Instant) {
+        let ttl = self.ttl;
+        while let Some(front) = self.entries.front() {
+            if now.duration_since(front.recorded_at) >= ttl {
+                self.entries.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+impl Default for RecentForwardIndex {
+    fn default() -> Self {
+        Self::new(DEFAULT_FORWARD_INDEX_CAPACITY, DEFAULT_FORWARD_INDEX_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lost_neighbor_yields_only_its_streams() {
+        let mut subject = RecentForwardIndex::default();
+        let now = Instant::now();
+        let lost = PublicKey::new(b"lost_neighbor");
+        let healthy = PublicKey::new(b"healthy_neighbor");
+        subject.record(&lost, StreamKey::make_meaningless(1), now);
+        subject.record(&healthy, StreamKey::make_meaningless(2), now);
+        subject.record(&lost, StreamKey::make_meaningless(3), now);
+        subject.record(&lost, StreamKey::make_meaningless(1), now); // duplicate
+
+        let affected = subject.affected_streams(&lost, now);
+
+        assert_eq!(
+            affected,
+            vec![StreamKey::make_meaningless(1), StreamKey::make_meaningless(3)]
+        );
+    }
+
+    #[test]
+    fn entries_decay_after_the_ttl() {
+        let mut subject = RecentForwardIndex::new(16, Duration::from_secs(30));
+        let now = Instant::now();
+        let neighbor = PublicKey::new(b"neighbor");
+        subject.record(&neighbor, StreamKey::make_meaningless(1), now);
+
+        let affected = subject.affected_streams(&neighbor, now + Duration::from_secs(30));
+
+        assert!(affected.is_empty());
+        assert!(subject.is_empty());
+    }
+
+    #[test]
+    fn capacity_bound_evicts_the_oldest() {
+        let mut subject = RecentForwardIndex::new(2, Duration::from_secs(30));
+        let now = Instant::now();
+        let neighbor = PublicKey::new(b"neighbor");
+        subject.record(&neighbor, StreamKey::make_meaningless(1), now);
+        subject.record(&neighbor, StreamKey::make_meaningless(2), now);
+        subject.record(&neighbor, StreamKey::make_meaningless(3), now);
+
+        let affected = subject.affected_streams(&neighbor, now);
+
+        assert_eq!(subject.len(), 2);
+        assert_eq!(
+            affected,
+            vec![StreamKey::make_meaningless(2), StreamKey::make_meaningless(3)]
+        );
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Replay protection for relayed packages. A captured package replayed
+//! later would pass every cryptographic check — the tag is genuine, just
+//! old — so the defense is bookkeeping: each sending neighbor stamps its
+//! link frames with a monotonic sequence number, and the receiver keeps
+//! a 64-entry sliding window per sender (highest sequence seen plus a
+//! bit mask of the 64 below it). A sequence already marked is a replay;
+//! one within the window is accepted once and marked; one ahead of the
+//! window slides it forward. Anything older than the window's reach is
+//! treated as a replay too — legitimate traffic is never 64 frames
+//! stale on one link. Unlike the Bloom-based loop detector next door,
+//! this check is exact: no false positives, no aging out.
+
+use crate::sub_lib::cryptde::PublicKey;
+use crate::sub_lib::route::RouteError;
+use std::collections::HashMap;
+
+/// How many sequence numbers behind the highest are still acceptable:
+/// the width of the bit mask.
+pub const WINDOW_BITS: u64 = 64;
+
+/// Per-sender sliding windows: highest sequence seen, and a mask whose
+/// bit n records whether (highest - 1 - n) arrived.
+#[derive(Default)]
+pub struct ReplayWindow {
+    windows: HashMap<PublicKey, (u64, u64)>,
+}
+
+impl ReplayWindow {
+    pub fn new() -> ReplayWindow {
+        Self::default()
+    }
+
+    /// Checks a sender's sequence number against its window and records
+    /// it. Ok means first sighting; Err(ReplayDetected) means this
+    /// sequence was already delivered (or is too old to tell).
+    pub fn check_and_record(
+        &mut self,
+        sender: &PublicKey,
+        sequence: u64,
+    ) -> Result<(), RouteError> {
+        let (highest, mask) = match self.windows.get_mut(sender) {
+            Some(window) => window,
+            None => {
+                // First frame from this sender establishes the window.
+                self.windows.insert(sender.clone(), (sequence, 0));
+                return Ok(());
+            }
+        };
+        if sequence > *highest {
+            let advance = sequence - *highest;
+            *mask = if advance > WINDOW_BITS {
+                0
+            } else if advance == WINDOW_BITS {
+                // Only the old highest survives, at the window's far edge;
+                // shifting by the full width would be UB.
+                1u64 << (WINDOW_BITS - 1)
+            } else {
+                // The old highest becomes bit (advance - 1).
+                (*mask << advance) | (1u64 << (advance - 1))
+            };
+            *highest = sequence;
+            return Ok(());
+        }
+        if sequence == *highest {
+            return Err(RouteError::ReplayDetected { sequence });
+        }
+        let age = *highest - sequence;
+        if age > WINDOW_BITS {
+            // Too old to have a bit; refusing is the safe side.
+            return Err(RouteError::ReplayDetected { sequence });
+        }
+        let bit = 1u64 << (age - 1);
+        if *mask & bit != 0 {
+            return Err(RouteError::ReplayDetected { sequence });
+        }
+        *mask |= bit;
+        Ok(())
+    }
+
+    /// Drops a sender's window when the neighbor connection ends; a
+    /// reconnecting neighbor starts a fresh sequence space.
+    pub fn forget_sender(&mut self, sender: &PublicKey) {
+        self.windows.remove(sender);
+    }
+
+    pub fn tracked_sender_count(&self) -> usize {
+        self.windows.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sender() -> PublicKey {
+        PublicKey::new(b"neighbor")
+    }
+
+    #[test]
+    fn a_replayed_sequence_is_detected() {
+        let mut subject = ReplayWindow::new();
+        subject.check_and_record(&sender(), 5).unwrap();
+
+        let result = subject.check_and_record(&sender(), 5);
+
+        assert_eq!(result, Err(RouteError::ReplayDetected { sequence: 5 }));
+    }
+
+    #[test]
+    fn reordered_delivery_within_the_window_is_accepted_exactly_once() {
+        let mut subject = ReplayWindow::new();
+        for sequence in [10, 13, 11, 12] {
+            subject.check_and_record(&sender(), sequence).unwrap();
+        }
+
+        for sequence in [10, 11, 12, 13] {
+            let result = subject.check_and_record(&sender(), sequence);
+
+            assert_eq!(
+                result,
+                Err(RouteError::ReplayDetected { sequence }),
+                "sequence {} accepted twice",
+                sequence
+            );
+        }
+    }
+
+    #[test]
+    fn a_sequence_older_than_the_window_is_refused() {
+        let mut subject = ReplayWindow::new();
+        subject.check_and_record(&sender(), 1).unwrap();
+        subject.check_and_record(&sender(), 1 + WINDOW_BITS + 10).unwrap();
+
+        let result = subject.check_and_record(&sender(), 1);
+
+        assert_eq!(result, Err(RouteError::ReplayDetected { sequence: 1 }));
+    }
+
+    #[test]
+    fn a_full_width_jump_still_remembers_the_old_highest() {
+        let mut subject = ReplayWindow::new();
+        subject.check_and_record(&sender(), 10).unwrap();
+
+        subject.check_and_record(&sender(), 10 + WINDOW_BITS).unwrap();
+
+        // The old highest sits exactly at the window's far edge and must
+        // still be recognized as delivered.
+        assert_eq!(
+            subject.check_and_record(&sender(), 10),
+            Err(RouteError::ReplayDetected { sequence: 10 })
+        );
+        // A fresh in-window sequence is still fine.
+        assert_eq!(subject.check_and_record(&sender(), 11), Ok(()));
+    }
+
+    #[test]
+    fn senders_have_independent_windows() {
+        let mut subject = ReplayWindow::new();
+        let other = PublicKey::new(b"other_neighbor");
+        subject.check_and_record(&sender(), 7).unwrap();
+
+        assert_eq!(subject.check_and_record(&other, 7), Ok(()));
+        assert_eq!(subject.tracked_sender_count(), 2);
+    }
+
+    #[test]
+    fn a_forgotten_sender_starts_over() {
+        let mut subject = ReplayWindow::new();
+        subject.check_and_record(&sender(), 42).unwrap();
+
+        subject.forget_sender(&sender());
+
+        assert_eq!(subject.check_and_record(&sender(), 42), Ok(()));
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! The hopper's relay-path bound on route size. `to_next_live` already
+//! refuses routes with too many hops; this guard sits in front of it with
+//! the operator's configured limits, additionally bounds the serialized
+//! size of the route it will forward (a few huge hops are as hostile as
+//! many small ones), and logs which immediate neighbor sent the offending
+//! package so the operator can see who is misbehaving.
+
+use crate::hopper::live_cores_package::LiveCoresPackage;
+use crate::sub_lib::cryptde::PublicKey;
+use crate::sub_lib::logger::Logger;
+use crate::sub_lib::route::DEFAULT_MAX_HOPS;
+
+/// Generous for DEFAULT_MAX_HOPS of real hops, far below what a
+/// resource-exhaustion route needs.
+pub const DEFAULT_MAX_ROUTE_BYTES: usize = 8192;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RouteVerdict {
+    Forward,
+    /// Dropped without a response: a malformed route is not worth the
+    /// bandwidth of a complaint.
+    Drop,
+}
+
+pub struct RouteLengthGuard {
+    max_hops: usize,
+    max_route_bytes: usize,
+    logger: Logger,
+}
+
+impl Default for RouteLengthGuard {
+    fn default() -> RouteLengthGuard {
+        RouteLengthGuard::new(DEFAULT_MAX_HOPS, DEFAULT_MAX_ROUTE_BYTES)
+    }
+}
+
+impl RouteLengthGuard {
+    pub fn new(max_hops: usize, max_route_bytes: usize) -> RouteLengthGuard {
+        RouteLengthGuard {
+            max_hops,
+            max_route_bytes,
+            logger: Logger::new("Hopper"),
+        }
+    }
+
+    /// Called on every package received for relay, before any hop is
+    /// peeled. `immediate_neighbor` is the node the package arrived from —
+    /// not necessarily the originator, but the only party this relay can
+    /// name.
+    pub fn verdict_for(
+        &self,
+        package: &LiveCoresPackage,
+        immediate_neighbor: &PublicKey,
+    ) -> RouteVerdict {
+        let hops = package.route.hop_count();
+        if hops > self.max_hops {
+            self.logger.warning(format!(
+                "Dropping package from neighbor {:?}: route has {} hops (max {})",
+                immediate_neighbor, hops, self.max_hops
+            ));
+            return RouteVerdict::Drop;
+        }
+        let route_bytes: usize = package.route.hops().iter().map(|hop| hop.len()).sum();
+        if route_bytes > self.max_route_bytes {
+            self.logger.warning(format!(
+                "Dropping package from neighbor {:?}: serialized route is {} bytes (max {})",
+                immediate_neighbor, route_bytes, self.max_route_bytes
+            ));
+            return RouteVerdict::Drop;
+        }
+        RouteVerdict::Forward
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sub_lib::cryptde::CryptData;
+    use crate::sub_lib::route::Route;
+
+    fn make_package(hops: Vec<CryptData>) -> LiveCoresPackage {
+        LiveCoresPackage::new(Route::new(hops), CryptData::new(b"payload"))
+    }
+
+    #[test]
+    fn a_route_at_both_limits_is_forwarded() {
+        let subject = RouteLengthGuard::new(3, 30);
+        let package = make_package(vec![CryptData::new(&[0u8; 10]); 3]);
+
+        let verdict = subject.verdict_for(&package, &PublicKey::new(b"neighbor"));
+
+        assert_eq!(verdict, RouteVerdict::Forward);
+    }
+
+    #[test]
+    fn one_hop_too_many_is_dropped() {
+        let subject = RouteLengthGuard::new(3, 30);
+        let package = make_package(vec![CryptData::new(&[0u8; 1]); 4]);
+
+        let verdict = subject.verdict_for(&package, &PublicKey::new(b"neighbor"));
+
+        assert_eq!(verdict, RouteVerdict::Drop);
+    }
+
+    #[test]
+    fn a_few_huge_hops_are_dropped_too() {
+        let subject = RouteLengthGuard::new(3, 30);
+        let package = make_package(vec![CryptData::new(&[0u8; 31])]);
+
+        let verdict = subject.verdict_for(&package, &PublicKey::new(b"neighbor"));
+
+        assert_eq!(verdict, RouteVerdict::Drop);
+    }
+
+    #[test]
+    fn the_default_guard_matches_the_route_constants() {
+        let subject = RouteLengthGuard::default();
+        let at_limit = make_package(vec![CryptData::new(&[0u8; 1]); DEFAULT_MAX_HOPS]);
+        let over_limit = make_package(vec![CryptData::new(&[0u8; 1]); DEFAULT_MAX_HOPS + 1]);
+
+        let neighbor = PublicKey::new(b"neighbor");
+        assert_eq!(subject.verdict_for(&at_limit, &neighbor), RouteVerdict::Forward);
+        assert_eq!(subject.verdict_for(&over_limit, &neighbor), RouteVerdict::Drop);
+    }
+}
@@ -0,0 +1,287 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! In-flight accounting for packages the hopper hands to the dispatcher.
+//! Each transmission gets a correlation id; the dispatcher reports the
+//! stream writer's outcome back as a TransmitResultMsg. On failure the
+//! hopper retries through an alternate connection to the same next hop
+//! when one exists, and otherwise raises route-failure feedback so the
+//! origin rebuilds its route. The map is bounded: when it fills, the
+//! oldest entry is evicted and counted as presumed success, which keeps a
+//! dispatcher that never reports from growing the hopper without bound.
+
+use crate::sub_lib::cryptde::PublicKey;
+use crate::sub_lib::dispatcher::TransmitResultMsg;
+use crate::sub_lib::logger::Logger;
+use std::collections::VecDeque;
+
+pub const DEFAULT_IN_FLIGHT_CAPACITY: usize = 1024;
+
+struct InFlightEntry {
+    correlation_id: u64,
+    next_hop_key: PublicKey,
+    data: Vec<u8>,
+}
+
+/// What the hopper should do about a failed transmission.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FailureDisposition {
+    /// The same next hop is reachable through another live connection;
+    /// resend the package there under a fresh correlation id.
+    RetryViaAlternate {
+        next_hop_key: PublicKey,
+        data: Vec<u8>,
+    },
+    /// No alternate path to the next hop; the origin needs to know the
+    /// route is broken.
+    ReportRouteFailure { failed_neighbor: PublicKey },
+}
+
+pub struct InFlightTracker {
+    capacity: usize,
+    next_correlation_id: u64,
+    entries: VecDeque<InFlightEntry>,
+    presumed_successes: u64,
+    logger: Logger,
+}
+
+impl InFlightTracker {
+    pub fn new(capacity: usize) -> InFlightTracker {
+        InFlightTracker {
+            capacity,
+            next_correlation_id: 0,
+            entries: VecDeque::new(),
+            presumed_successes: 0,
+            logger: Logger::new("Hopper"),
+        }
+    }
+
+    /// Registers a transmission about to go to the dispatcher; the returned
+    /// correlation id travels with the TransmitDataMsg. A full map evicts
+    /// its oldest entry as presumed success first.
+    pub fn register(&mut self, next_hop_key: &PublicKey, data: Vec<u8>) -> u64 {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+            self.presumed_successes += 1;
+        }
+        let correlation_id = self.next_correlation_id;
+        self.next_correlation_id += 1;
+        self.entries.push_back(InFlightEntry {
+            correlation_id,
+            next_hop_key: next_hop_key.clone(),
+            data,
+        });
+        correlation_id
+    }
+
+    /// Handles the dispatcher's report. Ok(_) and reports for entries that
+    /// were already evicted resolve to None; a failure for a tracked entry
+    /// yields the retry-or-feedback disposition, consulting the currently
+    /// connected neighbors for an alternate path.
+    pub fn on_result(
+        &mut self,
+        msg: TransmitResultMsg,
+        connected_neighbors: &[PublicKey],
+    ) -> Option<FailureDisposition> {
+        let position = self
+            .entries
+            .iter()
+            .position(|entry| entry.correlation_id == msg.correlation_id)?;
+        let entry = self.entries.remove(position).expect("entry vanished");
+        let error = match msg.result {
+            Ok(()) => return None,
+            Err(error) => error,
+        };
+        let alternate_available = connected_neighbors
+            .iter()
+            .any(|neighbor| neighbor == &entry.next_hop_key);
+        if alternate_available {
+            self.logger.warning(format!(
+                "Transmission {} to {} failed ({}); retrying via alternate connection",
+                msg.correlation_id, entry.next_hop_key, error
+            ));
+            Some(FailureDisposition::RetryViaAlternate {
+                next_hop_key: entry.next_hop_key,
+                data: entry.data,
+            })
+        } else {
+            self.logger.warning(format!(
+                "Transmission {} to {} failed ({}); no alternate connection, reporting route failure",
+                msg.correlation_id, entry.next_hop_key, error
+            ));
+            Some(FailureDisposition::ReportRouteFailure {
+                failed_neighbor: entry.next_hop_key,
+            })
+        }
+    }
+
+    pub fn in_flight_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Evictions forced by the capacity bound, kept for the metrics report.
+    pub fn presumed_success_count(&self) -> u64 {
+        self.presumed_successes
+    }
+}
+
+impl Default for InFlightTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_IN_FLIGHT_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    /// Stands in for the dispatcher's stream writer: attempts the write and
+    /// turns the outcome into the report the hopper consumes.
+    fn transmit_and_report(
+        writer: &mut dyn Write,
+        correlation_id: u64,
+        peer_key: &PublicKey,
+        data: &[u8],
+    ) -> TransmitResultMsg {
+        TransmitResultMsg {
+            correlation_id,
+            peer_key: peer_key.clone(),
+            result: writer
+                .write_all(data)
+                .map_err(|error| error.to_string()),
+        }
+    }
+
+    struct StreamWriterMock {
+        write_params: Arc<Mutex<Vec<Vec<u8>>>>,
+        write_results: Mutex<Vec<io::Result<()>>>,
+    }
+
+    impl Write for StreamWriterMock {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.write_params.lock().unwrap().push(buf.to_vec());
+            self.write_results
+                .lock()
+                .unwrap()
+                .remove(0)
+                .map(|()| buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl StreamWriterMock {
+        fn new() -> StreamWriterMock {
+            StreamWriterMock {
+                write_params: Arc::new(Mutex::new(vec![])),
+                write_results: Mutex::new(vec![]),
+            }
+        }
+
+        fn write_params(mut self, params: &Arc<Mutex<Vec<Vec<u8>>>>) -> StreamWriterMock {
+            self.write_params = params.clone();
+            self
+        }
+
+        fn write_result(self, result: io::Result<()>) -> StreamWriterMock {
+            self.write_results.lock().unwrap().push(result);
+            self
+        }
+    }
+
+    #[test]
+    fn a_successful_transmission_clears_its_entry() {
+        let mut subject = InFlightTracker::default();
+        let neighbor = PublicKey::new(b"neighbor");
+        let mut writer = StreamWriterMock::new().write_result(Ok(()));
+        let correlation_id = subject.register(&neighbor, b"package".to_vec());
+
+        let report = transmit_and_report(&mut writer, correlation_id, &neighbor, b"package");
+        let disposition = subject.on_result(report, &[neighbor]);
+
+        assert_eq!(disposition, None);
+        assert_eq!(subject.in_flight_count(), 0);
+    }
+
+    #[test]
+    fn a_failed_transmission_retries_when_an_alternate_connection_exists() {
+        let mut subject = InFlightTracker::default();
+        let neighbor = PublicKey::new(b"neighbor");
+        let write_params = Arc::new(Mutex::new(vec![]));
+        let mut writer = StreamWriterMock::new()
+            .write_params(&write_params)
+            .write_result(Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe")));
+        let correlation_id = subject.register(&neighbor, b"package".to_vec());
+
+        let report = transmit_and_report(&mut writer, correlation_id, &neighbor, b"package");
+        let disposition = subject.on_result(report, &[neighbor.clone()]);
+
+        assert_eq!(
+            disposition,
+            Some(FailureDisposition::RetryViaAlternate {
+                next_hop_key: neighbor,
+                data: b"package".to_vec(),
+            })
+        );
+        assert_eq!(*write_params.lock().unwrap(), vec![b"package".to_vec()]);
+        assert_eq!(subject.in_flight_count(), 0);
+    }
+
+    #[test]
+    fn a_failed_transmission_reports_route_failure_without_an_alternate() {
+        let mut subject = InFlightTracker::default();
+        let neighbor = PublicKey::new(b"neighbor");
+        let other = PublicKey::new(b"other_neighbor");
+        let mut writer = StreamWriterMock::new()
+            .write_result(Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe")));
+        let correlation_id = subject.register(&neighbor, b"package".to_vec());
+
+        let report = transmit_and_report(&mut writer, correlation_id, &neighbor, b"package");
+        let disposition = subject.on_result(report, &[other]);
+
+        assert_eq!(
+            disposition,
+            Some(FailureDisposition::ReportRouteFailure {
+                failed_neighbor: neighbor,
+            })
+        );
+    }
+
+    #[test]
+    fn a_full_map_evicts_the_oldest_entry_as_presumed_success() {
+        let mut subject = InFlightTracker::new(2);
+        let neighbor = PublicKey::new(b"neighbor");
+        let first_id = subject.register(&neighbor, b"first".to_vec());
+        subject.register(&neighbor, b"second".to_vec());
+
+        subject.register(&neighbor, b"third".to_vec());
+        let late_report = subject.on_result(
+            TransmitResultMsg {
+                correlation_id: first_id,
+                peer_key: neighbor.clone(),
+                result: Err("broken pipe".to_string()),
+            },
+            &[neighbor],
+        );
+
+        assert_eq!(subject.in_flight_count(), 2);
+        assert_eq!(subject.presumed_success_count(), 1);
+        assert_eq!(late_report, None);
+    }
+
+    #[test]
+    fn correlation_ids_are_never_reused() {
+        let mut subject = InFlightTracker::new(2);
+        let neighbor = PublicKey::new(b"neighbor");
+
+        let ids: Vec<u64> = (0..5)
+            .map(|_| subject.register(&neighbor, vec![]))
+            .collect();
+
+        assert_eq!(ids, vec![0, 1, 2, 3, 4]);
+    }
+}
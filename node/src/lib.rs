@@ -0,0 +1,19 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+pub mod accountant;
+pub mod actor_system_factory;
+pub mod blockchain_bridge;
+pub mod bootstrap_error;
+pub mod database;
+pub mod hopper;
+pub mod meek_client;
+pub mod metrics;
+pub mod neighborhood;
+pub mod node_configurator;
+pub mod obfs4_bridge;
+pub mod proxy_client;
+pub mod proxy_server;
+pub mod stream_handler_pool;
+pub mod sub_lib;
+pub mod telemetry;
+pub mod ui_gateway;
@@ -0,0 +1,311 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Meek pluggable transport, client side. Each encrypted LiveCoresPackage
+//! travels as the body of an HTTPS POST to a CDN edge (`front_url`) whose
+//! Host header names the real bridge (`back_url`) — domain fronting. The
+//! bridge queues return traffic, which the client collects by polling with
+//! GETs on the same session. HTTP itself goes through a trait so tests can
+//! stand in for the CDN.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const SESSION_HEADER: &str = "X-Session-Id";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MeekError {
+    TransportFailure(String),
+    /// The bridge answered with a non-success status.
+    BridgeRejected(u16),
+}
+
+/// The slice of HTTP the meek client needs; the real implementation rides
+/// reqwest's blocking client, the mock scripts responses.
+pub trait HttpTransport: Send {
+    /// POSTs `body` to `url` with the given Host header value and session
+    /// id; returns the response status.
+    fn post(&self, url: &str, host: &str, session_id: &str, body: &[u8])
+        -> Result<u16, MeekError>;
+    /// GETs from `url` with the given Host header value and session id;
+    /// returns the status and response body.
+    fn get(&self, url: &str, host: &str, session_id: &str) -> Result<(u16, Vec<u8>), MeekError>;
+}
+
+pub struct HttpTransportReal {
+    client: reqwest::blocking::Client,
+}
+
+impl HttpTransportReal {
+    pub fn new() -> HttpTransportReal {
+        HttpTransportReal {
+            client: reqwest::blocking::Client::new(),
+        }
+    }
+}
+
+impl Default for HttpTransportReal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpTransport for HttpTransportReal {
+    fn post(
+        &self,
+        url: &str,
+        host: &str,
+        session_id: &str,
+        body: &[u8],
+    ) -> Result<u16, MeekError> {
+        let response = self
+            .client
+            .post(url)
+            .header("Host", host)
+            .header(SESSION_HEADER, session_id)
+            .body(body.to_vec())
+            .send()
+            .map_err(|e| MeekError::TransportFailure(format!("{}", e)))?;
+        Ok(response.status().as_u16())
+    }
+
+    fn get(&self, url: &str, host: &str, session_id: &str) -> Result<(u16, Vec<u8>), MeekError> {
+        let response = self
+            .client
+            .get(url)
+            .header("Host", host)
+            .header(SESSION_HEADER, session_id)
+            .send()
+            .map_err(|e| MeekError::TransportFailure(format!("{}", e)))?;
+        let status = response.status().as_u16();
+        let body = response
+            .bytes()
+            .map_err(|e| MeekError::TransportFailure(format!("{}", e)))?
+            .to_vec();
+        Ok((status, body))
+    }
+}
+
+/// Sends encrypted packages through the front and polls for the bridge's
+/// queued responses.
+pub struct MeekClient {
+    transport: Box<dyn HttpTransport>,
+    front_url: String,
+    back_url: String,
+    session_id: String,
+}
+
+/// Session ids only need to be unique per client process.
+static NEXT_SESSION: AtomicU64 = AtomicU64::new(1);
+
+impl MeekClient {
+    pub fn new(transport: Box<dyn HttpTransport>, front_url: String, back_url: String) -> MeekClient {
+        let session_id = format!(
+            "{:016x}",
+            NEXT_SESSION.fetch_add(1, Ordering::Relaxed) ^ 0x436c_616e_6465_7374
+        );
+        MeekClient {
+            transport,
+            front_url,
+            back_url,
+            session_id,
+        }
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Ships one serialized, already-encrypted package to the bridge.
+    pub fn send_package(&self, serialized_package: &[u8]) -> Result<(), MeekError> {
+        let status = self.transport.post(
+            &self.front_url,
+            &self.back_url,
+            &self.session_id,
+            serialized_package,
+        )?;
+        if (200..300).contains(&status) {
+            Ok(())
+        } else {
+            Err(MeekError::BridgeRejected(status))
+        }
+    }
+
+    /// One poll for queued return traffic. An empty body means the bridge
+    /// had nothing for us this round.
+    pub fn poll_responses(&self) -> Result<Option<Vec<u8>>, MeekError> {
+        let (status, body) = self
+            .transport
+            .get(&self.front_url, &self.back_url, &self.session_id)?;
+        if !(200..300).contains(&status) {
+            return Err(MeekError::BridgeRejected(status));
+        }
+        if body.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(body))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    type PostParams = Arc<Mutex<Vec<(String, String, String, Vec<u8>)>>>;
+
+    struct HttpTransportMock {
+        post_params: PostParams,
+        post_results: Mutex<Vec<Result<u16, MeekError>>>,
+        get_params: Arc<Mutex<Vec<(String, String, String)>>>,
+        get_results: Mutex<Vec<Result<(u16, Vec<u8>), MeekError>>>,
+    }
+
+    impl HttpTransportMock {
+        fn new() -> HttpTransportMock {
+            HttpTransportMock {
+                post_params: Arc::new(Mutex::new(vec![])),
+                post_results: Mutex::new(vec![]),
+                get_params: Arc::new(Mutex::new(vec![])),
+                get_results: Mutex::new(vec![]),
+            }
+        }
+
+        fn post_params(mut self, params: &PostParams) -> HttpTransportMock {
+            self.post_params = params.clone();
+            self
+        }
+
+        fn post_result(self, result: Result<u16, MeekError>) -> HttpTransportMock {
+            self.post_results.lock().unwrap().push(result);
+            self
+        }
+
+        fn get_params(
+            mut self,
+            params: &Arc<Mutex<Vec<(String, String, String)>>>,
+        ) -> HttpTransportMock {
+            self.get_params = params.clone();
+            self
+        }
+
+        fn get_result(self, result: Result<(u16, Vec<u8>), MeekError>) -> HttpTransportMock {
+            self.get_results.lock().unwrap().push(result);
+            self
+        }
+    }
+
+    impl HttpTransport for HttpTransportMock {
+        fn post(
+            &self,
+            url: &str,
+            host: &str,
+            session_id: &str,
+            body: &[u8],
+        ) -> Result<u16, MeekError> {
+            self.post_params.lock().unwrap().push((
+                url.to_string(),
+                host.to_string(),
+                session_id.to_string(),
+                body.to_vec(),
+            ));
+            self.post_results.lock().unwrap().remove(0)
+        }
+
+        fn get(
+            &self,
+            url: &str,
+            host: &str,
+            session_id: &str,
+        ) -> Result<(u16, Vec<u8>), MeekError> {
+            self.get_params.lock().unwrap().push((
+                url.to_string(),
+                host.to_string(),
+                session_id.to_string(),
+            ));
+            self.get_results.lock().unwrap().remove(0)
+        }
+    }
+
+    fn make_subject(transport: HttpTransportMock) -> MeekClient {
+        MeekClient::new(
+            Box::new(transport),
+            "https://cdn.example.com/".to_string(),
+            "bridge.clandesti.net".to_string(),
+        )
+    }
+
+    #[test]
+    fn packages_are_posted_to_the_front_with_the_back_host_header() {
+        let post_params = Arc::new(Mutex::new(vec![]));
+        let transport = HttpTransportMock::new()
+            .post_params(&post_params)
+            .post_result(Ok(200));
+        let subject = make_subject(transport);
+
+        subject.send_package(b"encrypted live cores package").unwrap();
+
+        let params = post_params.lock().unwrap();
+        let (url, host, session_id, body) = &params[0];
+        assert_eq!(url, "https://cdn.example.com/");
+        assert_eq!(host, "bridge.clandesti.net");
+        assert_eq!(session_id, subject.session_id());
+        assert_eq!(body, b"encrypted live cores package");
+    }
+
+    #[test]
+    fn a_rejecting_bridge_surfaces_the_status() {
+        let transport = HttpTransportMock::new().post_result(Ok(502));
+        let subject = make_subject(transport);
+
+        let result = subject.send_package(b"payload");
+
+        assert_eq!(result, Err(MeekError::BridgeRejected(502)));
+    }
+
+    #[test]
+    fn polling_returns_queued_response_bytes() {
+        let get_params = Arc::new(Mutex::new(vec![]));
+        let transport = HttpTransportMock::new()
+            .get_params(&get_params)
+            .get_result(Ok((200, b"queued response".to_vec())));
+        let subject = make_subject(transport);
+
+        let result = subject.poll_responses().unwrap();
+
+        assert_eq!(result, Some(b"queued response".to_vec()));
+        let params = get_params.lock().unwrap();
+        assert_eq!(params[0].1, "bridge.clandesti.net");
+    }
+
+    #[test]
+    fn an_empty_poll_means_no_traffic_yet() {
+        let transport = HttpTransportMock::new().get_result(Ok((200, vec![])));
+        let subject = make_subject(transport);
+
+        assert_eq!(subject.poll_responses(), Ok(None));
+    }
+
+    #[test]
+    fn transport_failures_pass_through() {
+        let transport = HttpTransportMock::new().post_result(Err(MeekError::TransportFailure(
+            "connection reset".to_string(),
+        )));
+        let subject = make_subject(transport);
+
+        let result = subject.send_package(b"payload");
+
+        assert_eq!(
+            result,
+            Err(MeekError::TransportFailure("connection reset".to_string()))
+        );
+    }
+
+    #[test]
+    fn each_client_gets_its_own_session_id() {
+        let first = make_subject(HttpTransportMock::new());
+        let second = make_subject(HttpTransportMock::new());
+
+        assert_ne!(first.session_id(), second.session_id());
+        assert_eq!(first.session_id().len(), 16);
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Optional Prometheus scrape endpoint. Disabled unless an operator
+//! configures a metrics port; when enabled it serves `GET /metrics` from
+//! the shared registry and nothing else, following the same
+//! single-document pattern as the PAC server.
+
+use crate::metrics::registry::MetricsRegistry;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+pub struct MetricsServer {
+    local_addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Binds the scrape listener and renders the registry fresh for every
+    /// request. Port 0 picks an ephemeral port, which tests rely on.
+    pub fn start(port: u16, registry: Arc<MetricsRegistry>) -> io::Result<MetricsServer> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let local_addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let thread_shutdown = shutdown.clone();
+        let handle = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if thread_shutdown.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let _ = serve_one(stream, &registry);
+                }
+            }
+        });
+        Ok(MetricsServer {
+            local_addr,
+            shutdown,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Unblock the accept loop so the thread notices the flag.
+        let _ = TcpStream::connect(self.local_addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn serve_one(mut stream: TcpStream, registry: &MetricsRegistry) -> io::Result<()> {
+    let mut request = [0u8; 1024];
+    let count = stream.read(&mut request)?;
+    let request_line = String::from_utf8_lossy(&request[..count]);
+    let response = if request_line.starts_with("GET /metrics ") {
+        let body = registry.render();
+        format!(
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\
+             \r\n\
+             {}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scrape(addr: SocketAddr) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn value_of(scrape_body: &str, series: &str) -> u64 {
+        scrape_body
+            .lines()
+            .find(|line| line.starts_with(series) && !line.starts_with('#'))
+            .unwrap_or_else(|| panic!("series {} missing from scrape", series))
+            .rsplit(' ')
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn scrape_after_a_workload_shows_monotonic_counters() {
+        let registry = Arc::new(MetricsRegistry::new());
+        let server = MetricsServer::start(0, registry.clone()).unwrap();
+        let routed = registry.counter("hopper_packages_routed_total", "CORES packages relayed");
+        let streams = registry.gauge("proxy_server_active_streams", "Streams currently open");
+
+        // Scripted zero-hop-style workload: some relaying, a stream opens.
+        routed.add(3);
+        streams.set(1);
+        let first = scrape(server.local_addr());
+        // More traffic, the stream closes.
+        routed.add(2);
+        streams.set(0);
+        let second = scrape(server.local_addr());
+
+        assert!(first.starts_with("HTTP/1.1 200 OK"));
+        assert!(first.contains("# TYPE hopper_packages_routed_total counter"));
+        assert_eq!(value_of(&first, "hopper_packages_routed_total"), 3);
+        assert_eq!(value_of(&second, "hopper_packages_routed_total"), 5);
+        assert!(
+            value_of(&second, "hopper_packages_routed_total")
+                >= value_of(&first, "hopper_packages_routed_total")
+        );
+        assert_eq!(value_of(&second, "proxy_server_active_streams"), 0);
+    }
+
+    #[test]
+    fn metrics_registered_after_startup_appear_on_the_next_scrape() {
+        let registry = Arc::new(MetricsRegistry::new());
+        let server = MetricsServer::start(0, registry.clone()).unwrap();
+
+        registry
+            .counter("accountant_charges_recorded_total", "Charges recorded")
+            .increment();
+
+        let body = scrape(server.local_addr());
+        assert!(body.contains("accountant_charges_recorded_total 1"));
+    }
+
+    #[test]
+    fn other_paths_get_a_404() {
+        let registry = Arc::new(MetricsRegistry::new());
+        let server = MetricsServer::start(0, registry).unwrap();
+
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream
+            .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+    }
+}
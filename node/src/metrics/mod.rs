@@ -0,0 +1,4 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+pub mod metrics_server;
+pub mod registry;
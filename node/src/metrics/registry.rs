@@ -0,0 +1,263 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Central metrics registry. Actors obtain a handle once at startup and
+//! bump it with a single atomic operation on the hot path; the registry
+//! renders everything in Prometheus exposition format on demand. Metric
+//! names and label keys are snake_case and stable — they are an operator
+//! interface, not an implementation detail.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricKind {
+    /// Monotonically increasing; scrapers take rates over it.
+    Counter,
+    /// A point-in-time level that may go up or down (mailbox depth,
+    /// active stream count).
+    Gauge,
+}
+
+impl MetricKind {
+    fn type_label(&self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+        }
+    }
+}
+
+/// The cheap handle actors hold. Cloning shares the underlying cell.
+#[derive(Clone)]
+pub struct MetricHandle {
+    value: Arc<AtomicU64>,
+}
+
+impl MetricHandle {
+    pub fn increment(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, amount: u64) {
+        self.value.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    /// Gauges only; counters must never go backward.
+    pub fn set(&self, value: u64) {
+        self.value.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+struct Metric {
+    kind: MetricKind,
+    help: String,
+    /// Rendered label set (e.g. `protocol="http"`) to value cell. The
+    /// unlabeled series uses the empty string.
+    series: BTreeMap<String, Arc<AtomicU64>>,
+}
+
+/// All metrics the node exposes. One instance lives for the process
+/// lifetime, shared by Arc with every actor and with the metrics listener.
+#[derive(Default)]
+pub struct MetricsRegistry {
+    metrics: Mutex<BTreeMap<String, Metric>>,
+}
+
+impl MetricsRegistry {
+    pub fn new() -> MetricsRegistry {
+        Self::default()
+    }
+
+    pub fn counter(&self, name: &str, help: &str) -> MetricHandle {
+        self.handle(name, help, MetricKind::Counter, &[])
+    }
+
+    pub fn counter_with_labels(
+        &self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+    ) -> MetricHandle {
+        self.handle(name, help, MetricKind::Counter, labels)
+    }
+
+    pub fn gauge(&self, name: &str, help: &str) -> MetricHandle {
+        self.handle(name, help, MetricKind::Gauge, &[])
+    }
+
+    pub fn gauge_with_labels(
+        &self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+    ) -> MetricHandle {
+        self.handle(name, help, MetricKind::Gauge, labels)
+    }
+
+    fn handle(
+        &self,
+        name: &str,
+        help: &str,
+        kind: MetricKind,
+        labels: &[(&str, &str)],
+    ) -> MetricHandle {
+        assert!(
+            is_snake_case(name) && labels.iter().all(|(key, _)| is_snake_case(key)),
+            "metric names and label keys must be snake_case: {}",
+            name
+        );
+        let label_set = render_labels(labels);
+        let mut metrics = self.metrics.lock().expect("metrics registry poisoned");
+        let metric = metrics.entry(name.to_string()).or_insert_with(|| Metric {
+            kind,
+            help: help.to_string(),
+            series: BTreeMap::new(),
+        });
+        assert_eq!(
+            metric.kind, kind,
+            "metric {} already registered with a different kind",
+            name
+        );
+        let value = metric
+            .series
+            .entry(label_set)
+            .or_insert_with(|| Arc::new(AtomicU64::new(0)))
+            .clone();
+        MetricHandle { value }
+    }
+
+    /// Renders every registered series in Prometheus exposition format.
+    pub fn render(&self) -> String {
+        let metrics = self.metrics.lock().expect("metrics registry poisoned");
+        let mut output = String::new();
+        for (name, metric) in metrics.iter() {
+            let _ = writeln!(output, "# HELP {} {}", name, metric.help);
+            let _ = writeln!(output, "# TYPE {} {}", name, metric.kind.type_label());
+            for (label_set, value) in metric.series.iter() {
+                if label_set.is_empty() {
+                    let _ = writeln!(output, "{} {}", name, value.load(Ordering::Relaxed));
+                } else {
+                    let _ = writeln!(
+                        output,
+                        "{}{{{}}} {}",
+                        name,
+                        label_set,
+                        value.load(Ordering::Relaxed)
+                    );
+                }
+            }
+        }
+        output
+    }
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, value))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn is_snake_case(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_accumulate_across_cloned_handles() {
+        let registry = MetricsRegistry::new();
+        let handle = registry.counter("hopper_packages_routed_total", "CORES packages relayed");
+        let clone = handle.clone();
+
+        handle.increment();
+        clone.add(2);
+
+        assert_eq!(handle.get(), 3);
+    }
+
+    #[test]
+    fn render_emits_help_type_and_value_lines() {
+        let registry = MetricsRegistry::new();
+        registry
+            .counter("accountant_charges_recorded_total", "Charges recorded")
+            .add(7);
+        registry
+            .gauge("proxy_server_active_streams", "Streams currently open")
+            .set(4);
+
+        let output = registry.render();
+
+        assert!(output.contains("# HELP accountant_charges_recorded_total Charges recorded"));
+        assert!(output.contains("# TYPE accountant_charges_recorded_total counter"));
+        assert!(output.contains("accountant_charges_recorded_total 7"));
+        assert!(output.contains("# TYPE proxy_server_active_streams gauge"));
+        assert!(output.contains("proxy_server_active_streams 4"));
+    }
+
+    #[test]
+    fn labeled_series_render_independently() {
+        let registry = MetricsRegistry::new();
+        registry
+            .counter_with_labels(
+                "proxy_server_requests_total",
+                "Client requests by protocol",
+                &[("protocol", "http")],
+            )
+            .add(5);
+        registry
+            .counter_with_labels(
+                "proxy_server_requests_total",
+                "Client requests by protocol",
+                &[("protocol", "tls")],
+            )
+            .add(2);
+
+        let output = registry.render();
+
+        assert!(output.contains("proxy_server_requests_total{protocol=\"http\"} 5"));
+        assert!(output.contains("proxy_server_requests_total{protocol=\"tls\"} 2"));
+        assert_eq!(output.matches("# TYPE proxy_server_requests_total").count(), 1);
+    }
+
+    #[test]
+    fn requesting_an_existing_series_returns_the_same_cell() {
+        let registry = MetricsRegistry::new();
+        let first = registry.counter("hopper_duplicates_dropped_total", "Duplicates dropped");
+        first.add(3);
+
+        let second = registry.counter("hopper_duplicates_dropped_total", "Duplicates dropped");
+
+        assert_eq!(second.get(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "snake_case")]
+    fn camel_case_metric_names_are_refused() {
+        let registry = MetricsRegistry::new();
+
+        let _ = registry.counter("hopperPackagesRouted", "wrong");
+    }
+
+    #[test]
+    #[should_panic(expected = "different kind")]
+    fn re_registering_with_a_different_kind_is_refused() {
+        let registry = MetricsRegistry::new();
+        let _ = registry.counter("node_bandwidth_bytes_total", "Bandwidth");
+
+        let _ = registry.gauge("node_bandwidth_bytes_total", "Bandwidth");
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Startup peer discovery from a configured bootstrap list. Each bootstrap
+//! node is asked for its peer list (a gossip exchange on the wire); learned
+//! peers are folded into the Kademlia table and themselves queried until
+//! the node knows at least K neighbors or runs out of leads. Unreachable
+//! bootstrap nodes are skipped, not fatal — one good lead is enough.
+
+use crate::neighborhood::kademlia::{KademliaTable, NodeContact, K};
+use crate::sub_lib::cryptde::PublicKey;
+use crate::sub_lib::logger::Logger;
+use std::collections::HashSet;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BootstrapConfig {
+    /// Contacted in order at startup; empty means this node expects
+    /// inbound introductions instead.
+    pub bootstrap_nodes: Vec<NodeContact>,
+}
+
+/// Mockable peer-list exchange with one node. The real implementation
+/// rides the gossip protocol via the neighbor contactor.
+pub trait PeerListRequester: Send {
+    fn request_peers(&self, contact: &NodeContact) -> Result<Vec<NodeContact>, String>;
+}
+
+pub struct Bootstrapper {
+    requester: Box<dyn PeerListRequester>,
+    logger: Logger,
+}
+
+impl Bootstrapper {
+    pub fn new(requester: Box<dyn PeerListRequester>) -> Bootstrapper {
+        Bootstrapper {
+            requester,
+            logger: Logger::new("Bootstrapper"),
+        }
+    }
+
+    /// Populates `table` starting from the configured contacts. Returns
+    /// the number of neighbors known afterward; fewer than K means the
+    /// bootstrap list was thin or mostly unreachable, which is logged but
+    /// left to the caller to judge.
+    pub fn bootstrap(&self, table: &mut KademliaTable, contacts: Vec<NodeContact>) -> usize {
+        let mut queried: HashSet<PublicKey> = HashSet::new();
+        let mut frontier: Vec<NodeContact> = contacts;
+        while let Some(contact) = frontier.pop() {
+            if table.contact_count() >= K {
+                break;
+            }
+            if !queried.insert(contact.public_key.clone()) {
+                continue;
+            }
+            table.store(&contact.public_key, contact.node_addr.clone());
+            match self.requester.request_peers(&contact) {
+                Ok(peers) => {
+                    for peer in peers {
+                        table.store(&peer.public_key, peer.node_addr.clone());
+                        if !queried.contains(&peer.public_key) {
+                            frontier.push(peer);
+                        }
+                    }
+                }
+                Err(e) => self.logger.warning(format!(
+                    "Bootstrap node {:?} unreachable: {}",
+                    contact.public_key, e
+                )),
+            }
+        }
+        let count = table.contact_count();
+        if count < K {
+            self.logger.warning(format!(
+                "Bootstrap finished with only {} neighbors (wanted {})",
+                count, K
+            ));
+        } else {
+            self.logger
+                .info(format!("Bootstrap complete: {} neighbors known", count));
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sub_lib::node_addr::NodeAddr;
+    use std::collections::HashMap;
+    use std::net::IpAddr;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    fn contact(id: u8) -> NodeContact {
+        NodeContact {
+            public_key: PublicKey::new(&[id]),
+            node_addr: NodeAddr::new(
+                &IpAddr::from_str(&format!("10.1.0.{}", id)).unwrap(),
+                &[4646],
+            ),
+        }
+    }
+
+    struct PeerListRequesterMock {
+        peer_lists: HashMap<PublicKey, Result<Vec<NodeContact>, String>>,
+        requests: Arc<Mutex<Vec<PublicKey>>>,
+    }
+
+    impl PeerListRequester for PeerListRequesterMock {
+        fn request_peers(&self, contact: &NodeContact) -> Result<Vec<NodeContact>, String> {
+            self.requests.lock().unwrap().push(contact.public_key.clone());
+            self.peer_lists
+                .get(&contact.public_key)
+                .cloned()
+                .unwrap_or_else(|| Ok(vec![]))
+        }
+    }
+
+    fn make_subject(
+        peer_lists: Vec<(u8, Result<Vec<NodeContact>, String>)>,
+    ) -> (Bootstrapper, Arc<Mutex<Vec<PublicKey>>>) {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let requester = PeerListRequesterMock {
+            peer_lists: peer_lists
+                .into_iter()
+                .map(|(id, result)| (PublicKey::new(&[id]), result))
+                .collect(),
+            requests: requests.clone(),
+        };
+        (Bootstrapper::new(Box::new(requester)), requests)
+    }
+
+    #[test]
+    fn bootstrapping_reaches_k_neighbors_from_one_good_bootstrap_node() {
+        let (subject, _) = make_subject(vec![(
+            1,
+            Ok((2..=20).map(contact).collect()),
+        )]);
+        let mut table = KademliaTable::new(PublicKey::new(&[0]));
+
+        let count = subject.bootstrap(&mut table, vec![contact(1)]);
+
+        assert!(count >= K, "only {} neighbors after bootstrap", count);
+    }
+
+    #[test]
+    fn unreachable_bootstrap_nodes_are_skipped() {
+        let (subject, requests) = make_subject(vec![
+            (1, Err("connection refused".to_string())),
+            (2, Ok((10..=30).map(contact).collect())),
+        ]);
+        let mut table = KademliaTable::new(PublicKey::new(&[0]));
+
+        let count = subject.bootstrap(&mut table, vec![contact(2), contact(1)]);
+
+        assert!(count >= K);
+        // The dead node was tried (frontier is a stack, so contact(1) first)
+        // and did not stop discovery through the live one.
+        assert!(requests.lock().unwrap().contains(&PublicKey::new(&[1])));
+        assert!(requests.lock().unwrap().contains(&PublicKey::new(&[2])));
+    }
+
+    #[test]
+    fn discovery_follows_leads_transitively() {
+        // Node 1 only knows node 2; node 2 knows everyone else.
+        let (subject, _) = make_subject(vec![
+            (1, Ok(vec![contact(2)])),
+            (2, Ok((3..=15).map(contact).collect())),
+        ]);
+        let mut table = KademliaTable::new(PublicKey::new(&[0]));
+
+        let count = subject.bootstrap(&mut table, vec![contact(1)]);
+
+        assert!(count >= K);
+    }
+
+    #[test]
+    fn querying_stops_once_k_neighbors_are_known() {
+        let (subject, requests) = make_subject(vec![
+            (1, Ok((2..=40).map(contact).collect())),
+            (2, Ok(vec![])),
+        ]);
+        let mut table = KademliaTable::new(PublicKey::new(&[0]));
+
+        subject.bootstrap(&mut table, vec![contact(1)]);
+
+        // The first answer already supplied K neighbors; nobody else was asked.
+        assert_eq!(*requests.lock().unwrap(), vec![PublicKey::new(&[1])]);
+    }
+
+    #[test]
+    fn empty_bootstrap_list_leaves_the_table_empty() {
+        let (subject, requests) = make_subject(vec![]);
+        let mut table = KademliaTable::new(PublicKey::new(&[0]));
+
+        let count = subject.bootstrap(&mut table, vec![]);
+
+        assert_eq!(count, 0);
+        assert!(requests.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,279 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Churn accounting for flapping peers. A node whose IP changes or that
+//! restarts every few minutes pushes a version bump through the gossip
+//! mesh each time, and every node that accepts it rewrites its database
+//! and re-gossips — one unstable peer taxes the whole network. Accepted
+//! updates are counted per record over a sliding hour; a record past the
+//! threshold is damped: its further updates are held and released in one
+//! batch after the damping delay (so a genuinely new address still lands,
+//! just not instantly), and selection treats it as deprioritized for
+//! relay and exit duty until its rate drops back under the threshold.
+//! The top churners are exposed for the neighborhood dump.
+
+use crate::neighborhood::gossip::SignedGossip;
+use crate::sub_lib::cryptde::PublicKey;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// The sliding window over which update frequency is measured.
+pub const DEFAULT_CHURN_WINDOW: Duration = Duration::from_secs(3600);
+
+/// Accepted updates per window at which a record counts as churning.
+pub const DEFAULT_CHURN_THRESHOLD: usize = 6;
+
+/// How long a damped record's updates are held before release.
+pub const DEFAULT_DAMPING_DELAY: Duration = Duration::from_secs(600);
+
+/// What to do with an arriving update for one record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UpdateDisposition {
+    ApplyNow,
+    /// Held until the damping delay lapses; `due_updates` releases it.
+    Deferred,
+}
+
+struct RecordChurn {
+    accepted_at: VecDeque<Instant>,
+    /// The newest held update and when its hold started. Only the newest
+    /// matters — applying intermediate flaps would be pure churn.
+    pending: Option<(SignedGossip, Instant)>,
+}
+
+pub struct ChurnTracker {
+    window: Duration,
+    threshold: usize,
+    damping_delay: Duration,
+    records: HashMap<PublicKey, RecordChurn>,
+}
+
+impl ChurnTracker {
+    pub fn new(window: Duration, threshold: usize, damping_delay: Duration) -> ChurnTracker {
+        ChurnTracker {
+            window,
+            threshold,
+            damping_delay,
+            records: HashMap::new(),
+        }
+    }
+
+    /// Routes one accepted-for-processing update: counted, and either
+    /// passed through or held depending on the record's current rate.
+    pub fn submit_update(
+        &mut self,
+        update: SignedGossip,
+        now: Instant,
+    ) -> UpdateDisposition {
+        let key = update.message.public_key.clone();
+        let churning = self.update_rate(&key, now) >= self.threshold;
+        let record = self.records.entry(key).or_insert_with(|| RecordChurn {
+            accepted_at: VecDeque::new(),
+            pending: None,
+        });
+        record.accepted_at.push_back(now);
+        if !churning {
+            return UpdateDisposition::ApplyNow;
+        }
+        let hold_started = match &record.pending {
+            Some((_, started)) => *started,
+            None => now,
+        };
+        record.pending = Some((update, hold_started));
+        UpdateDisposition::Deferred
+    }
+
+    /// Releases every held update whose damping delay has lapsed; the
+    /// caller applies them like freshly accepted gossip. This is the path
+    /// that guarantees a flapper's final address is learned eventually.
+    pub fn due_updates(&mut self, now: Instant) -> Vec<SignedGossip> {
+        let delay = self.damping_delay;
+        let mut released = vec![];
+        for record in self.records.values_mut() {
+            let due = matches!(&record.pending, Some((_, started)) if now.duration_since(*started) >= delay);
+            if due {
+                let (update, _) = record.pending.take().expect("pending vanished");
+                released.push(update);
+            }
+        }
+        released
+    }
+
+    /// Version bumps accepted within the window.
+    pub fn update_rate(&mut self, key: &PublicKey, now: Instant) -> usize {
+        match self.records.get_mut(key) {
+            Some(record) => {
+                let window = self.window;
+                while let Some(oldest) = record.accepted_at.front() {
+                    if now.duration_since(*oldest) >= window {
+                        record.accepted_at.pop_front();
+                    } else {
+                        break;
+                    }
+                }
+                record.accepted_at.len()
+            }
+            None => 0,
+        }
+    }
+
+    /// Whether relay and exit selection should pass this record over.
+    pub fn is_deprioritized(&mut self, key: &PublicKey, now: Instant) -> bool {
+        self.update_rate(key, now) >= self.threshold
+    }
+
+    /// The `n` records with the highest update rates, for the
+    /// neighborhood dump; ties break toward the lexically smaller key so
+    /// the dump is stable.
+    pub fn top_churners(&mut self, n: usize, now: Instant) -> Vec<(PublicKey, usize)> {
+        let keys: Vec<PublicKey> = self.records.keys().cloned().collect();
+        let mut rates: Vec<(PublicKey, usize)> = keys
+            .into_iter()
+            .map(|key| {
+                let rate = self.update_rate(&key, now);
+                (key, rate)
+            })
+            .filter(|(_, rate)| *rate > 0)
+            .collect();
+        rates.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        rates.truncate(n);
+        rates
+    }
+}
+
+impl Default for ChurnTracker {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_CHURN_WINDOW,
+            DEFAULT_CHURN_THRESHOLD,
+            DEFAULT_DAMPING_DELAY,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neighborhood::gossip::GossipMessage;
+    use crate::sub_lib::cryptde::CryptData;
+    use crate::sub_lib::node_addr::NodeAddr;
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    fn update_for(key: &PublicKey, ip: &str) -> SignedGossip {
+        SignedGossip {
+            message: GossipMessage {
+                public_key: key.clone(),
+                node_addr_opt: Some(NodeAddr::new(
+                    &IpAddr::from_str(ip).unwrap(),
+                    &[5333],
+                )),
+                version: "0.4.0".to_string(),
+                protocol_version: 3,
+                capabilities: vec![],
+                earning_wallet: None,
+                exit_earning_wallet: None,
+            },
+            signature: CryptData::new(b"signature"),
+        }
+    }
+
+    fn flapper() -> PublicKey {
+        PublicKey::new(b"flapper")
+    }
+
+    #[test]
+    fn a_stable_record_applies_immediately_and_is_not_deprioritized() {
+        let mut subject = ChurnTracker::default();
+        let now = Instant::now();
+        let steady = PublicKey::new(b"steady");
+
+        let disposition = subject.submit_update(update_for(&steady, "1.2.3.4"), now);
+
+        assert_eq!(disposition, UpdateDisposition::ApplyNow);
+        assert!(!subject.is_deprioritized(&steady, now));
+    }
+
+    #[test]
+    fn a_flapping_record_crosses_the_threshold_into_damping() {
+        let mut subject = ChurnTracker::new(DEFAULT_CHURN_WINDOW, 3, DEFAULT_DAMPING_DELAY);
+        let now = Instant::now();
+        for n in 0..3 {
+            let disposition = subject.submit_update(
+                update_for(&flapper(), &format!("1.2.3.{}", n)),
+                now + Duration::from_secs(n as u64),
+            );
+            assert_eq!(disposition, UpdateDisposition::ApplyNow, "update {}", n);
+        }
+
+        let fourth = subject.submit_update(
+            update_for(&flapper(), "1.2.3.99"),
+            now + Duration::from_secs(10),
+        );
+
+        assert_eq!(fourth, UpdateDisposition::Deferred);
+        assert!(subject.is_deprioritized(&flapper(), now + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn held_updates_release_as_one_batch_with_only_the_newest_kept() {
+        let mut subject = ChurnTracker::new(DEFAULT_CHURN_WINDOW, 2, Duration::from_secs(600));
+        let now = Instant::now();
+        subject.submit_update(update_for(&flapper(), "1.1.1.1"), now);
+        subject.submit_update(update_for(&flapper(), "2.2.2.2"), now);
+        // Damped from here: three flaps land during the hold.
+        subject.submit_update(update_for(&flapper(), "3.3.3.3"), now + Duration::from_secs(1));
+        subject.submit_update(update_for(&flapper(), "4.4.4.4"), now + Duration::from_secs(2));
+        subject.submit_update(update_for(&flapper(), "5.5.5.5"), now + Duration::from_secs(3));
+
+        let too_early = subject.due_updates(now + Duration::from_secs(500));
+        let released = subject.due_updates(now + Duration::from_secs(601));
+
+        assert!(too_early.is_empty());
+        assert_eq!(released, vec![update_for(&flapper(), "5.5.5.5")]);
+        // The hold is measured from the first deferral, not the latest.
+        assert!(subject.due_updates(now + Duration::from_secs(602)).is_empty());
+    }
+
+    #[test]
+    fn a_settled_flapper_converges_and_is_reprioritized() {
+        let mut subject = ChurnTracker::new(Duration::from_secs(3600), 2, Duration::from_secs(600));
+        let now = Instant::now();
+        subject.submit_update(update_for(&flapper(), "1.1.1.1"), now);
+        subject.submit_update(update_for(&flapper(), "2.2.2.2"), now);
+        subject.submit_update(update_for(&flapper(), "3.3.3.3"), now + Duration::from_secs(5));
+        assert!(subject.is_deprioritized(&flapper(), now + Duration::from_secs(5)));
+
+        // The final address arrives via the batch release...
+        let released = subject.due_updates(now + Duration::from_secs(700));
+        // ...and once the window slides past the flapping, the record is
+        // eligible again.
+        let later = now + Duration::from_secs(3700);
+
+        assert_eq!(released, vec![update_for(&flapper(), "3.3.3.3")]);
+        assert!(!subject.is_deprioritized(&flapper(), later));
+        assert_eq!(
+            subject.submit_update(update_for(&flapper(), "3.3.3.3"), later),
+            UpdateDisposition::ApplyNow
+        );
+    }
+
+    #[test]
+    fn top_churners_ranks_by_rate() {
+        let mut subject = ChurnTracker::default();
+        let now = Instant::now();
+        let noisy = PublicKey::new(b"noisy");
+        let noisier = PublicKey::new(b"noisier");
+        let quiet = PublicKey::new(b"quiet");
+        for n in 0..2 {
+            subject.submit_update(update_for(&noisy, &format!("1.0.0.{}", n)), now);
+        }
+        for n in 0..4 {
+            subject.submit_update(update_for(&noisier, &format!("2.0.0.{}", n)), now);
+        }
+        subject.submit_update(update_for(&quiet, "3.0.0.1"), now);
+
+        let top = subject.top_churners(2, now);
+
+        assert_eq!(top, vec![(noisier, 4), (noisy, 2)]);
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use crate::sub_lib::cryptde::{CryptDE, CryptData, PlainData, PublicKey};
+use crate::sub_lib::node_addr::NodeAddr;
+use crate::sub_lib::wallet::{EarningWallets, Wallet};
+use serde::{Deserialize, Serialize};
+
+/// A node's self-description as it travels the network: identity, where to
+/// reach it, what software it runs, and what it can do.
+///
+/// The earning-wallet fields are a versioned extension: peers running
+/// software from before wallet separation omit both, and a node that
+/// advertises only `earning_wallet` is paid for everything there. Only
+/// nodes that want split books set `exit_earning_wallet` too.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GossipMessage {
+    pub public_key: PublicKey,
+    pub node_addr_opt: Option<NodeAddr>,
+    pub version: String,
+    pub protocol_version: u16,
+    pub capabilities: Vec<String>,
+    /// Routing income address; also the exit address unless overridden.
+    #[serde(default)]
+    pub earning_wallet: Option<String>,
+    /// Exit income address, for operators keeping separate books.
+    #[serde(default)]
+    pub exit_earning_wallet: Option<String>,
+}
+
+impl GossipMessage {
+    /// The wallets services rendered by this node should be paid to; None
+    /// when the record advertises no wallet at all. One wallet implies
+    /// both, so old-format records keep the single-wallet behavior.
+    pub fn earning_wallets(&self) -> Option<EarningWallets> {
+        let routing = Wallet::new(self.earning_wallet.as_deref()?);
+        Some(match &self.exit_earning_wallet {
+            Some(exit) => EarningWallets::split(routing, Wallet::new(exit)),
+            None => EarningWallets::single(routing),
+        })
+    }
+    pub fn sign(self, cryptde: &dyn CryptDE) -> SignedGossip {
+        let serialized = serde_cbor::ser::to_vec(&self)
+            .expect("Serialization of GossipMessage should never fail");
+        let signature = cryptde
+            .sign(&PlainData::from(serialized))
+            .expect("Signing of GossipMessage should never fail");
+        SignedGossip {
+            message: self,
+            signature,
+        }
+    }
+}
+
+/// A GossipMessage plus the signature of its serialized form, made with the
+/// private key matching the message's own public_key field.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedGossip {
+    pub message: GossipMessage,
+    pub signature: CryptData,
+}
+
+impl SignedGossip {
+    pub fn verify(&self, cryptde: &dyn CryptDE) -> bool {
+        let serialized = match serde_cbor::ser::to_vec(&self.message) {
+            Ok(bytes) => bytes,
+            Err(_) => return false,
+        };
+        cryptde.verify_signature(
+            &PlainData::from(serialized),
+            &self.signature,
+            &self.message.public_key,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sub_lib::cryptde_null::CryptDENull;
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    fn make_message(public_key: &PublicKey) -> GossipMessage {
+        GossipMessage {
+            public_key: public_key.clone(),
+            node_addr_opt: Some(NodeAddr::new(
+                &IpAddr::from_str("1.2.3.4").unwrap(),
+                &[1234],
+            )),
+            version: "0.4.0".to_string(),
+            protocol_version: crate::neighborhood::version_negotiation::CURRENT_PROTOCOL_VERSION,
+            capabilities: vec!["relay".to_string(), "exit".to_string()],
+            earning_wallet: None,
+            exit_earning_wallet: None,
+        }
+    }
+
+    #[test]
+    fn signed_gossip_verifies_with_signer_key() {
+        let cryptde = CryptDENull::from(&PublicKey::new(b"node_a"));
+        let message = make_message(cryptde.public_key());
+
+        let signed = message.sign(&cryptde);
+
+        assert!(signed.verify(&cryptde));
+    }
+
+    #[test]
+    fn gossip_signed_by_node_a_does_not_verify_against_node_b_key() {
+        let cryptde_a = CryptDENull::from(&PublicKey::new(b"node_a"));
+        let cryptde_b = CryptDENull::from(&PublicKey::new(b"node_b"));
+        // Sign as A, but the message claims B's identity: verification must fail.
+        let mut message = make_message(cryptde_a.public_key());
+        message.public_key = cryptde_b.public_key().clone();
+
+        let signed = message.sign(&cryptde_a);
+
+        assert!(!signed.verify(&cryptde_b));
+    }
+
+    #[test]
+    fn tampered_message_fails_verification() {
+        let cryptde = CryptDENull::from(&PublicKey::new(b"node_a"));
+        let message = make_message(cryptde.public_key());
+
+        let mut signed = message.sign(&cryptde);
+        signed.message.version = "9.9.9".to_string();
+
+        assert!(!signed.verify(&cryptde));
+    }
+
+    #[test]
+    fn old_format_gossip_without_wallet_fields_still_deserializes() {
+        // Serialized the way a pre-wallet-separation peer would: the two
+        // new fields absent entirely.
+        #[derive(serde::Serialize)]
+        #[serde(rename = "GossipMessage")]
+        struct OldGossipMessage {
+            public_key: PublicKey,
+            node_addr_opt: Option<NodeAddr>,
+            version: String,
+            protocol_version: u16,
+            capabilities: Vec<String>,
+        }
+        let old_bytes = serde_cbor::ser::to_vec(&OldGossipMessage {
+            public_key: PublicKey::new(b"old_peer"),
+            node_addr_opt: None,
+            version: "0.3.0".to_string(),
+            protocol_version: 2,
+            capabilities: vec!["relay".to_string()],
+        })
+        .unwrap();
+
+        let message: GossipMessage = serde_cbor::de::from_slice(&old_bytes).unwrap();
+
+        assert_eq!(message.earning_wallet, None);
+        assert_eq!(message.earning_wallets(), None);
+    }
+
+    #[test]
+    fn one_advertised_wallet_implies_both_services() {
+        let mut message = make_message(&PublicKey::new(b"node_a"));
+        message.earning_wallet = Some("0xboth".to_string());
+
+        let wallets = message.earning_wallets().unwrap();
+
+        assert_eq!(wallets.routing, crate::sub_lib::wallet::Wallet::new("0xboth"));
+        assert_eq!(wallets.exit, crate::sub_lib::wallet::Wallet::new("0xboth"));
+    }
+
+    #[test]
+    fn split_wallets_round_trip_through_serialization() {
+        let mut message = make_message(&PublicKey::new(b"node_a"));
+        message.earning_wallet = Some("0xrouting".to_string());
+        message.exit_earning_wallet = Some("0xexit".to_string());
+
+        let bytes = serde_cbor::ser::to_vec(&message).unwrap();
+        let round_tripped: GossipMessage = serde_cbor::de::from_slice(&bytes).unwrap();
+
+        let wallets = round_tripped.earning_wallets().unwrap();
+        assert_eq!(wallets.routing.address, "0xrouting");
+        assert_eq!(wallets.exit.address, "0xexit");
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use crate::neighborhood::gossip::GossipMessage;
+use crate::neighborhood::version_negotiation::CURRENT_PROTOCOL_VERSION;
+use crate::sub_lib::cryptde::CryptDE;
+use crate::sub_lib::node_addr::NodeAddr;
+use crate::sub_lib::wallet::EarningWallets;
+
+/// Produces the gossip this node sends out to describe itself to its
+/// neighborhood.
+pub trait GossipProducer: Send {
+    fn produce_gossip(&self) -> GossipMessage;
+}
+
+pub struct GossipProducerReal {
+    cryptde: Box<dyn CryptDE>,
+    node_addr_opt: Option<NodeAddr>,
+    version: String,
+    capabilities: Vec<String>,
+    earning_wallets: Option<EarningWallets>,
+}
+
+impl GossipProducerReal {
+    pub fn new(
+        cryptde: Box<dyn CryptDE>,
+        node_addr_opt: Option<NodeAddr>,
+        version: String,
+        capabilities: Vec<String>,
+        earning_wallets: Option<EarningWallets>,
+    ) -> GossipProducerReal {
+        GossipProducerReal {
+            cryptde,
+            node_addr_opt,
+            version,
+            capabilities,
+            earning_wallets,
+        }
+    }
+}
+
+impl GossipProducer for GossipProducerReal {
+    fn produce_gossip(&self) -> GossipMessage {
+        // The exit field is advertised only when it differs, so single-
+        // wallet operators gossip the compact old-format record.
+        let (earning_wallet, exit_earning_wallet) = match &self.earning_wallets {
+            None => (None, None),
+            Some(wallets) if wallets.exit == wallets.routing => {
+                (Some(wallets.routing.address.clone()), None)
+            }
+            Some(wallets) => (
+                Some(wallets.routing.address.clone()),
+                Some(wallets.exit.address.clone()),
+            ),
+        };
+        GossipMessage {
+            public_key: self.cryptde.public_key().clone(),
+            node_addr_opt: self.node_addr_opt.clone(),
+            version: self.version.clone(),
+            protocol_version: CURRENT_PROTOCOL_VERSION,
+            capabilities: self.capabilities.clone(),
+            earning_wallet,
+            exit_earning_wallet,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sub_lib::cryptde::PublicKey;
+    use crate::sub_lib::cryptde_null::CryptDENull;
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    #[test]
+    fn produce_gossip_describes_the_current_node() {
+        let cryptde = CryptDENull::from(&PublicKey::new(b"me"));
+        let node_addr = NodeAddr::new(&IpAddr::from_str("2.3.4.5").unwrap(), &[2345]);
+        let subject = GossipProducerReal::new(
+            Box::new(CryptDENull::from(cryptde.public_key())),
+            Some(node_addr.clone()),
+            "0.4.0".to_string(),
+            vec!["relay".to_string()],
+            None,
+        );
+
+        let gossip = subject.produce_gossip();
+
+        assert_eq!(gossip.public_key, *cryptde.public_key());
+        assert_eq!(gossip.node_addr_opt, Some(node_addr));
+        assert_eq!(gossip.version, "0.4.0");
+        assert_eq!(gossip.protocol_version, CURRENT_PROTOCOL_VERSION);
+        assert_eq!(gossip.capabilities, vec!["relay".to_string()]);
+    }
+
+    #[test]
+    fn produced_gossip_survives_a_sign_verify_round_trip() {
+        let cryptde = CryptDENull::from(&PublicKey::new(b"me"));
+        let subject = GossipProducerReal::new(
+            Box::new(CryptDENull::from(cryptde.public_key())),
+            None,
+            "0.4.0".to_string(),
+            vec![],
+            None,
+        );
+
+        let signed = subject.produce_gossip().sign(&cryptde);
+
+        assert!(signed.verify(&cryptde));
+    }
+
+    #[test]
+    fn a_single_earning_wallet_is_advertised_in_the_old_format() {
+        use crate::sub_lib::wallet::Wallet;
+        let subject = GossipProducerReal::new(
+            Box::new(CryptDENull::from(&PublicKey::new(b"me"))),
+            None,
+            "0.4.0".to_string(),
+            vec![],
+            Some(EarningWallets::single(Wallet::new("0xboth"))),
+        );
+
+        let gossip = subject.produce_gossip();
+
+        assert_eq!(gossip.earning_wallet, Some("0xboth".to_string()));
+        assert_eq!(gossip.exit_earning_wallet, None);
+    }
+
+    #[test]
+    fn split_earning_wallets_are_both_advertised() {
+        use crate::sub_lib::wallet::Wallet;
+        let subject = GossipProducerReal::new(
+            Box::new(CryptDENull::from(&PublicKey::new(b"me"))),
+            None,
+            "0.4.0".to_string(),
+            vec![],
+            Some(EarningWallets::split(
+                Wallet::new("0xrouting"),
+                Wallet::new("0xexit"),
+            )),
+        );
+
+        let gossip = subject.produce_gossip();
+
+        assert_eq!(gossip.earning_wallet, Some("0xrouting".to_string()));
+        assert_eq!(gossip.exit_earning_wallet, Some("0xexit".to_string()));
+    }
+}
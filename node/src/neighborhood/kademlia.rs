@@ -0,0 +1,249 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Kademlia-style routing table for node discovery. Contacts live in
+//! k-buckets keyed by XOR distance from the local key; lookups return the
+//! k closest known contacts, and iterative querying of those contacts
+//! converges on any reachable target. This augments gossip rather than
+//! replacing it: gossip keeps the table fed, the table answers "who is
+//! near this key" without flooding.
+
+use crate::sub_lib::cryptde::PublicKey;
+use crate::sub_lib::node_addr::NodeAddr;
+use std::collections::HashMap;
+
+/// Bucket capacity, Kademlia's k. Also the size of a find_node answer.
+pub const K: usize = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeContact {
+    pub public_key: PublicKey,
+    pub node_addr: NodeAddr,
+}
+
+/// XOR distance between two keys, zero-extending the shorter one. The
+/// result compares lexicographically: smaller means closer.
+pub fn xor_distance(a: &PublicKey, b: &PublicKey) -> Vec<u8> {
+    let a = a.as_slice();
+    let b = b.as_slice();
+    let len = a.len().max(b.len());
+    (0..len)
+        .map(|i| a.get(i).copied().unwrap_or(0) ^ b.get(i).copied().unwrap_or(0))
+        .collect()
+}
+
+/// Index of the bucket a key belongs in: the bit position of the first
+/// difference from the local key. None means the keys are identical.
+fn bucket_index(local: &PublicKey, other: &PublicKey) -> Option<usize> {
+    let distance = xor_distance(local, other);
+    for (byte_index, byte) in distance.iter().enumerate() {
+        if *byte != 0 {
+            return Some(byte_index * 8 + byte.leading_zeros() as usize);
+        }
+    }
+    None
+}
+
+pub struct KademliaTable {
+    local_key: PublicKey,
+    buckets: HashMap<usize, Vec<NodeContact>>,
+}
+
+impl KademliaTable {
+    pub fn new(local_key: PublicKey) -> KademliaTable {
+        KademliaTable {
+            local_key,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Records a contact. A known key is refreshed (moved to the
+    /// most-recently-seen end and its address updated); a newcomer to a
+    /// full bucket is dropped, Kademlia's bias toward long-lived contacts.
+    pub fn store(&mut self, key: &PublicKey, value: NodeAddr) {
+        let index = match bucket_index(&self.local_key, key) {
+            Some(index) => index,
+            None => return, // never store ourselves
+        };
+        let bucket = self.buckets.entry(index).or_default();
+        if let Some(position) = bucket.iter().position(|c| c.public_key == *key) {
+            bucket.remove(position);
+            bucket.push(NodeContact {
+                public_key: key.clone(),
+                node_addr: value,
+            });
+            return;
+        }
+        if bucket.len() < K {
+            bucket.push(NodeContact {
+                public_key: key.clone(),
+                node_addr: value,
+            });
+        }
+    }
+
+    /// The up-to-k known contacts closest to `target` by XOR distance,
+    /// closest first.
+    pub fn find_node(&self, target: &PublicKey) -> Vec<NodeContact> {
+        let mut contacts: Vec<&NodeContact> = self.buckets.values().flatten().collect();
+        contacts.sort_by(|a, b| {
+            xor_distance(&a.public_key, target).cmp(&xor_distance(&b.public_key, target))
+        });
+        contacts.into_iter().take(K).cloned().collect()
+    }
+
+    pub fn contact_count(&self) -> usize {
+        self.buckets.values().map(Vec::len).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    fn key(bytes: &[u8]) -> PublicKey {
+        PublicKey::new(bytes)
+    }
+
+    fn addr(last_octet: u8) -> NodeAddr {
+        NodeAddr::new(
+            &IpAddr::from_str(&format!("10.0.0.{}", last_octet)).unwrap(),
+            &[4646],
+        )
+    }
+
+    #[test]
+    fn xor_distance_is_symmetric_and_zero_extends() {
+        assert_eq!(
+            xor_distance(&key(&[0xF0, 0x0F]), &key(&[0x0F])),
+            vec![0xFF, 0x0F]
+        );
+        assert_eq!(
+            xor_distance(&key(&[0x0F]), &key(&[0xF0, 0x0F])),
+            vec![0xFF, 0x0F]
+        );
+        assert_eq!(xor_distance(&key(&[7, 7]), &key(&[7, 7])), vec![0, 0]);
+    }
+
+    #[test]
+    fn contacts_sort_into_buckets_by_first_differing_bit() {
+        // Local key 0x00: 0x80 differs at bit 0, 0x01 at bit 7.
+        assert_eq!(bucket_index(&key(&[0x00]), &key(&[0x80])), Some(0));
+        assert_eq!(bucket_index(&key(&[0x00]), &key(&[0x01])), Some(7));
+        assert_eq!(bucket_index(&key(&[0x00, 0x00]), &key(&[0x00, 0x10])), Some(11));
+        assert_eq!(bucket_index(&key(&[0x42]), &key(&[0x42])), None);
+    }
+
+    #[test]
+    fn the_local_key_is_never_stored() {
+        let mut subject = KademliaTable::new(key(&[0x42]));
+
+        subject.store(&key(&[0x42]), addr(1));
+
+        assert_eq!(subject.contact_count(), 0);
+    }
+
+    #[test]
+    fn a_full_bucket_drops_newcomers_and_keeps_the_old_guard() {
+        let mut subject = KademliaTable::new(key(&[0x00, 0x00]));
+        // All these keys share bucket 7 (first difference in bit 7).
+        for i in 0..K as u8 {
+            subject.store(&key(&[0x01, i]), addr(i));
+        }
+
+        subject.store(&key(&[0x01, 0xEE]), addr(99));
+
+        assert_eq!(subject.contact_count(), K);
+        let found = subject.find_node(&key(&[0x01, 0xEE]));
+        assert!(!found.iter().any(|c| c.public_key == key(&[0x01, 0xEE])));
+    }
+
+    #[test]
+    fn restoring_a_known_contact_refreshes_it_and_updates_its_address() {
+        let mut subject = KademliaTable::new(key(&[0x00]));
+        subject.store(&key(&[0x01]), addr(1));
+        subject.store(&key(&[0x01]), addr(2));
+
+        let found = subject.find_node(&key(&[0x01]));
+
+        assert_eq!(subject.contact_count(), 1);
+        assert_eq!(found[0].node_addr, addr(2));
+    }
+
+    #[test]
+    fn find_node_returns_the_closest_contacts_first() {
+        let mut subject = KademliaTable::new(key(&[0x00]));
+        for byte in [0x10u8, 0x11, 0x80, 0xF0, 0x02] {
+            subject.store(&key(&[byte]), addr(byte));
+        }
+
+        let found = subject.find_node(&key(&[0x12]));
+
+        let keys: Vec<&PublicKey> = found.iter().map(|c| &c.public_key).collect();
+        // 0x10^0x12=0x02, 0x11^0x12=0x03, 0x02^0x12=0x10, 0x80^0x12=0x92, 0xF0^0x12=0xE2
+        assert_eq!(
+            keys,
+            vec![
+                &key(&[0x10]),
+                &key(&[0x11]),
+                &key(&[0x02]),
+                &key(&[0x80]),
+                &key(&[0xF0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn iterative_lookup_converges_across_a_network_of_tables() {
+        // Sixty-four nodes, each with well-maintained buckets (contacts
+        // inserted closest-first, so full buckets keep the closest k). In
+        // that topology every hop at least halves the distance, so a
+        // lookup must reach the target within the key's bit length.
+        let keys: Vec<PublicKey> = (0u8..64).map(|i| key(&[i])).collect();
+        let mut tables: HashMap<PublicKey, KademliaTable> = keys
+            .iter()
+            .map(|k| (k.clone(), KademliaTable::new(k.clone())))
+            .collect();
+        for k in &keys {
+            let mut others: Vec<&PublicKey> = keys.iter().filter(|o| *o != k).collect();
+            others.sort_by(|a, b| xor_distance(a, k).cmp(&xor_distance(b, k)));
+            let table = tables.get_mut(k).unwrap();
+            for (i, other) in others.into_iter().enumerate() {
+                table.store(other, addr(i as u8));
+            }
+        }
+
+        let target = keys[50].clone();
+        let origin = &keys[0];
+        let mut queried: Vec<PublicKey> = vec![];
+        let mut candidates = tables[origin].find_node(&target);
+        let mut hops = 0;
+        let found = loop {
+            if candidates.iter().any(|c| c.public_key == target) {
+                break true;
+            }
+            hops += 1;
+            if hops > 8 {
+                break false;
+            }
+            let next = match candidates
+                .iter()
+                .find(|c| !queried.contains(&c.public_key))
+            {
+                Some(contact) => contact.public_key.clone(),
+                None => break false,
+            };
+            queried.push(next.clone());
+            let mut merged = candidates;
+            merged.extend(tables[&next].find_node(&target));
+            merged.sort_by(|a, b| {
+                xor_distance(&a.public_key, &target).cmp(&xor_distance(&b.public_key, &target))
+            });
+            merged.dedup_by(|a, b| a.public_key == b.public_key);
+            candidates = merged.into_iter().take(K).collect();
+        };
+
+        assert!(found, "lookup failed to converge within 8 hops");
+    }
+}
@@ -0,0 +1,14 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+pub mod bootstrap;
+pub mod churn_damping;
+pub mod gossip;
+pub mod gossip_producer;
+pub mod kademlia;
+pub mod mode_transitions;
+pub mod neighbor_contact;
+pub mod neighborhood_database;
+pub mod port_mapping;
+pub mod snapshot;
+pub mod stun_discovery;
+pub mod version_negotiation;
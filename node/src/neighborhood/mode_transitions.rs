@@ -0,0 +1,296 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Runtime neighborhood mode transitions. Historically any mode change meant
+//! a restart, which threw away neighbor relationships and warm routes. The
+//! safe subset is now handled live: standard ↔ originate-only (stop or start
+//! accepting debuts and advertising relay/exit), and consume-only nodes may
+//! toggle their gossip participation. Zero-hop changes still require a
+//! restart — there is no actor wiring to upgrade in place.
+
+use crate::sub_lib::logger::Logger;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NeighborhoodMode {
+    Standard,
+    OriginateOnly,
+    ConsumeOnly,
+    ZeroHop,
+}
+
+impl NeighborhoodMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            NeighborhoodMode::Standard => "standard",
+            NeighborhoodMode::OriginateOnly => "originate-only",
+            NeighborhoodMode::ConsumeOnly => "consume-only",
+            NeighborhoodMode::ZeroHop => "zero-hop",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModeTransitionError {
+    /// Zero-hop has no actor wiring to add or remove at runtime.
+    RequiresRestart {
+        from: NeighborhoodMode,
+        to: NeighborhoodMode,
+    },
+    /// The node is already in the requested mode.
+    AlreadyInMode(NeighborhoodMode),
+    /// Consume-only nodes have no services to enable; they can only toggle
+    /// gossip participation, not become relays or exits at runtime.
+    NotReachableAtRuntime {
+        from: NeighborhoodMode,
+        to: NeighborhoodMode,
+    },
+}
+
+/// What the actor system must do to realize a completed transition. The
+/// neighborhood applies `advertised_capabilities` to a freshly version-bumped
+/// record and re-gossips it; the other flags are dispatched to the affected
+/// actors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModeChange {
+    pub new_mode: NeighborhoodMode,
+    /// The bumped version for the node's own record, so neighbors replace
+    /// the old description rather than ignoring the new one.
+    pub record_version: u32,
+    pub advertised_capabilities: Vec<String>,
+    pub accept_debuts: bool,
+    /// Whether ProxyClient should serve exit requests; consume-only and
+    /// originate-only nodes refuse them.
+    pub serve_exit_requests: bool,
+    pub participate_in_gossip: bool,
+}
+
+/// Owns the current mode and the node's own record version, and adjudicates
+/// which transitions are legal without a restart.
+pub struct ModeManager {
+    mode: NeighborhoodMode,
+    record_version: u32,
+    gossip_participation: bool,
+    logger: Logger,
+}
+
+impl ModeManager {
+    pub fn new(mode: NeighborhoodMode, record_version: u32) -> ModeManager {
+        ModeManager {
+            mode,
+            record_version,
+            gossip_participation: mode != NeighborhoodMode::ZeroHop,
+            logger: Logger::new("Neighborhood"),
+        }
+    }
+
+    pub fn mode(&self) -> NeighborhoodMode {
+        self.mode
+    }
+
+    pub fn record_version(&self) -> u32 {
+        self.record_version
+    }
+
+    /// Attempts a runtime transition to `target`. On success the returned
+    /// ModeChange carries everything the actor system needs to apply it.
+    pub fn transition(
+        &mut self,
+        target: NeighborhoodMode,
+    ) -> Result<ModeChange, ModeTransitionError> {
+        if target == self.mode {
+            return Err(ModeTransitionError::AlreadyInMode(target));
+        }
+        if self.mode == NeighborhoodMode::ZeroHop || target == NeighborhoodMode::ZeroHop {
+            return Err(ModeTransitionError::RequiresRestart {
+                from: self.mode,
+                to: target,
+            });
+        }
+        let allowed = matches!(
+            (self.mode, target),
+            (NeighborhoodMode::Standard, NeighborhoodMode::OriginateOnly)
+                | (NeighborhoodMode::OriginateOnly, NeighborhoodMode::Standard)
+        );
+        if !allowed {
+            return Err(ModeTransitionError::NotReachableAtRuntime {
+                from: self.mode,
+                to: target,
+            });
+        }
+        let from = self.mode;
+        self.mode = target;
+        self.record_version += 1;
+        self.logger.info(format!(
+            "Neighborhood mode changed from {} to {} (record version {})",
+            from.label(),
+            target.label(),
+            self.record_version
+        ));
+        Ok(self.change_for_current_mode())
+    }
+
+    /// Consume-only nodes only: turns gossip participation on or off without
+    /// changing mode. A silent consume-only node still consumes over its
+    /// existing neighbors but learns nothing new about the network.
+    pub fn set_gossip_participation(
+        &mut self,
+        participate: bool,
+    ) -> Result<ModeChange, ModeTransitionError> {
+        if self.mode != NeighborhoodMode::ConsumeOnly {
+            return Err(ModeTransitionError::NotReachableAtRuntime {
+                from: self.mode,
+                to: self.mode,
+            });
+        }
+        self.gossip_participation = participate;
+        self.logger.info(format!(
+            "Consume-only gossip participation {}",
+            if participate { "enabled" } else { "disabled" }
+        ));
+        Ok(self.change_for_current_mode())
+    }
+
+    fn change_for_current_mode(&self) -> ModeChange {
+        let advertised_capabilities = match self.mode {
+            NeighborhoodMode::Standard => vec!["relay".to_string(), "exit".to_string()],
+            NeighborhoodMode::OriginateOnly
+            | NeighborhoodMode::ConsumeOnly
+            | NeighborhoodMode::ZeroHop => vec![],
+        };
+        ModeChange {
+            new_mode: self.mode,
+            record_version: self.record_version,
+            advertised_capabilities,
+            accept_debuts: self.mode == NeighborhoodMode::Standard,
+            serve_exit_requests: self.mode == NeighborhoodMode::Standard,
+            participate_in_gossip: match self.mode {
+                NeighborhoodMode::ConsumeOnly => self.gossip_participation,
+                NeighborhoodMode::ZeroHop => false,
+                _ => true,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_to_originate_only_withdraws_services() {
+        let mut subject = ModeManager::new(NeighborhoodMode::Standard, 4);
+
+        let change = subject.transition(NeighborhoodMode::OriginateOnly).unwrap();
+
+        assert_eq!(change.new_mode, NeighborhoodMode::OriginateOnly);
+        assert_eq!(change.record_version, 5);
+        assert!(change.advertised_capabilities.is_empty());
+        assert!(!change.accept_debuts);
+        assert!(!change.serve_exit_requests);
+        assert!(change.participate_in_gossip);
+    }
+
+    #[test]
+    fn originate_only_back_to_standard_restores_services() {
+        let mut subject = ModeManager::new(NeighborhoodMode::OriginateOnly, 7);
+
+        let change = subject.transition(NeighborhoodMode::Standard).unwrap();
+
+        assert_eq!(change.new_mode, NeighborhoodMode::Standard);
+        assert_eq!(change.record_version, 8);
+        assert_eq!(
+            change.advertised_capabilities,
+            vec!["relay".to_string(), "exit".to_string()]
+        );
+        assert!(change.accept_debuts);
+        assert!(change.serve_exit_requests);
+    }
+
+    #[test]
+    fn each_transition_bumps_the_record_version_again() {
+        let mut subject = ModeManager::new(NeighborhoodMode::Standard, 0);
+
+        subject.transition(NeighborhoodMode::OriginateOnly).unwrap();
+        let change = subject.transition(NeighborhoodMode::Standard).unwrap();
+
+        assert_eq!(change.record_version, 2);
+    }
+
+    #[test]
+    fn consume_only_can_toggle_gossip_participation() {
+        let mut subject = ModeManager::new(NeighborhoodMode::ConsumeOnly, 1);
+
+        let silenced = subject.set_gossip_participation(false).unwrap();
+        let resumed = subject.set_gossip_participation(true).unwrap();
+
+        assert!(!silenced.participate_in_gossip);
+        assert!(resumed.participate_in_gossip);
+        assert_eq!(subject.mode(), NeighborhoodMode::ConsumeOnly);
+        assert!(!silenced.serve_exit_requests);
+    }
+
+    #[test]
+    fn gossip_toggle_is_refused_outside_consume_only() {
+        let mut subject = ModeManager::new(NeighborhoodMode::Standard, 1);
+
+        let result = subject.set_gossip_participation(false);
+
+        assert_eq!(
+            result,
+            Err(ModeTransitionError::NotReachableAtRuntime {
+                from: NeighborhoodMode::Standard,
+                to: NeighborhoodMode::Standard,
+            })
+        );
+    }
+
+    #[test]
+    fn transitions_touching_zero_hop_require_a_restart() {
+        let mut from_zero_hop = ModeManager::new(NeighborhoodMode::ZeroHop, 1);
+        let mut to_zero_hop = ModeManager::new(NeighborhoodMode::Standard, 1);
+
+        assert_eq!(
+            from_zero_hop.transition(NeighborhoodMode::Standard),
+            Err(ModeTransitionError::RequiresRestart {
+                from: NeighborhoodMode::ZeroHop,
+                to: NeighborhoodMode::Standard,
+            })
+        );
+        assert_eq!(
+            to_zero_hop.transition(NeighborhoodMode::ZeroHop),
+            Err(ModeTransitionError::RequiresRestart {
+                from: NeighborhoodMode::Standard,
+                to: NeighborhoodMode::ZeroHop,
+            })
+        );
+    }
+
+    #[test]
+    fn consume_only_cannot_become_a_service_provider_at_runtime() {
+        let mut subject = ModeManager::new(NeighborhoodMode::ConsumeOnly, 1);
+
+        let result = subject.transition(NeighborhoodMode::Standard);
+
+        assert_eq!(
+            result,
+            Err(ModeTransitionError::NotReachableAtRuntime {
+                from: NeighborhoodMode::ConsumeOnly,
+                to: NeighborhoodMode::Standard,
+            })
+        );
+    }
+
+    #[test]
+    fn requesting_the_current_mode_is_rejected() {
+        let mut subject = ModeManager::new(NeighborhoodMode::Standard, 1);
+
+        let result = subject.transition(NeighborhoodMode::Standard);
+
+        assert_eq!(
+            result,
+            Err(ModeTransitionError::AlreadyInMode(
+                NeighborhoodMode::Standard
+            ))
+        );
+        assert_eq!(subject.record_version(), 1);
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use crate::sub_lib::cryptde::PublicKey;
+use crate::sub_lib::node_addr::NodeAddr;
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+/// How long we give each advertised port before moving on to the next one.
+pub const PER_PORT_CONNECT_TIMEOUT: Duration = Duration::from_millis(1500);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NeighborContactError {
+    /// Every advertised port was tried and none accepted.
+    Unreachable(NodeAddr),
+    /// The descriptor carried no usable socket address at all.
+    NoAddress,
+}
+
+/// A mockable TCP connect attempt. The real implementation dials; tests
+/// substitute scripted outcomes.
+pub trait TcpConnector: Send {
+    fn try_connect(&self, addr: SocketAddr, timeout: Duration) -> bool;
+}
+
+pub struct TcpConnectorReal;
+
+impl TcpConnector for TcpConnectorReal {
+    fn try_connect(&self, addr: SocketAddr, timeout: Duration) -> bool {
+        TcpStream::connect_timeout(&addr, timeout).is_ok()
+    }
+}
+
+/// Walks a neighbor's advertised ports until one accepts, and remembers the
+/// winner so later contacts (debut retries, dispatcher reconnects) lead with
+/// it instead of rediscovering the open port every time.
+pub struct NeighborContactor {
+    connector: Box<dyn TcpConnector>,
+    preferred_ports: HashMap<PublicKey, u16>,
+}
+
+impl NeighborContactor {
+    pub fn new(connector: Box<dyn TcpConnector>) -> NeighborContactor {
+        NeighborContactor {
+            connector,
+            preferred_ports: HashMap::new(),
+        }
+    }
+
+    /// Tries the neighbor's ports in preference order and returns the socket
+    /// address that accepted. On success the winning port becomes the
+    /// preferred port for this neighbor.
+    pub fn contact(
+        &mut self,
+        neighbor_key: &PublicKey,
+        node_addr: &NodeAddr,
+    ) -> Result<SocketAddr, NeighborContactError> {
+        let ports = self.ports_in_preference_order(neighbor_key, node_addr);
+        if ports.is_empty() {
+            return Err(NeighborContactError::NoAddress);
+        }
+        let ip_addr = node_addr.ip_addr();
+        for port in ports {
+            let addr = SocketAddr::new(ip_addr, port);
+            if self.connector.try_connect(addr, PER_PORT_CONNECT_TIMEOUT) {
+                self.preferred_ports.insert(neighbor_key.clone(), port);
+                return Ok(addr);
+            }
+        }
+        Err(NeighborContactError::Unreachable(node_addr.clone()))
+    }
+
+    pub fn preferred_port(&self, neighbor_key: &PublicKey) -> Option<u16> {
+        self.preferred_ports.get(neighbor_key).copied()
+    }
+
+    fn ports_in_preference_order(
+        &self,
+        neighbor_key: &PublicKey,
+        node_addr: &NodeAddr,
+    ) -> Vec<u16> {
+        let mut ports = node_addr.ports();
+        if let Some(preferred) = self.preferred_ports.get(neighbor_key) {
+            if let Some(position) = ports.iter().position(|p| p == preferred) {
+                ports.remove(position);
+                ports.insert(0, *preferred);
+            }
+        }
+        ports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::net::IpAddr;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    struct TcpConnectorMock {
+        accepted_ports: Vec<u16>,
+        attempts: Arc<Mutex<RefCell<Vec<SocketAddr>>>>,
+    }
+
+    impl TcpConnector for TcpConnectorMock {
+        fn try_connect(&self, addr: SocketAddr, _timeout: Duration) -> bool {
+            self.attempts.lock().unwrap().borrow_mut().push(addr);
+            self.accepted_ports.contains(&addr.port())
+        }
+    }
+
+    fn make_subject(
+        accepted_ports: Vec<u16>,
+    ) -> (NeighborContactor, Arc<Mutex<RefCell<Vec<SocketAddr>>>>) {
+        let attempts = Arc::new(Mutex::new(RefCell::new(vec![])));
+        let connector = TcpConnectorMock {
+            accepted_ports,
+            attempts: attempts.clone(),
+        };
+        (NeighborContactor::new(Box::new(connector)), attempts)
+    }
+
+    fn node_addr() -> NodeAddr {
+        NodeAddr::new(&IpAddr::from_str("1.2.3.4").unwrap(), &[1111, 2222, 3333])
+    }
+
+    #[test]
+    fn falls_back_to_second_port_when_first_is_firewalled() {
+        let (mut subject, attempts) = make_subject(vec![2222]);
+        let key = PublicKey::new(b"neighbor");
+
+        let result = subject.contact(&key, &node_addr());
+
+        assert_eq!(result, Ok(SocketAddr::from_str("1.2.3.4:2222").unwrap()));
+        let attempts = attempts.lock().unwrap().borrow().clone();
+        assert_eq!(
+            attempts.iter().map(|a| a.port()).collect::<Vec<u16>>(),
+            vec![1111, 2222]
+        );
+    }
+
+    #[test]
+    fn remembers_winning_port_and_tries_it_first_next_time() {
+        let (mut subject, attempts) = make_subject(vec![2222]);
+        let key = PublicKey::new(b"neighbor");
+        subject.contact(&key, &node_addr()).unwrap();
+        assert_eq!(subject.preferred_port(&key), Some(2222));
+
+        subject.contact(&key, &node_addr()).unwrap();
+
+        let attempts = attempts.lock().unwrap().borrow().clone();
+        // First contact: 1111 fails, 2222 succeeds. Second contact: 2222 leads.
+        assert_eq!(
+            attempts.iter().map(|a| a.port()).collect::<Vec<u16>>(),
+            vec![1111, 2222, 2222]
+        );
+    }
+
+    #[test]
+    fn reports_unreachable_after_exhausting_all_ports() {
+        let (mut subject, attempts) = make_subject(vec![]);
+        let key = PublicKey::new(b"neighbor");
+
+        let result = subject.contact(&key, &node_addr());
+
+        assert_eq!(result, Err(NeighborContactError::Unreachable(node_addr())));
+        assert_eq!(attempts.lock().unwrap().borrow().len(), 3);
+    }
+}
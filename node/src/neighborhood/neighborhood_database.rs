@@ -0,0 +1,185 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use crate::neighborhood::gossip::SignedGossip;
+use crate::sub_lib::cryptde::PublicKey;
+use std::collections::{HashMap, HashSet};
+
+/// One node the neighborhood knows about: its latest signed self-description
+/// plus how much we trust it. Records learned from a seeding snapshot start
+/// unconfirmed and are not used as exits until live gossip corroborates them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeRecord {
+    pub gossip: SignedGossip,
+    pub confirmed: bool,
+}
+
+impl NodeRecord {
+    pub fn public_key(&self) -> &PublicKey {
+        &self.gossip.message.public_key
+    }
+
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.gossip
+            .message
+            .capabilities
+            .iter()
+            .any(|c| c == capability)
+    }
+}
+
+/// In-memory picture of the neighborhood: node records plus the edges we
+/// know about between them.
+#[derive(Default)]
+pub struct NeighborhoodDatabase {
+    records: HashMap<PublicKey, NodeRecord>,
+    edges: HashSet<(PublicKey, PublicKey)>,
+}
+
+impl NeighborhoodDatabase {
+    pub fn new() -> NeighborhoodDatabase {
+        Self::default()
+    }
+
+    /// Adds or replaces a record learned from live gossip: fully trusted.
+    pub fn add_confirmed(&mut self, gossip: SignedGossip) {
+        let key = gossip.message.public_key.clone();
+        self.records.insert(
+            key,
+            NodeRecord {
+                gossip,
+                confirmed: true,
+            },
+        );
+    }
+
+    /// Adds a record from a seeding snapshot: kept, but untrusted until
+    /// corroborated. An existing confirmed record is never downgraded.
+    pub fn add_unconfirmed(&mut self, gossip: SignedGossip) {
+        let key = gossip.message.public_key.clone();
+        if let Some(existing) = self.records.get(&key) {
+            if existing.confirmed {
+                return;
+            }
+        }
+        self.records.insert(
+            key,
+            NodeRecord {
+                gossip,
+                confirmed: false,
+            },
+        );
+    }
+
+    /// Live gossip has corroborated a seeded record.
+    pub fn corroborate(&mut self, key: &PublicKey) {
+        if let Some(record) = self.records.get_mut(key) {
+            record.confirmed = true;
+        }
+    }
+
+    pub fn add_edge(&mut self, from: &PublicKey, to: &PublicKey) {
+        self.edges.insert((from.clone(), to.clone()));
+    }
+
+    pub fn has_edge(&self, from: &PublicKey, to: &PublicKey) -> bool {
+        self.edges.contains(&(from.clone(), to.clone()))
+    }
+
+    pub fn record(&self, key: &PublicKey) -> Option<&NodeRecord> {
+        self.records.get(key)
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &NodeRecord> {
+        self.records.values()
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = &(PublicKey, PublicKey)> {
+        self.edges.iter()
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Nodes eligible for exit selection: confirmed and advertising the
+    /// exit capability. Seeded-but-uncorroborated nodes never appear here.
+    pub fn eligible_exits(&self) -> Vec<&PublicKey> {
+        let mut exits: Vec<&PublicKey> = self
+            .records
+            .values()
+            .filter(|record| record.confirmed && record.has_capability("exit"))
+            .map(|record| record.public_key())
+            .collect();
+        exits.sort();
+        exits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neighborhood::gossip::GossipMessage;
+    use crate::sub_lib::cryptde::{CryptDE, PublicKey};
+    use crate::sub_lib::cryptde_null::CryptDENull;
+
+    pub fn signed_gossip_for(key_material: &[u8], capabilities: &[&str]) -> SignedGossip {
+        let cryptde = CryptDENull::from(&PublicKey::new(key_material));
+        GossipMessage {
+            public_key: cryptde.public_key().clone(),
+            node_addr_opt: None,
+            version: "0.4.0".to_string(),
+            protocol_version: 3,
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+            earning_wallet: None,
+            exit_earning_wallet: None,
+        }
+        .sign(&cryptde)
+    }
+
+    #[test]
+    fn confirmed_exit_capable_nodes_are_eligible_exits() {
+        let mut subject = NeighborhoodDatabase::new();
+        subject.add_confirmed(signed_gossip_for(b"exit_node", &["relay", "exit"]));
+        subject.add_confirmed(signed_gossip_for(b"relay_node", &["relay"]));
+
+        assert_eq!(subject.eligible_exits(), vec![&PublicKey::new(b"exit_node")]);
+    }
+
+    #[test]
+    fn unconfirmed_records_are_excluded_from_exit_selection() {
+        let mut subject = NeighborhoodDatabase::new();
+        subject.add_unconfirmed(signed_gossip_for(b"seeded_exit", &["exit"]));
+
+        assert!(subject.eligible_exits().is_empty());
+
+        subject.corroborate(&PublicKey::new(b"seeded_exit"));
+
+        assert_eq!(
+            subject.eligible_exits(),
+            vec![&PublicKey::new(b"seeded_exit")]
+        );
+    }
+
+    #[test]
+    fn seeding_never_downgrades_a_confirmed_record() {
+        let mut subject = NeighborhoodDatabase::new();
+        subject.add_confirmed(signed_gossip_for(b"node", &["exit"]));
+
+        subject.add_unconfirmed(signed_gossip_for(b"node", &[]));
+
+        let record = subject.record(&PublicKey::new(b"node")).unwrap();
+        assert!(record.confirmed);
+        assert!(record.has_capability("exit"));
+    }
+
+    #[test]
+    fn edges_are_directional() {
+        let mut subject = NeighborhoodDatabase::new();
+        let a = PublicKey::new(b"a");
+        let b = PublicKey::new(b"b");
+        subject.add_edge(&a, &b);
+
+        assert!(subject.has_edge(&a, &b));
+        assert!(!subject.has_edge(&b, &a));
+    }
+}
@@ -0,0 +1,314 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! UPnP port mapping so nodes behind consumer NAT can accept inbound
+//! connections without the operator touching their router. The mapping is
+//! leased, not permanent: a renewal clock re-requests it before the lease
+//! runs out, and a gateway that stops cooperating is logged rather than
+//! crashed over — the node keeps running, it just stops being reachable
+//! from outside.
+
+use crate::sub_lib::logger::Logger;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+/// Mockable face of the IGD gateway. The real implementation drives the
+/// `igd` crate; tests substitute canned answers.
+pub trait UpnpGateway: Send {
+    fn add_port_mapping(
+        &self,
+        external_port: u16,
+        internal_port: u16,
+        lease_seconds: u32,
+    ) -> Result<(), String>;
+    fn external_ip(&self) -> Result<Ipv4Addr, String>;
+    fn remove_port_mapping(&self, external_port: u16) -> Result<(), String>;
+}
+
+pub struct UpnpGatewayReal {
+    gateway: igd::Gateway,
+}
+
+impl UpnpGatewayReal {
+    /// Discovers the gateway on the local network; None when there is no
+    /// IGD device to talk to.
+    pub fn discover() -> Option<UpnpGatewayReal> {
+        igd::search_gateway(Default::default())
+            .ok()
+            .map(|gateway| UpnpGatewayReal { gateway })
+    }
+}
+
+impl UpnpGateway for UpnpGatewayReal {
+    fn add_port_mapping(
+        &self,
+        external_port: u16,
+        internal_port: u16,
+        lease_seconds: u32,
+    ) -> Result<(), String> {
+        let local_ip = local_ipv4().ok_or_else(|| "no local IPv4 address".to_string())?;
+        self.gateway
+            .add_port(
+                igd::PortMappingProtocol::TCP,
+                external_port,
+                std::net::SocketAddrV4::new(local_ip, internal_port),
+                lease_seconds,
+                "ClandestiNode",
+            )
+            .map_err(|e| e.to_string())
+    }
+
+    fn external_ip(&self) -> Result<Ipv4Addr, String> {
+        self.gateway.get_external_ip().map_err(|e| e.to_string())
+    }
+
+    fn remove_port_mapping(&self, external_port: u16) -> Result<(), String> {
+        self.gateway
+            .remove_port(igd::PortMappingProtocol::TCP, external_port)
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn local_ipv4() -> Option<Ipv4Addr> {
+    // Routing trick: connecting a UDP socket picks the outbound interface
+    // without sending anything.
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("1.1.1.1:80").ok()?;
+    match socket.local_addr().ok()? {
+        std::net::SocketAddr::V4(addr) => Some(*addr.ip()),
+        std::net::SocketAddr::V6(_) => None,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortMappingConfig {
+    pub upnp_enabled: bool,
+    /// How often the lease is re-requested. Kept well under the lease
+    /// duration so a missed renewal does not drop the mapping.
+    pub upnp_lease_renewal: Duration,
+}
+
+impl Default for PortMappingConfig {
+    fn default() -> Self {
+        PortMappingConfig {
+            upnp_enabled: false,
+            upnp_lease_renewal: Duration::from_secs(20 * 60),
+        }
+    }
+}
+
+/// Seconds of lease requested from the gateway: twice the default renewal
+/// interval, so one missed renewal is survivable.
+const LEASE_SECONDS: u32 = 40 * 60;
+
+pub struct PortMapper {
+    config: PortMappingConfig,
+    gateway: Box<dyn UpnpGateway>,
+    port: u16,
+    last_renewal: Option<Instant>,
+    logger: Logger,
+}
+
+impl PortMapper {
+    pub fn new(config: PortMappingConfig, gateway: Box<dyn UpnpGateway>, port: u16) -> PortMapper {
+        PortMapper {
+            config,
+            gateway,
+            port,
+            last_renewal: None,
+            logger: Logger::new("PortMapper"),
+        }
+    }
+
+    /// Requests the initial mapping. Returns the external address on
+    /// success; failure is logged and reported but is not fatal.
+    pub fn establish(&mut self, now: Instant) -> Result<(Ipv4Addr, u16), String> {
+        if !self.config.upnp_enabled {
+            return Err("UPnP disabled by configuration".to_string());
+        }
+        self.gateway
+            .add_port_mapping(self.port, self.port, LEASE_SECONDS)
+            .map_err(|e| {
+                self.logger
+                    .warning(format!("UPnP mapping for port {} failed: {}", self.port, e));
+                e
+            })?;
+        let external_ip = self.gateway.external_ip().map_err(|e| {
+            self.logger
+                .warning(format!("UPnP external IP query failed: {}", e));
+            e
+        })?;
+        self.last_renewal = Some(now);
+        self.logger.info(format!(
+            "UPnP mapping established: external {}:{} -> local port {}",
+            external_ip, self.port, self.port
+        ));
+        Ok((external_ip, self.port))
+    }
+
+    /// Called periodically; re-requests the mapping once the renewal
+    /// interval has passed. True when a renewal was attempted.
+    pub fn maybe_renew(&mut self, now: Instant) -> bool {
+        if !self.config.upnp_enabled {
+            return false;
+        }
+        let due = match self.last_renewal {
+            None => true,
+            Some(last) => now.duration_since(last) >= self.config.upnp_lease_renewal,
+        };
+        if !due {
+            return false;
+        }
+        match self
+            .gateway
+            .add_port_mapping(self.port, self.port, LEASE_SECONDS)
+        {
+            Ok(()) => {
+                self.last_renewal = Some(now);
+                self.logger
+                    .debug(format!("UPnP lease renewed for port {}", self.port));
+            }
+            Err(e) => self
+                .logger
+                .warning(format!("UPnP lease renewal for port {} failed: {}", self.port, e)),
+        }
+        true
+    }
+
+    /// Best-effort cleanup at shutdown.
+    pub fn tear_down(&mut self) {
+        if self.last_renewal.is_some() {
+            if let Err(e) = self.gateway.remove_port_mapping(self.port) {
+                self.logger
+                    .debug(format!("UPnP unmapping for port {} failed: {}", self.port, e));
+            }
+            self.last_renewal = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct UpnpGatewayMock {
+        add_port_params: Arc<Mutex<Vec<(u16, u16, u32)>>>,
+        add_port_results: Mutex<Vec<Result<(), String>>>,
+        external_ip_result: Result<Ipv4Addr, String>,
+        remove_port_params: Arc<Mutex<Vec<u16>>>,
+    }
+
+    impl UpnpGateway for UpnpGatewayMock {
+        fn add_port_mapping(
+            &self,
+            external_port: u16,
+            internal_port: u16,
+            lease_seconds: u32,
+        ) -> Result<(), String> {
+            self.add_port_params
+                .lock()
+                .unwrap()
+                .push((external_port, internal_port, lease_seconds));
+            self.add_port_results.lock().unwrap().remove(0)
+        }
+
+        fn external_ip(&self) -> Result<Ipv4Addr, String> {
+            self.external_ip_result.clone()
+        }
+
+        fn remove_port_mapping(&self, external_port: u16) -> Result<(), String> {
+            self.remove_port_params.lock().unwrap().push(external_port);
+            Ok(())
+        }
+    }
+
+    fn make_gateway(
+        add_port_results: Vec<Result<(), String>>,
+    ) -> (
+        UpnpGatewayMock,
+        Arc<Mutex<Vec<(u16, u16, u32)>>>,
+        Arc<Mutex<Vec<u16>>>,
+    ) {
+        let add_port_params = Arc::new(Mutex::new(vec![]));
+        let remove_port_params = Arc::new(Mutex::new(vec![]));
+        let gateway = UpnpGatewayMock {
+            add_port_params: add_port_params.clone(),
+            add_port_results: Mutex::new(add_port_results),
+            external_ip_result: Ok(Ipv4Addr::new(203, 0, 113, 7)),
+            remove_port_params: remove_port_params.clone(),
+        };
+        (gateway, add_port_params, remove_port_params)
+    }
+
+    fn enabled_config() -> PortMappingConfig {
+        PortMappingConfig {
+            upnp_enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn establish_maps_the_port_and_reports_the_external_address() {
+        let (gateway, add_port_params, _) = make_gateway(vec![Ok(())]);
+        let mut subject = PortMapper::new(enabled_config(), Box::new(gateway), 4646);
+
+        let result = subject.establish(Instant::now()).unwrap();
+
+        assert_eq!(result, (Ipv4Addr::new(203, 0, 113, 7), 4646));
+        assert_eq!(*add_port_params.lock().unwrap(), vec![(4646, 4646, LEASE_SECONDS)]);
+    }
+
+    #[test]
+    fn disabled_upnp_never_touches_the_gateway() {
+        let (gateway, add_port_params, _) = make_gateway(vec![]);
+        let mut subject = PortMapper::new(PortMappingConfig::default(), Box::new(gateway), 4646);
+
+        let result = subject.establish(Instant::now());
+        let renewed = subject.maybe_renew(Instant::now());
+
+        assert!(result.is_err());
+        assert!(!renewed);
+        assert!(add_port_params.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn gateway_refusal_is_reported_not_fatal() {
+        let (gateway, _, _) = make_gateway(vec![Err("403 from router".to_string())]);
+        let mut subject = PortMapper::new(enabled_config(), Box::new(gateway), 4646);
+
+        let result = subject.establish(Instant::now());
+
+        assert_eq!(result, Err("403 from router".to_string()));
+    }
+
+    #[test]
+    fn renewal_waits_for_the_interval_then_remaps() {
+        let (gateway, add_port_params, _) = make_gateway(vec![Ok(()), Ok(())]);
+        let config = PortMappingConfig {
+            upnp_enabled: true,
+            upnp_lease_renewal: Duration::from_secs(600),
+        };
+        let mut subject = PortMapper::new(config, Box::new(gateway), 4646);
+        let start = Instant::now();
+        subject.establish(start).unwrap();
+
+        let too_early = subject.maybe_renew(start + Duration::from_secs(599));
+        let on_time = subject.maybe_renew(start + Duration::from_secs(600));
+
+        assert!(!too_early);
+        assert!(on_time);
+        assert_eq!(add_port_params.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn tear_down_removes_an_established_mapping_exactly_once() {
+        let (gateway, _, remove_port_params) = make_gateway(vec![Ok(())]);
+        let mut subject = PortMapper::new(enabled_config(), Box::new(gateway), 4646);
+        subject.establish(Instant::now()).unwrap();
+
+        subject.tear_down();
+        subject.tear_down();
+
+        assert_eq!(*remove_port_params.lock().unwrap(), vec![4646]);
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Import/export of the neighborhood database for cold-start seeding. A lab
+//! fleet can export one warmed-up node's picture of the network and feed it
+//! to fresh nodes via `--seed-neighborhood-file`; imported records are
+//! low-trust until regular gossip corroborates them.
+
+use crate::neighborhood::gossip::SignedGossip;
+use crate::neighborhood::neighborhood_database::NeighborhoodDatabase;
+use crate::sub_lib::cryptde::PublicKey;
+use serde::{Deserialize, Serialize};
+
+/// Bump when the snapshot layout changes; importers refuse unknown versions.
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NeighborhoodSnapshot {
+    pub format_version: u32,
+    pub records: Vec<SignedGossip>,
+    pub edges: Vec<(PublicKey, PublicKey)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    UnsupportedVersion { found: u32, supported: u32 },
+    MalformedSnapshot(String),
+}
+
+/// Serializes the local database. Record signatures travel with the records,
+/// so the importing side can verify each one against its claimed key.
+pub fn export_snapshot(database: &NeighborhoodDatabase) -> Vec<u8> {
+    let snapshot = NeighborhoodSnapshot {
+        format_version: SNAPSHOT_FORMAT_VERSION,
+        records: database
+            .records()
+            .map(|record| record.gossip.clone())
+            .collect(),
+        edges: database.edges().cloned().collect(),
+    };
+    serde_cbor::ser::to_vec(&snapshot).expect("Snapshot serialization should never fail")
+}
+
+/// Imports a snapshot into the database as unconfirmed initial state.
+/// Returns the number of records imported.
+pub fn import_snapshot(
+    database: &mut NeighborhoodDatabase,
+    bytes: &[u8],
+) -> Result<usize, SnapshotError> {
+    let snapshot: NeighborhoodSnapshot = serde_cbor::de::from_slice(bytes)
+        .map_err(|e| SnapshotError::MalformedSnapshot(e.to_string()))?;
+    if snapshot.format_version != SNAPSHOT_FORMAT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion {
+            found: snapshot.format_version,
+            supported: SNAPSHOT_FORMAT_VERSION,
+        });
+    }
+    let mut imported = 0;
+    for gossip in snapshot.records {
+        database.add_unconfirmed(gossip);
+        imported += 1;
+    }
+    for (from, to) in snapshot.edges {
+        database.add_edge(&from, &to);
+    }
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neighborhood::gossip::GossipMessage;
+    use crate::sub_lib::cryptde::CryptDE;
+    use crate::sub_lib::cryptde_null::CryptDENull;
+
+    fn signed_gossip_for(key_material: &[u8], capabilities: &[&str]) -> SignedGossip {
+        let cryptde = CryptDENull::from(&PublicKey::new(key_material));
+        GossipMessage {
+            public_key: cryptde.public_key().clone(),
+            node_addr_opt: None,
+            version: "0.4.0".to_string(),
+            protocol_version: 3,
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+            earning_wallet: None,
+            exit_earning_wallet: None,
+        }
+        .sign(&cryptde)
+    }
+
+    #[test]
+    fn export_import_round_trip_preserves_records_and_edges() {
+        let mut source = NeighborhoodDatabase::new();
+        source.add_confirmed(signed_gossip_for(b"relay", &["relay"]));
+        source.add_confirmed(signed_gossip_for(b"exit", &["relay", "exit"]));
+        source.add_edge(&PublicKey::new(b"relay"), &PublicKey::new(b"exit"));
+        let bytes = export_snapshot(&source);
+
+        let mut target = NeighborhoodDatabase::new();
+        let imported = import_snapshot(&mut target, &bytes).unwrap();
+
+        assert_eq!(imported, 2);
+        assert_eq!(target.node_count(), 2);
+        assert!(target.has_edge(&PublicKey::new(b"relay"), &PublicKey::new(b"exit")));
+        // Routing through seeded records is possible...
+        assert!(target.record(&PublicKey::new(b"relay")).is_some());
+    }
+
+    #[test]
+    fn imported_exits_stay_restricted_until_corroborated() {
+        let mut source = NeighborhoodDatabase::new();
+        source.add_confirmed(signed_gossip_for(b"exit", &["exit"]));
+        let bytes = export_snapshot(&source);
+        let mut target = NeighborhoodDatabase::new();
+        import_snapshot(&mut target, &bytes).unwrap();
+
+        assert!(target.eligible_exits().is_empty());
+
+        target.corroborate(&PublicKey::new(b"exit"));
+
+        assert_eq!(target.eligible_exits(), vec![&PublicKey::new(b"exit")]);
+    }
+
+    #[test]
+    fn unknown_format_version_is_refused() {
+        let snapshot = NeighborhoodSnapshot {
+            format_version: SNAPSHOT_FORMAT_VERSION + 1,
+            records: vec![],
+            edges: vec![],
+        };
+        let bytes = serde_cbor::ser::to_vec(&snapshot).unwrap();
+
+        let result = import_snapshot(&mut NeighborhoodDatabase::new(), &bytes);
+
+        assert_eq!(
+            result,
+            Err(SnapshotError::UnsupportedVersion {
+                found: SNAPSHOT_FORMAT_VERSION + 1,
+                supported: SNAPSHOT_FORMAT_VERSION,
+            })
+        );
+    }
+
+    #[test]
+    fn garbage_bytes_are_reported_as_malformed() {
+        let result = import_snapshot(&mut NeighborhoodDatabase::new(), b"not a snapshot");
+
+        assert!(matches!(result, Err(SnapshotError::MalformedSnapshot(_))));
+    }
+}
@@ -0,0 +1,280 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! External IP discovery via STUN (RFC 5389) for nodes behind NAT. A
+//! binding request is sent to each configured server in order; the first
+//! parsable XOR-MAPPED-ADDRESS wins. The protocol footprint used here is
+//! tiny, so the binding request and response are hand-rolled rather than
+//! pulling in a STUN crate for two packet shapes.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const STUN_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StunConfig {
+    /// Tried in order; empty disables discovery.
+    pub stun_servers: Vec<SocketAddr>,
+    /// Used when every STUN server fails or none is configured.
+    pub external_ip: Option<IpAddr>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StunError {
+    /// Every server either failed to answer or answered garbage, and no
+    /// fallback IP was configured.
+    NoUsableAnswer,
+}
+
+/// Mockable single request/response exchange with one STUN server.
+pub trait StunTransport: Send {
+    fn exchange(&self, server: SocketAddr, request: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+pub struct StunTransportReal;
+
+impl StunTransport for StunTransportReal {
+    fn exchange(&self, server: SocketAddr, request: &[u8]) -> io::Result<Vec<u8>> {
+        let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_read_timeout(Some(STUN_TIMEOUT))?;
+        socket.send_to(request, server)?;
+        let mut buffer = [0u8; 576];
+        let (count, _) = socket.recv_from(&mut buffer)?;
+        Ok(buffer[..count].to_vec())
+    }
+}
+
+/// Asks each configured server for this node's mapped address. Falls back
+/// to the configured external IP; only when both avenues are exhausted
+/// does the caller get an error.
+pub fn discover_external_ip(
+    transport: &dyn StunTransport,
+    config: &StunConfig,
+    transaction_id: [u8; 12],
+) -> Result<IpAddr, StunError> {
+    for server in &config.stun_servers {
+        let request = build_binding_request(transaction_id);
+        let response = match transport.exchange(*server, &request) {
+            Ok(response) => response,
+            Err(_) => continue,
+        };
+        if let Some(addr) = parse_binding_response(&response, transaction_id) {
+            return Ok(addr.ip());
+        }
+    }
+    config.external_ip.ok_or(StunError::NoUsableAnswer)
+}
+
+pub fn build_binding_request(transaction_id: [u8; 12]) -> Vec<u8> {
+    let mut request = Vec::with_capacity(20);
+    request.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes()); // no attributes
+    request.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(&transaction_id);
+    request
+}
+
+/// Extracts the mapped address from a binding success response, preferring
+/// XOR-MAPPED-ADDRESS and tolerating legacy MAPPED-ADDRESS. Anything
+/// malformed — wrong type, wrong transaction, truncated attributes —
+/// yields None and the next server is tried.
+pub fn parse_binding_response(
+    response: &[u8],
+    transaction_id: [u8; 12],
+) -> Option<SocketAddr> {
+    if response.len() < 20 {
+        return None;
+    }
+    let message_type = u16::from_be_bytes([response[0], response[1]]);
+    if message_type != BINDING_SUCCESS {
+        return None;
+    }
+    if response[4..8] != MAGIC_COOKIE.to_be_bytes() || response[8..20] != transaction_id {
+        return None;
+    }
+    let mut mapped = None;
+    let mut offset = 20;
+    while offset + 4 <= response.len() {
+        let attr_type = u16::from_be_bytes([response[offset], response[offset + 1]]);
+        let attr_len = u16::from_be_bytes([response[offset + 2], response[offset + 3]]) as usize;
+        let value_start = offset + 4;
+        let value_end = value_start + attr_len;
+        if value_end > response.len() {
+            return None;
+        }
+        let value = &response[value_start..value_end];
+        match attr_type {
+            ATTR_XOR_MAPPED_ADDRESS => {
+                if let Some(addr) = parse_address_attribute(value, true) {
+                    return Some(addr);
+                }
+            }
+            ATTR_MAPPED_ADDRESS => {
+                if mapped.is_none() {
+                    mapped = parse_address_attribute(value, false);
+                }
+            }
+            _ => (),
+        }
+        // Attributes are padded to 4-byte boundaries.
+        offset = value_end + ((4 - attr_len % 4) % 4);
+    }
+    mapped
+}
+
+fn parse_address_attribute(value: &[u8], xored: bool) -> Option<SocketAddr> {
+    if value.len() < 8 || value[1] != 0x01 {
+        // Only the IPv4 family (0x01) is handled; NATed IPv6 is not a thing
+        // this node worries about.
+        return None;
+    }
+    let mut port = u16::from_be_bytes([value[2], value[3]]);
+    let mut ip_bits = u32::from_be_bytes([value[4], value[5], value[6], value[7]]);
+    if xored {
+        port ^= (MAGIC_COOKIE >> 16) as u16;
+        ip_bits ^= MAGIC_COOKIE;
+    }
+    Some(SocketAddr::new(
+        IpAddr::V4(Ipv4Addr::from(ip_bits)),
+        port,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    const TRANSACTION_ID: [u8; 12] = [9; 12];
+
+    fn success_response(ip: Ipv4Addr, port: u16) -> Vec<u8> {
+        let xored_port = port ^ (MAGIC_COOKIE >> 16) as u16;
+        let xored_ip = u32::from(ip) ^ MAGIC_COOKIE;
+        let mut response = vec![];
+        response.extend_from_slice(&BINDING_SUCCESS.to_be_bytes());
+        response.extend_from_slice(&12u16.to_be_bytes());
+        response.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        response.extend_from_slice(&TRANSACTION_ID);
+        response.extend_from_slice(&ATTR_XOR_MAPPED_ADDRESS.to_be_bytes());
+        response.extend_from_slice(&8u16.to_be_bytes());
+        response.extend_from_slice(&[0x00, 0x01]);
+        response.extend_from_slice(&xored_port.to_be_bytes());
+        response.extend_from_slice(&xored_ip.to_be_bytes());
+        response
+    }
+
+    struct StunTransportMock {
+        exchanges: Arc<Mutex<Vec<SocketAddr>>>,
+        results: Mutex<Vec<io::Result<Vec<u8>>>>,
+    }
+
+    impl StunTransport for StunTransportMock {
+        fn exchange(&self, server: SocketAddr, _request: &[u8]) -> io::Result<Vec<u8>> {
+            self.exchanges.lock().unwrap().push(server);
+            self.results.lock().unwrap().remove(0)
+        }
+    }
+
+    fn make_transport(
+        results: Vec<io::Result<Vec<u8>>>,
+    ) -> (StunTransportMock, Arc<Mutex<Vec<SocketAddr>>>) {
+        let exchanges = Arc::new(Mutex::new(vec![]));
+        let transport = StunTransportMock {
+            exchanges: exchanges.clone(),
+            results: Mutex::new(results),
+        };
+        (transport, exchanges)
+    }
+
+    fn servers(count: usize) -> Vec<SocketAddr> {
+        (0..count)
+            .map(|i| SocketAddr::from_str(&format!("198.51.100.{}:3478", i + 1)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn first_server_with_a_good_answer_wins() {
+        let (transport, exchanges) = make_transport(vec![Ok(success_response(
+            Ipv4Addr::new(203, 0, 113, 50),
+            60123,
+        ))]);
+        let config = StunConfig {
+            stun_servers: servers(2),
+            external_ip: None,
+        };
+
+        let result = discover_external_ip(&transport, &config, TRANSACTION_ID).unwrap();
+
+        assert_eq!(result, IpAddr::from_str("203.0.113.50").unwrap());
+        assert_eq!(exchanges.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn failing_servers_are_skipped_in_order() {
+        let (transport, exchanges) = make_transport(vec![
+            Err(io::Error::new(io::ErrorKind::TimedOut, "no answer")),
+            Ok(vec![0xFF; 40]), // garbage
+            Ok(success_response(Ipv4Addr::new(203, 0, 113, 51), 1)),
+        ]);
+        let config = StunConfig {
+            stun_servers: servers(3),
+            external_ip: None,
+        };
+
+        let result = discover_external_ip(&transport, &config, TRANSACTION_ID).unwrap();
+
+        assert_eq!(result, IpAddr::from_str("203.0.113.51").unwrap());
+        assert_eq!(*exchanges.lock().unwrap(), servers(3));
+    }
+
+    #[test]
+    fn configured_external_ip_is_the_fallback() {
+        let (transport, _) = make_transport(vec![Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "no answer",
+        ))]);
+        let config = StunConfig {
+            stun_servers: servers(1),
+            external_ip: Some(IpAddr::from_str("198.18.0.9").unwrap()),
+        };
+
+        let result = discover_external_ip(&transport, &config, TRANSACTION_ID).unwrap();
+
+        assert_eq!(result, IpAddr::from_str("198.18.0.9").unwrap());
+    }
+
+    #[test]
+    fn no_servers_and_no_fallback_is_an_error() {
+        let (transport, _) = make_transport(vec![]);
+
+        let result = discover_external_ip(&transport, &StunConfig::default(), TRANSACTION_ID);
+
+        assert_eq!(result, Err(StunError::NoUsableAnswer));
+    }
+
+    #[test]
+    fn response_with_wrong_transaction_id_is_rejected() {
+        let mut response = success_response(Ipv4Addr::new(203, 0, 113, 50), 60123);
+        response[8] ^= 0xFF;
+
+        assert_eq!(parse_binding_response(&response, TRANSACTION_ID), None);
+    }
+
+    #[test]
+    fn binding_request_has_the_rfc_shape() {
+        let request = build_binding_request(TRANSACTION_ID);
+
+        assert_eq!(request.len(), 20);
+        assert_eq!(&request[0..2], &BINDING_REQUEST.to_be_bytes());
+        assert_eq!(&request[2..4], &[0, 0]);
+        assert_eq!(&request[4..8], &MAGIC_COOKIE.to_be_bytes());
+        assert_eq!(&request[8..20], &TRANSACTION_ID);
+    }
+}
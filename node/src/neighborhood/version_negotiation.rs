@@ -0,0 +1,86 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+/// The gossip protocol version this build speaks.
+pub const CURRENT_PROTOCOL_VERSION: u16 = 3;
+
+/// How far behind a peer may be and still talk to us. Rolling upgrades mean
+/// version N must interoperate with N-1 and N-2; anything older is cut off.
+pub const COMPATIBILITY_WINDOW: u16 = 2;
+
+/// Versions at which optional features became available. A feature may only
+/// be used on a link whose negotiated version has reached its gate.
+pub const VERSION_COMPRESSION: u16 = 2;
+pub const VERSION_ED25519: u16 = 3;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NegotiationError {
+    Incompatible { local: u16, remote: u16 },
+}
+
+/// Settles on the protocol version for a link: the lower of the two, provided
+/// the two sides are within the compatibility window of each other.
+pub fn negotiate_version(local: u16, remote: u16) -> Result<u16, NegotiationError> {
+    let (lower, higher) = if local <= remote {
+        (local, remote)
+    } else {
+        (remote, local)
+    };
+    if higher - lower > COMPATIBILITY_WINDOW {
+        Err(NegotiationError::Incompatible { local, remote })
+    } else {
+        Ok(lower)
+    }
+}
+
+pub fn supports_compression(negotiated: u16) -> bool {
+    negotiated >= VERSION_COMPRESSION
+}
+
+pub fn supports_ed25519(negotiated: u16) -> bool {
+    negotiated >= VERSION_ED25519
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_version_negotiates_to_itself() {
+        assert_eq!(
+            negotiate_version(CURRENT_PROTOCOL_VERSION, CURRENT_PROTOCOL_VERSION),
+            Ok(CURRENT_PROTOCOL_VERSION)
+        );
+    }
+
+    #[test]
+    fn compatible_peers_settle_on_the_lower_version() {
+        assert_eq!(negotiate_version(3, 2), Ok(2));
+        assert_eq!(negotiate_version(1, 3), Ok(1));
+    }
+
+    #[test]
+    fn peers_outside_the_window_are_incompatible() {
+        assert_eq!(
+            negotiate_version(5, 2),
+            Err(NegotiationError::Incompatible {
+                local: 5,
+                remote: 2
+            })
+        );
+        assert_eq!(
+            negotiate_version(1, 4),
+            Err(NegotiationError::Incompatible {
+                local: 1,
+                remote: 4
+            })
+        );
+    }
+
+    #[test]
+    fn feature_gates_respect_the_negotiated_version() {
+        assert!(!supports_compression(1));
+        assert!(supports_compression(2));
+        assert!(!supports_ed25519(2));
+        assert!(supports_ed25519(3));
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! DNS server validation for the ProxyClient. An empty list used to be a
+//! panic deep inside `ProxyClient::new`; the requirement now lives here,
+//! where a configuration slip can be answered with discovery and, failing
+//! that, an error that explains itself. Loopback results are excluded:
+//! they point at a local stub resolver (often our own DNS subversion) and
+//! would send exit-side lookups in a circle.
+
+use std::net::IpAddr;
+
+/// Mockable view of the host's resolver configuration.
+pub trait DnsInspector: Send {
+    fn system_dns_servers(&self) -> Result<Vec<IpAddr>, String>;
+}
+
+pub struct DnsInspectorReal;
+
+impl DnsInspector for DnsInspectorReal {
+    fn system_dns_servers(&self) -> Result<Vec<IpAddr>, String> {
+        let contents = std::fs::read_to_string("/etc/resolv.conf")
+            .map_err(|e| format!("could not read /etc/resolv.conf: {}", e))?;
+        let servers: Vec<IpAddr> = contents
+            .lines()
+            .filter_map(|line| {
+                let mut words = line.split_whitespace();
+                match (words.next(), words.next()) {
+                    (Some("nameserver"), Some(address)) => address.parse().ok(),
+                    _ => None,
+                }
+            })
+            .collect();
+        Ok(servers)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DnsServersError {
+    /// Neither the flag nor discovery produced a usable server; the string
+    /// is the full operator-facing explanation.
+    NothingUsable(String),
+}
+
+/// Resolves the `--dns-servers` flag into a validated, non-empty list. An
+/// explicit flag wins as given; otherwise the host's resolvers are
+/// discovered and loopback entries dropped. The returned list is what
+/// `ProxyClient` receives, so it never needs to re-check.
+pub fn validate_dns_servers(
+    explicit: Option<Vec<IpAddr>>,
+    inspector: &dyn DnsInspector,
+) -> Result<Vec<IpAddr>, DnsServersError> {
+    if let Some(servers) = explicit {
+        if !servers.is_empty() {
+            return Ok(servers);
+        }
+    }
+    let discovery_failure = match inspector.system_dns_servers() {
+        Ok(discovered) => {
+            let usable: Vec<IpAddr> = discovered
+                .iter()
+                .filter(|ip| !ip.is_loopback())
+                .copied()
+                .collect();
+            if !usable.is_empty() {
+                return Ok(usable);
+            }
+            if discovered.is_empty() {
+                "the system resolver configuration lists no nameservers".to_string()
+            } else {
+                "the system resolver configuration lists only loopback nameservers, \
+                 which would route exit-side lookups back into this node"
+                    .to_string()
+            }
+        }
+        Err(e) => e,
+    };
+    Err(DnsServersError::NothingUsable(format!(
+        "No DNS servers available for exit traffic: --dns-servers was not supplied, and \
+         discovery failed ({}). Supply --dns-servers with at least one reachable, \
+         non-loopback resolver.",
+        discovery_failure
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    struct DnsInspectorMock {
+        result: Result<Vec<IpAddr>, String>,
+    }
+
+    impl DnsInspector for DnsInspectorMock {
+        fn system_dns_servers(&self) -> Result<Vec<IpAddr>, String> {
+            self.result.clone()
+        }
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn explicit_flag_wins_without_touching_discovery() {
+        let inspector = DnsInspectorMock {
+            result: Err("should not be called".to_string()),
+        };
+
+        let result =
+            validate_dns_servers(Some(vec![ip("9.9.9.9")]), &inspector).unwrap();
+
+        assert_eq!(result, vec![ip("9.9.9.9")]);
+    }
+
+    #[test]
+    fn discovery_supplies_servers_when_the_flag_is_absent() {
+        let inspector = DnsInspectorMock {
+            result: Ok(vec![ip("192.168.1.1"), ip("8.8.8.8")]),
+        };
+
+        let result = validate_dns_servers(None, &inspector).unwrap();
+
+        assert_eq!(result, vec![ip("192.168.1.1"), ip("8.8.8.8")]);
+    }
+
+    #[test]
+    fn loopback_discoveries_are_excluded() {
+        let inspector = DnsInspectorMock {
+            result: Ok(vec![ip("127.0.0.53"), ip("8.8.8.8")]),
+        };
+
+        let result = validate_dns_servers(None, &inspector).unwrap();
+
+        assert_eq!(result, vec![ip("8.8.8.8")]);
+    }
+
+    #[test]
+    fn loopback_only_discovery_is_rejected_with_an_explanation() {
+        let inspector = DnsInspectorMock {
+            result: Ok(vec![ip("127.0.0.53")]),
+        };
+
+        let result = validate_dns_servers(None, &inspector);
+
+        match result {
+            Err(DnsServersError::NothingUsable(message)) => {
+                assert!(message.contains("--dns-servers"));
+                assert!(message.contains("only loopback nameservers"));
+            }
+            other => panic!("expected NothingUsable, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn failed_discovery_produces_the_full_error_text() {
+        let inspector = DnsInspectorMock {
+            result: Err("could not read /etc/resolv.conf: permission denied".to_string()),
+        };
+
+        let result = validate_dns_servers(None, &inspector);
+
+        match result {
+            Err(DnsServersError::NothingUsable(message)) => {
+                assert!(message.contains("--dns-servers was not supplied"));
+                assert!(message.contains("permission denied"));
+            }
+            other => panic!("expected NothingUsable, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn empty_explicit_list_falls_through_to_discovery() {
+        let inspector = DnsInspectorMock {
+            result: Ok(vec![ip("8.8.4.4")]),
+        };
+
+        let result = validate_dns_servers(Some(vec![]), &inspector).unwrap();
+
+        assert_eq!(result, vec![ip("8.8.4.4")]);
+    }
+}
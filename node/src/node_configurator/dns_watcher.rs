@@ -0,0 +1,266 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Re-subversion watcher. DNS subversion succeeding once is no guarantee it
+//! stays: VPN clients and OS updates rewrite the system DNS settings and
+//! users' traffic silently stops flowing through the node. The watcher
+//! polls the effective DNS through the DnsModifier abstraction (file mtime
+//! on resolv.conf, registry polling on Windows, dynamic-store notifications
+//! on macOS — all behind the same trait), broadcasts a warning when the
+//! settings no longer point at the node, and repairs them when
+//! `--dns-subvert auto` is active. Rapid flapping is debounced so a VPN
+//! connect/disconnect cycle produces one warning, not a stream.
+
+use crate::sub_lib::logger::Logger;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Minimum quiet time between warnings for the same kind of change.
+pub const FLAP_DEBOUNCE: Duration = Duration::from_secs(30);
+
+/// Platform DNS access, already used for the original subversion; the
+/// watcher adds read-back and re-subversion on top.
+pub trait DnsModifier: Send {
+    /// The resolvers the system is effectively using right now.
+    fn effective_dns(&self) -> Result<Vec<IpAddr>, String>;
+    /// Points the system DNS back at the node.
+    fn subvert(&mut self) -> Result<(), String>;
+}
+
+/// Broadcast through the UI gateway when an external change is detected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsChangedBroadcast {
+    pub current_servers: Vec<IpAddr>,
+    pub repaired: bool,
+}
+
+pub struct DnsWatcher {
+    modifier: Box<dyn DnsModifier>,
+    /// The resolver address subversion installs, normally 127.0.0.1.
+    node_dns: IpAddr,
+    auto_resubvert: bool,
+    last_warning: Option<Instant>,
+    logger: Logger,
+}
+
+impl DnsWatcher {
+    pub fn new(modifier: Box<dyn DnsModifier>, node_dns: IpAddr, auto_resubvert: bool) -> DnsWatcher {
+        DnsWatcher {
+            modifier,
+            node_dns,
+            auto_resubvert,
+            last_warning: None,
+            logger: Logger::new("DnsWatcher"),
+        }
+    }
+
+    /// One poll of the effective DNS. Returns the broadcast to send through
+    /// the UI gateway, or None when the settings are fine (or the change is
+    /// within the debounce window of the last warning).
+    pub fn poll(&mut self, now: Instant) -> Option<DnsChangedBroadcast> {
+        let current = match self.modifier.effective_dns() {
+            Ok(servers) => servers,
+            Err(e) => {
+                self.logger
+                    .warning(format!("Could not read effective DNS: {}", e));
+                return None;
+            }
+        };
+        if current.contains(&self.node_dns) {
+            // Still subverted; a later flap may warn again.
+            self.last_warning = None;
+            return None;
+        }
+        if let Some(last) = self.last_warning {
+            if now.duration_since(last) < FLAP_DEBOUNCE {
+                return None;
+            }
+        }
+        self.last_warning = Some(now);
+        let repaired = if self.auto_resubvert {
+            match self.modifier.subvert() {
+                Ok(()) => {
+                    self.logger.info(
+                        "System DNS was changed externally; re-subverted automatically".to_string(),
+                    );
+                    true
+                }
+                Err(e) => {
+                    self.logger
+                        .warning(format!("Re-subversion after external DNS change failed: {}", e));
+                    false
+                }
+            }
+        } else {
+            self.logger.warning(format!(
+                "System DNS was changed externally to {:?}; traffic is no longer routed through \
+                 the node. Run the subvert command to repair.",
+                current
+            ));
+            false
+        };
+        Some(DnsChangedBroadcast {
+            current_servers: current,
+            repaired,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    struct DnsModifierMock {
+        effective_dns_results: Mutex<Vec<Result<Vec<IpAddr>, String>>>,
+        subvert_params: Arc<Mutex<Vec<()>>>,
+        subvert_results: Mutex<Vec<Result<(), String>>>,
+    }
+
+    impl DnsModifierMock {
+        fn new() -> DnsModifierMock {
+            DnsModifierMock {
+                effective_dns_results: Mutex::new(vec![]),
+                subvert_params: Arc::new(Mutex::new(vec![])),
+                subvert_results: Mutex::new(vec![]),
+            }
+        }
+
+        fn effective_dns_result(self, result: Result<Vec<IpAddr>, String>) -> DnsModifierMock {
+            self.effective_dns_results.lock().unwrap().push(result);
+            self
+        }
+
+        fn subvert_params(mut self, params: &Arc<Mutex<Vec<()>>>) -> DnsModifierMock {
+            self.subvert_params = params.clone();
+            self
+        }
+
+        fn subvert_result(self, result: Result<(), String>) -> DnsModifierMock {
+            self.subvert_results.lock().unwrap().push(result);
+            self
+        }
+    }
+
+    impl DnsModifier for DnsModifierMock {
+        fn effective_dns(&self) -> Result<Vec<IpAddr>, String> {
+            self.effective_dns_results.lock().unwrap().remove(0)
+        }
+
+        fn subvert(&mut self) -> Result<(), String> {
+            self.subvert_params.lock().unwrap().push(());
+            self.subvert_results.lock().unwrap().remove(0)
+        }
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn an_unchanged_subversion_produces_no_broadcast() {
+        let modifier =
+            DnsModifierMock::new().effective_dns_result(Ok(vec![ip("127.0.0.1")]));
+        let mut subject = DnsWatcher::new(Box::new(modifier), ip("127.0.0.1"), false);
+
+        assert_eq!(subject.poll(Instant::now()), None);
+    }
+
+    #[test]
+    fn an_external_change_broadcasts_a_warning_without_repair() {
+        let subvert_params = Arc::new(Mutex::new(vec![]));
+        let modifier = DnsModifierMock::new()
+            .subvert_params(&subvert_params)
+            .effective_dns_result(Ok(vec![ip("10.8.0.1")]));
+        let mut subject = DnsWatcher::new(Box::new(modifier), ip("127.0.0.1"), false);
+
+        let broadcast = subject.poll(Instant::now());
+
+        assert_eq!(
+            broadcast,
+            Some(DnsChangedBroadcast {
+                current_servers: vec![ip("10.8.0.1")],
+                repaired: false,
+            })
+        );
+        assert!(subvert_params.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn auto_mode_repairs_and_reports_the_repair() {
+        let subvert_params = Arc::new(Mutex::new(vec![]));
+        let modifier = DnsModifierMock::new()
+            .subvert_params(&subvert_params)
+            .effective_dns_result(Ok(vec![ip("10.8.0.1")]))
+            .subvert_result(Ok(()));
+        let mut subject = DnsWatcher::new(Box::new(modifier), ip("127.0.0.1"), true);
+
+        let broadcast = subject.poll(Instant::now());
+
+        assert_eq!(
+            broadcast,
+            Some(DnsChangedBroadcast {
+                current_servers: vec![ip("10.8.0.1")],
+                repaired: true,
+            })
+        );
+        assert_eq!(subvert_params.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn a_failed_repair_is_broadcast_as_unrepaired() {
+        let modifier = DnsModifierMock::new()
+            .effective_dns_result(Ok(vec![ip("10.8.0.1")]))
+            .subvert_result(Err("permission denied".to_string()));
+        let mut subject = DnsWatcher::new(Box::new(modifier), ip("127.0.0.1"), true);
+
+        let broadcast = subject.poll(Instant::now()).unwrap();
+
+        assert!(!broadcast.repaired);
+    }
+
+    #[test]
+    fn rapid_flapping_is_debounced() {
+        let modifier = DnsModifierMock::new()
+            .effective_dns_result(Ok(vec![ip("10.8.0.1")]))
+            .effective_dns_result(Ok(vec![ip("10.8.0.2")]))
+            .effective_dns_result(Ok(vec![ip("10.8.0.3")]));
+        let mut subject = DnsWatcher::new(Box::new(modifier), ip("127.0.0.1"), false);
+        let start = Instant::now();
+
+        let first = subject.poll(start);
+        let during_debounce = subject.poll(start + Duration::from_secs(5));
+        let after_debounce = subject.poll(start + FLAP_DEBOUNCE);
+
+        assert!(first.is_some());
+        assert_eq!(during_debounce, None);
+        assert!(after_debounce.is_some());
+    }
+
+    #[test]
+    fn recovery_resets_the_debounce_so_the_next_change_warns_immediately() {
+        let modifier = DnsModifierMock::new()
+            .effective_dns_result(Ok(vec![ip("10.8.0.1")]))
+            .effective_dns_result(Ok(vec![ip("127.0.0.1")]))
+            .effective_dns_result(Ok(vec![ip("10.8.0.1")]));
+        let mut subject = DnsWatcher::new(Box::new(modifier), ip("127.0.0.1"), false);
+        let start = Instant::now();
+
+        let first = subject.poll(start);
+        let recovered = subject.poll(start + Duration::from_secs(1));
+        let second = subject.poll(start + Duration::from_secs(2));
+
+        assert!(first.is_some());
+        assert_eq!(recovered, None);
+        assert!(second.is_some());
+    }
+
+    #[test]
+    fn an_unreadable_dns_state_is_logged_but_not_broadcast() {
+        let modifier =
+            DnsModifierMock::new().effective_dns_result(Err("resolv.conf vanished".to_string()));
+        let mut subject = DnsWatcher::new(Box::new(modifier), ip("127.0.0.1"), true);
+
+        assert_eq!(subject.poll(Instant::now()), None);
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Validation for the ProxyServer intercept listener ports. Unprivileged
+//! nodes cannot bind 80/443, so `--http-port`/`--tls-port` overrides are
+//! allowed — but they only make sense with an explicit browser/proxy
+//! configuration, so combining them with automatic DNS subversion draws a
+//! warning.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DnsSubvertMode {
+    Auto,
+    Manual,
+    Off,
+}
+
+/// How traffic on an extra intercept port is treated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortMode {
+    Http,
+    Tls,
+    /// Pure byte relay: nothing sniffed, destination from the original
+    /// socket destination. Only meaningful in transparent mode.
+    Raw,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExtraInterceptPort {
+    pub port: u16,
+    pub mode: PortMode,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterceptPortConfig {
+    pub http_port: u16,
+    pub tls_port: u16,
+    /// Accept iptables-REDIRECTed traffic and learn the true destination
+    /// via SO_ORIGINAL_DST.
+    pub transparent: bool,
+    /// Additional listeners beyond the standard pair, each with its own
+    /// handling mode (e.g. 8443 as tls, 993 as raw).
+    pub extra_ports: Vec<ExtraInterceptPort>,
+}
+
+impl Default for InterceptPortConfig {
+    fn default() -> Self {
+        InterceptPortConfig {
+            http_port: 80,
+            tls_port: 443,
+            transparent: false,
+            extra_ports: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InterceptPortError {
+    PortCollision(u16),
+    /// A raw-mode port without transparent mode has no way to learn its
+    /// destination.
+    RawRequiresTransparent(u16),
+}
+
+/// Resolves the override flags into a listener configuration, collecting
+/// human-readable warnings for combinations that are legal but suspicious.
+pub fn configure_intercept_ports(
+    http_port_override: Option<u16>,
+    tls_port_override: Option<u16>,
+    dns_subvert_mode: DnsSubvertMode,
+    transparent: bool,
+    extra_ports: Vec<ExtraInterceptPort>,
+) -> Result<(InterceptPortConfig, Vec<String>), InterceptPortError> {
+    let defaults = InterceptPortConfig::default();
+    let config = InterceptPortConfig {
+        http_port: http_port_override.unwrap_or(defaults.http_port),
+        tls_port: tls_port_override.unwrap_or(defaults.tls_port),
+        transparent,
+        extra_ports,
+    };
+    if config.http_port == config.tls_port {
+        return Err(InterceptPortError::PortCollision(config.http_port));
+    }
+    let mut seen_ports = vec![config.http_port, config.tls_port];
+    for extra in &config.extra_ports {
+        if seen_ports.contains(&extra.port) {
+            return Err(InterceptPortError::PortCollision(extra.port));
+        }
+        seen_ports.push(extra.port);
+        if extra.mode == PortMode::Raw && !config.transparent {
+            return Err(InterceptPortError::RawRequiresTransparent(extra.port));
+        }
+    }
+    let mut warnings = vec![];
+    let ports_overridden = http_port_override.is_some() || tls_port_override.is_some();
+    if ports_overridden && dns_subvert_mode == DnsSubvertMode::Auto && !transparent {
+        warnings.push(format!(
+            "Non-standard intercept ports ({}/{}) with --dns-subvert auto: subverted traffic \
+             will still arrive on 80/443. Use an explicit proxy configuration, or --transparent \
+             with an iptables REDIRECT rule.",
+            config.http_port, config.tls_port
+        ));
+    }
+    Ok((config, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_the_privileged_ports() {
+        let (config, warnings) =
+            configure_intercept_ports(None, None, DnsSubvertMode::Off, false, vec![]).unwrap();
+
+        assert_eq!(config, InterceptPortConfig::default());
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn overrides_are_honored() {
+        let (config, warnings) =
+            configure_intercept_ports(Some(8080), Some(8443), DnsSubvertMode::Manual, false, vec![])
+                .unwrap();
+
+        assert_eq!(config.http_port, 8080);
+        assert_eq!(config.tls_port, 8443);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn overridden_ports_with_auto_subversion_draw_a_warning() {
+        let (_, warnings) =
+            configure_intercept_ports(Some(8080), None, DnsSubvertMode::Auto, false, vec![]).unwrap();
+
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("--dns-subvert auto"));
+    }
+
+    #[test]
+    fn transparent_mode_silences_the_auto_subversion_warning() {
+        let (config, warnings) =
+            configure_intercept_ports(Some(8080), None, DnsSubvertMode::Auto, true, vec![]).unwrap();
+
+        assert!(config.transparent);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn colliding_ports_are_rejected() {
+        let result = configure_intercept_ports(Some(9000), Some(9000), DnsSubvertMode::Off, false, vec![]);
+
+        assert_eq!(result, Err(InterceptPortError::PortCollision(9000)));
+    }
+
+    #[test]
+    fn extra_ports_with_modes_are_accepted_in_transparent_mode() {
+        let extras = vec![
+            ExtraInterceptPort {
+                port: 8443,
+                mode: PortMode::Tls,
+            },
+            ExtraInterceptPort {
+                port: 993,
+                mode: PortMode::Raw,
+            },
+        ];
+
+        let (config, _) =
+            configure_intercept_ports(None, None, DnsSubvertMode::Off, true, extras.clone())
+                .unwrap();
+
+        assert_eq!(config.extra_ports, extras);
+    }
+
+    #[test]
+    fn an_extra_port_colliding_with_a_standard_port_is_rejected() {
+        let extras = vec![ExtraInterceptPort {
+            port: 443,
+            mode: PortMode::Tls,
+        }];
+
+        let result = configure_intercept_ports(None, None, DnsSubvertMode::Off, true, extras);
+
+        assert_eq!(result, Err(InterceptPortError::PortCollision(443)));
+    }
+
+    #[test]
+    fn duplicate_extra_ports_are_rejected() {
+        let extras = vec![
+            ExtraInterceptPort {
+                port: 8443,
+                mode: PortMode::Tls,
+            },
+            ExtraInterceptPort {
+                port: 8443,
+                mode: PortMode::Http,
+            },
+        ];
+
+        let result = configure_intercept_ports(None, None, DnsSubvertMode::Off, true, extras);
+
+        assert_eq!(result, Err(InterceptPortError::PortCollision(8443)));
+    }
+
+    #[test]
+    fn raw_mode_without_transparent_interception_is_rejected() {
+        let extras = vec![ExtraInterceptPort {
+            port: 993,
+            mode: PortMode::Raw,
+        }];
+
+        let result = configure_intercept_ports(None, None, DnsSubvertMode::Off, false, extras);
+
+        assert_eq!(result, Err(InterceptPortError::RawRequiresTransparent(993)));
+    }
+}
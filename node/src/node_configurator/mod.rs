@@ -0,0 +1,5 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+pub mod dns_servers;
+pub mod dns_watcher;
+pub mod intercept_ports;
@@ -0,0 +1,315 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Tor-style bridge mode. In censored regions plain ClandestiNode traffic
+//! is fingerprintable; with `ObfsMode::Obfs4` the node delegates its
+//! public listener to the `lyrebird` pluggable-transport binary, which
+//! speaks obfs4 to the outside and forwards deobfuscated connections to
+//! the node's real listener on localhost. The subprocess is managed
+//! through a spawner trait so tests never need the real binary.
+
+use crate::sub_lib::logger::Logger;
+use std::collections::HashMap;
+
+pub const DEFAULT_LYREBIRD_BINARY: &str = "lyrebird";
+
+/// Inbound obfuscation for the node's public listener.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ObfsMode {
+    /// Plain TCP, the historical behavior.
+    None,
+    /// Wrap inbound connections in obfs4 via a lyrebird subprocess.
+    Obfs4,
+    /// Tunnel traffic inside HTTPS to a CDN edge (domain fronting): TLS is
+    /// negotiated with `front_url` while the Host header names `back_url`,
+    /// so a censor sees only the CDN.
+    Meek { front_url: String, back_url: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BridgeError {
+    SpawnFailed(String),
+}
+
+/// A running transport subprocess, killable on shutdown.
+pub trait ManagedChild: Send {
+    fn kill(&mut self) -> Result<(), String>;
+    fn is_running(&mut self) -> bool;
+}
+
+/// Spawns the transport binary; mockable for tests.
+pub trait ProcessSpawner: Send {
+    fn spawn(
+        &self,
+        binary: &str,
+        env: &HashMap<String, String>,
+    ) -> Result<Box<dyn ManagedChild>, BridgeError>;
+}
+
+pub struct ProcessSpawnerReal;
+
+impl ProcessSpawner for ProcessSpawnerReal {
+    fn spawn(
+        &self,
+        binary: &str,
+        env: &HashMap<String, String>,
+    ) -> Result<Box<dyn ManagedChild>, BridgeError> {
+        let child = std::process::Command::new(binary)
+            .envs(env)
+            .spawn()
+            .map_err(|e| BridgeError::SpawnFailed(format!("{}: {}", binary, e)))?;
+        Ok(Box::new(ChildReal { child }))
+    }
+}
+
+struct ChildReal {
+    child: std::process::Child,
+}
+
+impl ManagedChild for ChildReal {
+    fn kill(&mut self) -> Result<(), String> {
+        self.child.kill().map_err(|e| format!("{}", e))
+    }
+
+    fn is_running(&mut self) -> bool {
+        // A child that try_wait reports no exit status for is still alive.
+        matches!(self.child.try_wait(), Ok(None))
+    }
+}
+
+/// The running bridge: owns the subprocess for the node's lifetime.
+pub struct ChildProcess {
+    child: Box<dyn ManagedChild>,
+}
+
+impl ChildProcess {
+    pub fn is_running(&mut self) -> bool {
+        self.child.is_running()
+    }
+}
+
+impl Drop for ChildProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+/// Configures and launches the lyrebird transport using the standard Tor
+/// pluggable-transport environment contract: lyrebird binds the public
+/// obfs4 listener and forwards plaintext to the node's real listener.
+pub struct Obfs4Bridge {
+    spawner: Box<dyn ProcessSpawner>,
+    binary: String,
+    /// Public port lyrebird listens on with obfs4.
+    public_port: u16,
+    /// Localhost port of the node's real listener.
+    forward_port: u16,
+    logger: Logger,
+}
+
+impl Obfs4Bridge {
+    pub fn new(
+        spawner: Box<dyn ProcessSpawner>,
+        binary: String,
+        public_port: u16,
+        forward_port: u16,
+    ) -> Obfs4Bridge {
+        Obfs4Bridge {
+            spawner,
+            binary,
+            public_port,
+            forward_port,
+            logger: Logger::new("Obfs4Bridge"),
+        }
+    }
+
+    pub fn start(&self) -> Result<ChildProcess, BridgeError> {
+        let child = self.spawner.spawn(&self.binary, &self.environment())?;
+        self.logger.info(format!(
+            "obfs4 bridge started: public port {}, forwarding to 127.0.0.1:{}",
+            self.public_port, self.forward_port
+        ));
+        Ok(ChildProcess { child })
+    }
+
+    fn environment(&self) -> HashMap<String, String> {
+        let mut env = HashMap::new();
+        env.insert("TOR_PT_MANAGED_TRANSPORT_VER".to_string(), "1".to_string());
+        env.insert("TOR_PT_SERVER_TRANSPORTS".to_string(), "obfs4".to_string());
+        env.insert(
+            "TOR_PT_SERVER_BINDADDR".to_string(),
+            format!("obfs4-0.0.0.0:{}", self.public_port),
+        );
+        env.insert(
+            "TOR_PT_ORPORT".to_string(),
+            format!("127.0.0.1:{}", self.forward_port),
+        );
+        env
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    struct ProcessSpawnerMock {
+        spawn_params: Arc<Mutex<Vec<(String, HashMap<String, String>)>>>,
+        spawn_results: Mutex<Vec<Result<Box<dyn ManagedChild>, BridgeError>>>,
+    }
+
+    impl ProcessSpawnerMock {
+        fn new() -> ProcessSpawnerMock {
+            ProcessSpawnerMock {
+                spawn_params: Arc::new(Mutex::new(vec![])),
+                spawn_results: Mutex::new(vec![]),
+            }
+        }
+
+        fn spawn_params(
+            mut self,
+            params: &Arc<Mutex<Vec<(String, HashMap<String, String>)>>>,
+        ) -> ProcessSpawnerMock {
+            self.spawn_params = params.clone();
+            self
+        }
+
+        fn spawn_result(
+            self,
+            result: Result<Box<dyn ManagedChild>, BridgeError>,
+        ) -> ProcessSpawnerMock {
+            self.spawn_results.lock().unwrap().push(result);
+            self
+        }
+    }
+
+    impl ProcessSpawner for ProcessSpawnerMock {
+        fn spawn(
+            &self,
+            binary: &str,
+            env: &HashMap<String, String>,
+        ) -> Result<Box<dyn ManagedChild>, BridgeError> {
+            self.spawn_params
+                .lock()
+                .unwrap()
+                .push((binary.to_string(), env.clone()));
+            self.spawn_results.lock().unwrap().remove(0)
+        }
+    }
+
+    /// Stands in for lyrebird: a TCP echo server on an ephemeral port,
+    /// alive until killed like the real subprocess would be.
+    struct EchoChild {
+        running: Arc<AtomicBool>,
+        port: u16,
+    }
+
+    impl EchoChild {
+        fn start() -> EchoChild {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let running = Arc::new(AtomicBool::new(true));
+            let thread_running = running.clone();
+            std::thread::spawn(move || {
+                while thread_running.load(Ordering::SeqCst) {
+                    let (mut stream, _) = match listener.accept() {
+                        Ok(accepted) => accepted,
+                        Err(_) => break,
+                    };
+                    let mut buffer = [0u8; 1024];
+                    if let Ok(count) = stream.read(&mut buffer) {
+                        let _ = stream.write_all(&buffer[..count]);
+                    }
+                }
+            });
+            EchoChild { running, port }
+        }
+    }
+
+    impl ManagedChild for EchoChild {
+        fn kill(&mut self) -> Result<(), String> {
+            self.running.store(false, Ordering::SeqCst);
+            // Unblock the accept loop.
+            let _ = TcpStream::connect(("127.0.0.1", self.port));
+            Ok(())
+        }
+
+        fn is_running(&mut self) -> bool {
+            self.running.load(Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn start_spawns_lyrebird_with_the_pluggable_transport_contract() {
+        let spawn_params = Arc::new(Mutex::new(vec![]));
+        let spawner = ProcessSpawnerMock::new()
+            .spawn_params(&spawn_params)
+            .spawn_result(Ok(Box::new(EchoChild::start())));
+        let subject = Obfs4Bridge::new(Box::new(spawner), "lyrebird".to_string(), 9443, 5333);
+
+        let mut child = subject.start().unwrap();
+
+        assert!(child.is_running());
+        let params = spawn_params.lock().unwrap();
+        let (binary, env) = &params[0];
+        assert_eq!(binary, "lyrebird");
+        assert_eq!(env.get("TOR_PT_SERVER_TRANSPORTS"), Some(&"obfs4".to_string()));
+        assert_eq!(
+            env.get("TOR_PT_SERVER_BINDADDR"),
+            Some(&"obfs4-0.0.0.0:9443".to_string())
+        );
+        assert_eq!(
+            env.get("TOR_PT_ORPORT"),
+            Some(&"127.0.0.1:5333".to_string())
+        );
+    }
+
+    #[test]
+    fn the_transport_subprocess_relays_bytes_while_running() {
+        let echo = EchoChild::start();
+        let port = echo.port;
+        let spawner = ProcessSpawnerMock::new().spawn_result(Ok(Box::new(echo)));
+        let subject = Obfs4Bridge::new(Box::new(spawner), "lyrebird".to_string(), port, 5333);
+        let mut child = subject.start().unwrap();
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.write_all(b"obfuscated handshake").unwrap();
+        let mut reply = [0u8; 20];
+        stream.read_exact(&mut reply).unwrap();
+
+        assert_eq!(&reply, b"obfuscated handshake");
+        assert!(child.is_running());
+    }
+
+    #[test]
+    fn dropping_the_child_process_kills_the_subprocess() {
+        let echo = EchoChild::start();
+        let running = echo.running.clone();
+        let spawner = ProcessSpawnerMock::new().spawn_result(Ok(Box::new(echo)));
+        let subject = Obfs4Bridge::new(Box::new(spawner), "lyrebird".to_string(), 9443, 5333);
+
+        let child = subject.start().unwrap();
+        drop(child);
+
+        assert!(!running.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn a_missing_binary_is_reported() {
+        let spawner = ProcessSpawnerMock::new().spawn_result(Err(BridgeError::SpawnFailed(
+            "lyrebird: No such file or directory".to_string(),
+        )));
+        let subject = Obfs4Bridge::new(Box::new(spawner), "lyrebird".to_string(), 9443, 5333);
+
+        let result = subject.start().err();
+
+        assert_eq!(
+            result,
+            Some(BridgeError::SpawnFailed(
+                "lyrebird: No such file or directory".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Exit bandwidth capping. Operators on metered links cap the bytes they
+//! will serve per clock hour; once the cap is hit, new streams get a
+//! synthesized 503 back through the hopper while streams already in flight
+//! are allowed to finish. The counter resets at the top of each hour.
+
+use crate::sub_lib::hopper::MessageType;
+use crate::sub_lib::logger::Logger;
+use crate::sub_lib::stream_key::StreamKey;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+/// The response body sent for streams refused at the cap.
+pub const CAP_EXCEEDED_RESPONSE: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\n\
+    Retry-After: 3600\r\n\
+    Content-Length: 0\r\n\
+    Connection: close\r\n\
+    \r\n";
+
+/// What the ProxyClient should do with a stream, given the cap state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CapVerdict {
+    /// Serve the stream and count its bytes.
+    Serve,
+    /// Refuse the new stream with a synthesized 503.
+    RefuseNewStream,
+}
+
+/// Rolling-hour byte counter behind `max_bandwidth_bytes_per_hour`.
+pub struct BandwidthCap {
+    max_bytes_per_hour: Option<u64>,
+    bytes_this_hour: u64,
+    /// The hour (as hours since the epoch) the counter belongs to.
+    counter_hour: u64,
+    cap_logged: bool,
+    logger: Logger,
+}
+
+impl BandwidthCap {
+    pub fn new(max_bytes_per_hour: Option<u64>) -> BandwidthCap {
+        BandwidthCap {
+            max_bytes_per_hour,
+            bytes_this_hour: 0,
+            counter_hour: 0,
+            cap_logged: false,
+            logger: Logger::new("ProxyClient"),
+        }
+    }
+
+    /// Records bytes served on an existing stream. Existing streams are
+    /// never cut off, so this counts unconditionally — the overshoot just
+    /// means new streams stay refused a little longer.
+    pub fn record_bytes(&mut self, count: u64, now: SystemTime) {
+        self.roll_hour_if_needed(now);
+        self.bytes_this_hour += count;
+    }
+
+    /// Decides whether a new stream may be opened right now.
+    pub fn verdict_for_new_stream(&mut self, now: SystemTime) -> CapVerdict {
+        self.roll_hour_if_needed(now);
+        match self.max_bytes_per_hour {
+            Some(cap) if self.bytes_this_hour >= cap => {
+                if !self.cap_logged {
+                    self.logger.warning(format!(
+                        "Bandwidth cap of {} bytes/hour reached ({} served); refusing new \
+                         streams until the hour rolls over",
+                        cap, self.bytes_this_hour
+                    ));
+                    self.cap_logged = true;
+                }
+                CapVerdict::RefuseNewStream
+            }
+            _ => CapVerdict::Serve,
+        }
+    }
+
+    /// The signal sent back along the route for a refused stream, so the
+    /// originating ProxyServer can self-throttle instead of retrying into
+    /// silence. None while the stream would be served.
+    pub fn rate_limit_signal(&mut self, stream_key: StreamKey, now: SystemTime) -> Option<MessageType> {
+        match self.verdict_for_new_stream(now) {
+            CapVerdict::Serve => None,
+            CapVerdict::RefuseNewStream => Some(MessageType::RateLimitSignal {
+                stream_key,
+                retry_after_ms: Self::millis_until_hour_rolls(now),
+            }),
+        }
+    }
+
+    pub fn bytes_this_hour(&self) -> u64 {
+        self.bytes_this_hour
+    }
+
+    fn millis_until_hour_rolls(now: SystemTime) -> u64 {
+        let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+        HOUR.as_millis() as u64 - (since_epoch.as_millis() as u64 % HOUR.as_millis() as u64)
+    }
+
+    fn roll_hour_if_needed(&mut self, now: SystemTime) {
+        let hour = now
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs()
+            / HOUR.as_secs();
+        if hour != self.counter_hour {
+            self.counter_hour = hour;
+            self.bytes_this_hour = 0;
+            self.cap_logged = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at_hour_offset(seconds: u64) -> SystemTime {
+        // Anchored at an exact hour boundary (472,222 * 3600) so offsets
+        // are unambiguous.
+        UNIX_EPOCH + Duration::from_secs(1_699_999_200 + seconds)
+    }
+
+    #[test]
+    fn streams_are_served_until_the_cap_is_reached() {
+        let mut subject = BandwidthCap::new(Some(1000));
+        let now = at_hour_offset(0);
+        subject.record_bytes(999, now);
+
+        assert_eq!(subject.verdict_for_new_stream(now), CapVerdict::Serve);
+
+        subject.record_bytes(1, now);
+
+        assert_eq!(
+            subject.verdict_for_new_stream(now),
+            CapVerdict::RefuseNewStream
+        );
+    }
+
+    #[test]
+    fn existing_streams_keep_counting_past_the_cap() {
+        let mut subject = BandwidthCap::new(Some(1000));
+        let now = at_hour_offset(0);
+        subject.record_bytes(1500, now);
+
+        subject.record_bytes(500, now);
+
+        assert_eq!(subject.bytes_this_hour(), 2000);
+        assert_eq!(
+            subject.verdict_for_new_stream(now),
+            CapVerdict::RefuseNewStream
+        );
+    }
+
+    #[test]
+    fn the_counter_resets_when_the_hour_rolls_over() {
+        let mut subject = BandwidthCap::new(Some(1000));
+        subject.record_bytes(1000, at_hour_offset(100));
+        assert_eq!(
+            subject.verdict_for_new_stream(at_hour_offset(3599)),
+            CapVerdict::RefuseNewStream
+        );
+
+        let verdict = subject.verdict_for_new_stream(at_hour_offset(3600));
+
+        assert_eq!(verdict, CapVerdict::Serve);
+        assert_eq!(subject.bytes_this_hour(), 0);
+    }
+
+    #[test]
+    fn no_cap_means_no_refusals() {
+        let mut subject = BandwidthCap::new(None);
+        let now = at_hour_offset(0);
+        subject.record_bytes(u64::MAX / 2, now);
+
+        assert_eq!(subject.verdict_for_new_stream(now), CapVerdict::Serve);
+    }
+
+    #[test]
+    fn a_throttled_stream_produces_a_rate_limit_signal() {
+        let mut subject = BandwidthCap::new(Some(1000));
+        let now = at_hour_offset(600);
+        subject.record_bytes(1000, now);
+
+        let signal = subject.rate_limit_signal(StreamKey::make_meaningless(7), now);
+
+        assert_eq!(
+            signal,
+            Some(MessageType::RateLimitSignal {
+                stream_key: StreamKey::make_meaningless(7),
+                retry_after_ms: 3_000_000, // 50 minutes until the hour rolls
+            })
+        );
+    }
+
+    #[test]
+    fn a_served_stream_produces_no_signal() {
+        let mut subject = BandwidthCap::new(Some(1000));
+        let now = at_hour_offset(0);
+
+        let signal = subject.rate_limit_signal(StreamKey::make_meaningless(7), now);
+
+        assert_eq!(signal, None);
+    }
+
+    #[test]
+    fn the_refusal_response_is_a_well_formed_503() {
+        let response = String::from_utf8_lossy(CAP_EXCEEDED_RESPONSE);
+
+        assert!(response.starts_with("HTTP/1.1 503 "));
+        assert!(response.contains("Retry-After: 3600"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+}
@@ -0,0 +1,305 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Reusable read buffers for the exit-side stream reader. Heap profiling
+//! showed a fresh Vec per inbound server read; at high throughput the
+//! allocator dominates, so reads borrow a pooled buffer and it returns
+//! itself on drop. The pool keeps separate freelists per size class
+//! (1K/4K/16K/64K) so a burst of small header reads does not pin
+//! 64K buffers, and an acquire is served from the smallest class that
+//! fits. Requests larger than the biggest class get a plain heap buffer
+//! that is dropped, not pooled.
+
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+/// The buffer capacities the pool maintains freelists for, ascending.
+pub const SIZE_CLASSES: [usize; 4] = [1024, 4 * 1024, 16 * 1024, 64 * 1024];
+
+/// Default number of buffers kept on each class's freelist before extras
+/// are dropped back to the allocator.
+pub const DEFAULT_POOL_CAPACITY: usize = 64;
+
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    capacity_per_class: usize,
+    free_lists: [Mutex<VecDeque<Vec<u8>>>; SIZE_CLASSES.len()],
+    fresh_allocations: Mutex<u64>,
+}
+
+impl BufferPool {
+    pub fn new(capacity_per_class: usize) -> BufferPool {
+        BufferPool {
+            inner: Arc::new(PoolInner {
+                capacity_per_class,
+                free_lists: Default::default(),
+                fresh_allocations: Mutex::new(0),
+            }),
+        }
+    }
+
+    /// Hands out a zeroed buffer of exactly `size` readable bytes, backed
+    /// by a pooled allocation from the smallest class that fits. The
+    /// buffer returns to its class's freelist when dropped.
+    pub fn acquire(&self, size: usize) -> PooledBuffer {
+        let class = SIZE_CLASSES.iter().position(|&c| c >= size);
+        let mut data = match class {
+            Some(index) => {
+                let recycled = self.inner.free_lists[index]
+                    .lock()
+                    .expect("buffer pool poisoned")
+                    .pop_front();
+                match recycled {
+                    Some(buffer) => buffer,
+                    None => {
+                        *self
+                            .inner
+                            .fresh_allocations
+                            .lock()
+                            .expect("buffer pool poisoned") += 1;
+                        Vec::with_capacity(SIZE_CLASSES[index])
+                    }
+                }
+            }
+            None => {
+                *self
+                    .inner
+                    .fresh_allocations
+                    .lock()
+                    .expect("buffer pool poisoned") += 1;
+                Vec::with_capacity(size)
+            }
+        };
+        data.clear();
+        data.resize(size, 0);
+        PooledBuffer {
+            data: Some(data),
+            class,
+            pool: self.inner.clone(),
+        }
+    }
+
+    /// Returns a buffer to the pool immediately rather than at end of
+    /// scope. Equivalent to dropping it; exists for call sites that want
+    /// the handback to read as an action.
+    pub fn release(&self, buffer: PooledBuffer) {
+        drop(buffer);
+    }
+
+    /// How many times acquire() had to fall back to the allocator. Used by
+    /// the allocation-count regression test.
+    pub fn fresh_allocations(&self) -> u64 {
+        *self
+            .inner
+            .fresh_allocations
+            .lock()
+            .expect("buffer pool poisoned")
+    }
+
+    pub fn free_count(&self) -> usize {
+        self.inner
+            .free_lists
+            .iter()
+            .map(|list| list.lock().expect("buffer pool poisoned").len())
+            .sum()
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_POOL_CAPACITY)
+    }
+}
+
+/// A borrowed read buffer. Derefs to the `size` bytes requested at
+/// acquire time; the backing allocation (one full size class) goes back
+/// to the pool on drop.
+pub struct PooledBuffer {
+    data: Option<Vec<u8>>,
+    class: Option<usize>,
+    pool: Arc<PoolInner>,
+}
+
+impl PooledBuffer {
+    /// Whether this buffer came from a size class (and will be recycled)
+    /// or was an oversize one-off heap allocation.
+    pub fn is_pooled(&self) -> bool {
+        self.class.is_some()
+    }
+
+    /// Copies out the first `len` bytes, for handing to an owning message
+    /// like InboundServerData while the buffer itself goes back to the
+    /// pool.
+    pub fn take_prefix(&self, len: usize) -> Vec<u8> {
+        self[..len].to_vec()
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.data.as_ref().expect("buffer already released")
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.data.as_mut().expect("buffer already released")
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let data = match self.data.take() {
+            Some(data) => data,
+            None => return,
+        };
+        let index = match self.class {
+            Some(index) => index,
+            None => return, // oversize one-off: back to the allocator
+        };
+        let mut free_list = match self.pool.free_lists[index].lock() {
+            Ok(list) => list,
+            Err(_) => return, // poisoned pool: let the buffer drop
+        };
+        if free_list.len() < self.pool.capacity_per_class {
+            free_list.push_back(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn dropped_buffers_are_reused_instead_of_reallocated() {
+        let subject = BufferPool::new(4);
+
+        let buffer = subject.acquire(1000);
+        assert_eq!(subject.fresh_allocations(), 1);
+        drop(buffer);
+        let _again = subject.acquire(1000);
+
+        assert_eq!(subject.fresh_allocations(), 1);
+    }
+
+    #[test]
+    fn acquire_picks_the_smallest_class_that_fits() {
+        let subject = BufferPool::new(4);
+
+        for (size, expected_class) in [(1, 0), (1024, 0), (1025, 1), (4096, 1), (60_000, 3)] {
+            let buffer = subject.acquire(size);
+            assert_eq!(buffer.class, Some(expected_class), "size {}", size);
+            assert_eq!(buffer.len(), size);
+        }
+    }
+
+    #[test]
+    fn classes_do_not_cross_pollinate() {
+        let subject = BufferPool::new(4);
+        drop(subject.acquire(1024)); // seeds the 1K freelist
+
+        let big = subject.acquire(64 * 1024);
+
+        assert_eq!(subject.fresh_allocations(), 2);
+        assert_eq!(big.len(), 64 * 1024);
+    }
+
+    #[test]
+    fn oversize_requests_are_served_unpooled() {
+        let subject = BufferPool::new(4);
+
+        let buffer = subject.acquire(1024 * 1024);
+        assert!(!buffer.is_pooled());
+        drop(buffer);
+
+        assert_eq!(subject.free_count(), 0);
+    }
+
+    #[test]
+    fn steady_state_workload_allocates_only_up_to_concurrency() {
+        let subject = BufferPool::new(8);
+
+        // Simulate 1,000 reads with at most 3 buffers in flight at once.
+        for _ in 0..1000 {
+            let a = subject.acquire(16 * 1024);
+            let b = subject.acquire(16 * 1024);
+            let c = subject.acquire(16 * 1024);
+            drop(a);
+            drop(b);
+            drop(c);
+        }
+
+        assert_eq!(subject.fresh_allocations(), 3);
+    }
+
+    #[test]
+    fn drop_beyond_class_capacity_releases_to_the_allocator() {
+        let subject = BufferPool::new(2);
+        let buffers: Vec<PooledBuffer> = (0..4).map(|_| subject.acquire(1024)).collect();
+
+        drop(buffers);
+
+        assert_eq!(subject.free_count(), 2);
+    }
+
+    #[test]
+    fn reacquired_buffers_come_back_zeroed_at_the_requested_length() {
+        let subject = BufferPool::new(4);
+        let mut buffer = subject.acquire(100);
+        buffer[..10].copy_from_slice(b"stale data");
+        subject.release(buffer);
+
+        let reused = subject.acquire(200);
+
+        assert_eq!(reused.len(), 200);
+        assert!(reused.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn take_prefix_copies_only_the_read_bytes() {
+        let subject = BufferPool::new(4);
+        let mut buffer = subject.acquire(1024);
+        buffer[..5].copy_from_slice(b"hello");
+
+        assert_eq!(buffer.take_prefix(5), b"hello".to_vec());
+    }
+
+    #[test]
+    #[ignore] // throughput micro-benchmark; run manually with --ignored
+    fn pooled_acquire_release_is_faster_than_fresh_allocation() {
+        const ROUNDS: usize = 1_000_000;
+        const READ_SIZE: usize = 16 * 1024;
+        let subject = BufferPool::new(8);
+
+        let pooled_start = Instant::now();
+        for _ in 0..ROUNDS {
+            let buffer = subject.acquire(READ_SIZE);
+            drop(buffer);
+        }
+        let pooled = pooled_start.elapsed();
+
+        let fresh_start = Instant::now();
+        for _ in 0..ROUNDS {
+            let buffer: Vec<u8> = vec![0; READ_SIZE];
+            drop(buffer);
+        }
+        let fresh = fresh_start.elapsed();
+
+        // The request that introduced size classes asked for >=30% less
+        // time spent in allocation on the read path.
+        assert!(
+            pooled.as_nanos() * 10 < fresh.as_nanos() * 7,
+            "pooled {:?} was not >=30% faster than fresh {:?}",
+            pooled,
+            fresh
+        );
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Cover traffic against timing analysis: when enabled, the ProxyClient
+//! emits packages of random bytes at Poisson-distributed intervals so an
+//! observer cannot tell quiet periods from active ones. Receivers discard
+//! CoverTraffic messages without processing.
+
+use crate::sub_lib::hopper::MessageType;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CoverTrafficConfig {
+    /// Mean inter-message interval of the Poisson process.
+    pub interval_ms: u64,
+    pub fake_payload_size: usize,
+}
+
+pub struct CoverTrafficGenerator {
+    config: CoverTrafficConfig,
+    rng_state: u64,
+}
+
+impl CoverTrafficGenerator {
+    pub fn new(config: CoverTrafficConfig, seed: u64) -> CoverTrafficGenerator {
+        CoverTrafficGenerator {
+            config,
+            rng_state: seed | 1,
+        }
+    }
+
+    /// Exponentially distributed delay until the next cover message, giving
+    /// Poisson arrivals with mean `interval_ms`.
+    pub fn next_delay(&mut self) -> Duration {
+        let uniform = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        let uniform = uniform.max(f64::MIN_POSITIVE);
+        let millis = -(uniform.ln()) * self.config.interval_ms as f64;
+        Duration::from_millis(millis as u64)
+    }
+
+    /// A cover package payload of random bytes.
+    pub fn make_cover_message(&mut self) -> MessageType {
+        let mut bytes = Vec::with_capacity(self.config.fake_payload_size);
+        while bytes.len() < self.config.fake_payload_size {
+            bytes.extend_from_slice(&self.next_u64().to_le_bytes());
+        }
+        bytes.truncate(self.config.fake_payload_size);
+        MessageType::CoverTraffic(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        // xorshift64*: cheap and plenty for cover noise; this is not key material.
+        let mut x = self.rng_state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng_state = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+/// Receivers call this before normal dispatch; cover traffic is dropped.
+pub fn is_cover_traffic(message: &MessageType) -> bool {
+    matches!(message, MessageType::CoverTraffic(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_subject(interval_ms: u64, size: usize) -> CoverTrafficGenerator {
+        CoverTrafficGenerator::new(
+            CoverTrafficConfig {
+                interval_ms,
+                fake_payload_size: size,
+            },
+            0x1234_5678_9abc_def0,
+        )
+    }
+
+    #[test]
+    fn delays_average_near_the_configured_interval() {
+        let mut subject = make_subject(100, 64);
+        const SAMPLES: u32 = 10_000;
+
+        let total: Duration = (0..SAMPLES).map(|_| subject.next_delay()).sum();
+        let mean_ms = total.as_millis() as f64 / SAMPLES as f64;
+
+        // Poisson mean should land within 10% of the configured interval
+        // over ten thousand samples.
+        assert!(
+            (90.0..110.0).contains(&mean_ms),
+            "mean delay {} ms not within 10% of 100 ms",
+            mean_ms
+        );
+    }
+
+    #[test]
+    fn delays_are_not_constant() {
+        let mut subject = make_subject(100, 64);
+
+        let first = subject.next_delay();
+        let second = subject.next_delay();
+        let third = subject.next_delay();
+
+        assert!(first != second || second != third);
+    }
+
+    #[test]
+    fn cover_messages_have_the_configured_size() {
+        let mut subject = make_subject(100, 123);
+
+        match subject.make_cover_message() {
+            MessageType::CoverTraffic(bytes) => assert_eq!(bytes.len(), 123),
+            other => panic!("expected CoverTraffic, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cover_messages_are_recognized_for_silent_discard() {
+        let mut subject = make_subject(100, 16);
+
+        assert!(is_cover_traffic(&subject.make_cover_message()));
+    }
+}
@@ -0,0 +1,705 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Certificate Transparency checking for exit-side TLS connections. A
+//! mis-issued certificate that no CT log has seen is exactly the kind an
+//! exit's upstream attacker would present, so with `check_ct_logs` on,
+//! the certificate's RFC 6962 leaf hash is looked up in the configured
+//! logs before the connection proceeds. The leaf hash covers the
+//! `MerkleTreeLeaf`, which embeds the timestamp from the SCT the server
+//! presented — the hash cannot be computed from the certificate DER
+//! alone, so the handshake's SCT list comes along, and a server that
+//! presents no SCTs is offering no CT evidence at all and is rejected.
+//! One inclusion anywhere is enough. The policy fails closed only on
+//! evidence: if every log answered and none contained the certificate,
+//! the connection is rejected; logs that were unreachable prove nothing
+//! about absence, so an outage degrades to a warning rather than an
+//! exit-wide TLS blackout.
+
+use crate::sub_lib::logger::Logger;
+use sha2::{Digest, Sha256};
+
+/// The default logs consulted; operators can override the list.
+pub const DEFAULT_CT_LOGS: [&str; 2] = [
+    "https://ct.googleapis.com/logs/argon2024",
+    "https://yeti2024.ct.digicert.com/log",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TlsError {
+    /// Every consulted log answered, and none had seen the certificate.
+    CertificateNotInCtLog { hostname: String },
+    /// The handshake carried no parseable SCT, so there is no leaf hash
+    /// to look up and no claim of logging to verify.
+    NoSctsPresented { hostname: String },
+}
+
+/// What one log said about one certificate hash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CtLookupResult {
+    Included,
+    NotFound,
+    /// The log could not be asked; carries the transport-level detail.
+    Unavailable(String),
+}
+
+/// Mockable query against a single CT log.
+pub trait CtLogClient: Send {
+    fn lookup(&self, log_url: &str, leaf_hash: &[u8; 32]) -> CtLookupResult;
+}
+
+pub struct CtLogClientReal {
+    client: reqwest::blocking::Client,
+}
+
+impl CtLogClientReal {
+    pub fn new() -> CtLogClientReal {
+        CtLogClientReal {
+            // Direct egress, as for DoH: CT lookups must not loop through
+            // the overlay they are protecting.
+            client: reqwest::blocking::Client::builder()
+                .no_proxy()
+                .build()
+                .expect("reqwest client construction is infallible here"),
+        }
+    }
+}
+
+impl Default for CtLogClientReal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CtLogClientReal {
+    /// `get-proof-by-hash` needs a tree size from a current STH; there is
+    /// no "latest" sentinel, so every lookup starts with `get-sth`.
+    fn current_tree_size(&self, base_url: &str) -> Result<u64, String> {
+        let url = format!("{}/ct/v1/get-sth", base_url);
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .map_err(|error| format!("get-sth failed: {}", error))?;
+        let status = response.status().as_u16();
+        if status != 200 {
+            return Err(format!("get-sth answered {}", status));
+        }
+        let body = response
+            .text()
+            .map_err(|error| format!("get-sth body unreadable: {}", error))?;
+        parse_tree_size(&body).ok_or_else(|| "get-sth body carried no tree_size".to_string())
+    }
+}
+
+impl CtLogClient for CtLogClientReal {
+    fn lookup(&self, log_url: &str, leaf_hash: &[u8; 32]) -> CtLookupResult {
+        let base_url = log_url.trim_end_matches('/');
+        let tree_size = match self.current_tree_size(base_url) {
+            Ok(tree_size) => tree_size,
+            Err(detail) => return CtLookupResult::Unavailable(detail),
+        };
+        let url = format!(
+            "{}/ct/v1/get-proof-by-hash?hash={}&tree_size={}",
+            base_url,
+            query_encode(&base64ish(leaf_hash)),
+            tree_size
+        );
+        match self.client.get(&url).send() {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                match status {
+                    200 => CtLookupResult::Included,
+                    // Logs report an unknown hash as a 400-class error
+                    // with a "not found" body. Any other 400 is a
+                    // malformed request of our own making and proves
+                    // nothing about inclusion, so it must not count as
+                    // an authoritative answer.
+                    400..=499 => {
+                        let body = response.text().unwrap_or_default();
+                        if body.to_lowercase().contains("not found") {
+                            CtLookupResult::NotFound
+                        } else {
+                            CtLookupResult::Unavailable(format!(
+                                "log answered {}: {}",
+                                status,
+                                body.trim()
+                            ))
+                        }
+                    }
+                    status => CtLookupResult::Unavailable(format!("log answered {}", status)),
+                }
+            }
+            Err(error) => CtLookupResult::Unavailable(format!("{}", error)),
+        }
+    }
+}
+
+/// Pulls `"tree_size": <n>` out of the get-sth JSON without dragging in a
+/// JSON parser for one integer field.
+fn parse_tree_size(body: &str) -> Option<u64> {
+    let after_key = body.split("\"tree_size\"").nth(1)?;
+    let after_colon = after_key.split(':').nth(1)?;
+    let digits: String = after_colon
+        .trim_start()
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+/// Percent-encodes a base64 value for a query string: `+` would be
+/// decoded as a space by the log frontend, corrupting the hash.
+fn query_encode(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '+' => "%2B".to_string(),
+            '/' => "%2F".to_string(),
+            '=' => "%3D".to_string(),
+            other => other.to_string(),
+        })
+        .collect()
+}
+
+pub struct CtVerifier {
+    log_urls: Vec<String>,
+    client: Box<dyn CtLogClient>,
+    logger: Logger,
+}
+
+impl CtVerifier {
+    pub fn new(log_urls: Vec<String>, client: Box<dyn CtLogClient>) -> CtVerifier {
+        CtVerifier {
+            log_urls,
+            client,
+            logger: Logger::new("ProxyClient"),
+        }
+    }
+
+    /// Verifies that the certificate presented for `hostname` is known to
+    /// at least one configured log, using the handshake's SCT list (the
+    /// `signed_certificate_timestamp` TLS extension bytes) for the
+    /// timestamps its leaf hashes are built from. Called only when
+    /// `check_ct_logs` is configured on.
+    pub fn verify(
+        &self,
+        hostname: &str,
+        certificate_der: &[u8],
+        sct_list: &[u8],
+    ) -> Result<(), TlsError> {
+        let timestamps = parse_sct_timestamps(sct_list);
+        if timestamps.is_empty() {
+            self.logger.warning(format!(
+                "{} presented no parseable SCT; rejecting the connection",
+                hostname
+            ));
+            return Err(TlsError::NoSctsPresented {
+                hostname: hostname.to_string(),
+            });
+        }
+        let mut all_answered = true;
+        let mut any_asked = false;
+        for timestamp in &timestamps {
+            let leaf_hash = leaf_hash(certificate_der, *timestamp);
+            for log_url in &self.log_urls {
+                any_asked = true;
+                match self.client.lookup(log_url, &leaf_hash) {
+                    CtLookupResult::Included => {
+                        self.logger.debug(format!(
+                            "Certificate for {} found in CT log {}",
+                            hostname, log_url
+                        ));
+                        return Ok(());
+                    }
+                    CtLookupResult::NotFound => (),
+                    CtLookupResult::Unavailable(detail) => {
+                        self.logger.warning(format!(
+                            "CT log {} unavailable while checking {}: {}",
+                            log_url, hostname, detail
+                        ));
+                        all_answered = false;
+                    }
+                }
+            }
+        }
+        if all_answered && any_asked {
+            self.logger.warning(format!(
+                "Certificate for {} not found in any of {} CT logs; rejecting the connection",
+                hostname,
+                self.log_urls.len()
+            ));
+            return Err(TlsError::CertificateNotInCtLog {
+                hostname: hostname.to_string(),
+            });
+        }
+        // Absence could not be established; availability wins.
+        Ok(())
+    }
+}
+
+/// The hash the logs key `get-proof-by-hash` by: SHA-256 over
+/// `0x00 || MerkleTreeLeaf`, where the leaf is a v1 timestamped x509
+/// entry carrying the SCT's timestamp, the DER certificate behind a
+/// 24-bit length, and empty extensions (RFC 6962 section 3.4).
+pub fn leaf_hash(certificate_der: &[u8], sct_timestamp_ms: u64) -> [u8; 32] {
+    let mut preimage = Vec::with_capacity(certificate_der.len() + 16);
+    preimage.push(0x00); // Merkle hash domain separator for leaves
+    preimage.push(0x00); // Version: v1
+    preimage.push(0x00); // MerkleLeafType: timestamped_entry
+    preimage.extend_from_slice(&sct_timestamp_ms.to_be_bytes());
+    preimage.extend_from_slice(&[0x00, 0x00]); // LogEntryType: x509_entry
+    let length = certificate_der.len();
+    preimage.extend_from_slice(&[(length >> 16) as u8, (length >> 8) as u8, length as u8]);
+    preimage.extend_from_slice(certificate_der);
+    preimage.extend_from_slice(&[0x00, 0x00]); // CtExtensions: empty
+    let digest = Sha256::digest(&preimage);
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&digest);
+    hash
+}
+
+/// Pulls the timestamps out of a TLS `SignedCertificateTimestampList`:
+/// a 16-bit list length, then per SCT a 16-bit length and the v1 SCT
+/// body (version, 32-byte log id, 64-bit timestamp, the rest). Entries
+/// that do not parse are skipped — one well-formed SCT is all a lookup
+/// needs.
+pub fn parse_sct_timestamps(sct_list: &[u8]) -> Vec<u64> {
+    let mut timestamps = vec![];
+    if sct_list.len() < 2 {
+        return timestamps;
+    }
+    let total = u16::from_be_bytes([sct_list[0], sct_list[1]]) as usize;
+    let mut rest = &sct_list[2..sct_list.len().min(2 + total)];
+    while rest.len() >= 2 {
+        let sct_len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        if rest.len() < 2 + sct_len {
+            break;
+        }
+        let sct = &rest[2..2 + sct_len];
+        // v1 SCT: version(1) || log_id(32) || timestamp(8) || ...
+        if sct_len >= 41 && sct[0] == 0 {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&sct[33..41]);
+            timestamps.push(u64::from_be_bytes(bytes));
+        }
+        rest = &rest[2 + sct_len..];
+    }
+    timestamps
+}
+
+// Padded standard base64, the encoding RFC 6962 specifies for hashes.
+fn base64ish(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        out.push(ALPHABET[(b[0] >> 2) as usize] as char);
+        out.push(ALPHABET[(((b[0] & 0x03) << 4) | (b[1] >> 4)) as usize] as char);
+        if chunk.len() > 1 {
+            out.push(ALPHABET[(((b[1] & 0x0F) << 2) | (b[2] >> 6)) as usize] as char);
+        } else {
+            out.push('=');
+        }
+        if chunk.len() > 2 {
+            out.push(ALPHABET[(b[2] & 0x3F) as usize] as char);
+        } else {
+            out.push('=');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// A known test certificate: any stable DER stand-in works, since the
+    /// logs are keyed by its leaf hash.
+    const TEST_CERTIFICATE_DER: &[u8] = b"\x30\x82\x01\x0atest certificate body";
+
+    const TEST_SCT_TIMESTAMP: u64 = 1_672_531_200_000;
+
+    /// A TLS SignedCertificateTimestampList carrying one v1 SCT per
+    /// timestamp; log id, extensions, and signature are stand-ins, since
+    /// only version and timestamp feed the leaf hash.
+    fn sct_list(timestamps: &[u64]) -> Vec<u8> {
+        let mut entries = vec![];
+        for timestamp in timestamps {
+            let mut sct = vec![0u8]; // version: v1
+            sct.extend_from_slice(&[7u8; 32]); // log id
+            sct.extend_from_slice(&timestamp.to_be_bytes());
+            sct.extend_from_slice(&[0, 0]); // extensions: empty
+            sct.extend_from_slice(&[4, 3, 0, 2, 0xde, 0xad]); // signature
+            entries.extend_from_slice(&(sct.len() as u16).to_be_bytes());
+            entries.extend_from_slice(&sct);
+        }
+        let mut list = (entries.len() as u16).to_be_bytes().to_vec();
+        list.extend_from_slice(&entries);
+        list
+    }
+
+    struct CtLogClientMock {
+        lookup_params: Arc<Mutex<Vec<(String, [u8; 32])>>>,
+        results: Mutex<Vec<CtLookupResult>>,
+    }
+
+    impl CtLogClientMock {
+        fn new() -> CtLogClientMock {
+            CtLogClientMock {
+                lookup_params: Arc::new(Mutex::new(vec![])),
+                results: Mutex::new(vec![]),
+            }
+        }
+
+        fn lookup_params(
+            mut self,
+            params: &Arc<Mutex<Vec<(String, [u8; 32])>>>,
+        ) -> CtLogClientMock {
+            self.lookup_params = params.clone();
+            self
+        }
+
+        fn lookup_result(self, result: CtLookupResult) -> CtLogClientMock {
+            self.results.lock().unwrap().push(result);
+            self
+        }
+    }
+
+    impl CtLogClient for CtLogClientMock {
+        fn lookup(&self, log_url: &str, leaf_hash: &[u8; 32]) -> CtLookupResult {
+            self.lookup_params
+                .lock()
+                .unwrap()
+                .push((log_url.to_string(), *leaf_hash));
+            self.results.lock().unwrap().remove(0)
+        }
+    }
+
+    fn logs() -> Vec<String> {
+        vec![
+            "https://log-one.example".to_string(),
+            "https://log-two.example".to_string(),
+        ]
+    }
+
+    #[test]
+    fn inclusion_in_any_log_passes_without_asking_the_rest() {
+        let params = Arc::new(Mutex::new(vec![]));
+        let subject = CtVerifier::new(
+            logs(),
+            Box::new(
+                CtLogClientMock::new()
+                    .lookup_params(&params)
+                    .lookup_result(CtLookupResult::Included),
+            ),
+        );
+
+        let result = subject.verify(
+            "example.com",
+            TEST_CERTIFICATE_DER,
+            &sct_list(&[TEST_SCT_TIMESTAMP]),
+        );
+
+        assert_eq!(result, Ok(()));
+        let recorded = params.lock().unwrap();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].0, "https://log-one.example");
+        assert_eq!(
+            recorded[0].1,
+            leaf_hash(TEST_CERTIFICATE_DER, TEST_SCT_TIMESTAMP)
+        );
+    }
+
+    #[test]
+    fn inclusion_in_the_second_log_also_passes() {
+        let subject = CtVerifier::new(
+            logs(),
+            Box::new(
+                CtLogClientMock::new()
+                    .lookup_result(CtLookupResult::NotFound)
+                    .lookup_result(CtLookupResult::Included),
+            ),
+        );
+
+        let result = subject.verify(
+            "example.com",
+            TEST_CERTIFICATE_DER,
+            &sct_list(&[TEST_SCT_TIMESTAMP]),
+        );
+
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn absence_from_every_answering_log_rejects_the_connection() {
+        let subject = CtVerifier::new(
+            logs(),
+            Box::new(
+                CtLogClientMock::new()
+                    .lookup_result(CtLookupResult::NotFound)
+                    .lookup_result(CtLookupResult::NotFound),
+            ),
+        );
+
+        let result = subject.verify(
+            "mitm.example.com",
+            TEST_CERTIFICATE_DER,
+            &sct_list(&[TEST_SCT_TIMESTAMP]),
+        );
+
+        assert_eq!(
+            result,
+            Err(TlsError::CertificateNotInCtLog {
+                hostname: "mitm.example.com".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn an_unreachable_log_cannot_prove_absence() {
+        let subject = CtVerifier::new(
+            logs(),
+            Box::new(
+                CtLogClientMock::new()
+                    .lookup_result(CtLookupResult::NotFound)
+                    .lookup_result(CtLookupResult::Unavailable("connection refused".to_string())),
+            ),
+        );
+
+        let result = subject.verify(
+            "example.com",
+            TEST_CERTIFICATE_DER,
+            &sct_list(&[TEST_SCT_TIMESTAMP]),
+        );
+
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn a_handshake_without_scts_is_rejected_outright() {
+        let params = Arc::new(Mutex::new(vec![]));
+        let subject = CtVerifier::new(
+            logs(),
+            Box::new(CtLogClientMock::new().lookup_params(&params)),
+        );
+
+        let result = subject.verify("bare.example.com", TEST_CERTIFICATE_DER, &[]);
+
+        assert_eq!(
+            result,
+            Err(TlsError::NoSctsPresented {
+                hostname: "bare.example.com".to_string()
+            })
+        );
+        assert!(params.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn every_sct_timestamp_is_tried_against_every_log() {
+        let params = Arc::new(Mutex::new(vec![]));
+        let mut client = CtLogClientMock::new().lookup_params(&params);
+        for _ in 0..4 {
+            client = client.lookup_result(CtLookupResult::NotFound);
+        }
+        let subject = CtVerifier::new(logs(), Box::new(client));
+        let timestamps = [TEST_SCT_TIMESTAMP, TEST_SCT_TIMESTAMP + 1];
+
+        let result = subject.verify("example.com", TEST_CERTIFICATE_DER, &sct_list(&timestamps));
+
+        assert_eq!(
+            result,
+            Err(TlsError::CertificateNotInCtLog {
+                hostname: "example.com".to_string()
+            })
+        );
+        let recorded = params.lock().unwrap();
+        let hashes: Vec<[u8; 32]> = recorded.iter().map(|(_, hash)| *hash).collect();
+        assert_eq!(
+            hashes,
+            vec![
+                leaf_hash(TEST_CERTIFICATE_DER, timestamps[0]),
+                leaf_hash(TEST_CERTIFICATE_DER, timestamps[0]),
+                leaf_hash(TEST_CERTIFICATE_DER, timestamps[1]),
+                leaf_hash(TEST_CERTIFICATE_DER, timestamps[1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_leaf_hash_is_sha256_over_the_merkle_tree_leaf() {
+        // Built independently of leaf_hash: leaf domain byte, v1,
+        // timestamped_entry, timestamp, x509_entry, 24-bit cert length,
+        // cert, empty extensions.
+        let mut preimage = vec![0x00, 0x00, 0x00];
+        preimage.extend_from_slice(&TEST_SCT_TIMESTAMP.to_be_bytes());
+        preimage.extend_from_slice(&[0x00, 0x00]);
+        preimage.extend_from_slice(&[0x00, 0x00, TEST_CERTIFICATE_DER.len() as u8]);
+        preimage.extend_from_slice(TEST_CERTIFICATE_DER);
+        preimage.extend_from_slice(&[0x00, 0x00]);
+        let expected: [u8; 32] = Sha256::digest(&preimage).into();
+
+        assert_eq!(leaf_hash(TEST_CERTIFICATE_DER, TEST_SCT_TIMESTAMP), expected);
+        // The timestamp is part of the hash: a DER-only digest cannot
+        // stand in for it.
+        assert_ne!(
+            leaf_hash(TEST_CERTIFICATE_DER, TEST_SCT_TIMESTAMP),
+            leaf_hash(TEST_CERTIFICATE_DER, TEST_SCT_TIMESTAMP + 1)
+        );
+    }
+
+    #[test]
+    fn sct_timestamps_parse_out_of_the_tls_extension_list() {
+        let timestamps = [1_672_531_200_000u64, 1_672_531_300_000];
+
+        assert_eq!(parse_sct_timestamps(&sct_list(&timestamps)), timestamps);
+        assert_eq!(parse_sct_timestamps(&[]), Vec::<u64>::new());
+        assert_eq!(parse_sct_timestamps(&[0x00]), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn an_unknown_sct_version_is_skipped_not_fatal() {
+        let mut list = sct_list(&[99, TEST_SCT_TIMESTAMP]);
+        list[4] = 2; // version byte of the first SCT
+
+        assert_eq!(parse_sct_timestamps(&list), vec![TEST_SCT_TIMESTAMP]);
+    }
+
+    #[test]
+    fn query_encode_escapes_the_characters_a_log_frontend_would_mangle() {
+        assert_eq!(query_encode("a+b/c="), "a%2Bb%2Fc%3D");
+        assert_eq!(query_encode("plain"), "plain");
+    }
+
+    #[test]
+    fn a_leaf_hash_encodes_as_padded_standard_base64() {
+        let encoded = base64ish(&[0u8; 32]);
+
+        assert_eq!(encoded, format!("{}=", "A".repeat(43)));
+    }
+
+    #[test]
+    fn get_sth_bodies_yield_their_tree_size() {
+        assert_eq!(
+            parse_tree_size("{\"tree_size\":842417,\"timestamp\":1}"),
+            Some(842417)
+        );
+        assert_eq!(
+            parse_tree_size("{ \"timestamp\": 1, \"tree_size\" : 42 }"),
+            Some(42)
+        );
+        assert_eq!(parse_tree_size("{\"timestamp\":1}"), None);
+        assert_eq!(parse_tree_size("not json"), None);
+        assert_eq!(parse_tree_size("{\"tree_size\":\"many\"}"), None);
+    }
+
+    /// A scripted HTTP server: answers the given (status line, body)
+    /// pairs one connection each and records each request line.
+    fn scripted_log_server(
+        responses: Vec<(&'static str, &'static str)>,
+    ) -> (u16, Arc<Mutex<Vec<String>>>, std::thread::JoinHandle<()>) {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let request_lines = Arc::new(Mutex::new(vec![]));
+        let request_lines_inner = request_lines.clone();
+        let server = thread::spawn(move || {
+            for (status_line, body) in responses {
+                let (mut socket, _) = listener.accept().unwrap();
+                let mut buffer = [0u8; 2048];
+                let count = socket.read(&mut buffer).unwrap();
+                let request = String::from_utf8_lossy(&buffer[..count]).to_string();
+                request_lines_inner
+                    .lock()
+                    .unwrap()
+                    .push(request.lines().next().unwrap_or("").to_string());
+                socket
+                    .write_all(
+                        format!(
+                            "{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            status_line,
+                            body.len(),
+                            body
+                        )
+                        .as_bytes(),
+                    )
+                    .unwrap();
+            }
+        });
+        (port, request_lines, server)
+    }
+
+    const STH_BODY: &str = "{\"tree_size\":42,\"timestamp\":1}";
+
+    #[test]
+    fn the_real_client_fetches_an_sth_and_maps_proof_responses_onto_lookup_results() {
+        let (port, request_lines, server) = scripted_log_server(vec![
+            ("HTTP/1.1 200 OK", STH_BODY),
+            ("HTTP/1.1 200 OK", "{\"leaf_index\":7,\"audit_path\":[]}"),
+            ("HTTP/1.1 200 OK", STH_BODY),
+            ("HTTP/1.1 400 Bad Request", "hash not found in tree"),
+            ("HTTP/1.1 200 OK", STH_BODY),
+            ("HTTP/1.1 400 Bad Request", "bad hash encoding"),
+        ]);
+        let subject = CtLogClientReal::new();
+        let log_url = format!("http://127.0.0.1:{}", port);
+        let hash = leaf_hash(TEST_CERTIFICATE_DER, TEST_SCT_TIMESTAMP);
+
+        let included = subject.lookup(&log_url, &hash);
+        let not_found = subject.lookup(&log_url, &hash);
+        let malformed = subject.lookup(&log_url, &hash);
+        let unreachable = subject.lookup("http://127.0.0.1:1", &hash);
+
+        assert_eq!(included, CtLookupResult::Included);
+        assert_eq!(not_found, CtLookupResult::NotFound);
+        match &malformed {
+            CtLookupResult::Unavailable(detail) => assert!(detail.contains("bad hash encoding")),
+            other => panic!("expected Unavailable, got {:?}", other),
+        }
+        assert!(matches!(unreachable, CtLookupResult::Unavailable(_)));
+        server.join().unwrap();
+        let recorded = request_lines.lock().unwrap();
+        assert!(recorded[0].starts_with("GET /ct/v1/get-sth "));
+        let proof_line = &recorded[1];
+        assert!(proof_line.contains("/ct/v1/get-proof-by-hash?hash="));
+        assert!(proof_line.contains("&tree_size=42 "));
+        let hash_param: &str = proof_line
+            .split("hash=")
+            .nth(1)
+            .unwrap()
+            .split('&')
+            .next()
+            .unwrap();
+        // Percent-encoded throughout: padding must appear as %3D, and
+        // nothing a frontend would decode as a space may survive raw.
+        assert!(hash_param.ends_with("%3D"), "hash param: {}", hash_param);
+        assert!(!hash_param.contains('+'), "hash param: {}", hash_param);
+        assert!(!hash_param.contains('/'), "hash param: {}", hash_param);
+    }
+
+    #[test]
+    fn a_failed_sth_fetch_is_unavailability_not_an_answer() {
+        let (port, request_lines, server) =
+            scripted_log_server(vec![("HTTP/1.1 500 Internal Server Error", "")]);
+        let subject = CtLogClientReal::new();
+        let hash = leaf_hash(TEST_CERTIFICATE_DER, TEST_SCT_TIMESTAMP);
+
+        let result = subject.lookup(&format!("http://127.0.0.1:{}", port), &hash);
+
+        match result {
+            CtLookupResult::Unavailable(detail) => {
+                assert!(detail.contains("get-sth answered 500"))
+            }
+            other => panic!("expected Unavailable, got {:?}", other),
+        }
+        server.join().unwrap();
+        assert_eq!(request_lines.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,278 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Exit-side DNS failure classification and retry. A single transient
+//! SERVFAIL from the first nameserver used to travel all the way back to
+//! the browser as "site can't be found"; now the lookup is classified,
+//! transient kinds get one retry against the next configured nameserver,
+//! and only then does a DnsResolveFailure — carrying the kind — go back
+//! to the originator. NXDOMAIN is authoritative: it is reported
+//! immediately, with no retry to burn time on.
+
+use crate::proxy_client::resolver_wrapper::{ResolveError, ResolverWrapper};
+use crate::sub_lib::logger::Logger;
+use crate::sub_lib::proxy_client::{DnsFailureKind, DnsResolveFailure};
+use crate::sub_lib::stream_key::StreamKey;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Maps a resolver error to the kind reported over the wire. The
+/// trust-dns error surface is stringly at this level, so transient
+/// subtypes are recognized by message; anything unrecognized is treated
+/// as SERVFAIL, which errs toward retrying.
+pub fn classify(error: &ResolveError) -> DnsFailureKind {
+    match error {
+        ResolveError::NoRecords(_) => DnsFailureKind::NxDomain,
+        ResolveError::ResolverFailure(message) => {
+            let lowered = message.to_ascii_lowercase();
+            if lowered.contains("timed out") || lowered.contains("timeout") {
+                DnsFailureKind::Timeout
+            } else if lowered.contains("refused") {
+                DnsFailureKind::Refused
+            } else {
+                DnsFailureKind::ServFail
+            }
+        }
+    }
+}
+
+/// Builds the versioned failure payload for the originator.
+pub fn failure_for(
+    stream_key: StreamKey,
+    hostname: &str,
+    error: &ResolveError,
+) -> DnsResolveFailure {
+    DnsResolveFailure {
+        stream_key,
+        hostname: hostname.to_string(),
+        kind: Some(classify(error)),
+    }
+}
+
+/// Wraps one ResolverWrapper per configured nameserver and retries a
+/// transient failure exactly once against the next one in the list.
+pub struct RetryingResolver {
+    resolvers: Vec<Box<dyn ResolverWrapper>>,
+    logger: Logger,
+}
+
+impl RetryingResolver {
+    pub fn new(resolvers: Vec<Box<dyn ResolverWrapper>>) -> RetryingResolver {
+        RetryingResolver {
+            resolvers,
+            logger: Logger::new("ProxyClient"),
+        }
+    }
+}
+
+impl ResolverWrapper for RetryingResolver {
+    fn lookup_ip_with_ttl(&self, hostname: &str) -> Result<Vec<(IpAddr, Duration)>, ResolveError> {
+        let first = match self.resolvers.first() {
+            Some(resolver) => resolver,
+            None => {
+                return Err(ResolveError::ResolverFailure(
+                    "no nameservers configured".to_string(),
+                ))
+            }
+        };
+        let error = match first.lookup_ip_with_ttl(hostname) {
+            Ok(pairs) => return Ok(pairs),
+            Err(error) => error,
+        };
+        let kind = classify(&error);
+        let second = match self.resolvers.get(1) {
+            Some(resolver) if kind.is_transient() => resolver,
+            _ => return Err(error),
+        };
+        self.logger.debug(format!(
+            "Transient {:?} resolving {}; retrying against the next nameserver",
+            kind, hostname
+        ));
+        second.lookup_ip_with_ttl(hostname).map_err(|retry_error| {
+            self.logger.warning(format!(
+                "Resolution of {} failed on both nameservers: {:?}, then {:?}",
+                hostname,
+                kind,
+                classify(&retry_error)
+            ));
+            retry_error
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    struct ResolverWrapperMock {
+        lookup_params: Arc<Mutex<Vec<String>>>,
+        lookup_results: Mutex<Vec<Result<Vec<(IpAddr, Duration)>, ResolveError>>>,
+    }
+
+    impl ResolverWrapperMock {
+        fn new() -> ResolverWrapperMock {
+            ResolverWrapperMock {
+                lookup_params: Arc::new(Mutex::new(vec![])),
+                lookup_results: Mutex::new(vec![]),
+            }
+        }
+
+        fn lookup_params(mut self, params: &Arc<Mutex<Vec<String>>>) -> ResolverWrapperMock {
+            self.lookup_params = params.clone();
+            self
+        }
+
+        fn lookup_result(
+            self,
+            result: Result<Vec<(IpAddr, Duration)>, ResolveError>,
+        ) -> ResolverWrapperMock {
+            self.lookup_results.lock().unwrap().push(result);
+            self
+        }
+    }
+
+    impl ResolverWrapper for ResolverWrapperMock {
+        fn lookup_ip_with_ttl(
+            &self,
+            hostname: &str,
+        ) -> Result<Vec<(IpAddr, Duration)>, ResolveError> {
+            self.lookup_params.lock().unwrap().push(hostname.to_string());
+            self.lookup_results.lock().unwrap().remove(0)
+        }
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
+
+    fn answer() -> Vec<(IpAddr, Duration)> {
+        vec![(ip("1.2.3.4"), Duration::from_secs(300))]
+    }
+
+    #[test]
+    fn classification_covers_the_reported_kinds() {
+        let cases: Vec<(ResolveError, DnsFailureKind)> = vec![
+            (
+                ResolveError::NoRecords("example.com".to_string()),
+                DnsFailureKind::NxDomain,
+            ),
+            (
+                ResolveError::ResolverFailure("request timed out".to_string()),
+                DnsFailureKind::Timeout,
+            ),
+            (
+                ResolveError::ResolverFailure("query REFUSED by server".to_string()),
+                DnsFailureKind::Refused,
+            ),
+            (
+                ResolveError::ResolverFailure("SERVFAIL".to_string()),
+                DnsFailureKind::ServFail,
+            ),
+            (
+                ResolveError::ResolverFailure("something inscrutable".to_string()),
+                DnsFailureKind::ServFail,
+            ),
+        ];
+
+        for (error, expected) in cases {
+            assert_eq!(classify(&error), expected, "for {:?}", error);
+        }
+    }
+
+    #[test]
+    fn nxdomain_is_reported_immediately_without_a_retry() {
+        let second_params = Arc::new(Mutex::new(vec![]));
+        let subject = RetryingResolver::new(vec![
+            Box::new(ResolverWrapperMock::new().lookup_result(Err(ResolveError::NoRecords(
+                "nosuch.example.com".to_string(),
+            )))),
+            Box::new(ResolverWrapperMock::new().lookup_params(&second_params)),
+        ]);
+
+        let result = subject.lookup_ip_with_ttl("nosuch.example.com");
+
+        assert_eq!(
+            result,
+            Err(ResolveError::NoRecords("nosuch.example.com".to_string()))
+        );
+        assert!(second_params.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_transient_failure_is_retried_once_against_the_next_nameserver() {
+        let second_params = Arc::new(Mutex::new(vec![]));
+        let subject = RetryingResolver::new(vec![
+            Box::new(ResolverWrapperMock::new().lookup_result(Err(
+                ResolveError::ResolverFailure("SERVFAIL".to_string()),
+            ))),
+            Box::new(
+                ResolverWrapperMock::new()
+                    .lookup_params(&second_params)
+                    .lookup_result(Ok(answer())),
+            ),
+        ]);
+
+        let result = subject.lookup_ip_with_ttl("example.com");
+
+        assert_eq!(result, Ok(answer()));
+        assert_eq!(*second_params.lock().unwrap(), vec!["example.com"]);
+    }
+
+    #[test]
+    fn a_second_failure_ends_the_retrying() {
+        let third_params = Arc::new(Mutex::new(vec![]));
+        let subject = RetryingResolver::new(vec![
+            Box::new(ResolverWrapperMock::new().lookup_result(Err(
+                ResolveError::ResolverFailure("request timed out".to_string()),
+            ))),
+            Box::new(ResolverWrapperMock::new().lookup_result(Err(
+                ResolveError::ResolverFailure("connection refused".to_string()),
+            ))),
+            Box::new(ResolverWrapperMock::new().lookup_params(&third_params)),
+        ]);
+
+        let result = subject.lookup_ip_with_ttl("example.com");
+
+        assert_eq!(
+            result,
+            Err(ResolveError::ResolverFailure(
+                "connection refused".to_string()
+            ))
+        );
+        assert!(third_params.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_single_nameserver_fails_without_retrying() {
+        let subject = RetryingResolver::new(vec![Box::new(
+            ResolverWrapperMock::new()
+                .lookup_result(Err(ResolveError::ResolverFailure("SERVFAIL".to_string()))),
+        )]);
+
+        let result = subject.lookup_ip_with_ttl("example.com");
+
+        assert_eq!(
+            result,
+            Err(ResolveError::ResolverFailure("SERVFAIL".to_string()))
+        );
+    }
+
+    #[test]
+    fn the_failure_payload_carries_the_classified_kind() {
+        let failure = failure_for(
+            StreamKey::make_meaningless(7),
+            "example.com",
+            &ResolveError::ResolverFailure("request timed out".to_string()),
+        );
+
+        assert_eq!(
+            failure,
+            DnsResolveFailure {
+                stream_key: StreamKey::make_meaningless(7),
+                hostname: "example.com".to_string(),
+                kind: Some(DnsFailureKind::Timeout),
+            }
+        );
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! DNS rebinding protection for the exit service: a hostile site can point
+//! its hostname at the exit operator's own LAN, so connections to resolved
+//! addresses in private or loopback ranges are refused.
+
+use crate::sub_lib::logger::Logger;
+use crate::sub_lib::stream_key::StreamKey;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DnsRebindingError {
+    DnsRebindingBlocked { ip: IpAddr },
+}
+
+/// Checks a resolved address before the StreamHandlerPool connects to it.
+/// Returns the blocked error (and logs a warning naming the stream) when
+/// `block_private_ips` is on and the address is private or loopback.
+pub fn check_resolved_ip(
+    block_private_ips: bool,
+    ip: IpAddr,
+    stream_key: &StreamKey,
+    logger: &Logger,
+) -> Result<(), DnsRebindingError> {
+    if block_private_ips && is_private_or_loopback(ip) {
+        logger.warning(format!(
+            "Blocked connection to private-range address {} for stream {} (possible DNS rebinding)",
+            ip, stream_key
+        ));
+        return Err(DnsRebindingError::DnsRebindingBlocked { ip });
+    }
+    Ok(())
+}
+
+fn is_private_or_loopback(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_blocked_v4(v4),
+        IpAddr::V6(v6) => {
+            v6.is_loopback()
+                || is_unique_local(v6)
+                || is_v6_link_local(v6)
+                || v6.to_ipv4_mapped().map_or(false, is_blocked_v4)
+        }
+    }
+}
+
+fn is_blocked_v4(ip: Ipv4Addr) -> bool {
+    // 169.254.0.0/16 matters beyond "it's local": it holds
+    // 169.254.169.254, the cloud metadata endpoint that is the canonical
+    // rebinding target.
+    ip.is_private() || ip.is_loopback() || ip.is_link_local() || is_cgnat(ip)
+}
+
+// 100.64.0.0/10: carrier-grade NAT space is as private as RFC 1918 in practice.
+fn is_cgnat(ip: Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    octets[0] == 100 && (octets[1] & 0xc0) == 64
+}
+
+// fc00::/7: unique-local addressing, the v6 analogue of RFC 1918.
+fn is_unique_local(ip: Ipv6Addr) -> bool {
+    (ip.segments()[0] & 0xfe00) == 0xfc00
+}
+
+// fe80::/10: link-local.
+fn is_v6_link_local(ip: Ipv6Addr) -> bool {
+    (ip.segments()[0] & 0xffc0) == 0xfe80
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn check(ip: &str) -> Result<(), DnsRebindingError> {
+        check_resolved_ip(
+            true,
+            IpAddr::from_str(ip).unwrap(),
+            &StreamKey::make_meaningless(1),
+            &Logger::new("DnsRebinding test"),
+        )
+    }
+
+    #[test]
+    fn private_ranges_are_blocked() {
+        for ip in ["10.1.2.3", "172.16.0.1", "172.31.255.255", "192.168.1.1"] {
+            assert_eq!(
+                check(ip),
+                Err(DnsRebindingError::DnsRebindingBlocked {
+                    ip: IpAddr::from_str(ip).unwrap()
+                }),
+                "{} should be blocked",
+                ip
+            );
+        }
+    }
+
+    #[test]
+    fn loopback_is_blocked() {
+        assert!(check("127.0.0.1").is_err());
+        assert!(check("127.255.0.3").is_err());
+        assert!(check("::1").is_err());
+    }
+
+    #[test]
+    fn the_cloud_metadata_endpoint_and_its_link_local_range_are_blocked() {
+        assert!(check("169.254.169.254").is_err());
+        assert!(check("169.254.0.1").is_err());
+        assert!(check("169.254.255.255").is_err());
+    }
+
+    #[test]
+    fn v6_unique_local_and_link_local_ranges_are_blocked() {
+        assert!(check("fc00::1").is_err());
+        assert!(check("fd12:3456:789a::1").is_err());
+        assert!(check("fe80::1").is_err());
+        assert!(check("febf::1").is_err());
+    }
+
+    #[test]
+    fn mapped_v4_private_addresses_are_blocked() {
+        assert!(check("::ffff:192.168.0.10").is_err());
+        assert!(check("::ffff:169.254.169.254").is_err());
+    }
+
+    #[test]
+    fn public_addresses_are_allowed() {
+        assert_eq!(check("93.184.216.34"), Ok(()));
+        assert_eq!(check("172.32.0.1"), Ok(()));
+        assert_eq!(check("169.255.0.1"), Ok(()));
+        assert_eq!(check("2606:2800:220:1::1"), Ok(()));
+        assert_eq!(check("fe00::1"), Ok(()));
+    }
+
+    #[test]
+    fn blocking_can_be_disabled_by_configuration() {
+        let result = check_resolved_ip(
+            false,
+            IpAddr::from_str("192.168.1.1").unwrap(),
+            &StreamKey::make_meaningless(1),
+            &Logger::new("DnsRebinding test"),
+        );
+
+        assert_eq!(result, Ok(()));
+    }
+}
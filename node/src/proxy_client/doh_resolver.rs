@@ -0,0 +1,650 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! DNS-over-HTTPS fallback for exits whose networks block port 53 (and
+//! usually DoT's 853) while letting HTTPS through. The DoH resolver
+//! implements ResolverWrapper by POSTing wire-format queries
+//! (application/dns-message) to a configurable URL set over a reused
+//! connection. It is engaged by FallbackResolver only after the primary
+//! resolver returns a run of network-level failures inside a window, and
+//! disengaged again after a cool-down so a recovered port 53 wins back
+//! the traffic. The HTTP client egresses directly — routing the lookup
+//! through the overlay would make resolving a hostname depend on having
+//! already resolved one.
+
+use crate::proxy_client::resolver_wrapper::{ResolveError, ResolverWrapper};
+use crate::sub_lib::logger::Logger;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_FAILURE_THRESHOLD: usize = 3;
+pub const DEFAULT_FAILURE_WINDOW: Duration = Duration::from_secs(60);
+pub const DEFAULT_FAIL_BACK_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// The slice of HTTP the DoH resolver needs; the real implementation
+/// rides a reused reqwest blocking client, the mock scripts responses.
+pub trait DohTransport: Send {
+    /// POSTs a wire-format DNS query to `url`; returns the wire-format
+    /// response body.
+    fn post_dns(&self, url: &str, query: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+pub struct DohTransportReal {
+    client: reqwest::blocking::Client,
+}
+
+impl DohTransportReal {
+    pub fn new() -> DohTransportReal {
+        DohTransportReal {
+            // Direct egress only: no system proxy, and never the overlay.
+            client: reqwest::blocking::Client::builder()
+                .no_proxy()
+                .build()
+                .expect("reqwest client construction is infallible here"),
+        }
+    }
+}
+
+impl Default for DohTransportReal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DohTransport for DohTransportReal {
+    fn post_dns(&self, url: &str, query: &[u8]) -> Result<Vec<u8>, String> {
+        let response = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/dns-message")
+            .header("Accept", "application/dns-message")
+            .body(query.to_vec())
+            .send()
+            .map_err(|e| format!("{}", e))?;
+        let status = response.status().as_u16();
+        if status != 200 {
+            return Err(format!("DoH server answered {}", status));
+        }
+        response
+            .bytes()
+            .map(|bytes| bytes.to_vec())
+            .map_err(|e| format!("{}", e))
+    }
+}
+
+/// Resolves over HTTPS; tries each configured URL in order and returns
+/// the first usable answer.
+pub struct DohResolver {
+    transport: Box<dyn DohTransport>,
+    urls: Vec<String>,
+}
+
+impl DohResolver {
+    pub fn new(transport: Box<dyn DohTransport>, urls: Vec<String>) -> DohResolver {
+        DohResolver { transport, urls }
+    }
+}
+
+impl ResolverWrapper for DohResolver {
+    fn lookup_ip_with_ttl(
+        &self,
+        hostname: &str,
+    ) -> Result<Vec<(IpAddr, Duration)>, ResolveError> {
+        let query = encode_a_query(hostname);
+        let mut last_error = "no DoH URLs configured".to_string();
+        for url in &self.urls {
+            match self.transport.post_dns(url, &query) {
+                Ok(response) => match decode_answers(&response) {
+                    Ok(pairs) if pairs.is_empty() => {
+                        return Err(ResolveError::NoRecords(hostname.to_string()))
+                    }
+                    Ok(pairs) => return Ok(pairs),
+                    Err(e) => last_error = format!("{}: malformed answer: {}", url, e),
+                },
+                Err(e) => last_error = format!("{}: {}", url, e),
+            }
+        }
+        Err(ResolveError::ResolverFailure(last_error))
+    }
+}
+
+/// Wire-format A query, recursion desired, id 0 (the HTTPS exchange
+/// correlates request and response; RFC 8484 recommends a fixed id for
+/// cache friendliness).
+pub fn encode_a_query(hostname: &str) -> Vec<u8> {
+    let mut query = vec![
+        0x00, 0x00, // id
+        0x01, 0x00, // flags: RD
+        0x00, 0x01, // one question
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // no answer/authority/additional
+    ];
+    for label in hostname.trim_end_matches('.').split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0x00);
+    query.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]); // QTYPE A, QCLASS IN
+    query
+}
+
+/// Extracts (address, TTL) pairs from a wire-format response; A and AAAA
+/// records only, anything else in the answer section is skipped.
+pub fn decode_answers(response: &[u8]) -> Result<Vec<(IpAddr, Duration)>, String> {
+    if response.len() < 12 {
+        return Err("truncated header".to_string());
+    }
+    let question_count = u16::from_be_bytes([response[4], response[5]]) as usize;
+    let answer_count = u16::from_be_bytes([response[6], response[7]]) as usize;
+    let mut cursor = 12;
+    for _ in 0..question_count {
+        cursor = skip_name(response, cursor)? + 4;
+    }
+    let mut pairs = vec![];
+    for _ in 0..answer_count {
+        cursor = skip_name(response, cursor)?;
+        if cursor + 10 > response.len() {
+            return Err("truncated answer".to_string());
+        }
+        let record_type = u16::from_be_bytes([response[cursor], response[cursor + 1]]);
+        let ttl = u32::from_be_bytes([
+            response[cursor + 4],
+            response[cursor + 5],
+            response[cursor + 6],
+            response[cursor + 7],
+        ]);
+        let rdata_len = u16::from_be_bytes([response[cursor + 8], response[cursor + 9]]) as usize;
+        cursor += 10;
+        if cursor + rdata_len > response.len() {
+            return Err("truncated rdata".to_string());
+        }
+        let rdata = &response[cursor..cursor + rdata_len];
+        cursor += rdata_len;
+        let address = match (record_type, rdata_len) {
+            (1, 4) => Some(IpAddr::from([rdata[0], rdata[1], rdata[2], rdata[3]])),
+            (28, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(rdata);
+                Some(IpAddr::from(octets))
+            }
+            _ => None,
+        };
+        if let Some(address) = address {
+            pairs.push((address, Duration::from_secs(u64::from(ttl))));
+        }
+    }
+    Ok(pairs)
+}
+
+fn skip_name(message: &[u8], mut cursor: usize) -> Result<usize, String> {
+    loop {
+        let length = *message
+            .get(cursor)
+            .ok_or_else(|| "truncated name".to_string())?;
+        if length == 0 {
+            return Ok(cursor + 1);
+        }
+        if length & 0xC0 == 0xC0 {
+            return Ok(cursor + 2); // compression pointer ends the name
+        }
+        cursor += 1 + length as usize;
+    }
+}
+
+struct FallbackState {
+    recent_failures: VecDeque<Instant>,
+    doh_until: Option<Instant>,
+}
+
+/// The resolver the exit actually uses: the primary until it proves
+/// unreachable, DoH for the cool-down after that, the primary again once
+/// the cool-down lapses.
+pub struct FallbackResolver {
+    primary: Box<dyn ResolverWrapper>,
+    doh: Box<dyn ResolverWrapper>,
+    failure_threshold: usize,
+    failure_window: Duration,
+    cooldown: Duration,
+    state: Mutex<FallbackState>,
+    logger: Logger,
+}
+
+impl FallbackResolver {
+    pub fn new(
+        primary: Box<dyn ResolverWrapper>,
+        doh: Box<dyn ResolverWrapper>,
+        failure_threshold: usize,
+        failure_window: Duration,
+        cooldown: Duration,
+    ) -> FallbackResolver {
+        FallbackResolver {
+            primary,
+            doh,
+            failure_threshold,
+            failure_window,
+            cooldown,
+            state: Mutex::new(FallbackState {
+                recent_failures: VecDeque::new(),
+                doh_until: None,
+            }),
+            logger: Logger::new("ProxyClient"),
+        }
+    }
+
+    /// The time-injectable core; the trait method calls this with
+    /// Instant::now().
+    pub fn lookup_at(
+        &self,
+        hostname: &str,
+        now: Instant,
+    ) -> Result<Vec<(IpAddr, Duration)>, ResolveError> {
+        {
+            let mut state = self.state.lock().expect("fallback state poisoned");
+            match state.doh_until {
+                Some(until) if now < until => return self.doh.lookup_ip_with_ttl(hostname),
+                Some(_) => {
+                    self.logger
+                        .info("DoH cool-down lapsed; trying the primary resolver again".to_string());
+                    state.doh_until = None;
+                    state.recent_failures.clear();
+                }
+                None => (),
+            }
+        }
+        let result = self.primary.lookup_ip_with_ttl(hostname);
+        if let Err(ResolveError::ResolverFailure(_)) = &result {
+            let mut state = self.state.lock().expect("fallback state poisoned");
+            state.recent_failures.push_back(now);
+            let window = self.failure_window;
+            while let Some(oldest) = state.recent_failures.front() {
+                if now.duration_since(*oldest) >= window {
+                    state.recent_failures.pop_front();
+                } else {
+                    break;
+                }
+            }
+            if state.recent_failures.len() >= self.failure_threshold {
+                self.logger.warning(format!(
+                    "Primary resolver failed {} times in {:?}; switching to DoH for {:?}",
+                    state.recent_failures.len(),
+                    self.failure_window,
+                    self.cooldown
+                ));
+                state.doh_until = Some(now + self.cooldown);
+                drop(state);
+                return self.doh.lookup_ip_with_ttl(hostname);
+            }
+        }
+        result
+    }
+}
+
+impl ResolverWrapper for FallbackResolver {
+    fn lookup_ip_with_ttl(
+        &self,
+        hostname: &str,
+    ) -> Result<Vec<(IpAddr, Duration)>, ResolveError> {
+        self.lookup_at(hostname, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy_client::resolver_wrapper::{ResolveError, ResolverWrapper};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn ip(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
+
+    /// A response that answers the given query with one A record.
+    fn canned_a_response(query: &[u8], address: [u8; 4], ttl: u32) -> Vec<u8> {
+        let mut response = query.to_vec();
+        response[2] = 0x81; // QR + RD
+        response[3] = 0x80; // RA
+        response[7] = 0x01; // one answer
+        response.extend_from_slice(&[0xC0, 0x0C]); // name: pointer to the question
+        response.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]); // A, IN
+        response.extend_from_slice(&ttl.to_be_bytes());
+        response.extend_from_slice(&[0x00, 0x04]);
+        response.extend_from_slice(&address);
+        response
+    }
+
+    struct DohTransportMock {
+        post_dns_params: Arc<Mutex<Vec<String>>>,
+        post_dns_results: Mutex<Vec<Result<Vec<u8>, String>>>,
+    }
+
+    impl DohTransportMock {
+        fn new() -> DohTransportMock {
+            DohTransportMock {
+                post_dns_params: Arc::new(Mutex::new(vec![])),
+                post_dns_results: Mutex::new(vec![]),
+            }
+        }
+
+        fn post_dns_params(mut self, params: &Arc<Mutex<Vec<String>>>) -> DohTransportMock {
+            self.post_dns_params = params.clone();
+            self
+        }
+
+        fn post_dns_result(self, result: Result<Vec<u8>, String>) -> DohTransportMock {
+            self.post_dns_results.lock().unwrap().push(result);
+            self
+        }
+    }
+
+    impl DohTransport for DohTransportMock {
+        fn post_dns(&self, url: &str, _query: &[u8]) -> Result<Vec<u8>, String> {
+            self.post_dns_params.lock().unwrap().push(url.to_string());
+            self.post_dns_results.lock().unwrap().remove(0)
+        }
+    }
+
+    #[test]
+    fn doh_resolver_returns_addresses_with_ttls() {
+        let query = encode_a_query("example.com");
+        let transport = DohTransportMock::new()
+            .post_dns_result(Ok(canned_a_response(&query, [93, 184, 216, 34], 300)));
+        let subject = DohResolver::new(
+            Box::new(transport),
+            vec!["https://doh.example/dns-query".to_string()],
+        );
+
+        let result = subject.lookup_ip_with_ttl("example.com").unwrap();
+
+        assert_eq!(result, vec![(ip("93.184.216.34"), Duration::from_secs(300))]);
+    }
+
+    #[test]
+    fn doh_resolver_tries_the_next_url_after_a_failure() {
+        let query = encode_a_query("example.com");
+        let urls_tried = Arc::new(Mutex::new(vec![]));
+        let transport = DohTransportMock::new()
+            .post_dns_params(&urls_tried)
+            .post_dns_result(Err("connect refused".to_string()))
+            .post_dns_result(Ok(canned_a_response(&query, [1, 2, 3, 4], 60)));
+        let subject = DohResolver::new(
+            Box::new(transport),
+            vec![
+                "https://first.example/dns-query".to_string(),
+                "https://second.example/dns-query".to_string(),
+            ],
+        );
+
+        let result = subject.lookup_ip_with_ttl("example.com").unwrap();
+
+        assert_eq!(result, vec![(ip("1.2.3.4"), Duration::from_secs(60))]);
+        assert_eq!(
+            *urls_tried.lock().unwrap(),
+            vec![
+                "https://first.example/dns-query".to_string(),
+                "https://second.example/dns-query".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn doh_resolver_reports_failure_when_every_url_fails() {
+        let transport = DohTransportMock::new()
+            .post_dns_result(Err("connect refused".to_string()));
+        let subject = DohResolver::new(
+            Box::new(transport),
+            vec!["https://doh.example/dns-query".to_string()],
+        );
+
+        let result = subject.lookup_ip_with_ttl("example.com");
+
+        match result {
+            Err(ResolveError::ResolverFailure(message)) => {
+                assert!(message.contains("connect refused"), "{}", message)
+            }
+            other => panic!("expected ResolverFailure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn doh_resolver_works_against_a_local_doh_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server_thread = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut request = vec![0u8; 4096];
+            let mut read = 0;
+            let query = loop {
+                read += socket.read(&mut request[read..]).unwrap();
+                if let Some(headers_end) =
+                    request[..read].windows(4).position(|w| w == b"\r\n\r\n")
+                {
+                    let header_block = String::from_utf8_lossy(&request[..headers_end]);
+                    let content_length: usize = header_block
+                        .lines()
+                        .find_map(|line| {
+                            let (name, value) = line.split_once(':')?;
+                            if name.eq_ignore_ascii_case("content-length") {
+                                value.trim().parse().ok()
+                            } else {
+                                None
+                            }
+                        })
+                        .unwrap();
+                    let body_start = headers_end + 4;
+                    while read < body_start + content_length {
+                        read += socket.read(&mut request[read..]).unwrap();
+                    }
+                    break request[body_start..body_start + content_length].to_vec();
+                }
+            };
+            let body = canned_a_response(&query, [10, 11, 12, 13], 120);
+            let mut response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            )
+            .into_bytes();
+            response.extend(body);
+            socket.write_all(&response).unwrap();
+        });
+        let subject = DohResolver::new(
+            Box::new(DohTransportReal::new()),
+            vec![format!("http://127.0.0.1:{}/dns-query", port)],
+        );
+
+        let result = subject.lookup_ip_with_ttl("example.com").unwrap();
+
+        assert_eq!(result, vec![(ip("10.11.12.13"), Duration::from_secs(120))]);
+        server_thread.join().unwrap();
+    }
+
+    struct ResolverWrapperMock {
+        lookup_params: Arc<Mutex<Vec<String>>>,
+        lookup_results: Mutex<Vec<Result<Vec<(IpAddr, Duration)>, ResolveError>>>,
+    }
+
+    impl ResolverWrapperMock {
+        fn new() -> ResolverWrapperMock {
+            ResolverWrapperMock {
+                lookup_params: Arc::new(Mutex::new(vec![])),
+                lookup_results: Mutex::new(vec![]),
+            }
+        }
+
+        fn lookup_ip_with_ttl_params(mut self, params: &Arc<Mutex<Vec<String>>>) -> ResolverWrapperMock {
+            self.lookup_params = params.clone();
+            self
+        }
+
+        fn lookup_ip_with_ttl_result(
+            self,
+            result: Result<Vec<(IpAddr, Duration)>, ResolveError>,
+        ) -> ResolverWrapperMock {
+            self.lookup_results.lock().unwrap().push(result);
+            self
+        }
+    }
+
+    impl ResolverWrapper for ResolverWrapperMock {
+        fn lookup_ip_with_ttl(
+            &self,
+            hostname: &str,
+        ) -> Result<Vec<(IpAddr, Duration)>, ResolveError> {
+            self.lookup_params.lock().unwrap().push(hostname.to_string());
+            self.lookup_results.lock().unwrap().remove(0)
+        }
+    }
+
+    fn network_failure() -> Result<Vec<(IpAddr, Duration)>, ResolveError> {
+        Err(ResolveError::ResolverFailure("port 53 blocked".to_string()))
+    }
+
+    fn good_answer(address: &str) -> Result<Vec<(IpAddr, Duration)>, ResolveError> {
+        Ok(vec![(ip(address), Duration::from_secs(60))])
+    }
+
+    #[test]
+    fn repeated_network_failures_inside_the_window_engage_doh() {
+        let primary = ResolverWrapperMock::new()
+            .lookup_ip_with_ttl_result(network_failure())
+            .lookup_ip_with_ttl_result(network_failure())
+            .lookup_ip_with_ttl_result(network_failure());
+        let doh_params = Arc::new(Mutex::new(vec![]));
+        let doh = ResolverWrapperMock::new()
+            .lookup_ip_with_ttl_params(&doh_params)
+            .lookup_ip_with_ttl_result(good_answer("9.9.9.9"))
+            .lookup_ip_with_ttl_result(good_answer("9.9.9.9"));
+        let subject = FallbackResolver::new(
+            Box::new(primary),
+            Box::new(doh),
+            3,
+            Duration::from_secs(60),
+            Duration::from_secs(300),
+        );
+        let start = Instant::now();
+
+        assert_eq!(subject.lookup_at("a.com", start), network_failure());
+        assert_eq!(
+            subject.lookup_at("b.com", start + Duration::from_secs(1)),
+            network_failure()
+        );
+        // The third failure crosses the threshold; the same lookup is
+        // retried over DoH immediately.
+        assert_eq!(
+            subject.lookup_at("c.com", start + Duration::from_secs(2)),
+            good_answer("9.9.9.9")
+        );
+        // And subsequent lookups go straight to DoH.
+        assert_eq!(
+            subject.lookup_at("d.com", start + Duration::from_secs(3)),
+            good_answer("9.9.9.9")
+        );
+        assert_eq!(
+            *doh_params.lock().unwrap(),
+            vec!["c.com".to_string(), "d.com".to_string()]
+        );
+    }
+
+    #[test]
+    fn no_records_answers_do_not_count_toward_the_threshold() {
+        let primary = ResolverWrapperMock::new()
+            .lookup_ip_with_ttl_result(Err(ResolveError::NoRecords("a.com".to_string())))
+            .lookup_ip_with_ttl_result(Err(ResolveError::NoRecords("b.com".to_string())))
+            .lookup_ip_with_ttl_result(Err(ResolveError::NoRecords("c.com".to_string())))
+            .lookup_ip_with_ttl_result(good_answer("1.1.1.1"));
+        let subject = FallbackResolver::new(
+            Box::new(primary),
+            Box::new(ResolverWrapperMock::new()),
+            3,
+            Duration::from_secs(60),
+            Duration::from_secs(300),
+        );
+        let start = Instant::now();
+
+        for (hostname, offset) in [("a.com", 0), ("b.com", 1), ("c.com", 2)] {
+            let result = subject.lookup_at(hostname, start + Duration::from_secs(offset));
+            assert_eq!(result, Err(ResolveError::NoRecords(hostname.to_string())));
+        }
+
+        assert_eq!(
+            subject.lookup_at("d.com", start + Duration::from_secs(3)),
+            good_answer("1.1.1.1")
+        );
+    }
+
+    #[test]
+    fn failures_outside_the_window_are_forgotten() {
+        let primary = ResolverWrapperMock::new()
+            .lookup_ip_with_ttl_result(network_failure())
+            .lookup_ip_with_ttl_result(network_failure())
+            .lookup_ip_with_ttl_result(network_failure())
+            .lookup_ip_with_ttl_result(good_answer("1.1.1.1"));
+        let subject = FallbackResolver::new(
+            Box::new(primary),
+            Box::new(ResolverWrapperMock::new()),
+            3,
+            Duration::from_secs(60),
+            Duration::from_secs(300),
+        );
+        let start = Instant::now();
+
+        subject.lookup_at("a.com", start).unwrap_err();
+        subject
+            .lookup_at("b.com", start + Duration::from_secs(90))
+            .unwrap_err();
+        // Only two failures remain inside any 60-second window.
+        subject
+            .lookup_at("c.com", start + Duration::from_secs(91))
+            .unwrap_err();
+
+        assert_eq!(
+            subject.lookup_at("d.com", start + Duration::from_secs(92)),
+            good_answer("1.1.1.1")
+        );
+    }
+
+    #[test]
+    fn the_primary_wins_back_the_traffic_after_the_cooldown() {
+        let primary_params = Arc::new(Mutex::new(vec![]));
+        let primary = ResolverWrapperMock::new()
+            .lookup_ip_with_ttl_params(&primary_params)
+            .lookup_ip_with_ttl_result(network_failure())
+            .lookup_ip_with_ttl_result(network_failure())
+            .lookup_ip_with_ttl_result(network_failure())
+            .lookup_ip_with_ttl_result(good_answer("8.8.8.8"));
+        let doh = ResolverWrapperMock::new()
+            .lookup_ip_with_ttl_result(good_answer("9.9.9.9"))
+            .lookup_ip_with_ttl_result(good_answer("9.9.9.9"));
+        let subject = FallbackResolver::new(
+            Box::new(primary),
+            Box::new(doh),
+            3,
+            Duration::from_secs(60),
+            Duration::from_secs(300),
+        );
+        let start = Instant::now();
+        for offset in 0..3 {
+            let _ = subject.lookup_at("a.com", start + Duration::from_secs(offset));
+        }
+        assert_eq!(
+            subject.lookup_at("b.com", start + Duration::from_secs(10)),
+            good_answer("9.9.9.9")
+        );
+
+        let result = subject.lookup_at("c.com", start + Duration::from_secs(303));
+
+        assert_eq!(result, good_answer("8.8.8.8"));
+        assert_eq!(
+            *primary_params.lock().unwrap(),
+            vec![
+                "a.com".to_string(),
+                "a.com".to_string(),
+                "a.com".to_string(),
+                "c.com".to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,224 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Destination-country policy for exit operators. Some jurisdictions make
+//! exiting traffic to certain countries legally risky; operators can block
+//! those (or allow only an explicit list) and the StreamHandlerPool checks
+//! every resolved address before connecting. Lookups go through a trait so
+//! tests do not need a real MaxMind database on disk.
+
+use crate::sub_lib::logger::Logger;
+use std::net::IpAddr;
+
+/// Country lookup abstraction over the GeoLite2 database.
+pub trait CountryLookup: Send {
+    /// ISO 3166-1 alpha-2 code for the address, or None when the database
+    /// has no answer.
+    fn country_code(&self, ip: IpAddr) -> Option<String>;
+}
+
+/// The real lookup, backed by a MaxMind GeoLite2 MMDB file read fully into
+/// memory at startup.
+pub struct GeoIpDatabase {
+    reader: maxminddb::Reader<Vec<u8>>,
+}
+
+impl GeoIpDatabase {
+    pub fn from_file(path: &std::path::Path) -> Result<GeoIpDatabase, String> {
+        let reader = maxminddb::Reader::open_readfile(path)
+            .map_err(|e| format!("Could not open GeoIP database {:?}: {}", path, e))?;
+        Ok(GeoIpDatabase { reader })
+    }
+}
+
+impl CountryLookup for GeoIpDatabase {
+    fn country_code(&self, ip: IpAddr) -> Option<String> {
+        self.reader
+            .lookup::<maxminddb::geoip2::Country>(ip)
+            .ok()
+            .and_then(|country| country.country)
+            .and_then(|c| c.iso_code)
+            .map(|code| code.to_string())
+    }
+}
+
+/// Operator policy. An empty allowed list means "allow everything not
+/// blocked"; a non-empty one means "allow only these". Blocks win over
+/// allows when a country appears in both.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct GeoIpPolicy {
+    pub allowed_countries: Vec<String>,
+    pub blocked_countries: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GeoVerdict {
+    Allow,
+    Blocked { country: Option<String> },
+}
+
+/// Applies the policy to every address the exit is about to connect to.
+pub struct GeoPolicyEnforcer {
+    lookup: Box<dyn CountryLookup>,
+    policy: GeoIpPolicy,
+    logger: Logger,
+}
+
+impl GeoPolicyEnforcer {
+    pub fn new(lookup: Box<dyn CountryLookup>, policy: GeoIpPolicy) -> GeoPolicyEnforcer {
+        GeoPolicyEnforcer {
+            lookup,
+            policy,
+            logger: Logger::new("ProxyClient"),
+        }
+    }
+
+    pub fn verdict_for(&self, ip: IpAddr) -> GeoVerdict {
+        let country = self.lookup.country_code(ip);
+        let blocked = match &country {
+            Some(code) => {
+                self.policy.blocked_countries.contains(code)
+                    || (!self.policy.allowed_countries.is_empty()
+                        && !self.policy.allowed_countries.contains(code))
+            }
+            // With an allowlist in force, an unlocatable address cannot be
+            // shown to be allowed, so it is refused.
+            None => !self.policy.allowed_countries.is_empty(),
+        };
+        if blocked {
+            self.logger.info(format!(
+                "Refusing exit connection to {} (country {:?}) by geographic policy",
+                ip, country
+            ));
+            GeoVerdict::Blocked { country }
+        } else {
+            GeoVerdict::Allow
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    struct CountryLookupMock {
+        answers: HashMap<IpAddr, String>,
+    }
+
+    impl CountryLookupMock {
+        fn new(answers: &[(&str, &str)]) -> CountryLookupMock {
+            CountryLookupMock {
+                answers: answers
+                    .iter()
+                    .map(|(ip, code)| (IpAddr::from_str(ip).unwrap(), code.to_string()))
+                    .collect(),
+            }
+        }
+    }
+
+    impl CountryLookup for CountryLookupMock {
+        fn country_code(&self, ip: IpAddr) -> Option<String> {
+            self.answers.get(&ip).cloned()
+        }
+    }
+
+    fn lookup() -> Box<dyn CountryLookup> {
+        Box::new(CountryLookupMock::new(&[
+            ("93.184.216.34", "US"),
+            ("82.165.8.211", "DE"),
+            ("101.32.0.1", "SG"),
+        ]))
+    }
+
+    #[test]
+    fn a_blocked_country_is_refused() {
+        let policy = GeoIpPolicy {
+            allowed_countries: vec![],
+            blocked_countries: vec!["DE".to_string()],
+        };
+        let subject = GeoPolicyEnforcer::new(lookup(), policy);
+
+        assert_eq!(
+            subject.verdict_for(IpAddr::from_str("82.165.8.211").unwrap()),
+            GeoVerdict::Blocked {
+                country: Some("DE".to_string())
+            }
+        );
+        assert_eq!(
+            subject.verdict_for(IpAddr::from_str("93.184.216.34").unwrap()),
+            GeoVerdict::Allow
+        );
+    }
+
+    #[test]
+    fn an_allowlist_refuses_everything_outside_it() {
+        let policy = GeoIpPolicy {
+            allowed_countries: vec!["US".to_string(), "DE".to_string()],
+            blocked_countries: vec![],
+        };
+        let subject = GeoPolicyEnforcer::new(lookup(), policy);
+
+        assert_eq!(
+            subject.verdict_for(IpAddr::from_str("93.184.216.34").unwrap()),
+            GeoVerdict::Allow
+        );
+        assert_eq!(
+            subject.verdict_for(IpAddr::from_str("101.32.0.1").unwrap()),
+            GeoVerdict::Blocked {
+                country: Some("SG".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn a_block_wins_over_an_allow_for_the_same_country() {
+        let policy = GeoIpPolicy {
+            allowed_countries: vec!["US".to_string()],
+            blocked_countries: vec!["US".to_string()],
+        };
+        let subject = GeoPolicyEnforcer::new(lookup(), policy);
+
+        assert_eq!(
+            subject.verdict_for(IpAddr::from_str("93.184.216.34").unwrap()),
+            GeoVerdict::Blocked {
+                country: Some("US".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn an_unlocatable_address_is_refused_only_under_an_allowlist() {
+        let unlocatable = IpAddr::from_str("10.1.2.3").unwrap();
+        let blocklist_only = GeoPolicyEnforcer::new(
+            lookup(),
+            GeoIpPolicy {
+                allowed_countries: vec![],
+                blocked_countries: vec!["DE".to_string()],
+            },
+        );
+        let allowlist = GeoPolicyEnforcer::new(
+            lookup(),
+            GeoIpPolicy {
+                allowed_countries: vec!["US".to_string()],
+                blocked_countries: vec![],
+            },
+        );
+
+        assert_eq!(blocklist_only.verdict_for(unlocatable), GeoVerdict::Allow);
+        assert_eq!(
+            allowlist.verdict_for(unlocatable),
+            GeoVerdict::Blocked { country: None }
+        );
+    }
+
+    #[test]
+    fn an_empty_policy_allows_everything() {
+        let subject = GeoPolicyEnforcer::new(lookup(), GeoIpPolicy::default());
+
+        assert_eq!(
+            subject.verdict_for(IpAddr::from_str("101.32.0.1").unwrap()),
+            GeoVerdict::Allow
+        );
+    }
+}
@@ -0,0 +1,247 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Opt-in exit-side HTTP header sanitization: identifying headers (Via,
+//! X-Forwarded-For and friends, usually added by sloppy local proxies) are
+//! stripped from the request before it leaves the exit. Header blocks that
+//! span several sequenced packets are buffered until complete or until a
+//! size cap is hit; anything that does not look like HTTP passes through
+//! untouched.
+
+const HTTP_METHODS: [&[u8]; 8] = [
+    b"GET ", b"POST ", b"PUT ", b"DELETE ", b"HEAD ", b"OPTIONS ", b"PATCH ", b"TRACE ",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeaderSanitizerConfig {
+    pub enabled: bool,
+    /// Header names (case-insensitive) removed from outbound requests.
+    pub denylist: Vec<String>,
+    /// Header blocks longer than this are forwarded unsanitized rather than
+    /// buffered forever.
+    pub max_header_block_bytes: usize,
+}
+
+impl Default for HeaderSanitizerConfig {
+    fn default() -> Self {
+        HeaderSanitizerConfig {
+            enabled: false,
+            denylist: vec![
+                "via".to_string(),
+                "x-forwarded-for".to_string(),
+                "forwarded".to_string(),
+                "x-real-ip".to_string(),
+            ],
+            max_header_block_bytes: 32 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SanitizerOutput {
+    /// Still buffering a header block; write nothing yet.
+    Hold,
+    /// Write these bytes to the server socket.
+    Flush(Vec<u8>),
+}
+
+enum SanitizerState {
+    /// First packet not yet seen.
+    Fresh,
+    /// Accumulating an HTTP header block.
+    Buffering(Vec<u8>),
+    /// Headers handled (or stream judged non-HTTP); relay everything as-is.
+    PassThrough,
+}
+
+pub struct HeaderSanitizer {
+    config: HeaderSanitizerConfig,
+    state: SanitizerState,
+}
+
+impl HeaderSanitizer {
+    pub fn new(config: HeaderSanitizerConfig) -> HeaderSanitizer {
+        HeaderSanitizer {
+            config,
+            state: SanitizerState::Fresh,
+        }
+    }
+
+    pub fn process(&mut self, data: &[u8]) -> SanitizerOutput {
+        if !self.config.enabled {
+            return SanitizerOutput::Flush(data.to_vec());
+        }
+        match std::mem::replace(&mut self.state, SanitizerState::PassThrough) {
+            SanitizerState::Fresh => {
+                if !looks_like_http(data) {
+                    return SanitizerOutput::Flush(data.to_vec());
+                }
+                self.buffer_and_check(data.to_vec())
+            }
+            SanitizerState::Buffering(mut buffer) => {
+                buffer.extend_from_slice(data);
+                self.buffer_and_check(buffer)
+            }
+            SanitizerState::PassThrough => SanitizerOutput::Flush(data.to_vec()),
+        }
+    }
+
+    fn buffer_and_check(&mut self, buffer: Vec<u8>) -> SanitizerOutput {
+        match find_subslice(&buffer, b"\r\n\r\n") {
+            Some(headers_end) => {
+                let sanitized = self.sanitize(&buffer, headers_end);
+                self.state = SanitizerState::PassThrough;
+                SanitizerOutput::Flush(sanitized)
+            }
+            None if buffer.len() > self.config.max_header_block_bytes => {
+                // Cap hit: give up on sanitizing rather than stalling the stream.
+                self.state = SanitizerState::PassThrough;
+                SanitizerOutput::Flush(buffer)
+            }
+            None => {
+                self.state = SanitizerState::Buffering(buffer);
+                SanitizerOutput::Hold
+            }
+        }
+    }
+
+    fn sanitize(&self, buffer: &[u8], headers_end: usize) -> Vec<u8> {
+        let header_block = &buffer[..headers_end];
+        let body = &buffer[headers_end + 4..];
+        let mut lines = header_block
+            .split(|b| *b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+        let mut output: Vec<u8> = vec![];
+        if let Some(request_line) = lines.next() {
+            output.extend_from_slice(request_line);
+            output.extend_from_slice(b"\r\n");
+        }
+        for line in lines {
+            let name = line
+                .splitn(2, |b| *b == b':')
+                .next()
+                .unwrap_or(b"")
+                .to_ascii_lowercase();
+            let name = String::from_utf8_lossy(&name).trim().to_string();
+            if self.config.denylist.iter().any(|denied| *denied == name) {
+                continue;
+            }
+            output.extend_from_slice(line);
+            output.extend_from_slice(b"\r\n");
+        }
+        // Blank line separating headers from body.
+        output.extend_from_slice(b"\r\n");
+        output.extend_from_slice(body);
+        output
+    }
+}
+
+fn looks_like_http(data: &[u8]) -> bool {
+    HTTP_METHODS.iter().any(|method| data.starts_with(method))
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled_config() -> HeaderSanitizerConfig {
+        HeaderSanitizerConfig {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn denylisted_headers_are_stripped() {
+        let mut subject = HeaderSanitizer::new(enabled_config());
+        let request = b"GET / HTTP/1.1\r\n\
+Host: example.com\r\n\
+Via: 1.1 local-proxy\r\n\
+X-Forwarded-For: 192.168.1.5\r\n\
+Accept: */*\r\n\
+\r\n\
+body bytes";
+
+        let output = subject.process(request);
+
+        let flushed = match output {
+            SanitizerOutput::Flush(bytes) => bytes,
+            other => panic!("expected Flush, got {:?}", other),
+        };
+        let text = String::from_utf8(flushed).unwrap();
+        assert!(!text.contains("Via:"));
+        assert!(!text.contains("X-Forwarded-For:"));
+        assert!(text.contains("Host: example.com"));
+        assert!(text.contains("Accept: */*"));
+        assert!(text.ends_with("\r\n\r\nbody bytes"));
+    }
+
+    #[test]
+    fn split_header_blocks_are_buffered_until_complete() {
+        let mut subject = HeaderSanitizer::new(enabled_config());
+
+        let first = subject.process(b"GET / HTTP/1.1\r\nHost: exam");
+        let second = subject.process(b"ple.com\r\nVia: 1.1 proxy\r\n\r\n");
+
+        assert_eq!(first, SanitizerOutput::Hold);
+        let flushed = match second {
+            SanitizerOutput::Flush(bytes) => String::from_utf8(bytes).unwrap(),
+            other => panic!("expected Flush, got {:?}", other),
+        };
+        assert!(flushed.contains("Host: example.com"));
+        assert!(!flushed.contains("Via:"));
+    }
+
+    #[test]
+    fn non_http_streams_pass_through_unmodified() {
+        let mut subject = HeaderSanitizer::new(enabled_config());
+        let tls_bytes = [0x16, 0x03, 0x01, 0x02, 0x00, 0x01];
+
+        let output = subject.process(&tls_bytes);
+
+        assert_eq!(output, SanitizerOutput::Flush(tls_bytes.to_vec()));
+    }
+
+    #[test]
+    fn oversized_header_block_is_flushed_unsanitized() {
+        let mut subject = HeaderSanitizer::new(HeaderSanitizerConfig {
+            enabled: true,
+            max_header_block_bytes: 64,
+            ..Default::default()
+        });
+        let mut request = b"GET / HTTP/1.1\r\n".to_vec();
+        request.extend(std::iter::repeat(b'x').take(100));
+
+        let output = subject.process(&request);
+
+        assert_eq!(output, SanitizerOutput::Flush(request));
+    }
+
+    #[test]
+    fn disabled_sanitizer_is_a_straight_pipe() {
+        let mut subject = HeaderSanitizer::new(HeaderSanitizerConfig::default());
+        let request = b"GET / HTTP/1.1\r\nVia: 1.1 proxy\r\n\r\n";
+
+        let output = subject.process(request);
+
+        assert_eq!(output, SanitizerOutput::Flush(request.to_vec()));
+    }
+
+    #[test]
+    fn packets_after_the_header_block_pass_through() {
+        let mut subject = HeaderSanitizer::new(enabled_config());
+        subject.process(b"GET / HTTP/1.1\r\nVia: p\r\n\r\n");
+
+        let output = subject.process(b"raw body continuation");
+
+        assert_eq!(
+            output,
+            SanitizerOutput::Flush(b"raw body continuation".to_vec())
+        );
+    }
+}
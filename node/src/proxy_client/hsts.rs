@@ -0,0 +1,171 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! HTTP Strict Transport Security enforcement at the exit: once a domain has
+//! told us it is HTTPS-only, later plain-HTTP requests to it are upgraded
+//! before we connect on the client's behalf.
+
+use std::collections::HashMap;
+
+/// Domain → expiry, as seconds since the Unix epoch.
+#[derive(Default)]
+pub struct HstsStore {
+    entries: HashMap<String, u64>,
+}
+
+impl HstsStore {
+    pub fn new() -> HstsStore {
+        Self::default()
+    }
+
+    /// Scans a response's header block for Strict-Transport-Security and
+    /// records (or clears, on max-age=0) the domain's entry.
+    pub fn note_response(&mut self, domain: &str, response: &[u8], now_secs: u64) {
+        let Some(max_age) = extract_sts_max_age(response) else {
+            return;
+        };
+        let domain = domain.to_ascii_lowercase();
+        if max_age == 0 {
+            self.entries.remove(&domain);
+        } else {
+            self.entries.insert(domain, now_secs.saturating_add(max_age));
+        }
+    }
+
+    /// True when the domain has an unexpired HSTS entry; expired entries are
+    /// dropped on the way through.
+    pub fn must_upgrade(&mut self, domain: &str, now_secs: u64) -> bool {
+        let domain = domain.to_ascii_lowercase();
+        match self.entries.get(&domain) {
+            Some(expiry) if *expiry > now_secs => true,
+            Some(_) => {
+                self.entries.remove(&domain);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// The upgrade decision for an outbound plain-HTTP request.
+pub fn upgraded_target(
+    store: &mut HstsStore,
+    enforce_hsts: bool,
+    domain: &str,
+    port: u16,
+    now_secs: u64,
+) -> (u16, bool) {
+    if enforce_hsts && port == 80 && store.must_upgrade(domain, now_secs) {
+        (443, true)
+    } else {
+        (port, false)
+    }
+}
+
+fn extract_sts_max_age(response: &[u8]) -> Option<u64> {
+    let headers_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap_or(response.len());
+    let header_block = String::from_utf8_lossy(&response[..headers_end]);
+    let value = header_block.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.eq_ignore_ascii_case("strict-transport-security") {
+            Some(value.trim().to_ascii_lowercase())
+        } else {
+            None
+        }
+    })?;
+    value.split(';').find_map(|directive| {
+        directive
+            .trim()
+            .strip_prefix("max-age=")?
+            .parse::<u64>()
+            .ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STS_RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\n\
+Strict-Transport-Security: max-age=31536000; includeSubDomains\r\n\
+\r\n\
+body";
+
+    #[test]
+    fn sts_response_populates_the_store() {
+        let mut subject = HstsStore::new();
+
+        subject.note_response("Example.com", STS_RESPONSE, 1_000);
+
+        assert!(subject.must_upgrade("example.com", 1_001));
+        assert_eq!(subject.len(), 1);
+    }
+
+    #[test]
+    fn entries_expire() {
+        let mut subject = HstsStore::new();
+        subject.note_response("example.com", STS_RESPONSE, 1_000);
+
+        assert!(!subject.must_upgrade("example.com", 1_000 + 31_536_000));
+        assert!(subject.is_empty());
+    }
+
+    #[test]
+    fn max_age_zero_clears_the_entry() {
+        let mut subject = HstsStore::new();
+        subject.note_response("example.com", STS_RESPONSE, 1_000);
+
+        subject.note_response(
+            "example.com",
+            b"HTTP/1.1 200 OK\r\nStrict-Transport-Security: max-age=0\r\n\r\n",
+            2_000,
+        );
+
+        assert!(!subject.must_upgrade("example.com", 2_001));
+    }
+
+    #[test]
+    fn http_request_to_known_domain_is_upgraded() {
+        let mut subject = HstsStore::new();
+        subject.note_response("example.com", STS_RESPONSE, 1_000);
+
+        let (port, upgraded) = upgraded_target(&mut subject, true, "example.com", 80, 1_001);
+
+        assert_eq!((port, upgraded), (443, true));
+    }
+
+    #[test]
+    fn upgrade_is_skipped_when_not_enforcing_or_not_port_80() {
+        let mut subject = HstsStore::new();
+        subject.note_response("example.com", STS_RESPONSE, 1_000);
+
+        assert_eq!(
+            upgraded_target(&mut subject, false, "example.com", 80, 1_001),
+            (80, false)
+        );
+        assert_eq!(
+            upgraded_target(&mut subject, true, "example.com", 8080, 1_001),
+            (8080, false)
+        );
+    }
+
+    #[test]
+    fn unknown_domain_is_left_alone() {
+        let mut subject = HstsStore::new();
+
+        assert_eq!(
+            upgraded_target(&mut subject, true, "example.org", 80, 1_001),
+            (80, false)
+        );
+    }
+}
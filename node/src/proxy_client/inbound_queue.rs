@@ -0,0 +1,181 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Lock-free intake for InboundServerData, the exit's highest-volume
+//! message. The general mailbox guards its queue with a mutex, and at
+//! tens of thousands of messages a second the lock handoff between the
+//! stream readers and the handler becomes the bottleneck. Server data
+//! now goes through a bounded crossbeam ArrayQueue instead — producers
+//! and the drain worker never contend on a lock — and a dedicated worker
+//! thread calls the handler directly. Everything else keeps using the
+//! mailbox.
+
+use crate::sub_lib::stream_key::StreamKey;
+use crossbeam::queue::ArrayQueue;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+pub const DEFAULT_QUEUE_CAPACITY: usize = 16_384;
+
+/// One chunk of server response data headed back toward the origin.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InboundServerData {
+    pub stream_key: StreamKey,
+    pub sequence_number: u64,
+    pub last_data: bool,
+    pub data: Vec<u8>,
+}
+
+/// The queue plus its drain worker. Dropping the intake stops the worker
+/// after it has drained whatever is still queued.
+pub struct InboundIntake {
+    queue: Arc<ArrayQueue<InboundServerData>>,
+    running: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl InboundIntake {
+    pub fn start<F>(capacity: usize, handler: F) -> InboundIntake
+    where
+        F: Fn(InboundServerData) + Send + 'static,
+    {
+        let queue = Arc::new(ArrayQueue::new(capacity));
+        let running = Arc::new(AtomicBool::new(true));
+        let worker_queue = queue.clone();
+        let worker_running = running.clone();
+        let worker = std::thread::spawn(move || loop {
+            match worker_queue.pop() {
+                Some(message) => handler(message),
+                None => {
+                    if !worker_running.load(Ordering::SeqCst) {
+                        return; // stopped, and the queue is drained
+                    }
+                    std::thread::sleep(Duration::from_micros(50));
+                }
+            }
+        });
+        InboundIntake {
+            queue,
+            running,
+            worker: Some(worker),
+        }
+    }
+
+    /// Lock-free enqueue. A full queue hands the message back so the
+    /// caller can apply its backpressure (stop reading the server socket)
+    /// instead of silently dropping response bytes.
+    pub fn offer(&self, message: InboundServerData) -> Result<(), InboundServerData> {
+        self.queue.push(message)
+    }
+
+    pub fn queued_count(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+impl Drop for InboundIntake {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn message(stream: u64, sequence_number: u64) -> InboundServerData {
+        InboundServerData {
+            stream_key: StreamKey::make_meaningless(stream),
+            sequence_number,
+            last_data: false,
+            data: vec![0xAB; 64],
+        }
+    }
+
+    #[test]
+    fn every_offered_message_reaches_the_handler() {
+        let handled = Arc::new(Mutex::new(vec![]));
+        let handled_in_handler = handled.clone();
+        let subject = InboundIntake::start(1024, move |message: InboundServerData| {
+            handled_in_handler
+                .lock()
+                .unwrap()
+                .push(message.sequence_number);
+        });
+
+        for sequence_number in 0..100 {
+            subject.offer(message(1, sequence_number)).unwrap();
+        }
+        drop(subject); // joins the worker after the drain
+
+        assert_eq!(*handled.lock().unwrap(), (0..100).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn a_full_queue_hands_the_message_back() {
+        // A handler that never returns keeps the queue from draining.
+        let subject = InboundIntake::start(2, |_| loop {
+            std::thread::sleep(Duration::from_secs(1))
+        });
+        while subject.offer(message(1, 0)).is_ok() {}
+
+        let rejected = subject.offer(message(1, 99));
+
+        assert_eq!(rejected, Err(message(1, 99)));
+        // Leak the wedged worker rather than joining it forever.
+        std::mem::forget(subject);
+    }
+
+    #[test]
+    fn messages_from_many_producers_are_all_processed() {
+        let handled = Arc::new(Mutex::new(vec![]));
+        let handled_in_handler = handled.clone();
+        let subject = Arc::new(InboundIntake::start(
+            4096,
+            move |message: InboundServerData| {
+                handled_in_handler
+                    .lock()
+                    .unwrap()
+                    .push((message.stream_key, message.sequence_number));
+            },
+        ));
+
+        let producers: Vec<_> = (0..4u64)
+            .map(|stream| {
+                let subject = subject.clone();
+                std::thread::spawn(move || {
+                    for sequence_number in 0..250 {
+                        let mut pending = message(stream, sequence_number);
+                        loop {
+                            match subject.offer(pending) {
+                                Ok(()) => break,
+                                Err(returned) => pending = returned,
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        drop(Arc::try_unwrap(subject).ok().expect("producers still hold the intake"));
+
+        let handled = handled.lock().unwrap();
+        assert_eq!(handled.len(), 1000);
+        for stream in 0..4u64 {
+            let sequence: Vec<u64> = handled
+                .iter()
+                .filter(|(key, _)| *key == StreamKey::make_meaningless(stream))
+                .map(|(_, sequence_number)| *sequence_number)
+                .collect();
+            // Per-producer FIFO survives the interleaving.
+            assert_eq!(sequence, (0..250).collect::<Vec<u64>>());
+        }
+    }
+}
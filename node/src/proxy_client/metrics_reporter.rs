@@ -0,0 +1,186 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Per-operator APM reporting for the exit side, alongside the Prometheus
+//! registry. The reporter trait keeps the ProxyClient ignorant of wire
+//! formats; the Datadog implementation speaks StatsD datagrams to a local
+//! DogStatsD agent, which is fire-and-forget by design.
+
+use std::net::UdpSocket;
+
+pub const PACKAGES_PROCESSED: &str = "proxy_client.packages_processed";
+pub const RESPONSE_LATENCY_MS: &str = "proxy_client.response_latency_ms";
+pub const STREAM_COUNT: &str = "proxy_client.stream_count";
+
+/// What the ProxyClient emits; None configured means no reporting at all.
+pub trait MetricsReporter: Send {
+    fn count(&self, name: &str, value: u64);
+    fn histogram(&self, name: &str, value: f64);
+    fn gauge(&self, name: &str, value: u64);
+}
+
+/// StatsD over UDP to a DogStatsD agent. Send failures are swallowed:
+/// metrics must never take down relay traffic.
+pub struct DatadogReporter {
+    socket: UdpSocket,
+    agent_addr: String,
+}
+
+impl DatadogReporter {
+    pub fn new(agent_addr: String) -> std::io::Result<DatadogReporter> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        Ok(DatadogReporter { socket, agent_addr })
+    }
+
+    fn send(&self, datagram: String) {
+        let _ = self.socket.send_to(datagram.as_bytes(), &self.agent_addr);
+    }
+}
+
+impl MetricsReporter for DatadogReporter {
+    fn count(&self, name: &str, value: u64) {
+        self.send(format!("{}:{}|c", name, value));
+    }
+
+    fn histogram(&self, name: &str, value: f64) {
+        self.send(format!("{}:{}|h", name, value));
+    }
+
+    fn gauge(&self, name: &str, value: u64) {
+        self.send(format!("{}:{}|g", name, value));
+    }
+}
+
+/// The ProxyClient's instrumentation hooks, called from package handling.
+/// With no reporter configured every call is a no-op branch.
+pub struct ProxyClientInstrumentation {
+    metrics_reporter: Option<Box<dyn MetricsReporter>>,
+}
+
+impl ProxyClientInstrumentation {
+    pub fn new(metrics_reporter: Option<Box<dyn MetricsReporter>>) -> ProxyClientInstrumentation {
+        ProxyClientInstrumentation { metrics_reporter }
+    }
+
+    pub fn package_processed(&self) {
+        if let Some(reporter) = &self.metrics_reporter {
+            reporter.count(PACKAGES_PROCESSED, 1);
+        }
+    }
+
+    pub fn response_latency(&self, millis: f64) {
+        if let Some(reporter) = &self.metrics_reporter {
+            reporter.histogram(RESPONSE_LATENCY_MS, millis);
+        }
+    }
+
+    pub fn stream_count_changed(&self, count: u64) {
+        if let Some(reporter) = &self.metrics_reporter {
+            reporter.gauge(STREAM_COUNT, count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Emitted {
+        Count(String, u64),
+        Histogram(String, f64),
+        Gauge(String, u64),
+    }
+
+    struct MockReporter {
+        emitted: Arc<Mutex<Vec<Emitted>>>,
+    }
+
+    impl MockReporter {
+        fn new(emitted: &Arc<Mutex<Vec<Emitted>>>) -> MockReporter {
+            MockReporter {
+                emitted: emitted.clone(),
+            }
+        }
+    }
+
+    impl MetricsReporter for MockReporter {
+        fn count(&self, name: &str, value: u64) {
+            self.emitted
+                .lock()
+                .unwrap()
+                .push(Emitted::Count(name.to_string(), value));
+        }
+
+        fn histogram(&self, name: &str, value: f64) {
+            self.emitted
+                .lock()
+                .unwrap()
+                .push(Emitted::Histogram(name.to_string(), value));
+        }
+
+        fn gauge(&self, name: &str, value: u64) {
+            self.emitted
+                .lock()
+                .unwrap()
+                .push(Emitted::Gauge(name.to_string(), value));
+        }
+    }
+
+    #[test]
+    fn each_hook_emits_the_documented_metric() {
+        let emitted = Arc::new(Mutex::new(vec![]));
+        let subject =
+            ProxyClientInstrumentation::new(Some(Box::new(MockReporter::new(&emitted))));
+
+        subject.package_processed();
+        subject.response_latency(12.5);
+        subject.stream_count_changed(4);
+
+        assert_eq!(
+            *emitted.lock().unwrap(),
+            vec![
+                Emitted::Count("proxy_client.packages_processed".to_string(), 1),
+                Emitted::Histogram("proxy_client.response_latency_ms".to_string(), 12.5),
+                Emitted::Gauge("proxy_client.stream_count".to_string(), 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_reporter_means_every_hook_is_a_no_op() {
+        let subject = ProxyClientInstrumentation::new(None);
+
+        subject.package_processed();
+        subject.response_latency(12.5);
+        subject.stream_count_changed(4);
+    }
+
+    #[test]
+    fn the_datadog_reporter_emits_statsd_datagrams() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        agent
+            .set_read_timeout(Some(std::time::Duration::from_secs(2)))
+            .unwrap();
+        let subject = DatadogReporter::new(agent.local_addr().unwrap().to_string()).unwrap();
+
+        subject.count(PACKAGES_PROCESSED, 1);
+        subject.histogram(RESPONSE_LATENCY_MS, 12.5);
+        subject.gauge(STREAM_COUNT, 4);
+
+        let mut datagrams = vec![];
+        let mut buffer = [0u8; 512];
+        for _ in 0..3 {
+            let (count, _) = agent.recv_from(&mut buffer).unwrap();
+            datagrams.push(String::from_utf8_lossy(&buffer[..count]).to_string());
+        }
+        assert_eq!(
+            datagrams,
+            vec![
+                "proxy_client.packages_processed:1|c",
+                "proxy_client.response_latency_ms:12.5|h",
+                "proxy_client.stream_count:4|g",
+            ]
+        );
+    }
+}
@@ -0,0 +1,28 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+pub mod bandwidth_cap;
+pub mod buffer_pool;
+pub mod cover_traffic;
+pub mod ct_check;
+pub mod dns_failure;
+pub mod dns_rebinding;
+pub mod doh_resolver;
+pub mod gdpr_audit;
+pub mod geo_policy;
+pub mod header_sanitizer;
+pub mod hsts;
+pub mod inbound_queue;
+pub mod metrics_reporter;
+pub mod mptcp;
+pub mod ocsp;
+pub mod payload;
+pub mod ratchet;
+pub mod request_dedup;
+pub mod resolver_wrapper;
+pub mod response_cache;
+pub mod return_tunnels;
+pub mod sendfile_serve;
+pub mod splice_relay;
+pub mod stream_halves;
+pub mod trace_headers;
+pub mod udp_relay;
@@ -0,0 +1,225 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! MPTCP for exit connections. On multi-homed exits (wired plus LTE, dual
+//! uplinks), opening origin-server connections as MPTCP lets the kernel
+//! spread subflows across paths and survive one of them dying mid-stream.
+//! The kernel support gate is runtime, not compile-time: a kernel older
+//! than 5.6, or one with MPTCP compiled out, refuses the socket and we
+//! degrade to plain TCP with a single warning.
+
+use crate::sub_lib::logger::Logger;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+
+/// What a connection actually ended up as, for logging and diagnostics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionKind {
+    Mptcp,
+    PlainTcp,
+}
+
+/// Mockable wrapper around MPTCP socket creation.
+pub trait MptcpSocketOpener: Send {
+    fn open_mptcp_stream(&self, addr: SocketAddr) -> io::Result<TcpStream>;
+}
+
+#[cfg(target_os = "linux")]
+pub struct MptcpSocketOpenerReal;
+
+#[cfg(target_os = "linux")]
+impl MptcpSocketOpener for MptcpSocketOpenerReal {
+    fn open_mptcp_stream(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        use nix::sys::socket::{connect, SockaddrStorage};
+        use std::os::unix::io::FromRawFd;
+
+        // IPPROTO_MPTCP; neither nix's SockProtocol nor libc names it yet.
+        const IPPROTO_MPTCP: libc::c_int = 262;
+        let family = match addr {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+        // Safety: a bare socket(2) call; the fd is checked and owned by the
+        // TcpStream before anything can fail past this point.
+        let fd = unsafe { libc::socket(family, libc::SOCK_STREAM, IPPROTO_MPTCP) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let stream = unsafe { TcpStream::from_raw_fd(fd) };
+        connect(fd, &SockaddrStorage::from(addr)).map_err(io::Error::from)?;
+        Ok(stream)
+    }
+}
+
+/// Opens exit connections, preferring MPTCP when configured and the kernel
+/// cooperates. The degradation warning fires once per process, not once
+/// per stream — a kernel does not grow MPTCP support mid-run.
+pub struct ExitConnector {
+    mptcp_enabled: bool,
+    kernel_refused: bool,
+    opener: Box<dyn MptcpSocketOpener>,
+    logger: Logger,
+}
+
+impl ExitConnector {
+    pub fn new(mptcp_enabled: bool, opener: Box<dyn MptcpSocketOpener>) -> ExitConnector {
+        ExitConnector {
+            mptcp_enabled,
+            kernel_refused: false,
+            opener,
+            logger: Logger::new("ProxyClient"),
+        }
+    }
+
+    pub fn connect(&mut self, addr: SocketAddr) -> io::Result<(TcpStream, ConnectionKind)> {
+        if !self.mptcp_enabled || self.kernel_refused {
+            return TcpStream::connect(addr).map(|s| (s, ConnectionKind::PlainTcp));
+        }
+        match self.opener.open_mptcp_stream(addr) {
+            Ok(stream) => Ok((stream, ConnectionKind::Mptcp)),
+            Err(e) if is_mptcp_unsupported(&e) => {
+                self.logger.warning(format!(
+                    "Kernel refused an MPTCP socket ({}); mptcp_enabled is configured but this \
+                     kernel does not support it (needs >= 5.6 with CONFIG_MPTCP). Falling back \
+                     to plain TCP for all exit connections.",
+                    e
+                ));
+                self.kernel_refused = true;
+                TcpStream::connect(addr).map(|s| (s, ConnectionKind::PlainTcp))
+            }
+            // A connect failure on a supported kernel is the target's
+            // problem, not MPTCP's; surface it like any other.
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// EPROTONOSUPPORT or EINVAL from socket(2) means the kernel cannot do
+/// MPTCP at all, as opposed to this particular connection failing.
+fn is_mptcp_unsupported(error: &io::Error) -> bool {
+    matches!(
+        error.raw_os_error(),
+        Some(code) if code == 93 /* EPROTONOSUPPORT */ || code == 22 /* EINVAL */
+    ) || error.kind() == io::ErrorKind::Unsupported
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    struct MptcpSocketOpenerMock {
+        open_mptcp_stream_params: Arc<Mutex<Vec<SocketAddr>>>,
+        open_mptcp_stream_results: Mutex<Vec<io::Result<TcpStream>>>,
+    }
+
+    impl MptcpSocketOpenerMock {
+        fn new() -> MptcpSocketOpenerMock {
+            MptcpSocketOpenerMock {
+                open_mptcp_stream_params: Arc::new(Mutex::new(vec![])),
+                open_mptcp_stream_results: Mutex::new(vec![]),
+            }
+        }
+
+        fn open_mptcp_stream_params(
+            mut self,
+            params: &Arc<Mutex<Vec<SocketAddr>>>,
+        ) -> MptcpSocketOpenerMock {
+            self.open_mptcp_stream_params = params.clone();
+            self
+        }
+
+        fn open_mptcp_stream_result(self, result: io::Result<TcpStream>) -> MptcpSocketOpenerMock {
+            self.open_mptcp_stream_results.lock().unwrap().push(result);
+            self
+        }
+    }
+
+    impl MptcpSocketOpener for MptcpSocketOpenerMock {
+        fn open_mptcp_stream(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+            self.open_mptcp_stream_params.lock().unwrap().push(addr);
+            self.open_mptcp_stream_results.lock().unwrap().remove(0)
+        }
+    }
+
+    fn listening_target() -> (TcpListener, SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        (listener, addr)
+    }
+
+    #[test]
+    fn disabled_mptcp_never_touches_the_opener() {
+        let (_listener, addr) = listening_target();
+        let params = Arc::new(Mutex::new(vec![]));
+        let opener = MptcpSocketOpenerMock::new().open_mptcp_stream_params(&params);
+        let mut subject = ExitConnector::new(false, Box::new(opener));
+
+        let (_stream, kind) = subject.connect(addr).unwrap();
+
+        assert_eq!(kind, ConnectionKind::PlainTcp);
+        assert!(params.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_cooperating_kernel_yields_mptcp_connections() {
+        let (listener, addr) = listening_target();
+        // The mock stands in for the kernel: hand back a connected stream.
+        let backing = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let opener = MptcpSocketOpenerMock::new().open_mptcp_stream_result(Ok(backing));
+        let mut subject = ExitConnector::new(true, Box::new(opener));
+
+        let (_stream, kind) = subject.connect(addr).unwrap();
+
+        assert_eq!(kind, ConnectionKind::Mptcp);
+    }
+
+    #[test]
+    fn a_refusing_kernel_degrades_to_plain_tcp_and_stops_asking() {
+        let (_listener, addr) = listening_target();
+        let params = Arc::new(Mutex::new(vec![]));
+        let opener = MptcpSocketOpenerMock::new()
+            .open_mptcp_stream_params(&params)
+            .open_mptcp_stream_result(Err(io::Error::from_raw_os_error(93)));
+        let mut subject = ExitConnector::new(true, Box::new(opener));
+
+        let (_first, first_kind) = subject.connect(addr).unwrap();
+        let (_second, second_kind) = subject.connect(addr).unwrap();
+
+        assert_eq!(first_kind, ConnectionKind::PlainTcp);
+        assert_eq!(second_kind, ConnectionKind::PlainTcp);
+        // Only the first connect consulted the kernel.
+        assert_eq!(params.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn an_ordinary_connect_failure_is_not_treated_as_missing_mptcp() {
+        let (_listener, addr) = listening_target();
+        let opener = MptcpSocketOpenerMock::new()
+            .open_mptcp_stream_result(Err(io::Error::from_raw_os_error(111 /* ECONNREFUSED */)));
+        let mut subject = ExitConnector::new(true, Box::new(opener));
+
+        let result = subject.connect(addr);
+
+        assert_eq!(result.unwrap_err().raw_os_error(), Some(111));
+    }
+
+    // Real-kernel check; passes on any Linux by accepting either outcome,
+    // but exercises the actual socket(2) path on kernels >= 5.6.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn the_real_opener_either_creates_an_mptcp_socket_or_degrades() {
+        let (listener, addr) = listening_target();
+        let mut subject = ExitConnector::new(true, Box::new(MptcpSocketOpenerReal));
+
+        let (_stream, kind) = subject.connect(addr).unwrap();
+
+        match kind {
+            ConnectionKind::Mptcp => {
+                let (accepted, _) = listener.accept().unwrap();
+                drop(accepted);
+            }
+            ConnectionKind::PlainTcp => (), // old kernel; degradation verified
+        }
+    }
+}
@@ -0,0 +1,454 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! OCSP-staple verification for exit-side TLS connections. A server that
+//! staples an OCSP response proves its certificate's revocation status
+//! at handshake time, with no side channel for the stream handler pool
+//! to leak the destination through. If the staple says revoked, the
+//! connection is closed on the spot and logged at ERROR — a revoked
+//! certificate in the wild is exactly the compromise OCSP exists to
+//! catch. Servers that staple nothing are the common case, so absence is
+//! tolerated by default; operators who want the strict posture set
+//! `require_ocsp` and unstapled (or unusable) responses are rejected
+//! too. Only the staple's DER structure is walked here; its signature is
+//! checked by the TLS stack alongside the certificate chain.
+
+use crate::sub_lib::logger::Logger;
+
+/// id-pkix-ocsp-basic: the one response type anyone staples.
+const OCSP_BASIC_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+
+/// What a parsed staple said about the certificate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CertStatus {
+    Good,
+    Revoked { revocation_time: String },
+    /// The responder does not know the certificate — no better than no
+    /// staple at all.
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OcspError {
+    /// No staple, and `require_ocsp` is on.
+    StapleMissing { hostname: String },
+    /// A staple arrived but could not be used, and `require_ocsp` is on.
+    StapleUnusable { hostname: String, detail: String },
+    CertificateRevoked {
+        hostname: String,
+        revocation_time: String,
+    },
+    /// The responder answered "unknown", and `require_ocsp` is on.
+    CertificateUnknown { hostname: String },
+}
+
+pub struct OcspVerifier {
+    require_ocsp: bool,
+    logger: Logger,
+}
+
+impl OcspVerifier {
+    pub fn new(require_ocsp: bool) -> OcspVerifier {
+        OcspVerifier {
+            require_ocsp,
+            logger: Logger::new("ProxyClient"),
+        }
+    }
+
+    /// Judges the staple (or its absence) from the handshake with
+    /// `hostname`. An Err means the connection must be closed.
+    pub fn verify_staple(
+        &self,
+        hostname: &str,
+        staple: Option<&[u8]>,
+    ) -> Result<(), OcspError> {
+        let staple = match staple {
+            Some(staple) => staple,
+            None if self.require_ocsp => {
+                self.logger.warning(format!(
+                    "{} stapled no OCSP response and require_ocsp is on; rejecting the connection",
+                    hostname
+                ));
+                return Err(OcspError::StapleMissing {
+                    hostname: hostname.to_string(),
+                });
+            }
+            None => return Ok(()),
+        };
+        match parse_ocsp_response(staple) {
+            Ok(CertStatus::Good) => {
+                self.logger
+                    .debug(format!("OCSP staple from {} reports good", hostname));
+                Ok(())
+            }
+            Ok(CertStatus::Revoked { revocation_time }) => {
+                self.logger.error(format!(
+                    "Certificate for {} was revoked at {}; closing the connection",
+                    hostname, revocation_time
+                ));
+                Err(OcspError::CertificateRevoked {
+                    hostname: hostname.to_string(),
+                    revocation_time,
+                })
+            }
+            Ok(CertStatus::Unknown) if self.require_ocsp => {
+                self.logger.warning(format!(
+                    "OCSP responder does not know the certificate for {}; rejecting under require_ocsp",
+                    hostname
+                ));
+                Err(OcspError::CertificateUnknown {
+                    hostname: hostname.to_string(),
+                })
+            }
+            Ok(CertStatus::Unknown) => {
+                self.logger.warning(format!(
+                    "OCSP responder does not know the certificate for {}; allowing without require_ocsp",
+                    hostname
+                ));
+                Ok(())
+            }
+            Err(detail) if self.require_ocsp => {
+                self.logger.warning(format!(
+                    "Unusable OCSP staple from {} ({}); rejecting under require_ocsp",
+                    hostname, detail
+                ));
+                Err(OcspError::StapleUnusable {
+                    hostname: hostname.to_string(),
+                    detail,
+                })
+            }
+            Err(detail) => {
+                self.logger.warning(format!(
+                    "Unusable OCSP staple from {} ({}); treating as unstapled",
+                    hostname, detail
+                ));
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Walks the DER of an OCSPResponse (RFC 6960) down to the first
+/// SingleResponse's certStatus. Fields on the way are length-checked and
+/// skipped; any structural surprise is an Err with the detail.
+pub fn parse_ocsp_response(staple: &[u8]) -> Result<CertStatus, String> {
+    let mut outer = DerReader::new(staple);
+    let mut response = DerReader::new(outer.expect(0x30, "OCSPResponse")?);
+    let status = response.expect(0x0a, "responseStatus")?;
+    match status {
+        [0] => (),
+        [code] => return Err(format!("responder answered status {}", code)),
+        _ => return Err("malformed responseStatus".to_string()),
+    }
+    let mut response_bytes =
+        DerReader::new(response.expect(0xa0, "responseBytes")?);
+    let mut bytes_seq = DerReader::new(response_bytes.expect(0x30, "ResponseBytes")?);
+    let response_type = bytes_seq.expect(0x06, "responseType")?;
+    if response_type != OCSP_BASIC_OID {
+        return Err("unsupported response type".to_string());
+    }
+    let basic_der = bytes_seq.expect(0x04, "response octets")?;
+    let mut basic = DerReader::new(basic_der);
+    let mut basic_seq = DerReader::new(basic.expect(0x30, "BasicOCSPResponse")?);
+    let mut tbs = DerReader::new(basic_seq.expect(0x30, "tbsResponseData")?);
+    let mut next = tbs.next("responderID")?;
+    if next.0 == 0xa0 {
+        // Explicit version; present only when not the default.
+        next = tbs.next("responderID")?;
+    }
+    if next.0 != 0xa1 && next.0 != 0xa2 {
+        return Err(format!("expected responderID, found tag {:#04x}", next.0));
+    }
+    tbs.expect(0x18, "producedAt")?;
+    let mut responses = DerReader::new(tbs.expect(0x30, "responses")?);
+    let mut single = DerReader::new(responses.expect(0x30, "SingleResponse")?);
+    single.expect(0x30, "certID")?;
+    let (status_tag, status_body) = single.next("certStatus")?;
+    match status_tag {
+        0x80 => Ok(CertStatus::Good),
+        0xa1 => {
+            let mut revoked_info = DerReader::new(status_body);
+            let time = revoked_info.expect(0x18, "revocationTime")?;
+            Ok(CertStatus::Revoked {
+                revocation_time: String::from_utf8_lossy(time).to_string(),
+            })
+        }
+        0x82 => Ok(CertStatus::Unknown),
+        tag => Err(format!("unrecognized certStatus tag {:#04x}", tag)),
+    }
+}
+
+/// A cursor over DER tag-length-value triples. Definite lengths only, as
+/// DER requires.
+struct DerReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> DerReader<'a> {
+    fn new(data: &'a [u8]) -> DerReader<'a> {
+        DerReader { data, position: 0 }
+    }
+
+    fn next(&mut self, field: &str) -> Result<(u8, &'a [u8]), String> {
+        let tag = *self
+            .data
+            .get(self.position)
+            .ok_or_else(|| format!("truncated before {}", field))?;
+        self.position += 1;
+        let length = self.read_length(field)?;
+        let start = self.position;
+        let end = start
+            .checked_add(length)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| format!("{} overruns the staple", field))?;
+        self.position = end;
+        Ok((tag, &self.data[start..end]))
+    }
+
+    fn expect(&mut self, expected_tag: u8, field: &str) -> Result<&'a [u8], String> {
+        let (tag, body) = self.next(field)?;
+        if tag == expected_tag {
+            Ok(body)
+        } else {
+            Err(format!(
+                "expected {} (tag {:#04x}), found tag {:#04x}",
+                field, expected_tag, tag
+            ))
+        }
+    }
+
+    fn read_length(&mut self, field: &str) -> Result<usize, String> {
+        let first = *self
+            .data
+            .get(self.position)
+            .ok_or_else(|| format!("truncated in {} length", field))?;
+        self.position += 1;
+        match first {
+            0x00..=0x7f => Ok(first as usize),
+            0x81 | 0x82 => {
+                let count = (first & 0x7f) as usize;
+                let mut length = 0usize;
+                for _ in 0..count {
+                    let byte = *self
+                        .data
+                        .get(self.position)
+                        .ok_or_else(|| format!("truncated in {} length", field))?;
+                    self.position += 1;
+                    length = (length << 8) | byte as usize;
+                }
+                Ok(length)
+            }
+            _ => Err(format!("unsupported length form in {}", field)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The pre-generated responses below stand in for a test CA: the
+    // verifier walks structure, so representative field bodies suffice.
+
+    fn der(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        match body.len() {
+            length @ 0..=0x7f => out.push(length as u8),
+            length @ 0x80..=0xff => out.extend_from_slice(&[0x81, length as u8]),
+            length => out.extend_from_slice(&[0x82, (length >> 8) as u8, length as u8]),
+        }
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn concat(parts: &[Vec<u8>]) -> Vec<u8> {
+        parts.iter().flatten().copied().collect()
+    }
+
+    fn response_with_status(cert_status: Vec<u8>) -> Vec<u8> {
+        let single_response = der(
+            0x30,
+            &concat(&[
+                der(0x30, b"cert id fields"),
+                cert_status,
+                der(0x18, b"20230101000000Z"), // thisUpdate
+            ]),
+        );
+        let tbs = der(
+            0x30,
+            &concat(&[
+                der(0xa1, b"responder name"),
+                der(0x18, b"20230102000000Z"), // producedAt
+                der(0x30, &single_response),
+            ]),
+        );
+        let basic = der(
+            0x30,
+            &concat(&[
+                tbs,
+                der(0x30, &der(0x06, b"\x2a\x86\x48\xce\x3d\x04\x03\x02")),
+                der(0x03, b"\x00signature bits"),
+            ]),
+        );
+        let response_bytes = der(
+            0x30,
+            &concat(&[der(0x06, OCSP_BASIC_OID), der(0x04, &basic)]),
+        );
+        der(
+            0x30,
+            &concat(&[der(0x0a, &[0]), der(0xa0, &response_bytes)]),
+        )
+    }
+
+    fn good_response() -> Vec<u8> {
+        response_with_status(der(0x80, b""))
+    }
+
+    fn revoked_response() -> Vec<u8> {
+        response_with_status(der(0xa1, &der(0x18, b"20230415120000Z")))
+    }
+
+    fn unknown_response() -> Vec<u8> {
+        response_with_status(der(0x82, b""))
+    }
+
+    #[test]
+    fn a_good_staple_parses_and_passes() {
+        let staple = good_response();
+
+        assert_eq!(parse_ocsp_response(&staple), Ok(CertStatus::Good));
+        assert_eq!(
+            OcspVerifier::new(false).verify_staple("example.com", Some(&staple)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn a_revoked_staple_closes_the_connection() {
+        let staple = revoked_response();
+
+        let result = OcspVerifier::new(false).verify_staple("revoked.example.com", Some(&staple));
+
+        assert_eq!(
+            result,
+            Err(OcspError::CertificateRevoked {
+                hostname: "revoked.example.com".to_string(),
+                revocation_time: "20230415120000Z".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn an_unknown_status_rejects_only_under_require_ocsp() {
+        let staple = unknown_response();
+
+        assert_eq!(
+            OcspVerifier::new(false).verify_staple("example.com", Some(&staple)),
+            Ok(())
+        );
+        assert_eq!(
+            OcspVerifier::new(true).verify_staple("example.com", Some(&staple)),
+            Err(OcspError::CertificateUnknown {
+                hostname: "example.com".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn an_absent_staple_rejects_only_under_require_ocsp() {
+        assert_eq!(
+            OcspVerifier::new(false).verify_staple("example.com", None),
+            Ok(())
+        );
+        assert_eq!(
+            OcspVerifier::new(true).verify_staple("example.com", None),
+            Err(OcspError::StapleMissing {
+                hostname: "example.com".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn a_try_later_response_counts_as_unusable() {
+        let try_later = der(0x30, &der(0x0a, &[3]));
+
+        let parse = parse_ocsp_response(&try_later);
+        let strict = OcspVerifier::new(true).verify_staple("example.com", Some(&try_later));
+        let lax = OcspVerifier::new(false).verify_staple("example.com", Some(&try_later));
+
+        assert_eq!(parse, Err("responder answered status 3".to_string()));
+        assert!(matches!(strict, Err(OcspError::StapleUnusable { .. })));
+        assert_eq!(lax, Ok(()));
+    }
+
+    #[test]
+    fn truncated_and_garbage_staples_are_unusable_not_panics() {
+        let good = good_response();
+        for staple in [
+            &good[..good.len() - 5],
+            &b"not DER at all"[..],
+            &[0x30, 0x82][..],
+            &[][..],
+        ] {
+            let result = parse_ocsp_response(staple);
+
+            assert!(result.is_err(), "for {:?}", staple);
+        }
+    }
+
+    #[test]
+    fn an_unexpected_response_type_is_rejected() {
+        let mut staple = good_response();
+        // Corrupt one byte of the id-pkix-ocsp-basic OID.
+        let oid_at = staple
+            .windows(OCSP_BASIC_OID.len())
+            .position(|w| w == OCSP_BASIC_OID)
+            .unwrap();
+        staple[oid_at] ^= 0xff;
+
+        assert_eq!(
+            parse_ocsp_response(&staple),
+            Err("unsupported response type".to_string())
+        );
+    }
+
+    #[test]
+    fn large_staples_use_the_long_length_form_correctly() {
+        // Pad the responder name past 256 bytes so outer lengths need the
+        // two-byte form.
+        let single_response = der(
+            0x30,
+            &concat(&[
+                der(0x30, b"cert id fields"),
+                der(0x80, b""),
+                der(0x18, b"20230101000000Z"),
+            ]),
+        );
+        let tbs = der(
+            0x30,
+            &concat(&[
+                der(0xa1, &[b'n'; 300]),
+                der(0x18, b"20230102000000Z"),
+                der(0x30, &single_response),
+            ]),
+        );
+        let basic = der(
+            0x30,
+            &concat(&[
+                tbs,
+                der(0x30, &der(0x06, b"\x2a\x86\x48\xce\x3d\x04\x03\x02")),
+                der(0x03, b"\x00signature bits"),
+            ]),
+        );
+        let response_bytes = der(
+            0x30,
+            &concat(&[der(0x06, OCSP_BASIC_OID), der(0x04, &basic)]),
+        );
+        let staple = der(
+            0x30,
+            &concat(&[der(0x0a, &[0]), der(0xa0, &response_bytes)]),
+        );
+
+        assert_eq!(parse_ocsp_response(&staple), Ok(CertStatus::Good));
+    }
+}
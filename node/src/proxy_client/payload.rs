@@ -0,0 +1,134 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Heap-or-mapped response payloads. A multi-hundred-megabyte download
+//! used to sit in a heap `Vec<u8>` from receipt to relay; payloads past a
+//! threshold now land in an unlinked memory-mapped temporary file
+//! instead, so the pages are the kernel's problem and heap pressure stops
+//! tracking download size. `Payload` derefs to `&[u8]` either way, so
+//! code that reads response bytes is oblivious to the storage.
+
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::ops::Deref;
+
+/// Bodies this size and up go to a mapped file; smaller ones stay heap
+/// allocations, where the mmap/munmap round trip would cost more than it
+/// saves.
+pub const DEFAULT_MMAP_THRESHOLD: usize = 4 * 1024 * 1024;
+
+pub enum Payload {
+    Heap(Vec<u8>),
+    Mapped(memmap2::Mmap),
+}
+
+impl Payload {
+    /// Stores `data` heap- or file-backed according to the threshold. A
+    /// failure to create or map the temporary file falls back to the heap
+    /// rather than failing the download.
+    pub fn store(data: Vec<u8>, mmap_threshold: usize) -> Payload {
+        if data.len() >= mmap_threshold {
+            if let Ok(mapped) = Self::map_to_temp_file(&data) {
+                return Payload::Mapped(mapped);
+            }
+        }
+        Payload::Heap(data)
+    }
+
+    /// The file is deleted immediately after creation; the mapping keeps
+    /// the pages alive and nothing lands durably on disk.
+    fn map_to_temp_file(data: &[u8]) -> io::Result<memmap2::Mmap> {
+        let path = std::env::temp_dir().join(format!(
+            "clandestinode_payload_{}_{:x}",
+            std::process::id(),
+            data.as_ptr() as usize
+        ));
+        let mut file = File::create(&path)?;
+        let unlink_result = std::fs::remove_file(&path);
+        file.write_all(data)?;
+        unlink_result?;
+        unsafe { memmap2::Mmap::map(&file) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.deref().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deref().is_empty()
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, Payload::Mapped(_))
+    }
+}
+
+impl Deref for Payload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Payload::Heap(data) => data.as_slice(),
+            Payload::Mapped(mapped) => &mapped[..],
+        }
+    }
+}
+
+impl std::fmt::Debug for Payload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Payload::Heap(data) => write!(f, "Payload::Heap({} bytes)", data.len()),
+            Payload::Mapped(mapped) => write!(f, "Payload::Mapped({} bytes)", mapped.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 13 % 241) as u8).collect()
+    }
+
+    #[test]
+    fn small_bodies_stay_on_the_heap() {
+        let data = body(1000);
+
+        let subject = Payload::store(data.clone(), 1001);
+
+        assert!(!subject.is_mapped());
+        assert_eq!(&subject[..], data.as_slice());
+    }
+
+    #[test]
+    fn bodies_at_the_threshold_are_mapped() {
+        let data = body(1000);
+
+        let subject = Payload::store(data.clone(), 1000);
+
+        assert!(subject.is_mapped());
+        assert_eq!(&subject[..], data.as_slice());
+    }
+
+    #[test]
+    fn both_variants_read_identically() {
+        let data = body(64 * 1024);
+
+        let heap = Payload::store(data.clone(), usize::MAX);
+        let mapped = Payload::store(data, 1);
+
+        assert!(!heap.is_mapped());
+        assert!(mapped.is_mapped());
+        assert_eq!(&heap[..], &mapped[..]);
+        assert_eq!(heap.len(), mapped.len());
+    }
+
+    #[test]
+    fn an_empty_body_works_in_either_form() {
+        let heap = Payload::store(vec![], usize::MAX);
+
+        assert!(heap.is_empty());
+        assert_eq!(&heap[..], b"");
+    }
+}
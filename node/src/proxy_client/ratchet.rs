@@ -0,0 +1,398 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Forward secrecy for long-lived streams. A stream that keeps one
+//! session key for hours makes that key worth stealing: compromise it
+//! once and every recorded packet opens. Each stream therefore carries a
+//! ratchet that replaces its key after every `interval` packets, deriving
+//! the next key from the previous one via HKDF-SHA256 and zeroing the
+//! old one — a captured key decrypts at most one epoch's traffic, and
+//! nothing before it. The two ends step in lockstep: the side that
+//! advances first sends a RatchetSyncMessage, and material sealed under
+//! a retired epoch is rejected, never decrypted.
+
+use crate::sub_lib::stream_key::StreamKey;
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use zeroize::Zeroize;
+
+/// Packets sealed under one key before the ratchet advances.
+pub const DEFAULT_RATCHET_INTERVAL: u64 = 1024;
+
+/// The farthest a sync message may move the ratchet forward in one step.
+/// Each skipped epoch costs an HKDF derivation, and the epoch in a sync
+/// message is attacker-controlled: without a bound, `new_epoch:
+/// u64::MAX` pins the thread in key derivations indefinitely. Honest
+/// peers advance one epoch per interval, so a legitimate gap larger than
+/// this means the stream is unrecoverable anyway.
+pub const MAX_SYNC_SKIP: u64 = 256;
+
+pub const RATCHET_KEY_BYTES: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RatchetError {
+    /// Sealed under an epoch this ratchet has already retired; the key
+    /// that could open it no longer exists.
+    StaleEpoch { sealed: u64, current: u64 },
+    /// Sealed under an epoch this ratchet has not reached yet.
+    FutureEpoch { sealed: u64, current: u64 },
+    /// Epoch matched but the authentication check failed.
+    BadSeal,
+    /// A sync message tried to move the ratchet backward.
+    EpochRegression { requested: u64, current: u64 },
+    /// A sync message asked for a forward jump past MAX_SYNC_SKIP; honest
+    /// peers never skip that far, and deriving that many keys on demand
+    /// would be a remote DoS.
+    SyncTooFar { requested: u64, current: u64 },
+}
+
+/// Tells the remote end to advance its ratchet for the stream to the
+/// named epoch before sealing anything further.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RatchetSyncMessage {
+    pub stream_key: StreamKey,
+    pub new_epoch: u64,
+}
+
+/// A packet sealed under one ratchet epoch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RatchetSealed {
+    pub epoch: u64,
+    pub tag: [u8; RATCHET_KEY_BYTES],
+    pub payload: Vec<u8>,
+}
+
+pub struct RatchetState {
+    key: [u8; RATCHET_KEY_BYTES],
+    epoch: u64,
+    packets_in_epoch: u64,
+    interval: u64,
+}
+
+impl RatchetState {
+    pub fn new(initial_key: [u8; RATCHET_KEY_BYTES], interval: u64) -> RatchetState {
+        RatchetState {
+            key: initial_key,
+            epoch: 0,
+            packets_in_epoch: 0,
+            interval,
+        }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Seals a payload under the current key, counting it against the
+    /// epoch. When the count reaches the interval the ratchet advances
+    /// and the sync message for the remote end is returned alongside.
+    pub fn seal(
+        &mut self,
+        stream_key: StreamKey,
+        payload: &[u8],
+    ) -> (RatchetSealed, Option<RatchetSyncMessage>) {
+        let sealed = RatchetSealed {
+            epoch: self.epoch,
+            tag: self.tag_for(payload),
+            payload: payload.to_vec(),
+        };
+        self.packets_in_epoch += 1;
+        let sync = if self.packets_in_epoch >= self.interval {
+            self.advance();
+            Some(RatchetSyncMessage {
+                stream_key,
+                new_epoch: self.epoch,
+            })
+        } else {
+            None
+        };
+        (sealed, sync)
+    }
+
+    /// Verifies a sealed packet against the current epoch's key. Packets
+    /// from retired epochs are rejected outright — their keys are gone,
+    /// which is the point of the ratchet.
+    pub fn open(&self, sealed: &RatchetSealed) -> Result<Vec<u8>, RatchetError> {
+        if sealed.epoch < self.epoch {
+            return Err(RatchetError::StaleEpoch {
+                sealed: sealed.epoch,
+                current: self.epoch,
+            });
+        }
+        if sealed.epoch > self.epoch {
+            return Err(RatchetError::FutureEpoch {
+                sealed: sealed.epoch,
+                current: self.epoch,
+            });
+        }
+        let expected = self.tag_for(&sealed.payload);
+        if !bool::from(subtle::ConstantTimeEq::ct_eq(
+            &expected[..],
+            &sealed.tag[..],
+        )) {
+            return Err(RatchetError::BadSeal);
+        }
+        Ok(sealed.payload.clone())
+    }
+
+    /// Steps the key forward once: the next key is HKDF-derived from the
+    /// previous one, which is then zeroed.
+    pub fn advance(&mut self) {
+        let hkdf = Hkdf::<Sha256>::new(Some(&self.epoch.to_be_bytes()), &self.key);
+        let mut next = [0u8; RATCHET_KEY_BYTES];
+        hkdf.expand(b"clandestine-stream-ratchet", &mut next)
+            .expect("32 bytes is a valid HKDF output length");
+        self.key.zeroize();
+        self.key = next;
+        self.epoch += 1;
+        self.packets_in_epoch = 0;
+    }
+
+    /// Applies a remote sync: advances to the requested epoch, stepping
+    /// as many times as needed. Regressions are refused — a peer cannot
+    /// talk this end back onto a retired key — and so are jumps past
+    /// MAX_SYNC_SKIP, since the epoch is remote-controlled and each step
+    /// costs a key derivation.
+    pub fn sync_to(&mut self, new_epoch: u64) -> Result<(), RatchetError> {
+        if new_epoch < self.epoch {
+            return Err(RatchetError::EpochRegression {
+                requested: new_epoch,
+                current: self.epoch,
+            });
+        }
+        if new_epoch - self.epoch > MAX_SYNC_SKIP {
+            return Err(RatchetError::SyncTooFar {
+                requested: new_epoch,
+                current: self.epoch,
+            });
+        }
+        while self.epoch < new_epoch {
+            self.advance();
+        }
+        Ok(())
+    }
+
+    // Keyed tag binding the payload to the current epoch's key; the
+    // payload bytes themselves travel under the hop encryption, so the
+    // ratchet's job is proving which epoch sealed them.
+    fn tag_for(&self, payload: &[u8]) -> [u8; RATCHET_KEY_BYTES] {
+        let hkdf = Hkdf::<Sha256>::new(Some(&self.key), payload);
+        let mut tag = [0u8; RATCHET_KEY_BYTES];
+        hkdf.expand(b"clandestine-ratchet-tag", &mut tag)
+            .expect("32 bytes is a valid HKDF output length");
+        tag
+    }
+}
+
+impl Drop for RatchetState {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+/// The ProxyClient's per-stream ratchets.
+#[derive(Default)]
+pub struct StreamRatchets {
+    states: HashMap<StreamKey, RatchetState>,
+}
+
+impl StreamRatchets {
+    pub fn new() -> StreamRatchets {
+        Self::default()
+    }
+
+    pub fn start_stream(&mut self, stream_key: StreamKey, initial_key: [u8; RATCHET_KEY_BYTES]) {
+        self.states.insert(
+            stream_key,
+            RatchetState::new(initial_key, DEFAULT_RATCHET_INTERVAL),
+        );
+    }
+
+    pub fn get_mut(&mut self, stream_key: &StreamKey) -> Option<&mut RatchetState> {
+        self.states.get_mut(stream_key)
+    }
+
+    /// Handles the remote's sync for one stream. Unknown streams are
+    /// ignored; the stream may already be torn down on this side.
+    pub fn on_sync(&mut self, message: &RatchetSyncMessage) -> Result<(), RatchetError> {
+        match self.states.get_mut(&message.stream_key) {
+            Some(state) => state.sync_to(message.new_epoch),
+            None => Ok(()),
+        }
+    }
+
+    pub fn end_stream(&mut self, stream_key: &StreamKey) {
+        self.states.remove(stream_key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn initial_key() -> [u8; RATCHET_KEY_BYTES] {
+        [7u8; RATCHET_KEY_BYTES]
+    }
+
+    fn stream_key() -> StreamKey {
+        StreamKey::make_meaningless(1)
+    }
+
+    #[test]
+    fn sealed_packets_open_within_their_epoch() {
+        let mut sender = RatchetState::new(initial_key(), 16);
+        let receiver = RatchetState::new(initial_key(), 16);
+
+        let (sealed, sync) = sender.seal(stream_key(), b"payload bytes");
+
+        assert_eq!(receiver.open(&sealed), Ok(b"payload bytes".to_vec()));
+        assert_eq!(sync, None);
+    }
+
+    #[test]
+    fn old_key_material_is_rejected_after_a_ratchet_step() {
+        let mut sender = RatchetState::new(initial_key(), 16);
+        let mut receiver = RatchetState::new(initial_key(), 16);
+        let (sealed_before, _) = sender.seal(stream_key(), b"recorded earlier");
+
+        receiver.advance();
+
+        assert_eq!(
+            receiver.open(&sealed_before),
+            Err(RatchetError::StaleEpoch {
+                sealed: 0,
+                current: 1
+            })
+        );
+    }
+
+    #[test]
+    fn the_interval_th_packet_advances_the_ratchet_and_emits_a_sync() {
+        let mut sender = RatchetState::new(initial_key(), 3);
+
+        let syncs: Vec<Option<RatchetSyncMessage>> = (0..3)
+            .map(|_| sender.seal(stream_key(), b"data").1)
+            .collect();
+
+        assert_eq!(syncs[0], None);
+        assert_eq!(syncs[1], None);
+        assert_eq!(
+            syncs[2],
+            Some(RatchetSyncMessage {
+                stream_key: stream_key(),
+                new_epoch: 1
+            })
+        );
+        assert_eq!(sender.epoch(), 1);
+    }
+
+    #[test]
+    fn a_synced_receiver_opens_the_next_epochs_packets() {
+        let mut sender = RatchetState::new(initial_key(), 2);
+        let mut receiver = RatchetState::new(initial_key(), 2);
+        sender.seal(stream_key(), b"one");
+        let (_, sync) = sender.seal(stream_key(), b"two");
+
+        receiver.sync_to(sync.unwrap().new_epoch).unwrap();
+        let (sealed, _) = sender.seal(stream_key(), b"three");
+
+        assert_eq!(receiver.open(&sealed), Ok(b"three".to_vec()));
+    }
+
+    #[test]
+    fn a_tampered_seal_is_rejected() {
+        let mut sender = RatchetState::new(initial_key(), 16);
+        let receiver = RatchetState::new(initial_key(), 16);
+        let (mut sealed, _) = sender.seal(stream_key(), b"payload");
+        sealed.payload[0] ^= 0xFF;
+
+        assert_eq!(receiver.open(&sealed), Err(RatchetError::BadSeal));
+    }
+
+    #[test]
+    fn sync_cannot_move_the_ratchet_backward() {
+        let mut subject = RatchetState::new(initial_key(), 16);
+        subject.advance();
+        subject.advance();
+
+        assert_eq!(
+            subject.sync_to(1),
+            Err(RatchetError::EpochRegression {
+                requested: 1,
+                current: 2
+            })
+        );
+    }
+
+    #[test]
+    fn a_sync_past_the_skip_bound_is_refused_without_deriving_anything() {
+        let mut subject = RatchetState::new(initial_key(), 16);
+        let key_before = subject.key;
+
+        // A hostile peer's u64::MAX must return promptly, not spend
+        // 2^64 key derivations.
+        let hostile = subject.sync_to(u64::MAX);
+        assert_eq!(
+            hostile,
+            Err(RatchetError::SyncTooFar {
+                requested: u64::MAX,
+                current: 0
+            })
+        );
+        assert_eq!(subject.key, key_before); // refused before any derivation
+        assert_eq!(subject.epoch(), 0);
+
+        let at_bound = subject.sync_to(MAX_SYNC_SKIP);
+        let past_bound = subject.sync_to(MAX_SYNC_SKIP + MAX_SYNC_SKIP + 1);
+
+        assert_eq!(at_bound, Ok(()));
+        assert_eq!(subject.epoch(), MAX_SYNC_SKIP);
+        assert_eq!(
+            past_bound,
+            Err(RatchetError::SyncTooFar {
+                requested: MAX_SYNC_SKIP * 2 + 1,
+                current: MAX_SYNC_SKIP
+            })
+        );
+    }
+
+    #[test]
+    fn advancing_discards_the_old_key() {
+        let mut subject = RatchetState::new(initial_key(), 16);
+        let before = subject.key;
+
+        subject.advance();
+
+        assert_ne!(subject.key, before);
+        assert_ne!(subject.key, [0u8; RATCHET_KEY_BYTES]);
+    }
+
+    #[test]
+    fn stream_ratchets_track_streams_independently() {
+        let mut subject = StreamRatchets::new();
+        subject.start_stream(StreamKey::make_meaningless(1), initial_key());
+        subject.start_stream(StreamKey::make_meaningless(2), [9u8; RATCHET_KEY_BYTES]);
+
+        subject
+            .on_sync(&RatchetSyncMessage {
+                stream_key: StreamKey::make_meaningless(1),
+                new_epoch: 3,
+            })
+            .unwrap();
+
+        assert_eq!(
+            subject.get_mut(&StreamKey::make_meaningless(1)).unwrap().epoch(),
+            3
+        );
+        assert_eq!(
+            subject.get_mut(&StreamKey::make_meaningless(2)).unwrap().epoch(),
+            0
+        );
+        let unknown = subject.on_sync(&RatchetSyncMessage {
+            stream_key: StreamKey::make_meaningless(3),
+            new_epoch: 1,
+        });
+        assert_eq!(unknown, Ok(()));
+    }
+}
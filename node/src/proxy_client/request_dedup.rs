@@ -0,0 +1,138 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Collapses identical in-flight requests at the exit: when two streams ask
+//! for exactly the same thing at the same moment, one upstream connection is
+//! opened and the response is fanned out to every waiting stream.
+
+use crate::sub_lib::proxy_server::ClientRequestPayload;
+use crate::sub_lib::stream_key::StreamKey;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// Identity of a request for deduplication purposes: where it is going plus
+/// a hash of the exact bytes being sent.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RequestKey {
+    target_hostname: Option<String>,
+    target_port: u16,
+    data_hash: u64,
+}
+
+impl RequestKey {
+    pub fn from_payload(payload: &ClientRequestPayload) -> RequestKey {
+        let mut hasher = DefaultHasher::new();
+        payload.sequenced_packet.data.hash(&mut hasher);
+        RequestKey {
+            target_hostname: payload.target_hostname.clone(),
+            target_port: payload.target_port,
+            data_hash: hasher.finish(),
+        }
+    }
+}
+
+/// What the caller should do with a just-arrived request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DedupDecision {
+    /// No identical request is in flight: open the upstream connection.
+    OpenUpstream,
+    /// An identical request is already in flight: wait for its response.
+    JoinExisting,
+}
+
+#[derive(Default)]
+pub struct InFlightRequests {
+    pending: HashMap<RequestKey, Vec<StreamKey>>,
+}
+
+impl InFlightRequests {
+    pub fn new() -> InFlightRequests {
+        Self::default()
+    }
+
+    pub fn register(&mut self, payload: &ClientRequestPayload) -> DedupDecision {
+        let key = RequestKey::from_payload(payload);
+        match self.pending.get_mut(&key) {
+            Some(waiters) => {
+                waiters.push(payload.stream_key);
+                DedupDecision::JoinExisting
+            }
+            None => {
+                self.pending.insert(key, vec![payload.stream_key]);
+                DedupDecision::OpenUpstream
+            }
+        }
+    }
+
+    /// The upstream answered (or failed): every waiting stream gets the
+    /// result. The entry is removed, so a later identical request opens a
+    /// fresh connection.
+    pub fn complete(&mut self, key: &RequestKey) -> Vec<StreamKey> {
+        self.pending.remove(key).unwrap_or_default()
+    }
+
+    pub fn in_flight_count(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sub_lib::cryptde::PublicKey;
+    use crate::sub_lib::proxy_server::ProxyProtocol;
+    use crate::sub_lib::sequence_buffer::SequencedPacket;
+
+    fn payload(stream_key: StreamKey, hostname: &str, data: &[u8]) -> ClientRequestPayload {
+        ClientRequestPayload {
+            stream_key,
+            sequenced_packet: SequencedPacket::new(data.to_vec(), 0, false),
+            target_hostname: Some(hostname.to_string()),
+            target_port: 80,
+            protocol: ProxyProtocol::HTTP,
+            originator_public_key: PublicKey::new(b"originator"),
+        }
+    }
+
+    #[test]
+    fn identical_simultaneous_requests_share_one_upstream() {
+        let mut subject = InFlightRequests::new();
+        let first = payload(StreamKey::make_meaningless(1), "example.com", b"GET / ...");
+        let second = payload(StreamKey::make_meaningless(2), "example.com", b"GET / ...");
+
+        assert_eq!(subject.register(&first), DedupDecision::OpenUpstream);
+        assert_eq!(subject.register(&second), DedupDecision::JoinExisting);
+        assert_eq!(subject.in_flight_count(), 1);
+
+        let waiters = subject.complete(&RequestKey::from_payload(&first));
+        assert_eq!(
+            waiters,
+            vec![StreamKey::make_meaningless(1), StreamKey::make_meaningless(2)]
+        );
+    }
+
+    #[test]
+    fn different_requests_each_open_upstream() {
+        let mut subject = InFlightRequests::new();
+        let first = payload(StreamKey::make_meaningless(1), "example.com", b"GET /a ...");
+        let second = payload(StreamKey::make_meaningless(2), "example.com", b"GET /b ...");
+        let third = payload(StreamKey::make_meaningless(3), "other.com", b"GET /a ...");
+
+        assert_eq!(subject.register(&first), DedupDecision::OpenUpstream);
+        assert_eq!(subject.register(&second), DedupDecision::OpenUpstream);
+        assert_eq!(subject.register(&third), DedupDecision::OpenUpstream);
+        assert_eq!(subject.in_flight_count(), 3);
+    }
+
+    #[test]
+    fn completion_clears_the_entry_for_future_requests() {
+        let mut subject = InFlightRequests::new();
+        let first = payload(StreamKey::make_meaningless(1), "example.com", b"GET / ...");
+        subject.register(&first);
+        subject.complete(&RequestKey::from_payload(&first));
+
+        let repeat = payload(StreamKey::make_meaningless(4), "example.com", b"GET / ...");
+
+        assert_eq!(subject.register(&repeat), DedupDecision::OpenUpstream);
+    }
+}
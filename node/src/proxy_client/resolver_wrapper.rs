@@ -0,0 +1,231 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! DNS resolution for the exit side. The wrapper trait exists so the
+//! StreamHandlerPool can be tested without real lookups; it now exposes
+//! record TTLs so the pre-resolution cache and connection reuse can make
+//! correct freshness decisions instead of guessing. `lookup_ip` remains as
+//! the compatibility surface for callers that do not care about TTLs.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResolveError {
+    NoRecords(String),
+    ResolverFailure(String),
+}
+
+pub trait ResolverWrapper: Send {
+    /// Resolves a hostname to addresses with the TTL of each record.
+    fn lookup_ip_with_ttl(&self, hostname: &str) -> Result<Vec<(IpAddr, Duration)>, ResolveError>;
+
+    /// Compatibility method for callers that only need addresses.
+    fn lookup_ip(&self, hostname: &str) -> Result<Vec<IpAddr>, ResolveError> {
+        Ok(self
+            .lookup_ip_with_ttl(hostname)?
+            .into_iter()
+            .map(|(ip, _)| ip)
+            .collect())
+    }
+}
+
+pub struct ResolverWrapperReal {
+    resolver: trust_dns_resolver::Resolver,
+}
+
+impl ResolverWrapperReal {
+    pub fn new(resolver: trust_dns_resolver::Resolver) -> ResolverWrapperReal {
+        ResolverWrapperReal { resolver }
+    }
+}
+
+impl ResolverWrapper for ResolverWrapperReal {
+    fn lookup_ip_with_ttl(&self, hostname: &str) -> Result<Vec<(IpAddr, Duration)>, ResolveError> {
+        let lookup = self
+            .resolver
+            .lookup_ip(hostname)
+            .map_err(|e| ResolveError::ResolverFailure(format!("{}", e)))?;
+        let pairs: Vec<(IpAddr, Duration)> = lookup
+            .as_lookup()
+            .record_iter()
+            .filter_map(|record| {
+                record
+                    .data()
+                    .and_then(|data| data.to_ip_addr())
+                    .map(|ip| (ip, Duration::from_secs(u64::from(record.ttl()))))
+            })
+            .collect();
+        if pairs.is_empty() {
+            Err(ResolveError::NoRecords(hostname.to_string()))
+        } else {
+            Ok(pairs)
+        }
+    }
+}
+
+struct CacheEntry {
+    addresses: Vec<IpAddr>,
+    expires_at: Instant,
+}
+
+/// Positive pre-resolution cache keyed by hostname. An entry lives for the
+/// minimum TTL among its records; zero-TTL answers are never cached, per
+/// the record owner's explicit request.
+#[derive(Default)]
+pub struct PositiveDnsCache {
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl PositiveDnsCache {
+    pub fn new() -> PositiveDnsCache {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, hostname: &str, pairs: &[(IpAddr, Duration)], now: Instant) {
+        let min_ttl = match pairs.iter().map(|(_, ttl)| *ttl).min() {
+            Some(ttl) if !ttl.is_zero() => ttl,
+            _ => return,
+        };
+        self.entries.insert(
+            hostname.to_string(),
+            CacheEntry {
+                addresses: pairs.iter().map(|(ip, _)| *ip).collect(),
+                expires_at: now + min_ttl,
+            },
+        );
+    }
+
+    /// Returns the cached addresses if the entry is still within its TTL,
+    /// dropping it otherwise.
+    pub fn fresh(&mut self, hostname: &str, now: Instant) -> Option<Vec<IpAddr>> {
+        match self.entries.get(hostname) {
+            Some(entry) if now < entry.expires_at => Some(entry.addresses.clone()),
+            Some(_) => {
+                self.entries.remove(hostname);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Whether an existing pooled connection to `hostname` may be reused:
+    /// only while the resolution that produced it is still fresh. After the
+    /// TTL lapses the target may have moved, and reuse would pin traffic to
+    /// a stale address.
+    pub fn may_reuse_connection(&mut self, hostname: &str, now: Instant) -> bool {
+        self.fresh(hostname, now).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    struct ResolverWrapperMock {
+        lookup_results: std::sync::Mutex<Vec<Result<Vec<(IpAddr, Duration)>, ResolveError>>>,
+    }
+
+    impl ResolverWrapperMock {
+        fn new() -> ResolverWrapperMock {
+            ResolverWrapperMock {
+                lookup_results: std::sync::Mutex::new(vec![]),
+            }
+        }
+
+        fn lookup_ip_with_ttl_result(
+            self,
+            result: Result<Vec<(IpAddr, Duration)>, ResolveError>,
+        ) -> ResolverWrapperMock {
+            self.lookup_results.lock().unwrap().push(result);
+            self
+        }
+    }
+
+    impl ResolverWrapper for ResolverWrapperMock {
+        fn lookup_ip_with_ttl(
+            &self,
+            _hostname: &str,
+        ) -> Result<Vec<(IpAddr, Duration)>, ResolveError> {
+            self.lookup_results.lock().unwrap().remove(0)
+        }
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn compatibility_lookup_drops_ttls_but_keeps_addresses() {
+        let subject = ResolverWrapperMock::new().lookup_ip_with_ttl_result(Ok(vec![
+            (ip("1.2.3.4"), Duration::from_secs(300)),
+            (ip("5.6.7.8"), Duration::from_secs(60)),
+        ]));
+
+        let result = subject.lookup_ip("example.com").unwrap();
+
+        assert_eq!(result, vec![ip("1.2.3.4"), ip("5.6.7.8")]);
+    }
+
+    #[test]
+    fn compatibility_lookup_passes_errors_through() {
+        let subject = ResolverWrapperMock::new()
+            .lookup_ip_with_ttl_result(Err(ResolveError::NoRecords("example.com".to_string())));
+
+        let result = subject.lookup_ip("example.com");
+
+        assert_eq!(
+            result,
+            Err(ResolveError::NoRecords("example.com".to_string()))
+        );
+    }
+
+    #[test]
+    fn cache_serves_entries_until_the_minimum_ttl_lapses() {
+        let mut subject = PositiveDnsCache::new();
+        let now = Instant::now();
+        subject.insert(
+            "example.com",
+            &[
+                (ip("1.2.3.4"), Duration::from_secs(300)),
+                (ip("5.6.7.8"), Duration::from_secs(60)),
+            ],
+            now,
+        );
+
+        let within = subject.fresh("example.com", now + Duration::from_secs(59));
+        let past = subject.fresh("example.com", now + Duration::from_secs(60));
+
+        assert_eq!(within, Some(vec![ip("1.2.3.4"), ip("5.6.7.8")]));
+        assert_eq!(past, None);
+    }
+
+    #[test]
+    fn zero_ttl_answers_are_never_cached() {
+        let mut subject = PositiveDnsCache::new();
+        let now = Instant::now();
+
+        subject.insert(
+            "example.com",
+            &[
+                (ip("1.2.3.4"), Duration::from_secs(300)),
+                (ip("5.6.7.8"), Duration::ZERO),
+            ],
+            now,
+        );
+
+        assert_eq!(subject.fresh("example.com", now), None);
+    }
+
+    #[test]
+    fn connection_reuse_stops_when_the_resolution_goes_stale() {
+        let mut subject = PositiveDnsCache::new();
+        let now = Instant::now();
+        subject.insert("example.com", &[(ip("1.2.3.4"), Duration::from_secs(30))], now);
+
+        assert!(subject.may_reuse_connection("example.com", now + Duration::from_secs(29)));
+        assert!(!subject.may_reuse_connection("example.com", now + Duration::from_secs(31)));
+        assert!(!subject.may_reuse_connection("never-resolved.com", now));
+    }
+}
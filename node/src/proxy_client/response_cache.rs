@@ -0,0 +1,221 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+struct CacheEntry {
+    data: Vec<u8>,
+    expires_at: Instant,
+}
+
+/// Exit-side cache for publicly cacheable HTTP responses, so repeated
+/// requests for the same static content are answered without opening a new
+/// connection to the origin.
+pub struct ResponseCache {
+    entries: HashMap<String, CacheEntry>,
+    max_size_bytes: usize,
+    current_size_bytes: usize,
+}
+
+impl ResponseCache {
+    pub fn new(max_size_bytes: usize) -> ResponseCache {
+        ResponseCache {
+            entries: HashMap::new(),
+            max_size_bytes,
+            current_size_bytes: 0,
+        }
+    }
+
+    /// Returns the max-age when the response advertises itself as publicly
+    /// cacheable (`Cache-Control: public, max-age=N`), None otherwise.
+    pub fn cacheable_for(response: &[u8]) -> Option<Duration> {
+        let headers_end = find_subslice(response, b"\r\n\r\n")?;
+        let header_block = String::from_utf8_lossy(&response[..headers_end]);
+        let cache_control = header_block
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                if name.eq_ignore_ascii_case("cache-control") {
+                    Some(value.trim().to_ascii_lowercase())
+                } else {
+                    None
+                }
+            })?;
+        if !cache_control.split(',').any(|d| d.trim() == "public") {
+            return None;
+        }
+        cache_control.split(',').find_map(|directive| {
+            let directive = directive.trim();
+            let seconds = directive.strip_prefix("max-age=")?.parse::<u64>().ok()?;
+            Some(Duration::from_secs(seconds))
+        })
+    }
+
+    /// Stores a response if it fits; oversized responses and responses that
+    /// would push the cache past its byte budget are skipped after expired
+    /// entries have been evicted.
+    pub fn store(&mut self, url: String, response: &[u8], max_age: Duration, now: Instant) {
+        if response.len() > self.max_size_bytes {
+            return;
+        }
+        self.evict_expired(now);
+        if self.current_size_bytes + response.len() > self.max_size_bytes {
+            return;
+        }
+        if let Some(old) = self.entries.insert(
+            url,
+            CacheEntry {
+                data: response.to_vec(),
+                expires_at: now + max_age,
+            },
+        ) {
+            self.current_size_bytes -= old.data.len();
+        }
+        self.current_size_bytes += response.len();
+    }
+
+    /// Returns the cached response for the URL when it is still fresh.
+    pub fn get(&mut self, url: &str, now: Instant) -> Option<&[u8]> {
+        if let Some(entry) = self.entries.get(url) {
+            if entry.expires_at <= now {
+                let removed = self.entries.remove(url).expect("entry vanished");
+                self.current_size_bytes -= removed.data.len();
+                return None;
+            }
+        }
+        self.entries.get(url).map(|entry| entry.data.as_slice())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn evict_expired(&mut self, now: Instant) {
+        let mut freed = 0;
+        self.entries.retain(|_, entry| {
+            if entry.expires_at <= now {
+                freed += entry.data.len();
+                false
+            } else {
+                true
+            }
+        });
+        self.current_size_bytes -= freed;
+    }
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CACHEABLE: &[u8] = b"HTTP/1.1 200 OK\r\n\
+Cache-Control: public, max-age=300\r\n\
+Content-Length: 5\r\n\
+\r\n\
+hello";
+
+    #[test]
+    fn public_max_age_responses_are_cacheable() {
+        assert_eq!(
+            ResponseCache::cacheable_for(CACHEABLE),
+            Some(Duration::from_secs(300))
+        );
+    }
+
+    #[test]
+    fn private_and_unmarked_responses_are_not_cacheable() {
+        let private: &[u8] =
+            b"HTTP/1.1 200 OK\r\nCache-Control: private, max-age=300\r\n\r\nhello";
+        let unmarked: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+
+        assert_eq!(ResponseCache::cacheable_for(private), None);
+        assert_eq!(ResponseCache::cacheable_for(unmarked), None);
+    }
+
+    #[test]
+    fn fresh_entry_is_served_from_cache() {
+        let mut subject = ResponseCache::new(1024);
+        let now = Instant::now();
+        subject.store(
+            "http://example.com/a".to_string(),
+            CACHEABLE,
+            Duration::from_secs(300),
+            now,
+        );
+
+        let hit = subject.get("http://example.com/a", now + Duration::from_secs(299));
+
+        assert_eq!(hit, Some(CACHEABLE));
+    }
+
+    #[test]
+    fn expired_entry_forces_a_refetch() {
+        let mut subject = ResponseCache::new(1024);
+        let now = Instant::now();
+        subject.store(
+            "http://example.com/a".to_string(),
+            CACHEABLE,
+            Duration::from_secs(300),
+            now,
+        );
+
+        let miss = subject.get("http://example.com/a", now + Duration::from_secs(300));
+
+        assert_eq!(miss, None);
+        assert!(subject.is_empty());
+    }
+
+    #[test]
+    fn cache_respects_its_byte_budget() {
+        let mut subject = ResponseCache::new(10);
+        let now = Instant::now();
+        subject.store(
+            "http://example.com/a".to_string(),
+            b"123456",
+            Duration::from_secs(300),
+            now,
+        );
+        subject.store(
+            "http://example.com/b".to_string(),
+            b"789012",
+            Duration::from_secs(300),
+            now,
+        );
+
+        assert_eq!(subject.len(), 1);
+        assert!(subject.get("http://example.com/b", now).is_none());
+    }
+
+    #[test]
+    fn expired_entries_are_evicted_to_make_room() {
+        let mut subject = ResponseCache::new(10);
+        let now = Instant::now();
+        subject.store(
+            "http://example.com/a".to_string(),
+            b"123456",
+            Duration::from_secs(1),
+            now,
+        );
+
+        subject.store(
+            "http://example.com/b".to_string(),
+            b"789012",
+            Duration::from_secs(300),
+            now + Duration::from_secs(2),
+        );
+
+        assert!(subject
+            .get("http://example.com/b", now + Duration::from_secs(2))
+            .is_some());
+    }
+}
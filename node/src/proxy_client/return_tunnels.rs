@@ -0,0 +1,140 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! I2P-style unidirectional tunnels: in paired mode the route a response
+//! takes home is not the reverse of the route the request arrived on, but a
+//! separately built return tunnel assigned per stream. One compromised path
+//! then sees only half the conversation.
+
+use crate::sub_lib::proxy_client::TunnelMode;
+use crate::sub_lib::route::Route;
+use crate::sub_lib::stream_key::StreamKey;
+use std::collections::HashMap;
+
+/// Message assigning a return tunnel to a stream; sent by the originator
+/// ahead of (or alongside) the first request on that stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssignReturnTunnel {
+    pub stream_key: StreamKey,
+    pub route: Route,
+}
+
+#[derive(Default)]
+pub struct ReturnTunnels {
+    contexts: HashMap<StreamKey, Route>,
+}
+
+impl ReturnTunnels {
+    pub fn new() -> ReturnTunnels {
+        Self::default()
+    }
+
+    pub fn assign(&mut self, message: AssignReturnTunnel) {
+        self.contexts.insert(message.stream_key, message.route);
+    }
+
+    /// Picks the route for send_response_to_hopper. In bidirectional mode
+    /// that is always the request's own return route; in paired mode the
+    /// assigned tunnel wins, with the request route as fallback for streams
+    /// whose assignment never arrived.
+    pub fn response_route<'a>(
+        &'a self,
+        mode: TunnelMode,
+        stream_key: &StreamKey,
+        request_return_route: &'a Route,
+    ) -> &'a Route {
+        match mode {
+            TunnelMode::Bidirectional => request_return_route,
+            TunnelMode::UnidirectionalPaired => self
+                .contexts
+                .get(stream_key)
+                .unwrap_or(request_return_route),
+        }
+    }
+
+    pub fn remove(&mut self, stream_key: &StreamKey) {
+        self.contexts.remove(stream_key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.contexts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contexts.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sub_lib::cryptde::CryptData;
+
+    fn route(tag: u8) -> Route {
+        Route::new(vec![CryptData::new(&[tag])])
+    }
+
+    #[test]
+    fn bidirectional_mode_reuses_the_request_route() {
+        let mut subject = ReturnTunnels::new();
+        let stream_key = StreamKey::make_meaningless(1);
+        subject.assign(AssignReturnTunnel {
+            stream_key,
+            route: route(9),
+        });
+        let request_route = route(1);
+
+        let chosen =
+            subject.response_route(TunnelMode::Bidirectional, &stream_key, &request_route);
+
+        assert_eq!(chosen, &request_route);
+    }
+
+    #[test]
+    fn paired_mode_uses_the_assigned_return_tunnel() {
+        let mut subject = ReturnTunnels::new();
+        let stream_key = StreamKey::make_meaningless(1);
+        let return_tunnel = route(9);
+        subject.assign(AssignReturnTunnel {
+            stream_key,
+            route: return_tunnel.clone(),
+        });
+        let request_route = route(1);
+
+        let chosen = subject.response_route(
+            TunnelMode::UnidirectionalPaired,
+            &stream_key,
+            &request_route,
+        );
+
+        assert_eq!(chosen, &return_tunnel);
+        assert_ne!(chosen, &request_route);
+    }
+
+    #[test]
+    fn paired_mode_falls_back_when_no_tunnel_was_assigned() {
+        let subject = ReturnTunnels::new();
+        let request_route = route(1);
+
+        let chosen = subject.response_route(
+            TunnelMode::UnidirectionalPaired,
+            &StreamKey::make_meaningless(1),
+            &request_route,
+        );
+
+        assert_eq!(chosen, &request_route);
+    }
+
+    #[test]
+    fn removed_streams_release_their_tunnels() {
+        let mut subject = ReturnTunnels::new();
+        let stream_key = StreamKey::make_meaningless(1);
+        subject.assign(AssignReturnTunnel {
+            stream_key,
+            route: route(9),
+        });
+
+        subject.remove(&stream_key);
+
+        assert!(subject.is_empty());
+    }
+}
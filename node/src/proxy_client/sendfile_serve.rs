@@ -0,0 +1,150 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Serving file-backed cache entries without userspace copies. Large
+//! cached responses spill from the in-memory ResponseCache to disk; when
+//! one of those is hit, sendfile(2) hands the pages straight from the
+//! page cache to the socket on Linux. Elsewhere the serve is an io::copy,
+//! byte-for-byte identical from the client's point of view.
+
+use std::fs::File;
+use std::io;
+use std::net::TcpStream;
+
+/// Sends the whole of `file` (from its current position) into `socket`;
+/// returns bytes sent.
+pub fn serve_file(file: &mut File, socket: &mut TcpStream) -> io::Result<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        sendfile_all(file, socket)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        io::copy(file, socket)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn sendfile_all(file: &mut File, socket: &mut TcpStream) -> io::Result<u64> {
+    use nix::sys::sendfile::sendfile;
+    use std::io::Seek;
+    use std::os::unix::io::AsRawFd;
+
+    let start = file.stream_position()?;
+    let remaining = file.metadata()?.len() - start;
+    let mut offset = start as libc::off_t;
+    let mut total = 0u64;
+    while total < remaining {
+        let sent = sendfile(
+            socket.as_raw_fd(),
+            file.as_raw_fd(),
+            Some(&mut offset),
+            (remaining - total) as usize,
+        )
+        .map_err(io::Error::from)?;
+        if sent == 0 {
+            break; // socket closed under us; caller sees the short count
+        }
+        total += sent as u64;
+    }
+    // sendfile moved the kernel offset via `offset`, not the File's own
+    // cursor; keep them consistent for any caller that reads on.
+    file.seek(io::SeekFrom::Start(offset as u64))?;
+    Ok(total)
+}
+
+/// The portable serve, and the baseline the benchmark and the equivalence
+/// test compare against.
+pub fn serve_file_userspace(file: &mut File, socket: &mut TcpStream) -> io::Result<u64> {
+    io::copy(file, socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn cached_file(name: &str, contents: &[u8]) -> File {
+        let dir = std::env::temp_dir().join("clandestinode_sendfile_tests");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        File::open(&path).unwrap()
+    }
+
+    fn serve_and_collect(
+        mut file: File,
+        serve: fn(&mut File, &mut TcpStream) -> io::Result<u64>,
+    ) -> (u64, Vec<u8>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let receiver = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut received = vec![];
+            socket.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        let mut socket = TcpStream::connect(addr).unwrap();
+        let sent = serve(&mut file, &mut socket).unwrap();
+        drop(socket);
+
+        (sent, receiver.join().unwrap())
+    }
+
+    fn response_payload(len: usize) -> Vec<u8> {
+        let body: Vec<u8> = (0..len).map(|i| (i * 17 % 253) as u8).collect();
+        let mut response =
+            format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", len).into_bytes();
+        response.extend(body);
+        response
+    }
+
+    #[test]
+    fn the_platform_serve_delivers_the_file_byte_for_byte() {
+        let payload = response_payload(1024 * 1024);
+        let file = cached_file("platform.bin", &payload);
+
+        let (sent, received) = serve_and_collect(file, serve_file);
+
+        assert_eq!(sent, payload.len() as u64);
+        assert_eq!(received, payload);
+    }
+
+    #[test]
+    fn both_paths_produce_identical_output() {
+        let payload = response_payload(300 * 1024 + 41);
+
+        let (_, via_platform) =
+            serve_and_collect(cached_file("equiv_platform.bin", &payload), serve_file);
+        let (_, via_userspace) = serve_and_collect(
+            cached_file("equiv_userspace.bin", &payload),
+            serve_file_userspace,
+        );
+
+        assert_eq!(via_platform, via_userspace);
+    }
+
+    #[test]
+    fn serving_starts_from_the_files_current_position() {
+        let payload = response_payload(10_000);
+        let mut file = cached_file("offset.bin", &payload);
+        file.seek(SeekFrom::Start(4_000)).unwrap();
+
+        let (sent, received) = serve_and_collect(file, serve_file);
+
+        assert_eq!(sent, (payload.len() - 4_000) as u64);
+        assert_eq!(received, payload[4_000..]);
+    }
+
+    #[test]
+    fn an_empty_file_serves_zero_bytes() {
+        let file = cached_file("empty.bin", &[]);
+
+        let (sent, received) = serve_and_collect(file, serve_file);
+
+        assert_eq!(sent, 0);
+        assert!(received.is_empty());
+    }
+}
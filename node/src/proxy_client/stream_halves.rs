@@ -0,0 +1,221 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Read/write separation for exit-side server connections. A server that
+//! stops draining our writes used to stall the task that also serviced
+//! reads from the same socket, delaying last_data detection and teardown.
+//! Each connection now runs an independent reader and writer joined by a
+//! bounded channel: write backpressure fills the channel and eventually
+//! blocks the hopper-facing sender, while reads — including noticing the
+//! server's FIN — continue unimpeded. The bounded channel is also the
+//! natural attachment point for throttling.
+
+use std::io;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Depth of the per-connection outbound write queue.
+pub const WRITE_QUEUE_CAPACITY: usize = 16;
+
+/// Mockable face of one exit-side server socket. `read_data` returning an
+/// empty buffer means the server sent FIN.
+pub trait ExitSocket: Send + Sync {
+    fn read_data(&self) -> io::Result<Vec<u8>>;
+    fn write_data(&self, data: &[u8]) -> io::Result<()>;
+}
+
+/// Fires its callback exactly once, after both halves have finished, in
+/// whichever order they finish. The mutex makes the "am I the last one"
+/// check and the callback take atomic with respect to the other half.
+struct TeardownLatch {
+    state: Mutex<(usize, Option<Box<dyn FnOnce() + Send>>)>,
+}
+
+impl TeardownLatch {
+    fn new(halves: usize, on_teardown: Box<dyn FnOnce() + Send>) -> TeardownLatch {
+        TeardownLatch {
+            state: Mutex::new((halves, Some(on_teardown))),
+        }
+    }
+
+    fn half_done(&self) {
+        let callback = {
+            let mut state = self.state.lock().expect("teardown latch poisoned");
+            state.0 -= 1;
+            if state.0 == 0 {
+                state.1.take()
+            } else {
+                None
+            }
+        };
+        if let Some(callback) = callback {
+            callback();
+        }
+    }
+}
+
+/// Spawns the two halves for one server connection. Outbound data goes
+/// into the returned bounded sender; inbound data (and the implicit FIN
+/// when the reader finishes) arrives on `inbound_tx`. `on_teardown` runs
+/// once, after both halves are done.
+pub fn spawn_split(
+    socket: Arc<dyn ExitSocket>,
+    inbound_tx: mpsc::Sender<Vec<u8>>,
+    on_teardown: Box<dyn FnOnce() + Send>,
+) -> SyncSender<Vec<u8>> {
+    let (write_tx, write_rx): (SyncSender<Vec<u8>>, Receiver<Vec<u8>>) =
+        mpsc::sync_channel(WRITE_QUEUE_CAPACITY);
+    let latch = Arc::new(TeardownLatch::new(2, on_teardown));
+
+    let reader_socket = socket.clone();
+    let reader_latch = latch.clone();
+    thread::spawn(move || {
+        loop {
+            match reader_socket.read_data() {
+                Ok(data) if data.is_empty() => break, // server FIN
+                Ok(data) => {
+                    if inbound_tx.send(data).is_err() {
+                        break; // nobody is listening anymore
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        reader_latch.half_done();
+    });
+
+    let writer_latch = latch;
+    thread::spawn(move || {
+        // Ends when the sender is dropped (stream torn down upstream) or
+        // the socket refuses a write (server gone).
+        for data in write_rx {
+            if socket.write_data(&data).is_err() {
+                break;
+            }
+        }
+        writer_latch.half_done();
+    });
+
+    write_tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    struct ExitSocketMock {
+        reads: Mutex<Vec<io::Result<Vec<u8>>>>,
+        write_attempts: AtomicUsize,
+        accept_writes: bool,
+    }
+
+    impl ExitSocket for ExitSocketMock {
+        fn read_data(&self) -> io::Result<Vec<u8>> {
+            let mut reads = self.reads.lock().unwrap();
+            if reads.is_empty() {
+                Ok(vec![]) // keep signalling FIN after the script runs out
+            } else {
+                reads.remove(0)
+            }
+        }
+
+        fn write_data(&self, _data: &[u8]) -> io::Result<()> {
+            self.write_attempts.fetch_add(1, Ordering::SeqCst);
+            if self.accept_writes {
+                Ok(())
+            } else {
+                Err(io::Error::new(io::ErrorKind::WouldBlock, "server not reading"))
+            }
+        }
+    }
+
+    fn wait_for(flag: &AtomicBool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !flag.load(Ordering::SeqCst) {
+            assert!(Instant::now() < deadline, "timed out waiting for teardown");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn reads_flow_and_teardown_completes_when_the_server_accepts_no_writes() {
+        let socket = Arc::new(ExitSocketMock {
+            reads: Mutex::new(vec![Ok(b"chunk one".to_vec()), Ok(b"chunk two".to_vec())]),
+            write_attempts: AtomicUsize::new(0),
+            accept_writes: false,
+        });
+        let (inbound_tx, inbound_rx) = mpsc::channel();
+        let torn_down = Arc::new(AtomicBool::new(false));
+        let teardown_flag = torn_down.clone();
+
+        let write_tx = spawn_split(
+            socket.clone(),
+            inbound_tx,
+            Box::new(move || teardown_flag.store(true, Ordering::SeqCst)),
+        );
+        write_tx.send(b"request bytes".to_vec()).unwrap();
+
+        // Reads arrive despite the stuck writer...
+        assert_eq!(inbound_rx.recv().unwrap(), b"chunk one".to_vec());
+        assert_eq!(inbound_rx.recv().unwrap(), b"chunk two".to_vec());
+        // ...and once the sender is dropped, both halves finish.
+        drop(write_tx);
+        wait_for(&torn_down);
+        assert_eq!(socket.write_attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn teardown_fires_once_whichever_half_finishes_last() {
+        let socket = Arc::new(ExitSocketMock {
+            reads: Mutex::new(vec![]),
+            write_attempts: AtomicUsize::new(0),
+            accept_writes: true,
+        });
+        let (inbound_tx, _inbound_rx) = mpsc::channel();
+        let teardown_count = Arc::new(AtomicUsize::new(0));
+        let counter = teardown_count.clone();
+        let torn_down = Arc::new(AtomicBool::new(false));
+        let teardown_flag = torn_down.clone();
+
+        let write_tx = spawn_split(
+            socket,
+            inbound_tx,
+            Box::new(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                teardown_flag.store(true, Ordering::SeqCst);
+            }),
+        );
+        write_tx.send(b"a write that succeeds".to_vec()).unwrap();
+        drop(write_tx);
+
+        wait_for(&torn_down);
+        assert_eq!(teardown_count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn read_errors_end_the_reader_without_hanging_the_connection() {
+        let socket = Arc::new(ExitSocketMock {
+            reads: Mutex::new(vec![Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "reset",
+            ))]),
+            write_attempts: AtomicUsize::new(0),
+            accept_writes: true,
+        });
+        let (inbound_tx, inbound_rx) = mpsc::channel();
+        let torn_down = Arc::new(AtomicBool::new(false));
+        let teardown_flag = torn_down.clone();
+
+        let write_tx = spawn_split(
+            socket,
+            inbound_tx,
+            Box::new(move || teardown_flag.store(true, Ordering::SeqCst)),
+        );
+        drop(write_tx);
+
+        wait_for(&torn_down);
+        assert!(inbound_rx.recv().is_err()); // channel closed, no data
+    }
+}
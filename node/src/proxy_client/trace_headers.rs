@@ -0,0 +1,178 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! B3 (Zipkin/Jaeger) trace propagation at the exit. When a client request
+//! arrives carrying `X-B3-TraceId`/`X-B3-SpanId` headers, the exit adopts
+//! that context for its own spans instead of minting a fresh trace, and
+//! injects the same headers into the response so the originator can stitch
+//! the round trip together. Requests without the headers — or streams that
+//! are not HTTP at all — are untouched.
+
+use crate::telemetry::TraceContext;
+
+pub const TRACE_ID_HEADER: &str = "X-B3-TraceId";
+pub const SPAN_ID_HEADER: &str = "X-B3-SpanId";
+
+pub struct TraceHeaderExtractor;
+
+impl TraceHeaderExtractor {
+    /// Pulls a trace context out of an HTTP header block. Both headers
+    /// must be present and valid hex (B3 allows 16- or 32-character trace
+    /// ids); anything less yields None rather than a half-formed context.
+    pub fn extract(data: &[u8]) -> Option<TraceContext> {
+        let headers_end = find_subslice(data, b"\r\n\r\n")?;
+        let mut trace_id = None;
+        let mut span_id = None;
+        for line in data[..headers_end].split(|b| *b == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let mut parts = line.splitn(2, |b| *b == b':');
+            let name = String::from_utf8_lossy(parts.next().unwrap_or(b""))
+                .trim()
+                .to_ascii_lowercase();
+            let value = String::from_utf8_lossy(parts.next().unwrap_or(b""))
+                .trim()
+                .to_string();
+            if name == TRACE_ID_HEADER.to_ascii_lowercase() {
+                trace_id = parse_trace_id(&value);
+            } else if name == SPAN_ID_HEADER.to_ascii_lowercase() {
+                span_id = u64::from_str_radix(&value, 16).ok().filter(|_| value.len() == 16);
+            }
+        }
+        Some(TraceContext {
+            trace_id: trace_id?,
+            span_id: span_id?,
+        })
+    }
+
+    /// Injects the context's headers into an HTTP response header block,
+    /// right after the status line. Non-HTTP data comes back unchanged:
+    /// there is nowhere to put a header in a TLS record.
+    pub fn inject(data: &[u8], context: &TraceContext) -> Vec<u8> {
+        let line_end = match find_subslice(data, b"\r\n") {
+            Some(line_end) if data.starts_with(b"HTTP/") => line_end,
+            _ => return data.to_vec(),
+        };
+        let mut output = data[..line_end + 2].to_vec();
+        output.extend_from_slice(
+            format!("{}: {:032x}\r\n", TRACE_ID_HEADER, context.trace_id).as_bytes(),
+        );
+        output.extend_from_slice(
+            format!("{}: {:016x}\r\n", SPAN_ID_HEADER, context.span_id).as_bytes(),
+        );
+        output.extend_from_slice(&data[line_end + 2..]);
+        output
+    }
+}
+
+fn parse_trace_id(value: &str) -> Option<u128> {
+    match value.len() {
+        16 | 32 => u128::from_str_radix(value, 16).ok(),
+        _ => None,
+    }
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn b3_headers_are_extracted_from_a_request() {
+        let request = b"GET / HTTP/1.1\r\n\
+Host: example.com\r\n\
+X-B3-TraceId: 463ac35c9f6413ad48485a3953bb6124\r\n\
+X-B3-SpanId: 0020000000000001\r\n\
+\r\n";
+
+        let context = TraceHeaderExtractor::extract(request).unwrap();
+
+        assert_eq!(context.trace_id, 0x463ac35c9f6413ad48485a3953bb6124);
+        assert_eq!(context.span_id, 0x0020000000000001);
+    }
+
+    #[test]
+    fn a_sixteen_character_trace_id_is_accepted_per_b3() {
+        let request = b"GET / HTTP/1.1\r\n\
+x-b3-traceid: 48485a3953bb6124\r\n\
+x-b3-spanid: 0020000000000001\r\n\
+\r\n";
+
+        let context = TraceHeaderExtractor::extract(request).unwrap();
+
+        assert_eq!(context.trace_id, 0x48485a3953bb6124);
+    }
+
+    #[test]
+    fn a_request_without_both_headers_yields_no_context() {
+        let no_span = b"GET / HTTP/1.1\r\n\
+X-B3-TraceId: 463ac35c9f6413ad48485a3953bb6124\r\n\
+\r\n";
+        let no_headers = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+
+        assert_eq!(TraceHeaderExtractor::extract(no_span), None);
+        assert_eq!(TraceHeaderExtractor::extract(no_headers), None);
+    }
+
+    #[test]
+    fn malformed_hex_yields_no_context() {
+        let request = b"GET / HTTP/1.1\r\n\
+X-B3-TraceId: not-hex-at-all-not-hex-at-all-no\r\n\
+X-B3-SpanId: 0020000000000001\r\n\
+\r\n";
+
+        assert_eq!(TraceHeaderExtractor::extract(request), None);
+    }
+
+    #[test]
+    fn non_http_data_yields_no_context() {
+        assert_eq!(
+            TraceHeaderExtractor::extract(&[0x16, 0x03, 0x01, 0x02, 0x00]),
+            None
+        );
+    }
+
+    #[test]
+    fn the_context_is_injected_into_a_response() {
+        let context = TraceContext {
+            trace_id: 0x463ac35c9f6413ad48485a3953bb6124,
+            span_id: 0x0020000000000001,
+        };
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nbody";
+
+        let injected = TraceHeaderExtractor::inject(response, &context);
+
+        let text = String::from_utf8(injected).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(text.contains("X-B3-TraceId: 463ac35c9f6413ad48485a3953bb6124\r\n"));
+        assert!(text.contains("X-B3-SpanId: 0020000000000001\r\n"));
+        assert!(text.ends_with("Content-Length: 4\r\n\r\nbody"));
+    }
+
+    #[test]
+    fn injection_round_trips_through_extraction() {
+        let context = TraceContext {
+            trace_id: 0xfeed_face_dead_beef,
+            span_id: 0x1234_5678_9abc_def0,
+        };
+        let response = b"HTTP/1.1 200 OK\r\n\r\n";
+
+        let injected = TraceHeaderExtractor::inject(response, &context);
+
+        assert_eq!(TraceHeaderExtractor::extract(&injected), Some(context));
+    }
+
+    #[test]
+    fn non_http_data_is_not_injected_into() {
+        let context = TraceContext {
+            trace_id: 1,
+            span_id: 2,
+        };
+        let tls_bytes = vec![0x16, 0x03, 0x01, 0x02, 0x00];
+
+        assert_eq!(TraceHeaderExtractor::inject(&tls_bytes, &context), tls_bytes);
+    }
+}
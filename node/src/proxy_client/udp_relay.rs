@@ -0,0 +1,235 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Exit-side UDP relay. Each datagram stream gets its own UDP socket so
+//! replies can be attributed by socket rather than by inspection; outbound
+//! datagrams go out as-is, replies come back wrapped in InboundServerData
+//! with the stream's next sequence number. Sequence numbers order nothing
+//! here — datagram streams promise no ordering — and teardown happens by
+//! idle timeout instead of last_data.
+
+use crate::sub_lib::proxy_client::InboundServerData;
+use crate::sub_lib::stream_key::StreamKey;
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Idle limit for exit-side datagram streams; matches the originator side.
+pub const DATAGRAM_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+const MAX_DATAGRAM_BYTES: usize = 64 * 1024;
+
+struct UdpStream {
+    socket: UdpSocket,
+    target: SocketAddr,
+    next_sequence_number: u64,
+    last_activity: Instant,
+}
+
+pub struct UdpRelay {
+    streams: HashMap<StreamKey, UdpStream>,
+    idle_timeout: Duration,
+}
+
+impl UdpRelay {
+    pub fn new() -> UdpRelay {
+        UdpRelay {
+            streams: HashMap::new(),
+            idle_timeout: DATAGRAM_IDLE_TIMEOUT,
+        }
+    }
+
+    #[cfg(test)]
+    fn with_idle_timeout(mut self, idle_timeout: Duration) -> UdpRelay {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Sends one datagram toward the target, binding this stream's socket
+    /// on first use.
+    pub fn send_datagram(
+        &mut self,
+        stream_key: StreamKey,
+        target: SocketAddr,
+        data: &[u8],
+        now: Instant,
+    ) -> io::Result<()> {
+        if !self.streams.contains_key(&stream_key) {
+            let socket = UdpSocket::bind("0.0.0.0:0")?;
+            socket.set_nonblocking(true)?;
+            self.streams.insert(
+                stream_key,
+                UdpStream {
+                    socket,
+                    target,
+                    next_sequence_number: 0,
+                    last_activity: now,
+                },
+            );
+        }
+        let stream = self.streams.get_mut(&stream_key).expect("just inserted");
+        stream.last_activity = now;
+        stream.socket.send_to(data, target)?;
+        Ok(())
+    }
+
+    /// Drains whatever replies have arrived on any stream's socket.
+    /// Non-blocking; meant to be called from the pool's service loop.
+    pub fn poll_replies(&mut self, now: Instant) -> Vec<InboundServerData> {
+        let mut replies = vec![];
+        let mut buffer = [0u8; MAX_DATAGRAM_BYTES];
+        for (stream_key, stream) in self.streams.iter_mut() {
+            loop {
+                match stream.socket.recv_from(&mut buffer) {
+                    Ok((count, source)) => {
+                        stream.last_activity = now;
+                        let sequence_number = stream.next_sequence_number;
+                        stream.next_sequence_number += 1;
+                        replies.push(InboundServerData {
+                            stream_key: *stream_key,
+                            last_data: false,
+                            sequence_number,
+                            source,
+                            data: buffer[..count].to_vec(),
+                        });
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(_) => break,
+                }
+            }
+        }
+        replies
+    }
+
+    /// Removes and returns streams that have gone idle; the caller reports
+    /// them upstream so the originator can forget the stream key too.
+    pub fn expire_idle(&mut self, now: Instant) -> Vec<StreamKey> {
+        let idle_timeout = self.idle_timeout;
+        let expired: Vec<StreamKey> = self
+            .streams
+            .iter()
+            .filter(|(_, stream)| now.duration_since(stream.last_activity) >= idle_timeout)
+            .map(|(stream_key, _)| *stream_key)
+            .collect();
+        for stream_key in &expired {
+            self.streams.remove(stream_key);
+        }
+        expired
+    }
+
+    pub fn stream_count(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn target_of(&self, stream_key: &StreamKey) -> Option<SocketAddr> {
+        self.streams.get(stream_key).map(|stream| stream.target)
+    }
+}
+
+impl Default for UdpRelay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// A local echo server standing in for the origin service in zero-hop
+    /// tests.
+    fn spawn_echo_server() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 2048];
+            while let Ok((count, source)) = socket.recv_from(&mut buffer) {
+                let _ = socket.send_to(&buffer[..count], source);
+            }
+        });
+        addr
+    }
+
+    fn poll_until_reply(relay: &mut UdpRelay, now: Instant) -> Vec<InboundServerData> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let replies = relay.poll_replies(now);
+            if !replies.is_empty() {
+                return replies;
+            }
+            assert!(Instant::now() < deadline, "no reply from echo server");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn datagrams_round_trip_through_a_local_echo_server() {
+        let echo = spawn_echo_server();
+        let mut subject = UdpRelay::new();
+        let stream_key = StreamKey::make_meaningless(1);
+        let now = Instant::now();
+
+        subject
+            .send_datagram(stream_key, echo, b"dns query bytes", now)
+            .unwrap();
+        let replies = poll_until_reply(&mut subject, now);
+
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].stream_key, stream_key);
+        assert_eq!(replies[0].data, b"dns query bytes".to_vec());
+        assert_eq!(replies[0].sequence_number, 0);
+        assert!(!replies[0].last_data);
+    }
+
+    #[test]
+    fn streams_are_isolated_per_stream_key() {
+        let echo = spawn_echo_server();
+        let mut subject = UdpRelay::new();
+        let now = Instant::now();
+
+        subject
+            .send_datagram(StreamKey::make_meaningless(1), echo, b"first", now)
+            .unwrap();
+        subject
+            .send_datagram(StreamKey::make_meaningless(2), echo, b"second", now)
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut replies = vec![];
+        while replies.len() < 2 {
+            replies.extend(subject.poll_replies(now));
+            assert!(Instant::now() < deadline, "echo replies missing");
+            thread::sleep(Duration::from_millis(5));
+        }
+        let mut by_stream: Vec<(StreamKey, Vec<u8>)> = replies
+            .into_iter()
+            .map(|r| (r.stream_key, r.data))
+            .collect();
+        by_stream.sort();
+        assert_eq!(
+            by_stream,
+            vec![
+                (StreamKey::make_meaningless(1), b"first".to_vec()),
+                (StreamKey::make_meaningless(2), b"second".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn idle_streams_are_torn_down_by_the_clock() {
+        let echo = spawn_echo_server();
+        let mut subject = UdpRelay::new().with_idle_timeout(Duration::from_secs(10));
+        let start = Instant::now();
+        subject
+            .send_datagram(StreamKey::make_meaningless(1), echo, b"ping", start)
+            .unwrap();
+
+        let too_early = subject.expire_idle(start + Duration::from_secs(9));
+        let expired = subject.expire_idle(start + Duration::from_secs(10));
+
+        assert!(too_early.is_empty());
+        assert_eq!(expired, vec![StreamKey::make_meaningless(1)]);
+        assert_eq!(subject.stream_count(), 0);
+    }
+}
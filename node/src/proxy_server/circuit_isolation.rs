@@ -0,0 +1,300 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Per-site circuit isolation. With `per_site_isolation` enabled, route
+//! queries are keyed by the target hostname's registrable domain: streams
+//! to the same site keep sharing an exit so logins and sessions stay
+//! coherent, while different sites get independently selected exits and
+//! no single exit sees the whole browsing profile. Assignments live in a
+//! small LRU with a TTL, so a long session re-rolls its exits
+//! periodically and an abandoned site's assignment ages out. The chosen
+//! exit is threaded into the route query as the existing
+//! `RouteQueryKey::exit_key` pin.
+
+use crate::proxy_server::route_queries::RouteQueryKey;
+use crate::sub_lib::cryptde::PublicKey;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long a domain keeps its exit before a fresh one is selected.
+pub const DEFAULT_ASSIGNMENT_TTL: Duration = Duration::from_secs(600);
+
+/// How many domain assignments are retained; beyond this the least
+/// recently used is evicted.
+pub const DEFAULT_ASSIGNMENT_CAPACITY: usize = 256;
+
+// Second-level labels under which the registrable domain is three labels
+// deep, not two. A handful of common entries; a miss here merely groups a
+// little too coarsely, it never mixes unrelated sites.
+const TWO_PART_SUFFIXES: [&str; 8] = [
+    "co.uk", "ac.uk", "gov.uk", "com.au", "net.au", "co.jp", "co.nz", "com.br",
+];
+
+/// Reduces a hostname to the label pair (or triple, under suffixes like
+/// co.uk) that identifies the site. IP literals and single-label names
+/// are used whole.
+pub fn registrable_domain(hostname: &str) -> String {
+    let normalized = hostname.trim_end_matches('.').to_ascii_lowercase();
+    if normalized.parse::<std::net::IpAddr>().is_ok() {
+        return normalized;
+    }
+    let labels: Vec<&str> = normalized.split('.').collect();
+    if labels.len() <= 2 {
+        return normalized;
+    }
+    let last_two = labels[labels.len() - 2..].join(".");
+    let keep = if TWO_PART_SUFFIXES.contains(&last_two.as_str()) {
+        3
+    } else {
+        2
+    };
+    if labels.len() <= keep {
+        normalized
+    } else {
+        labels[labels.len() - keep..].join(".")
+    }
+}
+
+/// The neighborhood-facing hook: selects an exit for a domain that has no
+/// live assignment. Each call is expected to be an independent selection.
+pub trait ExitSelector {
+    fn select_exit(&self) -> Option<PublicKey>;
+}
+
+struct Assignment {
+    exit: PublicKey,
+    assigned_at: Instant,
+    last_used: Instant,
+}
+
+/// The domain→exit LRU. One per ProxyServer; only consulted when
+/// per-site isolation is configured on.
+pub struct DomainExitAssignments {
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<String, Assignment>,
+}
+
+impl DomainExitAssignments {
+    pub fn new(capacity: usize, ttl: Duration) -> DomainExitAssignments {
+        DomainExitAssignments {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// The exit this hostname's site should use: the live assignment when
+    /// there is one, a fresh independent selection otherwise. Returns
+    /// None only when the selector itself has no exit to offer, in which
+    /// case nothing is cached and the route query goes out unpinned.
+    pub fn exit_for(
+        &mut self,
+        hostname: &str,
+        selector: &dyn ExitSelector,
+        now: Instant,
+    ) -> Option<PublicKey> {
+        let domain = registrable_domain(hostname);
+        if let Some(assignment) = self.entries.get_mut(&domain) {
+            if now.duration_since(assignment.assigned_at) < self.ttl {
+                assignment.last_used = now;
+                return Some(assignment.exit.clone());
+            }
+            self.entries.remove(&domain);
+        }
+        let exit = selector.select_exit()?;
+        if self.entries.len() >= self.capacity {
+            self.evict_least_recently_used();
+        }
+        self.entries.insert(
+            domain,
+            Assignment {
+                exit: exit.clone(),
+                assigned_at: now,
+                last_used: now,
+            },
+        );
+        Some(exit)
+    }
+
+    /// Builds the route-query key for a stream under isolation: the
+    /// site's exit becomes the query's exit pin.
+    pub fn route_query_key_for(
+        &mut self,
+        hostname: &str,
+        minimum_hop_count: usize,
+        selector: &dyn ExitSelector,
+        now: Instant,
+    ) -> RouteQueryKey {
+        RouteQueryKey {
+            minimum_hop_count,
+            exit_key: self.exit_for(hostname, selector, now),
+        }
+    }
+
+    pub fn assignment_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let stalest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, assignment)| assignment.last_used)
+            .map(|(domain, _)| domain.clone());
+        if let Some(domain) = stalest {
+            self.entries.remove(&domain);
+        }
+    }
+}
+
+impl Default for DomainExitAssignments {
+    fn default() -> Self {
+        Self::new(DEFAULT_ASSIGNMENT_CAPACITY, DEFAULT_ASSIGNMENT_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Stands in for a neighborhood holding several exits: each selection
+    /// hands out the next one, and the calls are counted.
+    struct ExitSelectorMock {
+        exits: RefCell<Vec<Option<PublicKey>>>,
+        calls: RefCell<usize>,
+    }
+
+    impl ExitSelectorMock {
+        fn with_exits(count: usize) -> ExitSelectorMock {
+            ExitSelectorMock {
+                exits: RefCell::new(
+                    (0..count)
+                        .map(|n| Some(PublicKey::new(format!("exit{}", n).as_bytes())))
+                        .collect(),
+                ),
+                calls: RefCell::new(0),
+            }
+        }
+
+        fn call_count(&self) -> usize {
+            *self.calls.borrow()
+        }
+    }
+
+    impl ExitSelector for ExitSelectorMock {
+        fn select_exit(&self) -> Option<PublicKey> {
+            *self.calls.borrow_mut() += 1;
+            let mut exits = self.exits.borrow_mut();
+            if exits.is_empty() {
+                None
+            } else {
+                exits.remove(0)
+            }
+        }
+    }
+
+    #[test]
+    fn hosts_within_one_registrable_domain_share_an_exit() {
+        let mut subject = DomainExitAssignments::default();
+        let selector = ExitSelectorMock::with_exits(4);
+        let now = Instant::now();
+
+        let first = subject.exit_for("www.example.com", &selector, now);
+        let second = subject.exit_for("api.example.com", &selector, now);
+        let third = subject.exit_for("example.com", &selector, now);
+
+        assert_eq!(first, Some(PublicKey::new(b"exit0")));
+        assert_eq!(second, first);
+        assert_eq!(third, first);
+        assert_eq!(selector.call_count(), 1);
+    }
+
+    #[test]
+    fn different_sites_get_independently_selected_exits() {
+        let mut subject = DomainExitAssignments::default();
+        let selector = ExitSelectorMock::with_exits(4);
+        let now = Instant::now();
+
+        let one = subject.exit_for("news.example.com", &selector, now);
+        let other = subject.exit_for("www.unrelated.org", &selector, now);
+
+        assert_eq!(one, Some(PublicKey::new(b"exit0")));
+        assert_eq!(other, Some(PublicKey::new(b"exit1")));
+        assert_eq!(selector.call_count(), 2);
+    }
+
+    #[test]
+    fn an_expired_assignment_is_reselected() {
+        let mut subject = DomainExitAssignments::new(16, Duration::from_secs(600));
+        let selector = ExitSelectorMock::with_exits(4);
+        let now = Instant::now();
+        subject.exit_for("example.com", &selector, now);
+
+        let inside = subject.exit_for("example.com", &selector, now + Duration::from_secs(599));
+        let outside = subject.exit_for("example.com", &selector, now + Duration::from_secs(600));
+
+        assert_eq!(inside, Some(PublicKey::new(b"exit0")));
+        assert_eq!(outside, Some(PublicKey::new(b"exit1")));
+    }
+
+    #[test]
+    fn the_least_recently_used_assignment_is_evicted_at_capacity() {
+        let mut subject = DomainExitAssignments::new(2, Duration::from_secs(600));
+        let selector = ExitSelectorMock::with_exits(8);
+        let now = Instant::now();
+        subject.exit_for("first.com", &selector, now);
+        subject.exit_for("second.com", &selector, now + Duration::from_secs(1));
+        // Touch first.com so second.com becomes the stalest.
+        subject.exit_for("first.com", &selector, now + Duration::from_secs(2));
+
+        subject.exit_for("third.com", &selector, now + Duration::from_secs(3));
+
+        assert_eq!(subject.assignment_count(), 2);
+        let refetched = subject.exit_for("first.com", &selector, now + Duration::from_secs(4));
+        assert_eq!(refetched, Some(PublicKey::new(b"exit0")));
+        let reselected = subject.exit_for("second.com", &selector, now + Duration::from_secs(5));
+        assert_eq!(reselected, Some(PublicKey::new(b"exit3")));
+    }
+
+    #[test]
+    fn a_selectorless_neighborhood_pins_nothing_and_caches_nothing() {
+        let mut subject = DomainExitAssignments::default();
+        let selector = ExitSelectorMock::with_exits(0);
+
+        let exit = subject.exit_for("example.com", &selector, Instant::now());
+
+        assert_eq!(exit, None);
+        assert_eq!(subject.assignment_count(), 0);
+    }
+
+    #[test]
+    fn the_route_query_key_carries_the_site_exit_as_its_pin() {
+        let mut subject = DomainExitAssignments::default();
+        let selector = ExitSelectorMock::with_exits(4);
+        let now = Instant::now();
+
+        let key = subject.route_query_key_for("shop.example.co.uk", 3, &selector, now);
+        let again = subject.route_query_key_for("cdn.example.co.uk", 3, &selector, now);
+
+        assert_eq!(
+            key,
+            RouteQueryKey {
+                minimum_hop_count: 3,
+                exit_key: Some(PublicKey::new(b"exit0")),
+            }
+        );
+        assert_eq!(again, key);
+    }
+
+    #[test]
+    fn registrable_domain_groups_sensibly() {
+        assert_eq!(registrable_domain("www.Example.COM"), "example.com");
+        assert_eq!(registrable_domain("a.b.c.example.com"), "example.com");
+        assert_eq!(registrable_domain("www.example.co.uk"), "example.co.uk");
+        assert_eq!(registrable_domain("example.co.uk"), "example.co.uk");
+        assert_eq!(registrable_domain("localhost"), "localhost");
+        assert_eq!(registrable_domain("192.168.1.10"), "192.168.1.10");
+        assert_eq!(registrable_domain("example.com."), "example.com");
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Originator-side handling of DnsResolveFailure reports. The kind the
+//! exit classified decides what the client sees: NXDOMAIN is
+//! authoritative, so the stream gets its error page at once; transient
+//! kinds feed the route-retry logic instead, because a different exit —
+//! with different nameservers and a different network position — may
+//! resolve the same name without trouble. Reports from peers old enough
+//! to omit the kind keep their historical behavior, an immediate error.
+
+use crate::sub_lib::logger::Logger;
+use crate::sub_lib::proxy_client::DnsResolveFailure;
+
+/// What the ProxyServer does with a stream whose resolution failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DnsFailureAction {
+    /// Synthesize the client-facing error and tear the stream down.
+    RespondImmediately,
+    /// Re-run route selection and resend the request via a new exit.
+    RetryOnNewRoute,
+}
+
+pub fn action_for(failure: &DnsResolveFailure, logger: &Logger) -> DnsFailureAction {
+    match failure.kind {
+        Some(kind) if kind.is_transient() => {
+            logger.debug(format!(
+                "Transient {:?} resolving {} at the exit; retrying over a new route",
+                kind, failure.hostname
+            ));
+            DnsFailureAction::RetryOnNewRoute
+        }
+        Some(kind) => {
+            logger.debug(format!(
+                "Authoritative {:?} for {}; reporting to the client",
+                kind, failure.hostname
+            ));
+            DnsFailureAction::RespondImmediately
+        }
+        None => {
+            logger.debug(format!(
+                "Unversioned DNS failure for {} from an older peer; reporting to the client",
+                failure.hostname
+            ));
+            DnsFailureAction::RespondImmediately
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sub_lib::proxy_client::DnsFailureKind;
+    use crate::sub_lib::stream_key::StreamKey;
+
+    fn failure(kind: Option<DnsFailureKind>) -> DnsResolveFailure {
+        DnsResolveFailure {
+            stream_key: StreamKey::make_meaningless(1),
+            hostname: "example.com".to_string(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn nxdomain_gets_the_client_an_immediate_error() {
+        let action = action_for(&failure(Some(DnsFailureKind::NxDomain)), &Logger::new("test"));
+
+        assert_eq!(action, DnsFailureAction::RespondImmediately);
+    }
+
+    #[test]
+    fn transient_kinds_trigger_a_route_retry() {
+        for kind in [
+            DnsFailureKind::ServFail,
+            DnsFailureKind::Timeout,
+            DnsFailureKind::Refused,
+        ] {
+            let action = action_for(&failure(Some(kind)), &Logger::new("test"));
+
+            assert_eq!(action, DnsFailureAction::RetryOnNewRoute, "for {:?}", kind);
+        }
+    }
+
+    #[test]
+    fn a_kindless_report_from_an_old_peer_keeps_the_historical_behavior() {
+        let action = action_for(&failure(None), &Logger::new("test"));
+
+        assert_eq!(action, DnsFailureAction::RespondImmediately);
+    }
+
+    #[test]
+    fn an_old_peers_frame_without_the_kind_field_still_deserializes() {
+        // The payload as serialized before the kind field existed.
+        #[derive(serde::Serialize)]
+        #[serde(rename = "DnsResolveFailure")]
+        struct OldDnsResolveFailure {
+            stream_key: StreamKey,
+            hostname: String,
+        }
+        let old_frame = serde_cbor::ser::to_vec(&OldDnsResolveFailure {
+            stream_key: StreamKey::make_meaningless(3),
+            hostname: "example.com".to_string(),
+        })
+        .unwrap();
+
+        let deserialized: DnsResolveFailure = serde_cbor::from_slice(&old_frame).unwrap();
+
+        assert_eq!(deserialized, failure_with_key_3());
+    }
+
+    fn failure_with_key_3() -> DnsResolveFailure {
+        DnsResolveFailure {
+            stream_key: StreamKey::make_meaningless(3),
+            hostname: "example.com".to_string(),
+            kind: None,
+        }
+    }
+}
@@ -0,0 +1,15 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+pub mod circuit_isolation;
+pub mod dns_failure_handling;
+pub mod original_dst;
+pub mod pac_server;
+pub mod rate_limit_handling;
+pub mod request_timeout;
+pub mod route_queries;
+pub mod socks5;
+pub mod stream_context_map;
+pub mod stream_registry;
+pub mod stream_tombstones;
+pub mod transparent_proxy;
+pub mod udp_intercept;
@@ -0,0 +1,83 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! SO_ORIGINAL_DST extraction for transparent intercept mode: when traffic
+//! reaches us through an iptables REDIRECT rule, the accepted socket's local
+//! address is ours, and the true destination must be recovered from the
+//! kernel's NAT table.
+
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::io::RawFd;
+
+/// Mockable wrapper around the getsockopt(SO_ORIGINAL_DST) syscall.
+pub trait OriginalDstReader: Send {
+    fn original_dst(&self, fd: RawFd) -> io::Result<SocketAddr>;
+}
+
+#[cfg(target_os = "linux")]
+pub struct OriginalDstReaderReal;
+
+#[cfg(target_os = "linux")]
+impl OriginalDstReader for OriginalDstReaderReal {
+    fn original_dst(&self, fd: RawFd) -> io::Result<SocketAddr> {
+        use nix::sys::socket::{getsockopt, sockopt::OriginalDst};
+        use std::net::{IpAddr, Ipv4Addr};
+
+        let addr = getsockopt(fd, OriginalDst).map_err(io::Error::from)?;
+        let ip = IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)));
+        let port = u16::from_be(addr.sin_port);
+        Ok(SocketAddr::new(ip, port))
+    }
+}
+
+/// Recovers the destination a redirected connection was originally headed
+/// for, so the ClientRequestPayload can carry the true target.
+pub fn extract_original_destination(
+    reader: &dyn OriginalDstReader,
+    fd: RawFd,
+) -> io::Result<(String, u16)> {
+    let addr = reader.original_dst(fd)?;
+    Ok((addr.ip().to_string(), addr.port()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    struct OriginalDstReaderMock {
+        result: io::Result<SocketAddr>,
+    }
+
+    impl OriginalDstReader for OriginalDstReaderMock {
+        fn original_dst(&self, _fd: RawFd) -> io::Result<SocketAddr> {
+            match &self.result {
+                Ok(addr) => Ok(*addr),
+                Err(e) => Err(io::Error::new(e.kind(), "syscall failed")),
+            }
+        }
+    }
+
+    #[test]
+    fn extracts_target_host_and_port_from_the_redirected_socket() {
+        let reader = OriginalDstReaderMock {
+            result: Ok(SocketAddr::from_str("93.184.216.34:443").unwrap()),
+        };
+
+        let (host, port) = extract_original_destination(&reader, 7).unwrap();
+
+        assert_eq!(host, "93.184.216.34");
+        assert_eq!(port, 443);
+    }
+
+    #[test]
+    fn syscall_failure_is_surfaced() {
+        let reader = OriginalDstReaderMock {
+            result: Err(io::Error::new(io::ErrorKind::InvalidInput, "nope")),
+        };
+
+        let result = extract_original_destination(&reader, 7);
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Minimal HTTP server that hands browsers a Proxy Auto-Configuration file
+//! pointing at this node's local proxy listener. Anything beyond
+//! `GET /proxy.pac` gets a 404; the server exists to serve exactly one
+//! document and carries no other ambitions.
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Generates the PAC file contents for the current proxy configuration.
+/// A configured SOCKS5 listener is preferred; otherwise browsers are
+/// directed at the plain HTTP proxy port.
+pub fn generate_pac(socks5_port: Option<u16>, http_port: u16) -> String {
+    let directive = match socks5_port {
+        Some(port) => format!("SOCKS5 127.0.0.1:{}; SOCKS 127.0.0.1:{}", port, port),
+        None => format!("PROXY 127.0.0.1:{}", http_port),
+    };
+    format!(
+        "function FindProxyForURL(url, host) {{\n    return \"{}\";\n}}\n",
+        directive
+    )
+}
+
+pub struct PacServer {
+    local_addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PacServer {
+    /// Binds the PAC listener and serves `pac_content` from a background
+    /// thread until the server is dropped. Port 0 picks an ephemeral port,
+    /// which tests rely on.
+    pub fn start(port: u16, pac_content: String) -> io::Result<PacServer> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let local_addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let thread_shutdown = shutdown.clone();
+        let handle = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if thread_shutdown.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let _ = serve_one(stream, &pac_content);
+                }
+            }
+        });
+        Ok(PacServer {
+            local_addr,
+            shutdown,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for PacServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Unblock the accept loop so the thread notices the flag.
+        let _ = TcpStream::connect(self.local_addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn serve_one(mut stream: TcpStream, pac_content: &str) -> io::Result<()> {
+    let mut request = [0u8; 1024];
+    let count = stream.read(&mut request)?;
+    let request_line = String::from_utf8_lossy(&request[..count]);
+    let response = if request_line.starts_with("GET /proxy.pac ") {
+        format!(
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: application/x-ns-proxy-autoconfig\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\
+             \r\n\
+             {}",
+            pac_content.len(),
+            pac_content
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fetch(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes())
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn pac_prefers_socks5_when_configured() {
+        let pac = generate_pac(Some(9050), 8080);
+
+        assert!(pac.contains("SOCKS5 127.0.0.1:9050"));
+        assert!(!pac.contains("PROXY"));
+    }
+
+    #[test]
+    fn pac_falls_back_to_the_http_proxy_port() {
+        let pac = generate_pac(None, 8080);
+
+        assert!(pac.contains("PROXY 127.0.0.1:8080"));
+        assert!(!pac.contains("SOCKS"));
+    }
+
+    #[test]
+    fn server_serves_the_pac_file_over_http() {
+        let server = PacServer::start(0, generate_pac(Some(9050), 8080)).unwrap();
+
+        let response = fetch(server.local_addr(), "/proxy.pac");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("application/x-ns-proxy-autoconfig"));
+        assert!(response.contains("SOCKS5 127.0.0.1:9050"));
+    }
+
+    #[test]
+    fn other_paths_get_a_404() {
+        let server = PacServer::start(0, generate_pac(None, 8080)).unwrap();
+
+        let response = fetch(server.local_addr(), "/wpad.dat");
+
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! What the ProxyServer does with a RateLimitSignal from an exit. Short
+//! waits are absorbed by buffering the original request and retrying when
+//! the window opens; anything longer is turned into an honest 429 so the
+//! client's own backoff takes over instead of a connection that hangs for
+//! most of an hour.
+
+use crate::sub_lib::hopper::MessageType;
+use crate::sub_lib::stream_key::StreamKey;
+use std::time::Duration;
+
+/// The longest throttle the ProxyServer will ride out by buffering before
+/// giving the client a 429 instead.
+pub const DEFAULT_MAX_BUFFER_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RateLimitAction {
+    /// Hold the buffered request and resend it after the delay.
+    BufferAndRetry {
+        stream_key: StreamKey,
+        delay: Duration,
+    },
+    /// Answer the client directly with the synthesized 429.
+    RespondTooManyRequests {
+        stream_key: StreamKey,
+        response: Vec<u8>,
+    },
+}
+
+pub struct RateLimitHandler {
+    max_buffer_delay: Duration,
+}
+
+impl Default for RateLimitHandler {
+    fn default() -> RateLimitHandler {
+        RateLimitHandler::new(DEFAULT_MAX_BUFFER_DELAY)
+    }
+}
+
+impl RateLimitHandler {
+    pub fn new(max_buffer_delay: Duration) -> RateLimitHandler {
+        RateLimitHandler { max_buffer_delay }
+    }
+
+    /// Maps an inbound signal to an action; None for any other message
+    /// type, so the dispatch site can call this unconditionally.
+    pub fn handle(&self, message: &MessageType) -> Option<RateLimitAction> {
+        let (stream_key, retry_after_ms) = match message {
+            MessageType::RateLimitSignal {
+                stream_key,
+                retry_after_ms,
+            } => (*stream_key, *retry_after_ms),
+            _ => return None,
+        };
+        let delay = Duration::from_millis(retry_after_ms);
+        if delay <= self.max_buffer_delay {
+            Some(RateLimitAction::BufferAndRetry { stream_key, delay })
+        } else {
+            Some(RateLimitAction::RespondTooManyRequests {
+                stream_key,
+                response: render_429(delay),
+            })
+        }
+    }
+}
+
+/// The 429 sent when the throttle window is too long to buffer through;
+/// Retry-After is rounded up so a compliant client never retries early.
+fn render_429(delay: Duration) -> Vec<u8> {
+    let retry_after_secs = (delay.as_millis() as u64 + 999) / 1000;
+    format!(
+        "HTTP/1.1 429 Too Many Requests\r\n\
+         Retry-After: {}\r\n\
+         Content-Length: 0\r\n\
+         Connection: close\r\n\
+         \r\n",
+        retry_after_secs
+    )
+    .into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_short_throttle_buffers_the_request() {
+        let subject = RateLimitHandler::default();
+        let signal = MessageType::RateLimitSignal {
+            stream_key: StreamKey::make_meaningless(7),
+            retry_after_ms: 2_500,
+        };
+
+        let action = subject.handle(&signal);
+
+        assert_eq!(
+            action,
+            Some(RateLimitAction::BufferAndRetry {
+                stream_key: StreamKey::make_meaningless(7),
+                delay: Duration::from_millis(2_500),
+            })
+        );
+    }
+
+    #[test]
+    fn a_long_throttle_becomes_a_429_with_a_rounded_up_retry_after() {
+        let subject = RateLimitHandler::default();
+        let signal = MessageType::RateLimitSignal {
+            stream_key: StreamKey::make_meaningless(7),
+            retry_after_ms: 90_500,
+        };
+
+        let action = subject.handle(&signal).unwrap();
+
+        let response = match action {
+            RateLimitAction::RespondTooManyRequests { stream_key, response } => {
+                assert_eq!(stream_key, StreamKey::make_meaningless(7));
+                String::from_utf8(response).unwrap()
+            }
+            other => panic!("expected RespondTooManyRequests, got {:?}", other),
+        };
+        assert!(response.starts_with("HTTP/1.1 429 "));
+        assert!(response.contains("Retry-After: 91\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn the_buffer_boundary_is_inclusive() {
+        let subject = RateLimitHandler::new(Duration::from_secs(5));
+        let at_boundary = MessageType::RateLimitSignal {
+            stream_key: StreamKey::make_meaningless(1),
+            retry_after_ms: 5_000,
+        };
+        let past_boundary = MessageType::RateLimitSignal {
+            stream_key: StreamKey::make_meaningless(1),
+            retry_after_ms: 5_001,
+        };
+
+        assert!(matches!(
+            subject.handle(&at_boundary),
+            Some(RateLimitAction::BufferAndRetry { .. })
+        ));
+        assert!(matches!(
+            subject.handle(&past_boundary),
+            Some(RateLimitAction::RespondTooManyRequests { .. })
+        ));
+    }
+
+    #[test]
+    fn other_message_types_are_not_this_handlers_business() {
+        let subject = RateLimitHandler::default();
+
+        assert_eq!(subject.handle(&MessageType::CoverTraffic(vec![1])), None);
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use crate::sub_lib::stream_key::StreamKey;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The synthetic response sent to a client whose request never got an answer
+/// from the exit side.
+pub const GATEWAY_TIMEOUT_RESPONSE: &[u8] = b"HTTP/1.1 504 Gateway Timeout\r\n\
+Content-Type: text/plain\r\n\
+Content-Length: 28\r\n\
+Connection: close\r\n\
+\r\n\
+Request timed out in transit";
+
+/// Tracks, per stream, how long a ClientRequestPayload has been waiting for
+/// its ClientResponsePayload. The ProxyServer starts a timer when it sends a
+/// request, cancels it when a response arrives, and sweeps periodically; any
+/// stream whose timer expires gets a synthetic 504 and its context removed.
+pub struct RequestTimeoutMonitor {
+    request_timeout: Duration,
+    pending: HashMap<StreamKey, Instant>,
+}
+
+impl RequestTimeoutMonitor {
+    pub fn new(request_timeout: Duration) -> RequestTimeoutMonitor {
+        RequestTimeoutMonitor {
+            request_timeout,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Called when a ClientRequestPayload goes out for the stream.
+    pub fn request_sent(&mut self, stream_key: StreamKey, now: Instant) {
+        self.pending.entry(stream_key).or_insert(now);
+    }
+
+    /// Called when a ClientResponsePayload arrives for the stream.
+    pub fn response_received(&mut self, stream_key: &StreamKey) {
+        self.pending.remove(stream_key);
+    }
+
+    /// Returns the streams whose requests have now waited too long, removing
+    /// them from the pending set. The caller sends GATEWAY_TIMEOUT_RESPONSE
+    /// to each and drops the stream context.
+    pub fn expired_streams(&mut self, now: Instant) -> Vec<StreamKey> {
+        let timeout = self.request_timeout;
+        let expired: Vec<StreamKey> = self
+            .pending
+            .iter()
+            .filter(|(_, sent_at)| now.duration_since(**sent_at) >= timeout)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in &expired {
+            self.pending.remove(key);
+        }
+        expired
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unanswered_request_times_out_with_a_504() {
+        let mut subject = RequestTimeoutMonitor::new(Duration::from_secs(30));
+        let stream_key = StreamKey::make_meaningless(1);
+        let sent_at = Instant::now();
+        subject.request_sent(stream_key, sent_at);
+
+        let expired = subject.expired_streams(sent_at + Duration::from_secs(30));
+
+        assert_eq!(expired, vec![stream_key]);
+        assert_eq!(subject.pending_count(), 0);
+        assert!(GATEWAY_TIMEOUT_RESPONSE.starts_with(b"HTTP/1.1 504"));
+    }
+
+    #[test]
+    fn response_arrival_cancels_the_timer() {
+        let mut subject = RequestTimeoutMonitor::new(Duration::from_secs(30));
+        let stream_key = StreamKey::make_meaningless(1);
+        let sent_at = Instant::now();
+        subject.request_sent(stream_key, sent_at);
+
+        subject.response_received(&stream_key);
+
+        let expired = subject.expired_streams(sent_at + Duration::from_secs(300));
+        assert!(expired.is_empty());
+    }
+
+    #[test]
+    fn request_not_yet_timed_out_is_left_pending() {
+        let mut subject = RequestTimeoutMonitor::new(Duration::from_secs(30));
+        let stream_key = StreamKey::make_meaningless(1);
+        let sent_at = Instant::now();
+        subject.request_sent(stream_key, sent_at);
+
+        let expired = subject.expired_streams(sent_at + Duration::from_secs(29));
+
+        assert!(expired.is_empty());
+        assert_eq!(subject.pending_count(), 1);
+    }
+
+    #[test]
+    fn retransmission_does_not_reset_the_timer() {
+        let mut subject = RequestTimeoutMonitor::new(Duration::from_secs(30));
+        let stream_key = StreamKey::make_meaningless(1);
+        let sent_at = Instant::now();
+        subject.request_sent(stream_key, sent_at);
+        subject.request_sent(stream_key, sent_at + Duration::from_secs(20));
+
+        let expired = subject.expired_streams(sent_at + Duration::from_secs(30));
+
+        assert_eq!(expired, vec![stream_key]);
+    }
+}
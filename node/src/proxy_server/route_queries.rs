@@ -0,0 +1,227 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Coalescing for originator-side route queries. A bursty page load opens
+//! dozens of streams within milliseconds, and every one of them used to
+//! fire its own RouteQueryMessage at the neighborhood while the first was
+//! still unanswered. Streams needing the same kind of route now share one
+//! in-flight query: the first asker leads and actually sends the message,
+//! the rest wait on the shared answer, and for a short window afterward
+//! the answer is reused outright. Routes are still assigned per stream —
+//! what is shared is the neighborhood's response, not the route's use.
+
+use crate::sub_lib::cryptde::PublicKey;
+use crate::sub_lib::route::Route;
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a completed answer keeps being handed out without re-asking.
+pub const DEFAULT_REUSE_WINDOW: Duration = Duration::from_millis(250);
+
+/// The parameters that make two route queries "the same query".
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RouteQueryKey {
+    pub minimum_hop_count: usize,
+    /// Pinned exit node, when the stream requires one.
+    pub exit_key: Option<PublicKey>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RouteQueryError {
+    /// The shared query failed; every waiter gets this and may retry,
+    /// which will elect a new leader.
+    Retryable(String),
+}
+
+pub type RouteQueryResult = Result<Route, RouteQueryError>;
+
+/// What the coalescer tells an asking stream to do.
+pub enum QueryRole {
+    /// Nobody is asking yet: send the RouteQueryMessage and report the
+    /// answer via `complete`.
+    Lead,
+    /// A query is in flight: the answer will arrive on this receiver.
+    Wait(Receiver<RouteQueryResult>),
+    /// A recent answer is still fresh; use it as-is.
+    Ready(RouteQueryResult),
+}
+
+enum EntryState {
+    InFlight(Vec<Sender<RouteQueryResult>>),
+    Completed { result: Route, at: Instant },
+}
+
+pub struct RouteQueryCoalescer {
+    entries: Mutex<HashMap<RouteQueryKey, EntryState>>,
+    reuse_window: Duration,
+}
+
+impl RouteQueryCoalescer {
+    pub fn new(reuse_window: Duration) -> RouteQueryCoalescer {
+        RouteQueryCoalescer {
+            entries: Mutex::new(HashMap::new()),
+            reuse_window,
+        }
+    }
+
+    /// Called by a stream that needs a route. Exactly one concurrent
+    /// caller per key is told to lead; the decision and the subscription
+    /// happen under one lock, so there is no window for a second leader.
+    pub fn request(&self, key: RouteQueryKey, now: Instant) -> QueryRole {
+        let mut entries = self.entries.lock().expect("coalescer poisoned");
+        match entries.get_mut(&key) {
+            Some(EntryState::InFlight(waiters)) => {
+                let (tx, rx) = mpsc::channel();
+                waiters.push(tx);
+                QueryRole::Wait(rx)
+            }
+            Some(EntryState::Completed { result, at }) => {
+                if now.duration_since(*at) <= self.reuse_window {
+                    QueryRole::Ready(Ok(result.clone()))
+                } else {
+                    entries.insert(key, EntryState::InFlight(vec![]));
+                    QueryRole::Lead
+                }
+            }
+            None => {
+                entries.insert(key, EntryState::InFlight(vec![]));
+                QueryRole::Lead
+            }
+        }
+    }
+
+    /// Called by the leader when the neighborhood answers. Every waiter
+    /// receives a clone; a success additionally opens the reuse window,
+    /// while a failure clears the entry so the next asker re-queries.
+    pub fn complete(&self, key: RouteQueryKey, result: RouteQueryResult, now: Instant) {
+        let waiters = {
+            let mut entries = self.entries.lock().expect("coalescer poisoned");
+            let waiters = match entries.remove(&key) {
+                Some(EntryState::InFlight(waiters)) => waiters,
+                _ => vec![],
+            };
+            if let Ok(route) = &result {
+                entries.insert(
+                    key,
+                    EntryState::Completed {
+                        result: route.clone(),
+                        at: now,
+                    },
+                );
+            }
+            waiters
+        };
+        for waiter in waiters {
+            let _ = waiter.send(result.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    fn key() -> RouteQueryKey {
+        RouteQueryKey {
+            minimum_hop_count: 3,
+            exit_key: None,
+        }
+    }
+
+    fn subject() -> RouteQueryCoalescer {
+        RouteQueryCoalescer::new(DEFAULT_REUSE_WINDOW)
+    }
+
+    #[test]
+    fn twenty_concurrent_streams_produce_one_query() {
+        let subject = Arc::new(subject());
+        let barrier = Arc::new(Barrier::new(20));
+        let queries_sent = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..20)
+            .map(|_| {
+                let subject = subject.clone();
+                let barrier = barrier.clone();
+                let queries_sent = queries_sent.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    match subject.request(key(), Instant::now()) {
+                        QueryRole::Lead => {
+                            // This stand-in for the neighborhood recorder
+                            // counts RouteQueryMessages actually sent.
+                            queries_sent.fetch_add(1, Ordering::SeqCst);
+                            subject.complete(key(), Ok(Route::new(vec![])), Instant::now());
+                            Ok(Route::new(vec![]))
+                        }
+                        QueryRole::Wait(rx) => rx.recv().unwrap(),
+                        QueryRole::Ready(result) => result,
+                    }
+                })
+            })
+            .collect();
+
+        let results: Vec<RouteQueryResult> =
+            handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        assert_eq!(queries_sent.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|r| r == &Ok(Route::new(vec![]))));
+    }
+
+    #[test]
+    fn waiters_all_receive_the_shared_failure() {
+        let subject = subject();
+        let now = Instant::now();
+        assert!(matches!(subject.request(key(), now), QueryRole::Lead));
+        let waiters: Vec<Receiver<RouteQueryResult>> = (0..5)
+            .map(|_| match subject.request(key(), now) {
+                QueryRole::Wait(rx) => rx,
+                _ => panic!("expected Wait while the query is in flight"),
+            })
+            .collect();
+
+        subject.complete(
+            key(),
+            Err(RouteQueryError::Retryable("no route to exit".to_string())),
+            now,
+        );
+
+        for waiter in waiters {
+            assert_eq!(
+                waiter.recv().unwrap(),
+                Err(RouteQueryError::Retryable("no route to exit".to_string()))
+            );
+        }
+        // Failure opens no reuse window: the next asker leads a fresh query.
+        assert!(matches!(subject.request(key(), now), QueryRole::Lead));
+    }
+
+    #[test]
+    fn a_fresh_answer_is_reused_within_the_window() {
+        let subject = subject();
+        let now = Instant::now();
+        assert!(matches!(subject.request(key(), now), QueryRole::Lead));
+        subject.complete(key(), Ok(Route::new(vec![])), now);
+
+        let inside = subject.request(key(), now + Duration::from_millis(200));
+        let outside = subject.request(key(), now + Duration::from_millis(300));
+
+        assert!(matches!(inside, QueryRole::Ready(Ok(_))));
+        assert!(matches!(outside, QueryRole::Lead));
+    }
+
+    #[test]
+    fn different_parameters_are_different_queries() {
+        let subject = subject();
+        let now = Instant::now();
+        let other_key = RouteQueryKey {
+            minimum_hop_count: 5,
+            exit_key: None,
+        };
+
+        assert!(matches!(subject.request(key(), now), QueryRole::Lead));
+        assert!(matches!(subject.request(other_key, now), QueryRole::Lead));
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! A Tor-compatible SOCKS5 front end for the ProxyServer: browsers point at
+//! a local port, speak the RFC 1928 CONNECT handshake, and their traffic is
+//! injected into the normal ClientRequestPayload pipeline. Only CONNECT with
+//! no authentication is supported, matching what Tor clients expect.
+
+use crate::sub_lib::cryptde::PublicKey;
+use crate::sub_lib::proxy_server::{ClientRequestPayload, ProxyProtocol};
+use crate::sub_lib::sequence_buffer::SequencedPacket;
+use crate::sub_lib::stream_key::StreamKey;
+
+pub const SOCKS_VERSION: u8 = 0x05;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_UNACCEPTABLE: u8 = 0xff;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Socks5Outcome {
+    /// Send these bytes back to the client and keep reading.
+    Reply(Vec<u8>),
+    /// Handshake complete: send the reply, then relay subsequent data to
+    /// the given target through the node.
+    Established {
+        reply: Vec<u8>,
+        target_hostname: String,
+        target_port: u16,
+    },
+    /// Protocol violation: send the reply (if any) and drop the connection.
+    Reject(Option<Vec<u8>>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Socks5State {
+    AwaitingGreeting,
+    AwaitingRequest,
+    Relaying,
+}
+
+pub struct Socks5Connection {
+    state: Socks5State,
+}
+
+impl Socks5Connection {
+    pub fn new() -> Socks5Connection {
+        Socks5Connection {
+            state: Socks5State::AwaitingGreeting,
+        }
+    }
+
+    pub fn handle_data(&mut self, data: &[u8]) -> Socks5Outcome {
+        match self.state {
+            Socks5State::AwaitingGreeting => self.handle_greeting(data),
+            Socks5State::AwaitingRequest => self.handle_request(data),
+            Socks5State::Relaying => Socks5Outcome::Reject(None),
+        }
+    }
+
+    pub fn is_relaying(&self) -> bool {
+        self.state == Socks5State::Relaying
+    }
+
+    fn handle_greeting(&mut self, data: &[u8]) -> Socks5Outcome {
+        if data.len() < 2 || data[0] != SOCKS_VERSION {
+            return Socks5Outcome::Reject(None);
+        }
+        let method_count = data[1] as usize;
+        let methods = &data[2..];
+        if methods.len() < method_count || !methods[..method_count].contains(&METHOD_NO_AUTH) {
+            return Socks5Outcome::Reject(Some(vec![SOCKS_VERSION, METHOD_UNACCEPTABLE]));
+        }
+        self.state = Socks5State::AwaitingRequest;
+        Socks5Outcome::Reply(vec![SOCKS_VERSION, METHOD_NO_AUTH])
+    }
+
+    fn handle_request(&mut self, data: &[u8]) -> Socks5Outcome {
+        if data.len() < 7 || data[0] != SOCKS_VERSION {
+            return Socks5Outcome::Reject(None);
+        }
+        if data[1] != CMD_CONNECT {
+            // 0x07: command not supported.
+            return Socks5Outcome::Reject(Some(Self::reply(0x07)));
+        }
+        let (target_hostname, port_offset) = match data[3] {
+            ATYP_IPV4 if data.len() >= 10 => {
+                let host = format!("{}.{}.{}.{}", data[4], data[5], data[6], data[7]);
+                (host, 8)
+            }
+            ATYP_DOMAIN if data.len() >= 5 => {
+                let len = data[4] as usize;
+                if data.len() < 5 + len + 2 {
+                    return Socks5Outcome::Reject(None);
+                }
+                match String::from_utf8(data[5..5 + len].to_vec()) {
+                    Ok(host) => (host, 5 + len),
+                    Err(_) => return Socks5Outcome::Reject(Some(Self::reply(0x08))),
+                }
+            }
+            ATYP_IPV6 if data.len() >= 22 => {
+                let mut segments = [0u16; 8];
+                for (i, segment) in segments.iter_mut().enumerate() {
+                    *segment = u16::from_be_bytes([data[4 + i * 2], data[5 + i * 2]]);
+                }
+                (std::net::Ipv6Addr::from(segments).to_string(), 20)
+            }
+            // 0x08: address type not supported.
+            _ => return Socks5Outcome::Reject(Some(Self::reply(0x08))),
+        };
+        let target_port = u16::from_be_bytes([data[port_offset], data[port_offset + 1]]);
+        self.state = Socks5State::Relaying;
+        Socks5Outcome::Established {
+            reply: Self::reply(0x00),
+            target_hostname,
+            target_port,
+        }
+    }
+
+    // Reply with a zeroed IPv4 bind address, as Tor's client does.
+    fn reply(code: u8) -> Vec<u8> {
+        vec![SOCKS_VERSION, code, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0]
+    }
+}
+
+impl Default for Socks5Connection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Wraps post-handshake SOCKS5 data into the normal request pipeline.
+pub fn to_client_request_payload(
+    stream_key: StreamKey,
+    target_hostname: String,
+    target_port: u16,
+    data: Vec<u8>,
+    sequence_number: u64,
+    originator_public_key: PublicKey,
+) -> ClientRequestPayload {
+    ClientRequestPayload {
+        stream_key,
+        sequenced_packet: SequencedPacket::new(data, sequence_number, false),
+        target_hostname: Some(target_hostname),
+        target_port,
+        protocol: ProxyProtocol::HTTP,
+        originator_public_key,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn greeting_with_no_auth_is_accepted() {
+        let mut subject = Socks5Connection::new();
+
+        let outcome = subject.handle_data(&[0x05, 0x02, 0x00, 0x02]);
+
+        assert_eq!(outcome, Socks5Outcome::Reply(vec![0x05, 0x00]));
+    }
+
+    #[test]
+    fn greeting_without_no_auth_is_rejected() {
+        let mut subject = Socks5Connection::new();
+
+        let outcome = subject.handle_data(&[0x05, 0x01, 0x02]);
+
+        assert_eq!(outcome, Socks5Outcome::Reject(Some(vec![0x05, 0xff])));
+    }
+
+    #[test]
+    fn connect_to_domain_establishes_relay() {
+        let mut subject = Socks5Connection::new();
+        subject.handle_data(&[0x05, 0x01, 0x00]);
+        let mut request = vec![0x05, 0x01, 0x00, 0x03, 11];
+        request.extend_from_slice(b"example.com");
+        request.extend_from_slice(&443u16.to_be_bytes());
+
+        let outcome = subject.handle_data(&request);
+
+        match outcome {
+            Socks5Outcome::Established {
+                target_hostname,
+                target_port,
+                reply,
+            } => {
+                assert_eq!(target_hostname, "example.com");
+                assert_eq!(target_port, 443);
+                assert_eq!(reply[0..2], [0x05, 0x00]);
+            }
+            other => panic!("expected Established, got {:?}", other),
+        }
+        assert!(subject.is_relaying());
+    }
+
+    #[test]
+    fn connect_to_ipv4_establishes_relay() {
+        let mut subject = Socks5Connection::new();
+        subject.handle_data(&[0x05, 0x01, 0x00]);
+        let request = vec![0x05, 0x01, 0x00, 0x01, 93, 184, 216, 34, 0x00, 80];
+
+        let outcome = subject.handle_data(&request);
+
+        match outcome {
+            Socks5Outcome::Established {
+                target_hostname,
+                target_port,
+                ..
+            } => {
+                assert_eq!(target_hostname, "93.184.216.34");
+                assert_eq!(target_port, 80);
+            }
+            other => panic!("expected Established, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn non_connect_commands_are_refused() {
+        let mut subject = Socks5Connection::new();
+        subject.handle_data(&[0x05, 0x01, 0x00]);
+        // BIND command.
+        let request = vec![0x05, 0x02, 0x00, 0x01, 1, 2, 3, 4, 0x00, 80];
+
+        let outcome = subject.handle_data(&request);
+
+        match outcome {
+            Socks5Outcome::Reject(Some(reply)) => assert_eq!(reply[1], 0x07),
+            other => panic!("expected Reject, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn established_connection_feeds_the_request_pipeline() {
+        let payload = to_client_request_payload(
+            StreamKey::make_meaningless(1),
+            "example.com".to_string(),
+            443,
+            b"data".to_vec(),
+            0,
+            PublicKey::new(b"originator"),
+        );
+
+        assert_eq!(payload.target_hostname, Some("example.com".to_string()));
+        assert_eq!(payload.target_port, 443);
+        assert_eq!(payload.protocol, ProxyProtocol::HTTP);
+    }
+}
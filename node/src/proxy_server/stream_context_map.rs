@@ -0,0 +1,64 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! The stream-context table's map type. Lookups happen once per relayed
+//! chunk, so at high message rates even single-threaded hashing and
+//! collision chains show up in profiles. Contexts now live in a
+//! hashbrown map (SwissTable probing, drop-in API) hashed with AHash,
+//! which turns a StreamKey's u64 into a full hash in a couple of cycles
+//! instead of running SipHash. Every holder of stream contexts uses this
+//! alias so the choice is made in one place.
+
+use crate::sub_lib::stream_key::StreamKey;
+
+/// The map every stream-context table uses.
+pub type StreamKeyMap<V> = hashbrown::HashMap<StreamKey, V, ahash::RandomState>;
+
+pub fn new_stream_key_map<V>() -> StreamKeyMap<V> {
+    hashbrown::HashMap::with_hasher(ahash::RandomState::new())
+}
+
+pub fn with_capacity<V>(capacity: usize) -> StreamKeyMap<V> {
+    hashbrown::HashMap::with_capacity_and_hasher(capacity, ahash::RandomState::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserts_and_lookups_behave_like_the_std_map() {
+        let mut subject: StreamKeyMap<&str> = new_stream_key_map();
+
+        subject.insert(StreamKey::make_meaningless(1), "first");
+        subject.insert(StreamKey::make_meaningless(2), "second");
+        subject.insert(StreamKey::make_meaningless(1), "replaced");
+
+        assert_eq!(subject.get(&StreamKey::make_meaningless(1)), Some(&"replaced"));
+        assert_eq!(subject.get(&StreamKey::make_meaningless(2)), Some(&"second"));
+        assert_eq!(subject.get(&StreamKey::make_meaningless(3)), None);
+        assert_eq!(subject.len(), 2);
+    }
+
+    #[test]
+    fn removal_and_entry_reuse_work() {
+        let mut subject: StreamKeyMap<u32> = with_capacity(16);
+        subject.insert(StreamKey::make_meaningless(1), 10);
+
+        assert_eq!(subject.remove(&StreamKey::make_meaningless(1)), Some(10));
+        assert_eq!(subject.remove(&StreamKey::make_meaningless(1)), None);
+        *subject.entry(StreamKey::make_meaningless(1)).or_insert(0) += 5;
+        assert_eq!(subject.get(&StreamKey::make_meaningless(1)), Some(&5));
+    }
+
+    #[test]
+    fn ten_thousand_distinct_keys_all_stay_retrievable() {
+        let mut subject: StreamKeyMap<u64> = new_stream_key_map();
+        for n in 0..10_000u64 {
+            subject.insert(StreamKey::make_meaningless(n), n);
+        }
+
+        for n in 0..10_000u64 {
+            assert_eq!(subject.get(&StreamKey::make_meaningless(n)), Some(&n));
+        }
+    }
+}
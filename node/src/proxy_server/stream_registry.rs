@@ -0,0 +1,308 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Stream-level introspection and cancellation. When one stream wedges,
+//! operators can now list the ProxyServer's active streams — key, protocol,
+//! target, age, bytes each way — and kill exactly the stuck one: the client
+//! socket closes and a stream-shutdown payload goes toward the exit, while
+//! every other stream is untouched. `masq streams list|kill <key>` drives
+//! this through the UI gateway.
+
+use crate::sub_lib::proxy_server::ProxyProtocol;
+use crate::sub_lib::stream_key::StreamKey;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How a registered stream's client socket gets closed on cancellation.
+pub trait StreamShutdownHandle: Send {
+    fn close_client_socket(&self);
+}
+
+/// One row of the `masq streams list` output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ActiveStreamInfo {
+    pub stream_key: String,
+    pub protocol: ProxyProtocol,
+    pub target_hostname: Option<String>,
+    pub age: Duration,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StreamCancelError {
+    NoSuchStream(String),
+}
+
+struct RegisteredStream {
+    protocol: ProxyProtocol,
+    target_hostname: Option<String>,
+    opened_at: Instant,
+    bytes_up: u64,
+    bytes_down: u64,
+    shutdown_handle: Box<dyn StreamShutdownHandle>,
+}
+
+/// The ProxyServer's own picture of its streams, grown byte counters so the
+/// listing needs no other source.
+#[derive(Default)]
+pub struct StreamRegistry {
+    streams: HashMap<StreamKey, RegisteredStream>,
+}
+
+impl StreamRegistry {
+    pub fn new() -> StreamRegistry {
+        Self::default()
+    }
+
+    pub fn register(
+        &mut self,
+        stream_key: StreamKey,
+        protocol: ProxyProtocol,
+        target_hostname: Option<String>,
+        shutdown_handle: Box<dyn StreamShutdownHandle>,
+        now: Instant,
+    ) {
+        self.streams.insert(
+            stream_key,
+            RegisteredStream {
+                protocol,
+                target_hostname,
+                opened_at: now,
+                bytes_up: 0,
+                bytes_down: 0,
+                shutdown_handle,
+            },
+        );
+    }
+
+    pub fn record_bytes_up(&mut self, stream_key: &StreamKey, count: u64) {
+        if let Some(stream) = self.streams.get_mut(stream_key) {
+            stream.bytes_up += count;
+        }
+    }
+
+    pub fn record_bytes_down(&mut self, stream_key: &StreamKey, count: u64) {
+        if let Some(stream) = self.streams.get_mut(stream_key) {
+            stream.bytes_down += count;
+        }
+    }
+
+    /// Normal stream teardown; the key disappears from listings.
+    pub fn deregister(&mut self, stream_key: &StreamKey) {
+        self.streams.remove(stream_key);
+    }
+
+    pub fn stream_count(&self) -> usize {
+        self.streams.len()
+    }
+
+    /// Answers the `masq streams list` query, oldest stream first.
+    pub fn list(&self, now: Instant) -> Vec<ActiveStreamInfo> {
+        let mut infos: Vec<(Instant, ActiveStreamInfo)> = self
+            .streams
+            .iter()
+            .map(|(key, stream)| {
+                (
+                    stream.opened_at,
+                    ActiveStreamInfo {
+                        stream_key: key.to_string(),
+                        protocol: stream.protocol,
+                        target_hostname: stream.target_hostname.clone(),
+                        age: now.duration_since(stream.opened_at),
+                        bytes_up: stream.bytes_up,
+                        bytes_down: stream.bytes_down,
+                    },
+                )
+            })
+            .collect();
+        infos.sort_by_key(|(opened_at, _)| *opened_at);
+        infos.into_iter().map(|(_, info)| info).collect()
+    }
+
+    /// Kills the stream named by the rendered key (as shown in `list`):
+    /// closes the client socket and removes the stream. The caller sends
+    /// the stream-shutdown payload toward the exit using the returned key.
+    pub fn cancel(&mut self, rendered_key: &str) -> Result<StreamKey, StreamCancelError> {
+        let stream_key = *self
+            .streams
+            .keys()
+            .find(|key| key.to_string() == rendered_key)
+            .ok_or_else(|| StreamCancelError::NoSuchStream(rendered_key.to_string()))?;
+        let stream = self
+            .streams
+            .remove(&stream_key)
+            .expect("key was found just above");
+        stream.shutdown_handle.close_client_socket();
+        Ok(stream_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+
+    struct StreamShutdownHandleMock {
+        close_params: Arc<Mutex<Vec<()>>>,
+    }
+
+    impl StreamShutdownHandleMock {
+        fn new(params: &Arc<Mutex<Vec<()>>>) -> StreamShutdownHandleMock {
+            StreamShutdownHandleMock {
+                close_params: params.clone(),
+            }
+        }
+    }
+
+    impl StreamShutdownHandle for StreamShutdownHandleMock {
+        fn close_client_socket(&self) {
+            self.close_params.lock().unwrap().push(());
+        }
+    }
+
+    fn noop_handle() -> Box<dyn StreamShutdownHandle> {
+        Box::new(StreamShutdownHandleMock::new(&Arc::new(Mutex::new(vec![]))))
+    }
+
+    #[test]
+    fn listing_shows_each_stream_with_counters_and_age() {
+        let mut subject = StreamRegistry::new();
+        let start = Instant::now();
+        subject.register(
+            StreamKey::make_meaningless(1),
+            ProxyProtocol::HTTP,
+            Some("example.com".to_string()),
+            noop_handle(),
+            start,
+        );
+        subject.register(
+            StreamKey::make_meaningless(2),
+            ProxyProtocol::TLS,
+            Some("other.com".to_string()),
+            noop_handle(),
+            start + Duration::from_secs(5),
+        );
+        subject.record_bytes_up(&StreamKey::make_meaningless(1), 400);
+        subject.record_bytes_down(&StreamKey::make_meaningless(1), 9000);
+
+        let listing = subject.list(start + Duration::from_secs(10));
+
+        assert_eq!(
+            listing,
+            vec![
+                ActiveStreamInfo {
+                    stream_key: StreamKey::make_meaningless(1).to_string(),
+                    protocol: ProxyProtocol::HTTP,
+                    target_hostname: Some("example.com".to_string()),
+                    age: Duration::from_secs(10),
+                    bytes_up: 400,
+                    bytes_down: 9000,
+                },
+                ActiveStreamInfo {
+                    stream_key: StreamKey::make_meaningless(2).to_string(),
+                    protocol: ProxyProtocol::TLS,
+                    target_hostname: Some("other.com".to_string()),
+                    age: Duration::from_secs(5),
+                    bytes_up: 0,
+                    bytes_down: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn cancelling_closes_the_client_socket_and_removes_only_that_stream() {
+        let close_params = Arc::new(Mutex::new(vec![]));
+        let mut subject = StreamRegistry::new();
+        let now = Instant::now();
+        subject.register(
+            StreamKey::make_meaningless(1),
+            ProxyProtocol::HTTP,
+            None,
+            Box::new(StreamShutdownHandleMock::new(&close_params)),
+            now,
+        );
+        subject.register(
+            StreamKey::make_meaningless(2),
+            ProxyProtocol::HTTP,
+            None,
+            noop_handle(),
+            now,
+        );
+
+        let cancelled = subject
+            .cancel(&StreamKey::make_meaningless(1).to_string())
+            .unwrap();
+
+        assert_eq!(cancelled, StreamKey::make_meaningless(1));
+        assert_eq!(close_params.lock().unwrap().len(), 1);
+        assert_eq!(subject.stream_count(), 1);
+    }
+
+    #[test]
+    fn cancelling_an_unknown_key_is_an_error() {
+        let mut subject = StreamRegistry::new();
+
+        let result = subject.cancel("feedfacedeadbeef");
+
+        assert_eq!(
+            result,
+            Err(StreamCancelError::NoSuchStream(
+                "feedfacedeadbeef".to_string()
+            ))
+        );
+    }
+
+    /// A shutdown handle backed by a real socket, as the ProxyServer
+    /// registers for each accepted client connection.
+    struct SocketShutdownHandle {
+        stream: TcpStream,
+    }
+
+    impl StreamShutdownHandle for SocketShutdownHandle {
+        fn close_client_socket(&self) {
+            let _ = self.stream.shutdown(std::net::Shutdown::Both);
+        }
+    }
+
+    #[test]
+    fn killing_a_stuck_stream_closes_the_client_socket_zero_hop() {
+        // A never-responding origin: the listener accepts and then ignores
+        // the connection, like the one tab that never loads.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stuck_thread = std::thread::spawn(move || {
+            let (_stream, _) = listener.accept().unwrap();
+            std::thread::sleep(Duration::from_secs(5));
+        });
+        let mut client = TcpStream::connect(addr).unwrap();
+        let server_side = client.try_clone().unwrap();
+        let mut subject = StreamRegistry::new();
+        let key = StreamKey::new(b"salt", client.peer_addr().unwrap());
+        subject.register(
+            key,
+            ProxyProtocol::HTTP,
+            Some("neverloads.example".to_string()),
+            Box::new(SocketShutdownHandle {
+                stream: server_side,
+            }),
+            Instant::now(),
+        );
+        let listing = subject.list(Instant::now());
+        assert_eq!(listing.len(), 1);
+
+        subject.cancel(&listing[0].stream_key).unwrap();
+
+        // The shutdown unblocks the read with EOF (or a reset error);
+        // either way the socket is dead, which is the point.
+        let mut buffer = [0u8; 1];
+        match client.read(&mut buffer) {
+            Ok(0) | Err(_) => (),
+            Ok(n) => panic!("expected a closed socket, read {} bytes", n),
+        }
+        assert_eq!(subject.stream_count(), 0);
+        drop(stuck_thread);
+    }
+}
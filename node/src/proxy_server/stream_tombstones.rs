@@ -0,0 +1,250 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Classifying unsolicited InboundServerData. One "Received unsolicited
+//! response ... ignoring" error used to cover three different situations:
+//! a response racing the stream-context removal after last_data, a response
+//! for a context the TTL sweep evicted, and a stream key this node never
+//! knew. Support could not tell them apart. The ProxyServer now leaves a
+//! short-lived tombstone behind every removed context; unsolicited
+//! responses are classified against the tombstones, logged at a severity
+//! matching what they mean, and counted per class.
+
+use crate::sub_lib::logger::Logger;
+use crate::sub_lib::stream_key::StreamKey;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Tombstones outlive their context by this much; a response later than
+/// this is indistinguishable from a never-known key anyway.
+pub const TOMBSTONE_TTL: Duration = Duration::from_secs(60);
+
+/// Why a stream context was removed, recorded on the tombstone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RemovalReason {
+    /// Normal teardown after last_data; a straggler response is a benign
+    /// race.
+    ClosedAfterLastData,
+    /// The TTL sweep reclaimed the context; a response now means the sweep
+    /// fired on a stream that was still alive.
+    EvictedByTtlSweep,
+}
+
+/// What an unsolicited response turned out to be.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UnsolicitedClass {
+    LateAfterClose,
+    Evicted { age: Duration },
+    Unknown,
+}
+
+/// Per-class counters, surfaced alongside the ProxyServer's other stats.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UnsolicitedStats {
+    pub late_after_close: u64,
+    pub evicted: u64,
+    pub unknown: u64,
+}
+
+struct Tombstone {
+    stream_key: StreamKey,
+    reason: RemovalReason,
+    removed_at: Instant,
+}
+
+pub struct StreamTombstones {
+    ttl: Duration,
+    entries: VecDeque<Tombstone>,
+    stats: UnsolicitedStats,
+    logger: Logger,
+}
+
+impl StreamTombstones {
+    pub fn new(ttl: Duration) -> StreamTombstones {
+        StreamTombstones {
+            ttl,
+            entries: VecDeque::new(),
+            stats: UnsolicitedStats::default(),
+            logger: Logger::new("ProxyServer"),
+        }
+    }
+
+    /// Called wherever a stream context is removed, with the reason.
+    pub fn record_removal(&mut self, stream_key: StreamKey, reason: RemovalReason, now: Instant) {
+        self.prune(now);
+        self.entries.push_back(Tombstone {
+            stream_key,
+            reason,
+            removed_at: now,
+        });
+    }
+
+    /// Classifies an unsolicited response, logs the reason-specific
+    /// message, and bumps the matching counter.
+    pub fn classify(&mut self, stream_key: &StreamKey, now: Instant) -> UnsolicitedClass {
+        self.prune(now);
+        let tombstone = self
+            .entries
+            .iter()
+            .rev()
+            .find(|tombstone| &tombstone.stream_key == stream_key);
+        match tombstone {
+            Some(tombstone) => match tombstone.reason {
+                RemovalReason::ClosedAfterLastData => {
+                    self.logger.debug(format!(
+                        "Response for stream {} arrived after the stream closed normally; ignoring",
+                        stream_key
+                    ));
+                    self.stats.late_after_close += 1;
+                    UnsolicitedClass::LateAfterClose
+                }
+                RemovalReason::EvictedByTtlSweep => {
+                    let age = now.duration_since(tombstone.removed_at);
+                    self.logger.warning(format!(
+                        "Response for stream {} arrived {:?} after its context was evicted by the TTL sweep; ignoring",
+                        stream_key, age
+                    ));
+                    self.stats.evicted += 1;
+                    UnsolicitedClass::Evicted { age }
+                }
+            },
+            None => {
+                self.logger.error(format!(
+                    "Received unsolicited response for never-known stream {}; ignoring",
+                    stream_key
+                ));
+                self.stats.unknown += 1;
+                UnsolicitedClass::Unknown
+            }
+        }
+    }
+
+    pub fn stats(&self) -> &UnsolicitedStats {
+        &self.stats
+    }
+
+    pub fn tombstone_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn prune(&mut self, now: Instant) {
+        let ttl = self.ttl;
+        while let Some(front) = self.entries.front() {
+            if now.duration_since(front.removed_at) >= ttl {
+                self.entries.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+impl Default for StreamTombstones {
+    fn default() -> Self {
+        Self::new(TOMBSTONE_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_response_racing_a_normal_close_is_late_after_close() {
+        let mut subject = StreamTombstones::default();
+        let now = Instant::now();
+        subject.record_removal(
+            StreamKey::make_meaningless(1),
+            RemovalReason::ClosedAfterLastData,
+            now,
+        );
+
+        let class = subject.classify(
+            &StreamKey::make_meaningless(1),
+            now + Duration::from_millis(40),
+        );
+
+        assert_eq!(class, UnsolicitedClass::LateAfterClose);
+        assert_eq!(subject.stats().late_after_close, 1);
+        assert_eq!(subject.stats().evicted, 0);
+        assert_eq!(subject.stats().unknown, 0);
+    }
+
+    #[test]
+    fn a_response_for_a_ttl_evicted_context_reports_the_age() {
+        let mut subject = StreamTombstones::default();
+        let now = Instant::now();
+        subject.record_removal(
+            StreamKey::make_meaningless(1),
+            RemovalReason::EvictedByTtlSweep,
+            now,
+        );
+
+        let class = subject.classify(
+            &StreamKey::make_meaningless(1),
+            now + Duration::from_secs(12),
+        );
+
+        assert_eq!(
+            class,
+            UnsolicitedClass::Evicted {
+                age: Duration::from_secs(12)
+            }
+        );
+        assert_eq!(subject.stats().evicted, 1);
+    }
+
+    #[test]
+    fn a_never_known_stream_key_is_unknown() {
+        let mut subject = StreamTombstones::default();
+        let now = Instant::now();
+        subject.record_removal(
+            StreamKey::make_meaningless(1),
+            RemovalReason::ClosedAfterLastData,
+            now,
+        );
+
+        let class = subject.classify(&StreamKey::make_meaningless(2), now);
+
+        assert_eq!(class, UnsolicitedClass::Unknown);
+        assert_eq!(subject.stats().unknown, 1);
+    }
+
+    #[test]
+    fn tombstones_expire_after_the_ttl() {
+        let mut subject = StreamTombstones::default();
+        let now = Instant::now();
+        subject.record_removal(
+            StreamKey::make_meaningless(1),
+            RemovalReason::EvictedByTtlSweep,
+            now,
+        );
+
+        let class = subject.classify(&StreamKey::make_meaningless(1), now + TOMBSTONE_TTL);
+
+        assert_eq!(class, UnsolicitedClass::Unknown);
+        assert_eq!(subject.tombstone_count(), 0);
+    }
+
+    #[test]
+    fn the_newest_tombstone_for_a_reused_key_wins() {
+        let mut subject = StreamTombstones::default();
+        let now = Instant::now();
+        subject.record_removal(
+            StreamKey::make_meaningless(1),
+            RemovalReason::EvictedByTtlSweep,
+            now,
+        );
+        subject.record_removal(
+            StreamKey::make_meaningless(1),
+            RemovalReason::ClosedAfterLastData,
+            now + Duration::from_secs(5),
+        );
+
+        let class = subject.classify(
+            &StreamKey::make_meaningless(1),
+            now + Duration::from_secs(6),
+        );
+
+        assert_eq!(class, UnsolicitedClass::LateAfterClose);
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Transparent proxy mode: an iptables REDIRECT rule steers outbound
+//! traffic to a dedicated listener, and the true destination is recovered
+//! from the kernel NAT table via SO_ORIGINAL_DST. The browser needs no
+//! configuration at all — no PAC file, no SOCKS settings.
+
+use crate::node_configurator::intercept_ports::PortMode;
+use crate::proxy_server::original_dst::OriginalDstReader;
+use crate::sub_lib::cryptde::PublicKey;
+use crate::sub_lib::proxy_server::{ClientRequestPayload, ProxyProtocol};
+use crate::sub_lib::sequence_buffer::SequencedPacket;
+use crate::sub_lib::stream_key::StreamKey;
+use std::io;
+use std::os::unix::io::RawFd;
+
+pub struct TransparentProxyMode {
+    reader: Box<dyn OriginalDstReader>,
+}
+
+impl TransparentProxyMode {
+    pub fn new(reader: Box<dyn OriginalDstReader>) -> TransparentProxyMode {
+        TransparentProxyMode { reader }
+    }
+
+    /// Builds the ClientRequestPayload for data arriving on a redirected
+    /// connection: the original IP becomes the target hostname and the
+    /// original port picks the protocol (443 means TLS, everything else is
+    /// treated as HTTP).
+    pub fn request_payload(
+        &self,
+        fd: RawFd,
+        stream_key: StreamKey,
+        data: Vec<u8>,
+        sequence_number: u64,
+        originator_public_key: PublicKey,
+    ) -> io::Result<ClientRequestPayload> {
+        let addr = self.reader.original_dst(fd)?;
+        let protocol = if addr.port() == 443 {
+            ProxyProtocol::TLS
+        } else {
+            ProxyProtocol::HTTP
+        };
+        Ok(ClientRequestPayload {
+            stream_key,
+            sequenced_packet: SequencedPacket::new(data, sequence_number, false),
+            target_hostname: Some(addr.ip().to_string()),
+            target_port: addr.port(),
+            protocol,
+            originator_public_key,
+        })
+    }
+
+    /// Like `request_payload`, but for an extra intercept port whose
+    /// handling mode was configured explicitly rather than inferred from
+    /// the port number. Raw-mode streams carry ProxyProtocol::Raw and are
+    /// relayed byte-for-byte with no sniffing at either end.
+    pub fn request_payload_for_mode(
+        &self,
+        fd: RawFd,
+        mode: PortMode,
+        stream_key: StreamKey,
+        data: Vec<u8>,
+        sequence_number: u64,
+        originator_public_key: PublicKey,
+    ) -> io::Result<ClientRequestPayload> {
+        let addr = self.reader.original_dst(fd)?;
+        let protocol = match mode {
+            PortMode::Http => ProxyProtocol::HTTP,
+            PortMode::Tls => ProxyProtocol::TLS,
+            PortMode::Raw => ProxyProtocol::Raw,
+        };
+        Ok(ClientRequestPayload {
+            stream_key,
+            sequenced_packet: SequencedPacket::new(data, sequence_number, false),
+            target_hostname: Some(addr.ip().to_string()),
+            target_port: addr.port(),
+            protocol,
+            originator_public_key,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+
+    struct OriginalDstReaderMock {
+        result: io::Result<SocketAddr>,
+    }
+
+    impl OriginalDstReader for OriginalDstReaderMock {
+        fn original_dst(&self, _fd: RawFd) -> io::Result<SocketAddr> {
+            match &self.result {
+                Ok(addr) => Ok(*addr),
+                Err(e) => Err(io::Error::new(e.kind(), "syscall failed")),
+            }
+        }
+    }
+
+    fn make_subject(result: io::Result<SocketAddr>) -> TransparentProxyMode {
+        TransparentProxyMode::new(Box::new(OriginalDstReaderMock { result }))
+    }
+
+    #[test]
+    fn redirected_https_connection_becomes_a_tls_payload() {
+        let subject = make_subject(Ok(SocketAddr::from_str("93.184.216.34:443").unwrap()));
+
+        let payload = subject
+            .request_payload(
+                7,
+                StreamKey::make_meaningless(1),
+                b"\x16\x03\x01".to_vec(),
+                0,
+                PublicKey::new(b"originator"),
+            )
+            .unwrap();
+
+        assert_eq!(payload.target_hostname, Some("93.184.216.34".to_string()));
+        assert_eq!(payload.target_port, 443);
+        assert_eq!(payload.protocol, ProxyProtocol::TLS);
+    }
+
+    #[test]
+    fn redirected_http_connection_becomes_an_http_payload() {
+        let subject = make_subject(Ok(SocketAddr::from_str("93.184.216.34:80").unwrap()));
+
+        let payload = subject
+            .request_payload(
+                7,
+                StreamKey::make_meaningless(1),
+                b"GET / HTTP/1.1\r\n".to_vec(),
+                0,
+                PublicKey::new(b"originator"),
+            )
+            .unwrap();
+
+        assert_eq!(payload.target_port, 80);
+        assert_eq!(payload.protocol, ProxyProtocol::HTTP);
+    }
+
+    #[test]
+    fn sockopt_failure_is_surfaced_instead_of_guessing_a_target() {
+        let subject = make_subject(Err(io::Error::new(io::ErrorKind::NotFound, "no NAT entry")));
+
+        let result = subject.request_payload(
+            7,
+            StreamKey::make_meaningless(1),
+            vec![],
+            0,
+            PublicKey::new(b"originator"),
+        );
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn a_configured_raw_port_produces_a_raw_payload() {
+        let subject = make_subject(Ok(SocketAddr::from_str("93.184.216.34:993").unwrap()));
+
+        let payload = subject
+            .request_payload_for_mode(
+                7,
+                PortMode::Raw,
+                StreamKey::make_meaningless(1),
+                vec![0x01, 0x02, 0x03],
+                0,
+                PublicKey::new(b"originator"),
+            )
+            .unwrap();
+
+        assert_eq!(payload.protocol, ProxyProtocol::Raw);
+        assert_eq!(payload.target_hostname, Some("93.184.216.34".to_string()));
+        assert_eq!(payload.target_port, 993);
+    }
+
+    #[test]
+    fn raw_mode_relays_bytes_opaquely_zero_hop() {
+        use std::io::{Read, Write};
+        use std::net::{TcpListener, TcpStream};
+
+        // Echo server standing in for the origin service.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let echo_addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer = [0u8; 1024];
+            let count = stream.read(&mut buffer).unwrap();
+            stream.write_all(&buffer[..count]).unwrap();
+        });
+        let subject = make_subject(Ok(echo_addr));
+        // Bytes that would confuse any protocol sniffer.
+        let opaque = vec![0x00, 0xFF, 0x16, 0x03, b'G', b'E', b'T', 0x00];
+
+        let payload = subject
+            .request_payload_for_mode(
+                7,
+                PortMode::Raw,
+                StreamKey::make_meaningless(1),
+                opaque.clone(),
+                0,
+                PublicKey::new(b"originator"),
+            )
+            .unwrap();
+        // Zero-hop: act as the exit ourselves, connecting to the payload's
+        // target and relaying the payload bytes untouched.
+        let target = SocketAddr::from_str(&format!(
+            "{}:{}",
+            payload.target_hostname.as_ref().unwrap(),
+            payload.target_port
+        ))
+        .unwrap();
+        let mut exit_stream = TcpStream::connect(target).unwrap();
+        exit_stream
+            .write_all(&payload.sequenced_packet.data)
+            .unwrap();
+        let mut reply = vec![0u8; opaque.len()];
+        exit_stream.read_exact(&mut reply).unwrap();
+
+        assert_eq!(payload.protocol, ProxyProtocol::Raw);
+        assert_eq!(reply, opaque);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn real_reader_reports_an_error_for_a_connection_that_was_never_redirected() {
+        use crate::proxy_server::original_dst::OriginalDstReaderReal;
+        use std::net::{TcpListener, TcpStream};
+        use std::os::unix::io::AsRawFd;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+
+        // No iptables REDIRECT happened, so there is no NAT entry to read.
+        let result = OriginalDstReaderReal.original_dst(accepted.as_raw_fd());
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Originator-side UDP interception. Datagrams arriving on the UDP
+//! intercept socket are wrapped into Datagram-protocol client requests:
+//! one datagram per sequenced packet, a stream key per local client
+//! socket, sequence numbers assigned for the wire but carrying no
+//! ordering promise. There is no last_data in datagram land — streams
+//! end when they go idle.
+
+use crate::sub_lib::cryptde::PublicKey;
+use crate::sub_lib::proxy_server::{ClientRequestPayload, ProxyProtocol};
+use crate::sub_lib::sequence_buffer::SequencedPacket;
+use crate::sub_lib::stream_key::StreamKey;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// A datagram stream with no traffic for this long is forgotten; replies
+/// arriving later are dropped.
+pub const DATAGRAM_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+struct DatagramStream {
+    stream_key: StreamKey,
+    next_sequence_number: u64,
+    last_activity: Instant,
+}
+
+/// Tracks which local client socket maps to which stream key, assigns
+/// sequence numbers, and expires idle streams.
+pub struct DatagramStreamRegistry {
+    salt: Vec<u8>,
+    streams: HashMap<SocketAddr, DatagramStream>,
+    idle_timeout: Duration,
+}
+
+impl DatagramStreamRegistry {
+    pub fn new(salt: &[u8]) -> DatagramStreamRegistry {
+        DatagramStreamRegistry {
+            salt: salt.to_vec(),
+            streams: HashMap::new(),
+            idle_timeout: DATAGRAM_IDLE_TIMEOUT,
+        }
+    }
+
+    #[cfg(test)]
+    fn with_idle_timeout(mut self, idle_timeout: Duration) -> DatagramStreamRegistry {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Wraps one datagram from `client_addr` into a request payload,
+    /// creating the stream on first sight and bumping its activity clock.
+    pub fn wrap_datagram(
+        &mut self,
+        client_addr: SocketAddr,
+        target_hostname: String,
+        target_port: u16,
+        data: Vec<u8>,
+        originator_public_key: PublicKey,
+        now: Instant,
+    ) -> ClientRequestPayload {
+        let salt = &self.salt;
+        let stream = self
+            .streams
+            .entry(client_addr)
+            .or_insert_with(|| DatagramStream {
+                stream_key: StreamKey::new(salt, client_addr),
+                next_sequence_number: 0,
+                last_activity: now,
+            });
+        let sequence_number = stream.next_sequence_number;
+        stream.next_sequence_number += 1;
+        stream.last_activity = now;
+        ClientRequestPayload {
+            stream_key: stream.stream_key,
+            sequenced_packet: SequencedPacket::new(data, sequence_number, false),
+            target_hostname: Some(target_hostname),
+            target_port,
+            protocol: ProxyProtocol::Datagram,
+            originator_public_key,
+        }
+    }
+
+    /// The client address for a reply's stream key, touching the activity
+    /// clock; None when the stream has expired or never existed.
+    pub fn client_for_reply(&mut self, stream_key: &StreamKey, now: Instant) -> Option<SocketAddr> {
+        let (addr, stream) = self
+            .streams
+            .iter_mut()
+            .find(|(_, stream)| stream.stream_key == *stream_key)?;
+        stream.last_activity = now;
+        Some(*addr)
+    }
+
+    /// Removes and returns the stream keys that have gone idle.
+    pub fn expire_idle(&mut self, now: Instant) -> Vec<StreamKey> {
+        let idle_timeout = self.idle_timeout;
+        let expired_addrs: Vec<SocketAddr> = self
+            .streams
+            .iter()
+            .filter(|(_, stream)| now.duration_since(stream.last_activity) >= idle_timeout)
+            .map(|(addr, _)| *addr)
+            .collect();
+        expired_addrs
+            .into_iter()
+            .map(|addr| {
+                self.streams
+                    .remove(&addr)
+                    .expect("just enumerated")
+                    .stream_key
+            })
+            .collect()
+    }
+
+    pub fn stream_count(&self) -> usize {
+        self.streams.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn client(port: u16) -> SocketAddr {
+        SocketAddr::from_str(&format!("127.0.0.1:{}", port)).unwrap()
+    }
+
+    fn subject() -> DatagramStreamRegistry {
+        DatagramStreamRegistry::new(b"salt")
+    }
+
+    #[test]
+    fn datagrams_from_one_client_share_a_stream_and_count_up() {
+        let mut subject = subject();
+        let now = Instant::now();
+
+        let first = subject.wrap_datagram(
+            client(5353),
+            "8.8.8.8".to_string(),
+            53,
+            b"query one".to_vec(),
+            PublicKey::new(b"originator"),
+            now,
+        );
+        let second = subject.wrap_datagram(
+            client(5353),
+            "8.8.8.8".to_string(),
+            53,
+            b"query two".to_vec(),
+            PublicKey::new(b"originator"),
+            now,
+        );
+
+        assert_eq!(first.stream_key, second.stream_key);
+        assert_eq!(first.protocol, ProxyProtocol::Datagram);
+        assert_eq!(first.sequenced_packet.sequence_number, 0);
+        assert_eq!(second.sequenced_packet.sequence_number, 1);
+        assert!(!first.sequenced_packet.last_data);
+    }
+
+    #[test]
+    fn different_clients_get_different_streams() {
+        let mut subject = subject();
+        let now = Instant::now();
+
+        let first = subject.wrap_datagram(
+            client(5353),
+            "8.8.8.8".to_string(),
+            53,
+            vec![],
+            PublicKey::new(b"originator"),
+            now,
+        );
+        let second = subject.wrap_datagram(
+            client(5354),
+            "8.8.8.8".to_string(),
+            53,
+            vec![],
+            PublicKey::new(b"originator"),
+            now,
+        );
+
+        assert_ne!(first.stream_key, second.stream_key);
+    }
+
+    #[test]
+    fn replies_find_their_way_back_to_the_client() {
+        let mut subject = subject();
+        let now = Instant::now();
+        let payload = subject.wrap_datagram(
+            client(5353),
+            "8.8.8.8".to_string(),
+            53,
+            vec![],
+            PublicKey::new(b"originator"),
+            now,
+        );
+
+        let addr = subject.client_for_reply(&payload.stream_key, now);
+
+        assert_eq!(addr, Some(client(5353)));
+        assert_eq!(subject.client_for_reply(&StreamKey::make_meaningless(9), now), None);
+    }
+
+    #[test]
+    fn idle_streams_expire_and_active_ones_survive() {
+        let mut subject = subject().with_idle_timeout(Duration::from_secs(10));
+        let start = Instant::now();
+        let idle = subject.wrap_datagram(
+            client(1000),
+            "8.8.8.8".to_string(),
+            53,
+            vec![],
+            PublicKey::new(b"originator"),
+            start,
+        );
+        subject.wrap_datagram(
+            client(2000),
+            "8.8.8.8".to_string(),
+            53,
+            vec![],
+            PublicKey::new(b"originator"),
+            start,
+        );
+        // The second stream stays busy.
+        subject.wrap_datagram(
+            client(2000),
+            "8.8.8.8".to_string(),
+            53,
+            vec![],
+            PublicKey::new(b"originator"),
+            start + Duration::from_secs(8),
+        );
+
+        let expired = subject.expire_idle(start + Duration::from_secs(12));
+
+        assert_eq!(expired, vec![idle.stream_key]);
+        assert_eq!(subject.stream_count(), 1);
+    }
+}
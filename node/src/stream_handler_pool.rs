@@ -0,0 +1,180 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Pluggable I/O backend for the dispatcher's stream threads. The default
+//! backend is the portable blocking read/write loop the node has always
+//! used. On Linux, building with `--features io-uring` swaps in an
+//! io_uring-backed implementation (via `rio`): reads and writes become
+//! submission-queue entries and the kernel completes them without a
+//! syscall per operation, which is where the throughput goes at high
+//! stream counts. The backend choice is made once at startup; stream
+//! threads only ever see the `IoBackend` trait.
+
+use crate::proxy_client::buffer_pool::{BufferPool, PooledBuffer};
+use std::io;
+use std::net::TcpStream;
+
+/// The operations a stream thread performs through its backend. One
+/// backend instance serves the whole pool and must be shareable across
+/// its threads.
+pub trait IoBackend: Send + Sync {
+    fn backend_name(&self) -> &'static str;
+    fn read(&self, stream: &TcpStream, buffer: &mut [u8]) -> io::Result<usize>;
+    fn write(&self, stream: &TcpStream, data: &[u8]) -> io::Result<usize>;
+}
+
+/// Picks the io_uring backend when it is compiled in and the kernel
+/// accepts the ring; the blocking backend otherwise.
+pub fn default_backend() -> Box<dyn IoBackend> {
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    {
+        match uring::UringBackend::new() {
+            Ok(backend) => return Box::new(backend),
+            Err(error) => {
+                crate::sub_lib::logger::Logger::new("Dispatcher").warning(format!(
+                    "io_uring unavailable ({}); using the blocking backend",
+                    error
+                ));
+            }
+        }
+    }
+    Box::new(BlockingBackend {})
+}
+
+/// One server-response read into a pooled buffer instead of a fresh Vec.
+/// Returns the buffer and how many bytes landed in it; the caller copies
+/// the prefix it needs (`take_prefix`) and the allocation recycles on
+/// drop.
+pub fn read_pooled(
+    backend: &dyn IoBackend,
+    stream: &TcpStream,
+    pool: &BufferPool,
+    read_size: usize,
+) -> io::Result<(PooledBuffer, usize)> {
+    let mut buffer = pool.acquire(read_size);
+    let count = backend.read(stream, &mut buffer)?;
+    Ok((buffer, count))
+}
+
+/// The portable backend: one blocking syscall per operation, exactly the
+/// behavior the pool had before backends existed.
+pub struct BlockingBackend {}
+
+impl IoBackend for BlockingBackend {
+    fn backend_name(&self) -> &'static str {
+        "blocking"
+    }
+
+    fn read(&self, stream: &TcpStream, mut buffer: &mut [u8]) -> io::Result<usize> {
+        use std::io::Read;
+        (&mut (&*stream)).read(&mut buffer)
+    }
+
+    fn write(&self, stream: &TcpStream, data: &[u8]) -> io::Result<usize> {
+        use std::io::Write;
+        (&mut (&*stream)).write(data)
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring {
+    use super::IoBackend;
+    use std::io;
+    use std::net::TcpStream;
+
+    /// One ring for the whole pool. `rio` serializes submissions
+    /// internally and parks the calling thread on the completion, so each
+    /// stream thread's view stays synchronous while the kernel batches
+    /// the work underneath.
+    pub struct UringBackend {
+        ring: rio::Rio,
+    }
+
+    impl UringBackend {
+        pub fn new() -> io::Result<UringBackend> {
+            Ok(UringBackend { ring: rio::new()? })
+        }
+    }
+
+    impl IoBackend for UringBackend {
+        fn backend_name(&self) -> &'static str {
+            "io_uring"
+        }
+
+        fn read(&self, stream: &TcpStream, buffer: &mut [u8]) -> io::Result<usize> {
+            self.ring.recv(stream, &buffer).wait()
+        }
+
+        fn write(&self, stream: &TcpStream, data: &[u8]) -> io::Result<usize> {
+            self.ring.send(stream, &data).wait()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn round_trip_through(backend: &dyn IoBackend) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peer_thread = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut buffer = [0u8; 16];
+            let count = socket.read(&mut buffer).unwrap();
+            socket.write_all(&buffer[..count]).unwrap();
+        });
+        let stream = TcpStream::connect(addr).unwrap();
+
+        let written = backend.write(&stream, b"ping").unwrap();
+        let mut buffer = [0u8; 16];
+        let read = backend.read(&stream, &mut buffer).unwrap();
+
+        assert_eq!(written, 4);
+        assert_eq!(&buffer[..read], b"ping");
+        peer_thread.join().unwrap();
+    }
+
+    #[test]
+    fn the_blocking_backend_round_trips() {
+        round_trip_through(&BlockingBackend {});
+    }
+
+    #[test]
+    fn the_default_backend_round_trips_whichever_it_is() {
+        let backend = default_backend();
+
+        round_trip_through(backend.as_ref());
+    }
+
+    #[cfg(not(feature = "io-uring"))]
+    #[test]
+    fn without_the_feature_the_default_backend_is_blocking() {
+        assert_eq!(default_backend().backend_name(), "blocking");
+    }
+
+    #[test]
+    fn read_pooled_reuses_its_buffer_across_reads() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peer_thread = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            socket.write_all(b"first response").unwrap();
+            socket.write_all(b"second").unwrap();
+        });
+        let stream = TcpStream::connect(addr).unwrap();
+        peer_thread.join().unwrap();
+        let backend = BlockingBackend {};
+        let pool = BufferPool::new(4);
+
+        let (buffer, count) = read_pooled(&backend, &stream, &pool, 16 * 1024).unwrap();
+        let first = buffer.take_prefix(count);
+        drop(buffer);
+        let (_buffer, _count) = read_pooled(&backend, &stream, &pool, 16 * 1024).unwrap();
+
+        assert_eq!(&first[..14], b"first response");
+        assert_eq!(pool.fresh_allocations(), 1);
+    }
+}
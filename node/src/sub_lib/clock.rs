@@ -0,0 +1,211 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Wall-clock jump detection. Accountant maturity math, gossip last-seen
+//! ages, and reaping all assume wall time moves like monotonic time; a
+//! laptop waking from sleep or an NTP step breaks that and causes spurious
+//! delinquency bans and reaped records. This service pairs each wall reading
+//! with a monotonic one, flags jumps past a threshold, and suppresses
+//! age-based decisions for one evaluation cycle afterward.
+
+use crate::sub_lib::logger::Logger;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Wall-vs-monotonic drift beyond this is treated as a clock jump.
+pub const DEFAULT_JUMP_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// Injectable pair of clock readings.
+pub trait TimeSource: Send {
+    fn wall(&self) -> SystemTime;
+    fn monotonic(&self) -> Instant;
+}
+
+pub struct TimeSourceReal;
+
+impl TimeSource for TimeSourceReal {
+    fn wall(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn monotonic(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockStatus {
+    Steady,
+    /// Wall time moved this much further (positive) or less far (negative)
+    /// than monotonic time since the last check.
+    Jumped { drift_ms: i64 },
+}
+
+pub struct JumpDetectingClock {
+    source: Box<dyn TimeSource>,
+    threshold: Duration,
+    last_wall: SystemTime,
+    last_monotonic: Instant,
+    suppressed_cycles: u32,
+    logger: Logger,
+}
+
+impl JumpDetectingClock {
+    pub fn new(source: Box<dyn TimeSource>, threshold: Duration) -> JumpDetectingClock {
+        let last_wall = source.wall();
+        let last_monotonic = source.monotonic();
+        JumpDetectingClock {
+            source,
+            threshold,
+            last_wall,
+            last_monotonic,
+            suppressed_cycles: 0,
+            logger: Logger::new("JumpDetectingClock"),
+        }
+    }
+
+    pub fn now(&self) -> SystemTime {
+        self.source.wall()
+    }
+
+    /// Called at the top of each evaluation cycle (accountant scan,
+    /// neighborhood reap). Detects jumps and arms the suppression.
+    pub fn check(&mut self) -> ClockStatus {
+        let wall = self.source.wall();
+        let monotonic = self.source.monotonic();
+        let wall_delta_ms = match wall.duration_since(self.last_wall) {
+            Ok(forward) => forward.as_millis() as i64,
+            Err(e) => -(e.duration().as_millis() as i64),
+        };
+        let monotonic_delta_ms = monotonic.duration_since(self.last_monotonic).as_millis() as i64;
+        self.last_wall = wall;
+        self.last_monotonic = monotonic;
+        let drift_ms = wall_delta_ms - monotonic_delta_ms;
+        if drift_ms.unsigned_abs() as u128 > self.threshold.as_millis() {
+            self.logger.warning(format!(
+                "System clock jumped {} ms relative to monotonic time; \
+                 suppressing age-based decisions for one cycle",
+                drift_ms
+            ));
+            self.suppressed_cycles = 1;
+            ClockStatus::Jumped { drift_ms }
+        } else {
+            ClockStatus::Steady
+        }
+    }
+
+    /// True when age-based decisions (delinquency, reaping) should be
+    /// skipped this cycle; consumes one suppression cycle.
+    pub fn age_decisions_suppressed(&mut self) -> bool {
+        if self.suppressed_cycles > 0 {
+            self.suppressed_cycles -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct TimeSourceMock {
+        wall_offset_ms: Rc<Cell<i64>>,
+        monotonic_offset_ms: Rc<Cell<u64>>,
+        wall_base: SystemTime,
+        monotonic_base: Instant,
+    }
+
+    // The mock is only used single-threaded inside tests.
+    unsafe impl Send for TimeSourceMock {}
+
+    impl TimeSource for TimeSourceMock {
+        fn wall(&self) -> SystemTime {
+            let offset = self.wall_offset_ms.get();
+            if offset >= 0 {
+                self.wall_base + Duration::from_millis(offset as u64)
+            } else {
+                self.wall_base - Duration::from_millis((-offset) as u64)
+            }
+        }
+
+        fn monotonic(&self) -> Instant {
+            self.monotonic_base + Duration::from_millis(self.monotonic_offset_ms.get())
+        }
+    }
+
+    fn make_subject() -> (JumpDetectingClock, Rc<Cell<i64>>, Rc<Cell<u64>>) {
+        let wall = Rc::new(Cell::new(0i64));
+        let monotonic = Rc::new(Cell::new(0u64));
+        let source = TimeSourceMock {
+            wall_offset_ms: wall.clone(),
+            monotonic_offset_ms: monotonic.clone(),
+            wall_base: SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000),
+            monotonic_base: Instant::now(),
+        };
+        let subject = JumpDetectingClock::new(Box::new(source), Duration::from_secs(30));
+        (subject, wall, monotonic)
+    }
+
+    #[test]
+    fn synchronized_clocks_read_steady() {
+        let (mut subject, wall, monotonic) = make_subject();
+        wall.set(10_000);
+        monotonic.set(10_000);
+
+        assert_eq!(subject.check(), ClockStatus::Steady);
+        assert!(!subject.age_decisions_suppressed());
+    }
+
+    #[test]
+    fn forward_jump_is_detected_and_suppresses_one_cycle() {
+        let (mut subject, wall, monotonic) = make_subject();
+        // Wall leaps two hours ahead while only 10 s of monotonic time pass.
+        wall.set(7_200_000);
+        monotonic.set(10_000);
+
+        let status = subject.check();
+
+        assert_eq!(
+            status,
+            ClockStatus::Jumped {
+                drift_ms: 7_190_000
+            }
+        );
+        assert!(subject.age_decisions_suppressed());
+        assert!(!subject.age_decisions_suppressed());
+    }
+
+    #[test]
+    fn backward_jump_is_detected() {
+        let (mut subject, wall, monotonic) = make_subject();
+        wall.set(-600_000);
+        monotonic.set(5_000);
+
+        let status = subject.check();
+
+        assert_eq!(
+            status,
+            ClockStatus::Jumped {
+                drift_ms: -605_000
+            }
+        );
+        assert!(subject.age_decisions_suppressed());
+    }
+
+    #[test]
+    fn steady_cycle_after_a_jump_runs_normally() {
+        let (mut subject, wall, monotonic) = make_subject();
+        wall.set(7_200_000);
+        monotonic.set(10_000);
+        subject.check();
+        assert!(subject.age_decisions_suppressed());
+
+        wall.set(7_210_000);
+        monotonic.set(20_000);
+
+        assert_eq!(subject.check(), ClockStatus::Steady);
+        assert!(!subject.age_decisions_suppressed());
+    }
+}
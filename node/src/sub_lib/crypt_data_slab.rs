@@ -0,0 +1,210 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Slab recycling for small CryptData buffers. Routing creates and drops
+//! encrypted-hop buffers millions of times, almost all of them 256 bytes
+//! or less; going through the general allocator for each one fragments
+//! the heap and costs malloc/free on the hot path. The slab keeps a pool
+//! of fixed 256-byte blocks behind a mutex: an alloc pops a block (or
+//! mints one when the pool is dry), the guard hands it back on drop.
+//! Oversized requests quietly fall through to a plain heap allocation,
+//! so callers never need to care which they got.
+
+use crate::sub_lib::cryptde::CryptData;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, OnceLock};
+
+/// Block size; covers an encrypted LiveHop plus cipher overhead.
+pub const SLAB_BLOCK_BYTES: usize = 256;
+/// Blocks retained when handles come back; beyond this they are freed so
+/// a burst does not pin its high-water mark forever.
+pub const MAX_RETAINED_BLOCKS: usize = 4096;
+
+type Block = Box<[u8; SLAB_BLOCK_BYTES]>;
+
+pub struct CryptDataSlab {
+    free: Mutex<Vec<Block>>,
+}
+
+impl CryptDataSlab {
+    pub fn new() -> CryptDataSlab {
+        CryptDataSlab {
+            free: Mutex::new(vec![]),
+        }
+    }
+
+    /// The process-wide slab `CryptData::alloc_from_slab` draws from.
+    pub fn global() -> &'static CryptDataSlab {
+        static GLOBAL: OnceLock<CryptDataSlab> = OnceLock::new();
+        GLOBAL.get_or_init(CryptDataSlab::new)
+    }
+
+    /// A zeroed, writable buffer of `size` bytes: slab-backed when it
+    /// fits a block, heap-backed otherwise.
+    pub fn alloc(&self, size: usize) -> CryptDataSlabHandle<'_> {
+        let storage = if size <= SLAB_BLOCK_BYTES {
+            let recycled = self.free.lock().expect("slab poisoned").pop();
+            let mut block = recycled.unwrap_or_else(|| Box::new([0u8; SLAB_BLOCK_BYTES]));
+            block[..size].fill(0);
+            SlabStorage::Block(block)
+        } else {
+            SlabStorage::Heap(vec![0u8; size])
+        };
+        CryptDataSlabHandle {
+            slab: self,
+            storage: Some(storage),
+            len: size,
+        }
+    }
+
+    pub fn retained_count(&self) -> usize {
+        self.free.lock().expect("slab poisoned").len()
+    }
+
+    fn recycle(&self, block: Block) {
+        let mut free = self.free.lock().expect("slab poisoned");
+        if free.len() < MAX_RETAINED_BLOCKS {
+            free.push(block);
+        }
+    }
+}
+
+impl Default for CryptDataSlab {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+enum SlabStorage {
+    Block(Block),
+    Heap(Vec<u8>),
+}
+
+/// Guard over a slab (or fallback heap) buffer; derefs to exactly the
+/// requested length and returns its block to the slab on drop.
+pub struct CryptDataSlabHandle<'a> {
+    slab: &'a CryptDataSlab,
+    storage: Option<SlabStorage>,
+    len: usize,
+}
+
+impl CryptDataSlabHandle<'_> {
+    pub fn is_slab_backed(&self) -> bool {
+        matches!(self.storage, Some(SlabStorage::Block(_)))
+    }
+
+    /// Copies the buffer into an owned CryptData; the block still goes
+    /// back to the slab when the handle drops.
+    pub fn to_crypt_data(&self) -> CryptData {
+        CryptData::new(&self[..])
+    }
+}
+
+impl Deref for CryptDataSlabHandle<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self.storage.as_ref().expect("storage taken") {
+            SlabStorage::Block(block) => &block[..self.len],
+            SlabStorage::Heap(data) => &data[..],
+        }
+    }
+}
+
+impl DerefMut for CryptDataSlabHandle<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        let len = self.len;
+        match self.storage.as_mut().expect("storage taken") {
+            SlabStorage::Block(block) => &mut block[..len],
+            SlabStorage::Heap(data) => &mut data[..],
+        }
+    }
+}
+
+impl Drop for CryptDataSlabHandle<'_> {
+    fn drop(&mut self) {
+        if let Some(SlabStorage::Block(block)) = self.storage.take() {
+            self.slab.recycle(block);
+        }
+    }
+}
+
+impl CryptData {
+    /// Allocates a writable buffer from the process-wide slab; the usual
+    /// pattern is alloc, encrypt into it, `to_crypt_data`.
+    pub fn alloc_from_slab(size: usize) -> CryptDataSlabHandle<'static> {
+        CryptDataSlab::global().alloc(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_block_is_recycled_and_comes_back_zeroed() {
+        let slab = CryptDataSlab::new();
+        {
+            let mut handle = slab.alloc(16);
+            handle[..4].copy_from_slice(b"junk");
+        }
+        assert_eq!(slab.retained_count(), 1);
+
+        let handle = slab.alloc(16);
+
+        assert_eq!(slab.retained_count(), 0);
+        assert!(handle.is_slab_backed());
+        assert_eq!(&handle[..], &[0u8; 16]);
+    }
+
+    #[test]
+    fn the_handle_derefs_to_the_requested_length() {
+        let slab = CryptDataSlab::new();
+
+        let mut handle = slab.alloc(24);
+        handle.copy_from_slice(&[7u8; 24]);
+
+        assert_eq!(handle.len(), 24);
+        assert_eq!(handle.to_crypt_data(), CryptData::new(&[7u8; 24]));
+    }
+
+    #[test]
+    fn oversized_requests_fall_through_to_the_heap() {
+        let slab = CryptDataSlab::new();
+        {
+            let handle = slab.alloc(SLAB_BLOCK_BYTES + 1);
+            assert!(!handle.is_slab_backed());
+            assert_eq!(handle.len(), SLAB_BLOCK_BYTES + 1);
+        }
+
+        assert_eq!(slab.retained_count(), 0);
+    }
+
+    #[test]
+    fn a_request_of_exactly_one_block_is_slab_backed() {
+        let slab = CryptDataSlab::new();
+
+        let handle = slab.alloc(SLAB_BLOCK_BYTES);
+
+        assert!(handle.is_slab_backed());
+    }
+
+    #[test]
+    fn the_global_slab_backs_crypt_data_alloc() {
+        let mut handle = CryptData::alloc_from_slab(32);
+        handle[0] = 0x5A;
+
+        assert!(handle.is_slab_backed());
+        assert_eq!(handle.to_crypt_data().as_slice()[0], 0x5A);
+    }
+
+    #[test]
+    fn the_pool_stops_retaining_at_its_cap() {
+        let slab = CryptDataSlab::new();
+        let handles: Vec<_> = (0..8).map(|_| slab.alloc(64)).collect();
+
+        drop(handles);
+
+        assert!(slab.retained_count() <= MAX_RETAINED_BLOCKS);
+        assert_eq!(slab.retained_count(), 8);
+    }
+}
@@ -0,0 +1,351 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Key and data types for the node's cryptography. The sensitive ones —
+//! PrivateKey, PlainData, CryptData — zero their bytes on drop (via
+//! `zeroize`, whose writes the optimizer may not elide), so decrypted
+//! payloads and key material do not linger in freed heap pages waiting
+//! for a core dump, a swap-out, or an allocator reuse to expose them.
+//! Zeroing is a mitigation, not a guarantee: copies made while the value
+//! was alive (reallocations, clones, serialization buffers) are not
+//! tracked and must be minimized at the call sites that make them.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use zeroize::Zeroize;
+
+/// A public identity key for a node. The raw bytes are opaque to everything
+/// except the CryptDE implementation that minted them.
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Default, PartialOrd, Ord)]
+pub struct PublicKey {
+    data: Vec<u8>,
+}
+
+impl PublicKey {
+    pub fn new(data: &[u8]) -> PublicKey {
+        PublicKey {
+            data: data.to_vec(),
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", base64ish(&self.data))
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", base64ish(&self.data))
+    }
+}
+
+impl From<Vec<u8>> for PublicKey {
+    fn from(data: Vec<u8>) -> Self {
+        PublicKey { data }
+    }
+}
+
+/// A private key; never serialized, never logged in full, zeroed on drop.
+#[derive(Clone, PartialEq, Eq, Zeroize)]
+#[zeroize(drop)]
+pub struct PrivateKey {
+    data: Vec<u8>,
+}
+
+impl PrivateKey {
+    pub fn new(data: &[u8]) -> PrivateKey {
+        PrivateKey {
+            data: data.to_vec(),
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+}
+
+impl fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PrivateKey(<{} bytes>)", self.data.len())
+    }
+}
+
+/// Ciphertext or signature bytes produced by a CryptDE; zeroed on drop.
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Default, Zeroize)]
+#[zeroize(drop)]
+pub struct CryptData {
+    data: Vec<u8>,
+}
+
+impl CryptData {
+    pub fn new(data: &[u8]) -> CryptData {
+        CryptData {
+            data: data.to_vec(),
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Constant-time equality, for comparing secret-bearing values like
+    /// authentication tags and signatures. The derived `==` short-circuits
+    /// at the first differing byte, which leaks how much of a forged value
+    /// matched; this runs in time dependent only on the lengths, which are
+    /// public.
+    pub fn ct_eq(&self, other: &CryptData) -> bool {
+        use subtle::ConstantTimeEq;
+        self.data.ct_eq(&other.data).into()
+    }
+}
+
+impl fmt::Debug for CryptData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CryptData({})", base64ish(&self.data))
+    }
+}
+
+impl From<Vec<u8>> for CryptData {
+    fn from(data: Vec<u8>) -> Self {
+        CryptData { data }
+    }
+}
+
+/// Plaintext bytes on their way into or out of a CryptDE; zeroed on drop.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Default, Zeroize)]
+#[zeroize(drop)]
+pub struct PlainData {
+    data: Vec<u8>,
+}
+
+impl PlainData {
+    pub fn new(data: &[u8]) -> PlainData {
+        PlainData {
+            data: data.to_vec(),
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl fmt::Debug for PlainData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PlainData({})", base64ish(&self.data))
+    }
+}
+
+impl From<Vec<u8>> for PlainData {
+    fn from(data: Vec<u8>) -> Self {
+        PlainData { data }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CryptdecError {
+    EmptyKey,
+    EmptyData,
+    InvalidKey(String),
+    InvalidSignature,
+    OtherError(String),
+}
+
+/// The node's cryptographic engine: asymmetric encryption plus signing,
+/// abstracted so tests can substitute a null implementation.
+pub trait CryptDE: Send + Sync {
+    fn encode(&self, public_key: &PublicKey, data: &PlainData) -> Result<CryptData, CryptdecError>;
+    fn decode(&self, data: &CryptData) -> Result<PlainData, CryptdecError>;
+    fn sign(&self, data: &PlainData) -> Result<CryptData, CryptdecError>;
+    fn verify_signature(
+        &self,
+        data: &PlainData,
+        signature: &CryptData,
+        public_key: &PublicKey,
+    ) -> bool;
+    fn public_key(&self) -> &PublicKey;
+    fn dup(&self) -> Box<dyn CryptDE>;
+}
+
+fn base64ish(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        out.push(ALPHABET[(b[0] >> 2) as usize] as char);
+        out.push(ALPHABET[(((b[0] & 0x03) << 4) | (b[1] >> 4)) as usize] as char);
+        if chunk.len() > 1 {
+            out.push(ALPHABET[(((b[1] & 0x0f) << 2) | (b[2] >> 6)) as usize] as char);
+        }
+        if chunk.len() > 2 {
+            out.push(ALPHABET[(b[2] & 0x3f) as usize] as char);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_key_debug_and_display_agree() {
+        let key = PublicKey::new(&[1, 2, 3, 4]);
+
+        assert_eq!(format!("{}", key), format!("{:?}", key));
+    }
+
+    #[test]
+    fn private_key_debug_does_not_reveal_contents() {
+        let key = PrivateKey::new(&[9, 9, 9]);
+
+        let debugged = format!("{:?}", key);
+
+        assert_eq!(debugged, "PrivateKey(<3 bytes>)");
+    }
+
+    #[test]
+    fn plain_data_round_trips_through_from_vec() {
+        let data = PlainData::from(vec![10, 20, 30]);
+
+        assert_eq!(data.as_slice(), &[10, 20, 30]);
+        assert_eq!(data.len(), 3);
+        assert!(!data.is_empty());
+    }
+
+    // Reads an allocation back through a raw pointer captured before its
+    // owner dropped. Strictly speaking a use-after-free, but nothing
+    // allocates between the drop and the read, so the page still holds
+    // whatever the drop left there — zeros, if zeroize did its job.
+    fn residue_after_drop<T>(value: T, ptr: *const u8, len: usize) -> Vec<u8> {
+        drop(value);
+        (0..len)
+            .map(|i| unsafe { std::ptr::read_volatile(ptr.add(i)) })
+            .collect()
+    }
+
+    #[test]
+    fn dropped_crypt_data_leaves_only_zeros_behind() {
+        let sensitive = CryptData::new(&[0xDE; 64]);
+        let ptr = sensitive.as_slice().as_ptr();
+
+        let residue = residue_after_drop(sensitive, ptr, 64);
+
+        assert_eq!(residue, vec![0u8; 64]);
+    }
+
+    #[test]
+    fn dropped_plain_data_leaves_only_zeros_behind() {
+        let sensitive = PlainData::new(&[0xAD; 64]);
+        let ptr = sensitive.as_slice().as_ptr();
+
+        let residue = residue_after_drop(sensitive, ptr, 64);
+
+        assert_eq!(residue, vec![0u8; 64]);
+    }
+
+    #[test]
+    fn dropped_private_key_leaves_only_zeros_behind() {
+        let sensitive = PrivateKey::new(&[0xBE; 64]);
+        let ptr = sensitive.as_slice().as_ptr();
+
+        let residue = residue_after_drop(sensitive, ptr, 64);
+
+        assert_eq!(residue, vec![0u8; 64]);
+    }
+
+    #[test]
+    fn ct_eq_agrees_with_derived_equality() {
+        let reference = CryptData::new(&[1, 2, 3, 4]);
+
+        assert!(reference.ct_eq(&CryptData::new(&[1, 2, 3, 4])));
+        assert!(!reference.ct_eq(&CryptData::new(&[1, 2, 3, 5])));
+        assert!(!reference.ct_eq(&CryptData::new(&[9, 2, 3, 4])));
+        assert!(!reference.ct_eq(&CryptData::new(&[1, 2, 3])));
+        assert!(CryptData::new(&[]).ct_eq(&CryptData::new(&[])));
+    }
+
+    /// Statistical timing check, too noise-sensitive for CI: compares a
+    /// tag differing in its first byte against one differing in its last
+    /// and requires the timings not to diverge the way a short-circuiting
+    /// comparison's would. Run with `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore]
+    fn ct_eq_timing_does_not_depend_on_the_position_of_the_difference() {
+        use std::time::Instant;
+        const TAG_LEN: usize = 4096;
+        const TRIALS: usize = 200;
+        const ROUNDS_PER_TRIAL: usize = 1000;
+        let reference = CryptData::new(&vec![0xAB; TAG_LEN]);
+        let mut early = vec![0xAB; TAG_LEN];
+        early[0] ^= 0xFF;
+        let early = CryptData::new(&early);
+        let mut late = vec![0xAB; TAG_LEN];
+        late[TAG_LEN - 1] ^= 0xFF;
+        let late = CryptData::new(&late);
+        let median_nanos = |candidate: &CryptData| {
+            let mut samples: Vec<u128> = (0..TRIALS)
+                .map(|_| {
+                    let start = Instant::now();
+                    for _ in 0..ROUNDS_PER_TRIAL {
+                        assert!(!std::hint::black_box(reference.ct_eq(candidate)));
+                    }
+                    start.elapsed().as_nanos()
+                })
+                .collect();
+            samples.sort_unstable();
+            samples[TRIALS / 2]
+        };
+
+        let early_median = median_nanos(&early);
+        let late_median = median_nanos(&late);
+
+        // A short-circuiting compare finishes the early case hundreds of
+        // times faster at this length; constant time keeps the medians
+        // within ordinary jitter of each other.
+        let (faster, slower) = if early_median < late_median {
+            (early_median, late_median)
+        } else {
+            (late_median, early_median)
+        };
+        assert!(
+            slower < faster * 2,
+            "medians diverged: early {} ns, late {} ns",
+            early_median,
+            late_median
+        );
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use crate::sub_lib::cryptde::{
+    CryptDE, CryptData, CryptdecError, PlainData, PrivateKey, PublicKey,
+};
+
+/// A deliberately insecure CryptDE for tests and zero-hop operation.
+/// "Encryption" prepends the destination public key; "signing" prepends
+/// the signer's public key and a trivial checksum. Nothing here is secret,
+/// but the algebra (what decodes what, what verifies against what) matches
+/// the real implementation's contract.
+pub struct CryptDENull {
+    public_key: PublicKey,
+    private_key: PrivateKey,
+}
+
+impl CryptDENull {
+    pub fn new() -> CryptDENull {
+        Self::from(&PublicKey::new(b"nullkey"))
+    }
+
+    pub fn from(public_key: &PublicKey) -> CryptDENull {
+        CryptDENull {
+            public_key: public_key.clone(),
+            private_key: Self::private_from_public(public_key),
+        }
+    }
+
+    pub fn private_from_public(public_key: &PublicKey) -> PrivateKey {
+        let inverted: Vec<u8> = public_key.as_slice().iter().map(|b| !b).collect();
+        PrivateKey::new(&inverted)
+    }
+
+    fn checksum(data: &[u8]) -> u8 {
+        data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b))
+    }
+}
+
+impl Default for CryptDENull {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CryptDE for CryptDENull {
+    fn encode(&self, public_key: &PublicKey, data: &PlainData) -> Result<CryptData, CryptdecError> {
+        if public_key.is_empty() {
+            return Err(CryptdecError::EmptyKey);
+        }
+        if data.is_empty() {
+            return Err(CryptdecError::EmptyData);
+        }
+        let mut bytes = Self::private_from_public(public_key).as_slice().to_vec();
+        bytes.extend_from_slice(data.as_slice());
+        Ok(CryptData::from(bytes))
+    }
+
+    fn decode(&self, data: &CryptData) -> Result<PlainData, CryptdecError> {
+        if data.is_empty() {
+            return Err(CryptdecError::EmptyData);
+        }
+        let private = self.private_key.as_slice();
+        if data.len() < private.len() || &data.as_slice()[..private.len()] != private {
+            return Err(CryptdecError::InvalidKey(format!(
+                "Could not decrypt with {:?}",
+                self.public_key
+            )));
+        }
+        Ok(PlainData::new(&data.as_slice()[private.len()..]))
+    }
+
+    fn sign(&self, data: &PlainData) -> Result<CryptData, CryptdecError> {
+        if data.is_empty() {
+            return Err(CryptdecError::EmptyData);
+        }
+        let mut bytes = self.public_key.as_slice().to_vec();
+        bytes.push(Self::checksum(data.as_slice()));
+        Ok(CryptData::from(bytes))
+    }
+
+    fn verify_signature(
+        &self,
+        data: &PlainData,
+        signature: &CryptData,
+        public_key: &PublicKey,
+    ) -> bool {
+        let mut expected = public_key.as_slice().to_vec();
+        expected.push(Self::checksum(data.as_slice()));
+        // ct_eq: signature checks must not leak the matching prefix length.
+        signature.ct_eq(&CryptData::from(expected))
+    }
+
+    fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    fn dup(&self) -> Box<dyn CryptDE> {
+        Box::new(CryptDENull {
+            public_key: self.public_key.clone(),
+            private_key: self.private_key.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let subject = CryptDENull::from(&PublicKey::new(b"key"));
+        let plain = PlainData::new(b"hello");
+
+        let encoded = subject.encode(subject.public_key(), &plain).unwrap();
+        let decoded = subject.decode(&encoded).unwrap();
+
+        assert_eq!(decoded, plain);
+    }
+
+    #[test]
+    fn decode_with_wrong_key_fails() {
+        let alice = CryptDENull::from(&PublicKey::new(b"alice"));
+        let bob = CryptDENull::from(&PublicKey::new(b"bob"));
+        let plain = PlainData::new(b"hello");
+
+        let encoded = alice.encode(alice.public_key(), &plain).unwrap();
+        let result = bob.decode(&encoded);
+
+        assert!(matches!(result, Err(CryptdecError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn signature_verifies_with_signer_key_only() {
+        let alice = CryptDENull::from(&PublicKey::new(b"alice"));
+        let bob = CryptDENull::from(&PublicKey::new(b"bob"));
+        let plain = PlainData::new(b"message");
+
+        let signature = alice.sign(&plain).unwrap();
+
+        assert!(alice.verify_signature(&plain, &signature, alice.public_key()));
+        assert!(!alice.verify_signature(&plain, &signature, bob.public_key()));
+    }
+
+    #[test]
+    fn encode_rejects_empty_inputs() {
+        let subject = CryptDENull::new();
+
+        assert_eq!(
+            subject.encode(&PublicKey::new(b""), &PlainData::new(b"x")),
+            Err(CryptdecError::EmptyKey)
+        );
+        assert_eq!(
+            subject.encode(&PublicKey::new(b"k"), &PlainData::new(b"")),
+            Err(CryptdecError::EmptyData)
+        );
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Encryption+serialization codec helpers. `decodex` handles data from the
+//! wire and therefore trusts nothing: plaintext size is capped after
+//! decryption and the CBOR deserializer reads through a bounded reader, so a
+//! crafted package claiming a multi-gigabyte array fails fast with a typed
+//! error instead of taking the hopper down.
+
+use crate::sub_lib::cryptde::{CryptDE, CryptData, CryptdecError, PlainData, PublicKey};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{self, Read};
+
+/// Ceiling on the plaintext produced by a single package or gossip decode.
+pub const DEFAULT_MAX_PLAINTEXT_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CodexError {
+    EncryptionFailed(CryptdecError),
+    DecryptionFailed(CryptdecError),
+    /// The decrypted plaintext exceeded the configured ceiling. Counts
+    /// toward banning the sending neighbor.
+    PlaintextTooLong { actual: usize, limit: usize },
+    /// CBOR refused the input, ran past the size bound, or the claimed
+    /// structure could not be satisfied by the bytes present.
+    DeserializationFailed(String),
+    SerializationFailed(String),
+}
+
+pub fn encodex<T: Serialize>(
+    cryptde: &dyn CryptDE,
+    public_key: &PublicKey,
+    item: &T,
+) -> Result<CryptData, CodexError> {
+    let serialized =
+        serde_cbor::ser::to_vec(item).map_err(|e| CodexError::SerializationFailed(e.to_string()))?;
+    cryptde
+        .encode(public_key, &PlainData::from(serialized))
+        .map_err(CodexError::EncryptionFailed)
+}
+
+pub fn decodex<T: DeserializeOwned>(
+    cryptde: &dyn CryptDE,
+    data: &CryptData,
+) -> Result<T, CodexError> {
+    decodex_limited(cryptde, data, DEFAULT_MAX_PLAINTEXT_BYTES)
+}
+
+pub fn decodex_limited<T: DeserializeOwned>(
+    cryptde: &dyn CryptDE,
+    data: &CryptData,
+    max_plaintext_bytes: usize,
+) -> Result<T, CodexError> {
+    let plain = cryptde.decode(data).map_err(CodexError::DecryptionFailed)?;
+    if plain.len() > max_plaintext_bytes {
+        return Err(CodexError::PlaintextTooLong {
+            actual: plain.len(),
+            limit: max_plaintext_bytes,
+        });
+    }
+    let bounded = BoundedReader::new(plain.as_slice(), max_plaintext_bytes);
+    serde_cbor::de::from_reader(bounded)
+        .map_err(|e| CodexError::DeserializationFailed(e.to_string()))
+}
+
+/// An io::Read that refuses to produce more than its budget, so a
+/// deserializer cannot be talked into consuming unbounded input.
+struct BoundedReader<'a> {
+    inner: &'a [u8],
+    remaining: usize,
+}
+
+impl<'a> BoundedReader<'a> {
+    fn new(inner: &'a [u8], budget: usize) -> BoundedReader<'a> {
+        BoundedReader {
+            inner,
+            remaining: budget,
+        }
+    }
+}
+
+impl<'a> Read for BoundedReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 && !self.inner.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "read budget exhausted",
+            ));
+        }
+        let allowed = buf.len().min(self.remaining);
+        let count = self.inner.read(&mut buf[..allowed])?;
+        self.remaining -= count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sub_lib::cryptde_null::CryptDENull;
+
+    #[test]
+    fn round_trip_works_within_limits() {
+        let cryptde = CryptDENull::from(&PublicKey::new(b"key"));
+        let item = vec!["alpha".to_string(), "beta".to_string()];
+
+        let encoded = encodex(&cryptde, cryptde.public_key(), &item).unwrap();
+        let decoded: Vec<String> = decodex(&cryptde, &encoded).unwrap();
+
+        assert_eq!(decoded, item);
+    }
+
+    #[test]
+    fn oversized_plaintext_is_rejected_before_deserialization() {
+        let cryptde = CryptDENull::from(&PublicKey::new(b"key"));
+        let item = vec![0u8; 2048];
+        let encoded = encodex(&cryptde, cryptde.public_key(), &item).unwrap();
+
+        let result: Result<Vec<u8>, CodexError> = decodex_limited(&cryptde, &encoded, 1024);
+
+        match result {
+            Err(CodexError::PlaintextTooLong { actual, limit }) => {
+                assert!(actual > limit);
+                assert_eq!(limit, 1024);
+            }
+            other => panic!("expected PlaintextTooLong, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cbor_header_claiming_giant_array_fails_fast() {
+        let cryptde = CryptDENull::from(&PublicKey::new(b"key"));
+        // CBOR array header claiming 2^40 elements, with no elements behind it.
+        let bomb: Vec<u8> = vec![0x9b, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let encoded = cryptde
+            .encode(cryptde.public_key(), &PlainData::from(bomb))
+            .unwrap();
+
+        let result: Result<Vec<u8>, CodexError> = decodex(&cryptde, &encoded);
+
+        assert!(
+            matches!(result, Err(CodexError::DeserializationFailed(_))),
+            "expected DeserializationFailed, got {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn wrong_key_is_reported_as_decryption_failure() {
+        let alice = CryptDENull::from(&PublicKey::new(b"alice"));
+        let bob = CryptDENull::from(&PublicKey::new(b"bob"));
+        let encoded = encodex(&alice, alice.public_key(), &7u64).unwrap();
+
+        let result: Result<u64, CodexError> = decodex(&bob, &encoded);
+
+        assert!(matches!(result, Err(CodexError::DecryptionFailed(_))));
+    }
+}
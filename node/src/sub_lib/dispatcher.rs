@@ -0,0 +1,137 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use crate::sub_lib::cryptde::PublicKey;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// Published by the dispatcher when the TCP connection to a neighbor dies,
+/// so components holding live state that routes through that neighbor can
+/// react immediately instead of waiting for timeouts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NeighborStreamLostMessage {
+    pub peer_key: PublicKey,
+    pub peer_addr: SocketAddr,
+}
+
+/// Reported by the dispatcher for each transmission the hopper handed it,
+/// keyed by the correlation id the hopper attached. Without this the hopper
+/// fire-and-forgets: a write failure in the stream writer silently drops
+/// the package.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransmitResultMsg {
+    pub correlation_id: u64,
+    pub peer_key: PublicKey,
+    pub result: Result<(), String>,
+}
+
+/// Inbound clandestine traffic, attributed to the neighbor's verified
+/// public key. The source address is carried for logging only: behind
+/// CGNAT two neighbors legitimately share an IP, and an IP is spoofable
+/// in a way a handshake-verified key is not, so the hopper, neighborhood,
+/// and malefactor accounting all key off `peer_key`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InboundClandestineData {
+    pub peer_key: PublicKey,
+    pub peer_addr: SocketAddr,
+    pub data: Vec<u8>,
+}
+
+/// The dispatcher's per-connection attribution table. A connection earns
+/// an entry only when its link handshake verifies the peer's key; bytes
+/// arriving on a connection without one cannot be attributed and are
+/// handed back as such.
+#[derive(Default)]
+pub struct ConnectionAttribution {
+    entries: HashMap<u64, (PublicKey, SocketAddr)>,
+}
+
+impl ConnectionAttribution {
+    pub fn new() -> ConnectionAttribution {
+        Self::default()
+    }
+
+    /// Called once the handshake on `connection_id` has verified the peer.
+    pub fn register(&mut self, connection_id: u64, peer_key: PublicKey, peer_addr: SocketAddr) {
+        self.entries.insert(connection_id, (peer_key, peer_addr));
+    }
+
+    pub fn deregister(&mut self, connection_id: u64) {
+        self.entries.remove(&connection_id);
+    }
+
+    /// Attributes inbound bytes to the connection's verified key; None for
+    /// connections that have not completed (or have lost) their handshake.
+    pub fn attribute(&self, connection_id: u64, data: Vec<u8>) -> Option<InboundClandestineData> {
+        self.entries
+            .get(&connection_id)
+            .map(|(peer_key, peer_addr)| InboundClandestineData {
+                peer_key: peer_key.clone(),
+                peer_addr: *peer_addr,
+                data,
+            })
+    }
+
+    /// The verified keys currently connected from `addr`'s IP — usually
+    /// one, more behind CGNAT. For logging and diagnostics.
+    pub fn keys_at_ip(&self, addr: &SocketAddr) -> Vec<PublicKey> {
+        let mut keys: Vec<PublicKey> = self
+            .entries
+            .values()
+            .filter(|(_, peer_addr)| peer_addr.ip() == addr.ip())
+            .map(|(peer_key, _)| peer_key.clone())
+            .collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn two_connections_from_one_ip_are_attributed_separately() {
+        let mut subject = ConnectionAttribution::new();
+        let shared_ip_a = SocketAddr::from_str("198.51.100.9:41000").unwrap();
+        let shared_ip_b = SocketAddr::from_str("198.51.100.9:41001").unwrap();
+        subject.register(1, PublicKey::new(b"neighbor_one"), shared_ip_a);
+        subject.register(2, PublicKey::new(b"neighbor_two"), shared_ip_b);
+
+        let first = subject.attribute(1, b"package_one".to_vec()).unwrap();
+        let second = subject.attribute(2, b"package_two".to_vec()).unwrap();
+
+        assert_eq!(first.peer_key, PublicKey::new(b"neighbor_one"));
+        assert_eq!(second.peer_key, PublicKey::new(b"neighbor_two"));
+        assert_eq!(first.peer_addr.ip(), second.peer_addr.ip());
+        let mut keys = subject.keys_at_ip(&shared_ip_a);
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![
+                PublicKey::new(b"neighbor_one"),
+                PublicKey::new(b"neighbor_two"),
+            ]
+        );
+    }
+
+    #[test]
+    fn an_unverified_connection_cannot_be_attributed() {
+        let subject = ConnectionAttribution::new();
+
+        assert_eq!(subject.attribute(7, b"mystery".to_vec()), None);
+    }
+
+    #[test]
+    fn a_closed_connection_loses_its_attribution() {
+        let mut subject = ConnectionAttribution::new();
+        let addr = SocketAddr::from_str("198.51.100.9:41000").unwrap();
+        subject.register(1, PublicKey::new(b"neighbor"), addr);
+
+        subject.deregister(1);
+
+        assert_eq!(subject.attribute(1, b"late".to_vec()), None);
+        assert!(subject.keys_at_ip(&addr).is_empty());
+    }
+}
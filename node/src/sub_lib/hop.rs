@@ -0,0 +1,19 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use serde::{Deserialize, Serialize};
+
+use crate::sub_lib::cryptde::PublicKey;
+
+/// One decrypted layer of a Route: who the package should be handed to next.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LiveHop {
+    pub public_key: PublicKey,
+}
+
+impl LiveHop {
+    pub fn new(public_key: &PublicKey) -> LiveHop {
+        LiveHop {
+            public_key: public_key.clone(),
+        }
+    }
+}
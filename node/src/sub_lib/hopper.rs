@@ -0,0 +1,318 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use crate::neighborhood::gossip::SignedGossip;
+use crate::sub_lib::cryptde::{CryptDE, CryptData, PlainData, PublicKey};
+use crate::sub_lib::decodex::CodexError;
+use crate::sub_lib::proxy_client::ClientResponsePayload;
+use crate::sub_lib::proxy_server::ClientRequestPayload;
+use crate::sub_lib::route::Route;
+use serde::{Deserialize, Serialize};
+
+/// Everything that can travel inside a CORES package.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageType {
+    ClientRequest(ClientRequestPayload),
+    ClientResponse(ClientResponsePayload),
+    Gossip(SignedGossip),
+    /// Random bytes sent to mask real traffic timing; receivers discard
+    /// them silently.
+    CoverTraffic(Vec<u8>),
+    /// Sent back along the route when the exit throttles a stream, so the
+    /// originating ProxyServer can buffer or answer 429 instead of letting
+    /// the client retry blindly into silently dropped packets.
+    RateLimitSignal {
+        stream_key: crate::sub_lib::stream_key::StreamKey,
+        retry_after_ms: u64,
+    },
+}
+
+impl MessageType {
+    /// Stable name for logging and metrics; one per variant.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MessageType::ClientRequest(_) => "client_request",
+            MessageType::ClientResponse(_) => "client_response",
+            MessageType::Gossip(_) => "gossip",
+            MessageType::CoverTraffic(_) => "cover_traffic",
+            MessageType::RateLimitSignal { .. } => "rate_limit_signal",
+        }
+    }
+
+    /// Dense index for metric arrays; keep in step with VARIANT_COUNT.
+    pub fn index(&self) -> usize {
+        match self {
+            MessageType::ClientRequest(_) => 0,
+            MessageType::ClientResponse(_) => 1,
+            MessageType::Gossip(_) => 2,
+            MessageType::CoverTraffic(_) => 3,
+            MessageType::RateLimitSignal { .. } => 4,
+        }
+    }
+
+    pub const VARIANT_COUNT: usize = 5;
+}
+
+/// Capability string a node advertises in its gossip when its hopper can
+/// strip envelope padding. Only destinations advertising it are sent padded
+/// packages; everyone else gets the bare envelope they have always gotten.
+pub const PADDED_CORES_CAPABILITY: &str = "padded_cores";
+
+pub fn destination_accepts_padding(capabilities: &[String]) -> bool {
+    capabilities
+        .iter()
+        .any(|capability| capability == PADDED_CORES_CAPABILITY)
+}
+
+/// The buckets an envelope plaintext is rounded up to, sorted ascending.
+/// Plaintexts larger than the last bucket travel unpadded rather than
+/// being inflated without bound.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaddingSchedule {
+    buckets: Vec<usize>,
+}
+
+impl PaddingSchedule {
+    pub fn new(mut buckets: Vec<usize>) -> PaddingSchedule {
+        buckets.retain(|bucket| *bucket > 0);
+        buckets.sort_unstable();
+        buckets.dedup();
+        PaddingSchedule { buckets }
+    }
+
+    /// The smallest bucket that can hold `len`, or None if `len` is past the
+    /// end of the schedule.
+    pub fn bucket_for(&self, len: usize) -> Option<usize> {
+        self.buckets.iter().find(|bucket| **bucket >= len).copied()
+    }
+
+    fn next_bucket_above(&self, bucket: usize) -> Option<usize> {
+        self.buckets.iter().find(|b| **b > bucket).copied()
+    }
+}
+
+impl Default for PaddingSchedule {
+    /// Powers of two from 256 bytes to 16 KiB.
+    fn default() -> Self {
+        PaddingSchedule::new(vec![256, 512, 1024, 2048, 4096, 8192, 16384])
+    }
+}
+
+/// Operator-facing padding knobs. Off by default: padding trades bandwidth
+/// for resistance to size fingerprinting, and that trade belongs to the
+/// operator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoresPaddingConfig {
+    pub enabled: bool,
+    pub schedule: PaddingSchedule,
+}
+
+impl Default for CoresPaddingConfig {
+    fn default() -> Self {
+        CoresPaddingConfig {
+            enabled: false,
+            schedule: Default::default(),
+        }
+    }
+}
+
+/// What actually gets end-to-end encrypted into a CORES package: the message
+/// plus filler bytes. The pad length rides inside the ciphertext as the
+/// length of `padding`, so relays see only bucketed sizes and the receiving
+/// end strips the filler for free during deserialization.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaddedEnvelope {
+    pub message: MessageType,
+    pub padding: Vec<u8>,
+}
+
+/// A CORES package as built by the originating component, before the hopper
+/// turns it into a LiveCoresPackage for the first relay.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IncipientCoresPackage {
+    pub route: Route,
+    pub payload: CryptData,
+}
+
+impl IncipientCoresPackage {
+    /// Wraps `message` in a PaddedEnvelope and encrypts it for the payload
+    /// destination. With a schedule, the serialized envelope is rounded up
+    /// to the next bucket; without one (or past the last bucket) the
+    /// envelope carries no filler.
+    pub fn new(
+        cryptde: &dyn CryptDE,
+        route: Route,
+        message: MessageType,
+        payload_destination_key: &PublicKey,
+        padding: Option<&PaddingSchedule>,
+    ) -> Result<IncipientCoresPackage, CodexError> {
+        let plaintext = match padding {
+            Some(schedule) => pad_to_bucket(&message, schedule)?,
+            None => serialize_envelope(&message, 0)?,
+        };
+        let payload = cryptde
+            .encode(payload_destination_key, &PlainData::from(plaintext))
+            .map_err(CodexError::EncryptionFailed)?;
+        Ok(IncipientCoresPackage { route, payload })
+    }
+}
+
+/// A CORES package that has reached the end of its route and been decrypted
+/// for local consumption.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpiredCoresPackage {
+    pub remaining_route: Route,
+    pub payload: MessageType,
+}
+
+fn serialize_envelope(message: &MessageType, pad_len: usize) -> Result<Vec<u8>, CodexError> {
+    let envelope = PaddedEnvelope {
+        message: message.clone(),
+        padding: vec![0u8; pad_len],
+    };
+    serde_cbor::ser::to_vec(&envelope).map_err(|e| CodexError::SerializationFailed(e.to_string()))
+}
+
+/// Serializes `message` inside a PaddedEnvelope whose total length lands
+/// exactly on a schedule bucket. CBOR length headers grow with the padding,
+/// so the pad length is adjusted iteratively; when a bucket cannot be hit
+/// exactly the next one is tried, and past the schedule the envelope is
+/// returned unpadded.
+fn pad_to_bucket(message: &MessageType, schedule: &PaddingSchedule) -> Result<Vec<u8>, CodexError> {
+    let bare = serialize_envelope(message, 0)?;
+    let mut target = match schedule.bucket_for(bare.len()) {
+        Some(target) => target,
+        None => return Ok(bare),
+    };
+    let mut pad_len = target - bare.len();
+    let mut attempts_left = 8;
+    loop {
+        let serialized = serialize_envelope(message, pad_len)?;
+        if serialized.len() == target {
+            return Ok(serialized);
+        }
+        attempts_left -= 1;
+        let overshoot = serialized.len().checked_sub(target);
+        let bump_bucket = match overshoot {
+            // A target one byte inside a header jump is unreachable; the
+            // attempt budget breaks the resulting oscillation.
+            _ if attempts_left == 0 => true,
+            Some(overshoot) => {
+                if pad_len >= overshoot {
+                    pad_len -= overshoot;
+                    false
+                } else {
+                    true
+                }
+            }
+            None => {
+                pad_len += target - serialized.len();
+                false
+            }
+        };
+        if bump_bucket {
+            target = match schedule.next_bucket_above(target) {
+                Some(next) => next,
+                None => return Ok(bare),
+            };
+            pad_len = target - bare.len();
+            attempts_left = 8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sub_lib::cryptde_null::CryptDENull;
+
+    fn make_message(payload_size: usize) -> MessageType {
+        MessageType::CoverTraffic(vec![0xAB; payload_size])
+    }
+
+    #[test]
+    fn padded_plaintext_lands_exactly_on_a_bucket_boundary() {
+        let cryptde = CryptDENull::from(&PublicKey::new(b"dest"));
+        let schedule = PaddingSchedule::default();
+
+        for payload_size in [0, 100, 500, 3000, 9000] {
+            let package = IncipientCoresPackage::new(
+                &cryptde,
+                Route::new(vec![]),
+                make_message(payload_size),
+                cryptde.public_key(),
+                Some(&schedule),
+            )
+            .unwrap();
+
+            let plaintext = cryptde.decode(&package.payload).unwrap();
+            assert_eq!(
+                Some(plaintext.len()),
+                schedule.bucket_for(plaintext.len()),
+                "payload size {} produced off-bucket plaintext of {} bytes",
+                payload_size,
+                plaintext.len()
+            );
+        }
+    }
+
+    #[test]
+    fn message_past_the_last_bucket_travels_unpadded() {
+        let cryptde = CryptDENull::from(&PublicKey::new(b"dest"));
+        let schedule = PaddingSchedule::default();
+        let message = make_message(20_000);
+
+        let padded = IncipientCoresPackage::new(
+            &cryptde,
+            Route::new(vec![]),
+            message.clone(),
+            cryptde.public_key(),
+            Some(&schedule),
+        )
+        .unwrap();
+        let bare = IncipientCoresPackage::new(
+            &cryptde,
+            Route::new(vec![]),
+            message,
+            cryptde.public_key(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(padded.payload.len(), bare.payload.len());
+    }
+
+    #[test]
+    fn bucket_for_is_inclusive_at_boundaries() {
+        let schedule = PaddingSchedule::default();
+
+        assert_eq!(schedule.bucket_for(0), Some(256));
+        assert_eq!(schedule.bucket_for(256), Some(256));
+        assert_eq!(schedule.bucket_for(257), Some(512));
+        assert_eq!(schedule.bucket_for(16384), Some(16384));
+        assert_eq!(schedule.bucket_for(16385), None);
+    }
+
+    #[test]
+    fn padding_schedule_sorts_and_dedups_buckets() {
+        let schedule = PaddingSchedule::new(vec![1024, 0, 256, 1024, 512]);
+
+        assert_eq!(schedule.bucket_for(300), Some(512));
+        assert_eq!(schedule.bucket_for(600), Some(1024));
+        assert_eq!(schedule.bucket_for(2000), None);
+    }
+
+    #[test]
+    fn padding_is_off_by_default() {
+        assert!(!CoresPaddingConfig::default().enabled);
+    }
+
+    #[test]
+    fn capability_gate_requires_the_exact_capability_string() {
+        assert!(destination_accepts_padding(&[
+            "exit".to_string(),
+            PADDED_CORES_CAPABILITY.to_string(),
+        ]));
+        assert!(!destination_accepts_padding(&["exit".to_string()]));
+        assert!(!destination_accepts_padding(&[]));
+    }
+}
@@ -0,0 +1,38 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use log::{debug, error, info, trace, warn};
+
+/// A named logger handle. Thin wrapper over the `log` facade so call sites
+/// carry the owning component's name without repeating it.
+#[derive(Clone, Debug)]
+pub struct Logger {
+    name: String,
+}
+
+impl Logger {
+    pub fn new(name: &str) -> Logger {
+        Logger {
+            name: name.to_string(),
+        }
+    }
+
+    pub fn trace(&self, msg: String) {
+        trace!("{}: {}", self.name, msg);
+    }
+
+    pub fn debug(&self, msg: String) {
+        debug!("{}: {}", self.name, msg);
+    }
+
+    pub fn info(&self, msg: String) {
+        info!("{}: {}", self.name, msg);
+    }
+
+    pub fn warning(&self, msg: String) {
+        warn!("{}: {}", self.name, msg);
+    }
+
+    pub fn error(&self, msg: String) {
+        error!("{}: {}", self.name, msg);
+    }
+}
@@ -0,0 +1,19 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+pub mod clock;
+pub mod crypt_data_slab;
+pub mod cryptde;
+pub mod cryptde_null;
+pub mod decodex;
+pub mod dispatcher;
+pub mod hop;
+pub mod hopper;
+pub mod logger;
+pub mod route;
+pub mod node_addr;
+pub mod proxy_client;
+pub mod proxy_server;
+pub mod secret_sharing;
+pub mod sequence_buffer;
+pub mod stream_key;
+pub mod wallet;
@@ -0,0 +1,214 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+/// Where a node can be reached on the clandestine network: the IP addresses
+/// it is known by (IPv4 and/or IPv6) and the ports it listens on at all of
+/// them.
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NodeAddr {
+    ip_addrs: Vec<IpAddr>,
+    ports: Vec<u16>,
+}
+
+impl NodeAddr {
+    pub fn new(ip_addr: &IpAddr, ports: &[u16]) -> NodeAddr {
+        Self::new_multihomed(&[*ip_addr], ports)
+    }
+
+    pub fn new_multihomed(ip_addrs: &[IpAddr], ports: &[u16]) -> NodeAddr {
+        let mut ip_addrs = ip_addrs.to_vec();
+        ip_addrs.dedup();
+        let mut ports = ports.to_vec();
+        ports.sort_unstable();
+        ports.dedup();
+        NodeAddr { ip_addrs, ports }
+    }
+
+    /// The node's primary address: the first one advertised.
+    pub fn ip_addr(&self) -> IpAddr {
+        self.ip_addrs[0]
+    }
+
+    pub fn ip_addrs(&self) -> Vec<IpAddr> {
+        self.ip_addrs.clone()
+    }
+
+    pub fn ports(&self) -> Vec<u16> {
+        self.ports.clone()
+    }
+
+    /// The first advertised IPv6 address with the first port, if any.
+    pub fn prefer_ipv6(&self) -> Option<SocketAddr> {
+        self.prefer(|ip| ip.is_ipv6())
+    }
+
+    /// The first advertised IPv4 address with the first port, if any.
+    pub fn prefer_ipv4(&self) -> Option<SocketAddr> {
+        self.prefer(|ip| ip.is_ipv4())
+    }
+
+    /// The socket address a peer should contact, honoring the peer's
+    /// address-family capability: IPv6 when the peer supports it and we
+    /// advertise one, otherwise IPv4, otherwise whatever we have.
+    pub fn contact_socket_addr(&self, peer_supports_ipv6: bool) -> Option<SocketAddr> {
+        if peer_supports_ipv6 {
+            self.prefer_ipv6().or_else(|| self.prefer_ipv4())
+        } else {
+            self.prefer_ipv4()
+        }
+    }
+
+    fn prefer(&self, predicate: fn(&IpAddr) -> bool) -> Option<SocketAddr> {
+        let port = *self.ports.first()?;
+        self.ip_addrs
+            .iter()
+            .find(|ip| predicate(ip))
+            .map(|ip| SocketAddr::new(*ip, port))
+    }
+}
+
+impl fmt::Debug for NodeAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for NodeAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ips = self
+            .ip_addrs
+            .iter()
+            .map(|ip| ip.to_string())
+            .collect::<Vec<String>>()
+            .join(";");
+        let ports = self
+            .ports
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+        write!(f, "{}:{}", ips, ports)
+    }
+}
+
+impl From<&NodeAddr> for Vec<SocketAddr> {
+    fn from(node_addr: &NodeAddr) -> Vec<SocketAddr> {
+        node_addr
+            .ip_addrs()
+            .into_iter()
+            .flat_map(|ip| {
+                node_addr
+                    .ports()
+                    .into_iter()
+                    .map(move |port| SocketAddr::new(ip, port))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    #[test]
+    fn new_sorts_and_dedups_ports() {
+        let subject = NodeAddr::new(
+            &IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)),
+            &[4555, 1234, 4555, 2345],
+        );
+
+        assert_eq!(subject.ports(), vec![1234, 2345, 4555]);
+    }
+
+    #[test]
+    fn display_renders_ip_and_port_list() {
+        let subject = NodeAddr::new(&IpAddr::from_str("5.6.7.8").unwrap(), &[80, 443]);
+
+        assert_eq!(subject.to_string(), "5.6.7.8:80,443");
+    }
+
+    #[test]
+    fn converts_into_socket_addrs() {
+        let subject = NodeAddr::new(&IpAddr::from_str("5.6.7.8").unwrap(), &[80, 443]);
+
+        let socket_addrs: Vec<SocketAddr> = (&subject).into();
+
+        assert_eq!(
+            socket_addrs,
+            vec![
+                SocketAddr::from_str("5.6.7.8:80").unwrap(),
+                SocketAddr::from_str("5.6.7.8:443").unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn dual_stack_node_addr_serializes_both_families() {
+        let subject = NodeAddr::new_multihomed(
+            &[
+                IpAddr::from_str("5.6.7.8").unwrap(),
+                IpAddr::from_str("2001:db8::1").unwrap(),
+            ],
+            &[1234],
+        );
+
+        let serialized = serde_cbor::ser::to_vec(&subject).unwrap();
+        let deserialized: NodeAddr = serde_cbor::de::from_slice(&serialized).unwrap();
+
+        assert_eq!(deserialized, subject);
+        assert_eq!(deserialized.ip_addrs().len(), 2);
+    }
+
+    #[test]
+    fn prefer_selectors_pick_the_right_family() {
+        let subject = NodeAddr::new_multihomed(
+            &[
+                IpAddr::from_str("5.6.7.8").unwrap(),
+                IpAddr::from_str("2001:db8::1").unwrap(),
+            ],
+            &[1234, 2345],
+        );
+
+        assert_eq!(
+            subject.prefer_ipv4(),
+            Some(SocketAddr::from_str("5.6.7.8:1234").unwrap())
+        );
+        assert_eq!(
+            subject.prefer_ipv6(),
+            Some(SocketAddr::from_str("[2001:db8::1]:1234").unwrap())
+        );
+    }
+
+    #[test]
+    fn prefer_selectors_return_none_when_family_absent() {
+        let v4_only = NodeAddr::new(&IpAddr::from_str("5.6.7.8").unwrap(), &[1234]);
+
+        assert_eq!(v4_only.prefer_ipv6(), None);
+        assert!(v4_only.prefer_ipv4().is_some());
+    }
+
+    #[test]
+    fn contact_socket_addr_honors_peer_capability() {
+        let dual = NodeAddr::new_multihomed(
+            &[
+                IpAddr::from_str("5.6.7.8").unwrap(),
+                IpAddr::from_str("2001:db8::1").unwrap(),
+            ],
+            &[1234],
+        );
+
+        assert_eq!(
+            dual.contact_socket_addr(true),
+            Some(SocketAddr::from_str("[2001:db8::1]:1234").unwrap())
+        );
+        assert_eq!(
+            dual.contact_socket_addr(false),
+            Some(SocketAddr::from_str("5.6.7.8:1234").unwrap())
+        );
+    }
+}
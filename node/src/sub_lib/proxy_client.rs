@@ -0,0 +1,118 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use crate::sub_lib::sequence_buffer::SequencedPacket;
+use crate::sub_lib::stream_key::StreamKey;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+
+/// A server response on its way from the exit node back to the originating
+/// ProxyServer.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientResponsePayload {
+    pub stream_key: StreamKey,
+    pub sequenced_packet: SequencedPacket,
+}
+
+/// Raw bytes read from an origin server at the exit, before being wrapped
+/// into a ClientResponsePayload.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InboundServerData {
+    pub stream_key: StreamKey,
+    pub last_data: bool,
+    pub sequence_number: u64,
+    pub source: SocketAddr,
+    pub data: Vec<u8>,
+}
+
+/// Why an exit-side DNS lookup failed, as reported back to the
+/// originator. Transient kinds are worth retrying — at the exit against
+/// another nameserver, and at the originator over another route — while
+/// NXDOMAIN is authoritative and retrying it only delays the error page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DnsFailureKind {
+    NxDomain,
+    ServFail,
+    Timeout,
+    Refused,
+}
+
+impl DnsFailureKind {
+    pub fn is_transient(&self) -> bool {
+        !matches!(self, DnsFailureKind::NxDomain)
+    }
+}
+
+/// Exit-to-originator report that a stream's target hostname would not
+/// resolve. `kind` was added in the second version of this payload; it is
+/// optional so frames from older peers (which omit it) still deserialize,
+/// and older peers ignore the unknown field in frames from newer ones. A
+/// missing kind gets the pre-versioning behavior: an immediate error.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsResolveFailure {
+    pub stream_key: StreamKey,
+    pub hostname: String,
+    #[serde(default)]
+    pub kind: Option<DnsFailureKind>,
+}
+
+/// How response traffic travels back to the originator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TunnelMode {
+    /// Responses ride the reverse of the request route.
+    Bidirectional,
+    /// Responses use a separately assigned return tunnel per stream.
+    UnidirectionalPaired,
+}
+
+/// Exit-side configuration. Grows a field per operator-facing knob.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyClientConfig {
+    /// Resolvers for exit-side lookups. Validated non-empty by the node
+    /// configurator before the ProxyClient ever sees it, so construction
+    /// no longer panics on a configuration slip.
+    pub dns_servers: Vec<IpAddr>,
+    pub exit_service_rate: u64,
+    pub cache_max_size_bytes: usize,
+    pub enforce_hsts: bool,
+    pub block_private_ips: bool,
+    /// PKCS#7 block size for LiveCoresPackage payload padding; None disables
+    /// padding.
+    pub padding_block_size: Option<usize>,
+    pub tunnel_mode: TunnelMode,
+    pub header_sanitizer: crate::proxy_client::header_sanitizer::HeaderSanitizerConfig,
+    /// Rolling-hour cap on bytes served as an exit; None means unlimited.
+    /// New streams past the cap get a synthesized 503 until the hour rolls.
+    pub max_bandwidth_bytes_per_hour: Option<u64>,
+    /// Destination-country policy enforced before exit connections.
+    pub geo_policy: crate::proxy_client::geo_policy::GeoIpPolicy,
+    /// Open exit connections as MPTCP subflows where the kernel supports
+    /// it; degrades to plain TCP (with a warning) where it does not.
+    pub mptcp_enabled: bool,
+    /// Check server certificates of SNI TLS connections against
+    /// Certificate Transparency logs; certificates no log has seen are
+    /// rejected.
+    pub check_ct_logs: bool,
+    /// Reject TLS connections whose servers staple no usable OCSP
+    /// response; off by default, since most servers staple nothing.
+    pub require_ocsp: bool,
+}
+
+impl Default for ProxyClientConfig {
+    fn default() -> Self {
+        ProxyClientConfig {
+            dns_servers: vec![],
+            exit_service_rate: 0,
+            cache_max_size_bytes: 8 * 1024 * 1024,
+            enforce_hsts: false,
+            block_private_ips: true,
+            padding_block_size: None,
+            tunnel_mode: TunnelMode::Bidirectional,
+            header_sanitizer: Default::default(),
+            max_bandwidth_bytes_per_hour: None,
+            geo_policy: Default::default(),
+            mptcp_enabled: false,
+            check_ct_logs: false,
+            require_ocsp: false,
+        }
+    }
+}
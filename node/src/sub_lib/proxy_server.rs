@@ -0,0 +1,69 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use crate::sub_lib::cryptde::PublicKey;
+use crate::sub_lib::sequence_buffer::SequencedPacket;
+use crate::sub_lib::stream_key::StreamKey;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How long a stream may wait for its first response before the ProxyServer
+/// synthesizes a 504 and tears the stream down.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProxyProtocol {
+    HTTP,
+    TLS,
+    /// UDP datagrams (DNS, QUIC, game traffic). Each sequenced packet is
+    /// one datagram; ordering is not guaranteed, and streams end by idle
+    /// timeout rather than last_data.
+    Datagram,
+    /// Opaque byte relay for arbitrary intercepted ports. Nothing is
+    /// sniffed from the stream; the destination comes from the original
+    /// socket destination, so raw ports require transparent mode.
+    Raw,
+}
+
+/// A client request on its way from the originating ProxyServer toward an
+/// exit node.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientRequestPayload {
+    pub stream_key: StreamKey,
+    pub sequenced_packet: SequencedPacket,
+    pub target_hostname: Option<String>,
+    pub target_port: u16,
+    pub protocol: ProxyProtocol,
+    pub originator_public_key: PublicKey,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyServerConfig {
+    pub request_timeout: Duration,
+    /// Local SOCKS5 listener for browser integration; None disables it.
+    pub socks5_port: Option<u16>,
+    /// Listener for iptables-REDIRECTed traffic; targets are recovered via
+    /// SO_ORIGINAL_DST. None disables transparent mode.
+    pub transparent_proxy_port: Option<u16>,
+    /// HTTP listener serving /proxy.pac for browser auto-configuration;
+    /// None disables it.
+    pub pac_port: Option<u16>,
+    /// UDP intercept socket for datagram relay; None disables UDP.
+    pub udp_intercept_port: Option<u16>,
+    /// Key route selection by the target's registrable domain so
+    /// different sites exit through different nodes. Costs route
+    /// diversity per site; off by default.
+    pub per_site_isolation: bool,
+}
+
+impl Default for ProxyServerConfig {
+    fn default() -> Self {
+        ProxyServerConfig {
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            socks5_port: None,
+            transparent_proxy_port: None,
+            pac_port: None,
+            udp_intercept_port: None,
+            per_site_isolation: false,
+        }
+    }
+}
@@ -0,0 +1,282 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use crate::sub_lib::cryptde::{CryptDE, CryptData};
+use crate::sub_lib::decodex::{decodex, encodex, CodexError};
+use crate::sub_lib::hop::LiveHop;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::sync::Arc;
+
+/// An onion-wrapped source route: each element is a LiveHop encrypted to the
+/// key of the node that should peel it. A node decrypts hops[0] to learn the
+/// next destination, shifts it off, and forwards the rest.
+///
+/// Hops are interned behind an Arc so the clone into every StreamContext and
+/// retransmission buffer is a pointer bump, not a copy of the (dominant) hop
+/// storage; shift() copies on write. The serde wire format is unchanged from
+/// the plain-Vec representation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Route {
+    hops: Arc<[CryptData]>,
+}
+
+/// The longest route this node will build or relay, counted in encrypted
+/// hops (the destination's self-naming hop included). Ten leaves generous
+/// headroom over the three-hop routes the neighborhood actually builds while
+/// still bounding the work a malicious originator can demand of a relay.
+pub const DEFAULT_MAX_HOPS: usize = 10;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RouteError {
+    EmptyRoute,
+    HopDecodeFailed(String),
+    /// The payload's authentication tag did not verify: either the tag or
+    /// the payload was swapped in transit.
+    PayloadTagInvalid,
+    TooManyHops { hops: usize, max: usize },
+    /// The sending neighbor already delivered a package with this link
+    /// sequence number; the package is a replay and is dropped unprocessed.
+    ReplayDetected { sequence: u64 },
+}
+
+impl Route {
+    pub fn new(hops: Vec<CryptData>) -> Route {
+        Route { hops: hops.into() }
+    }
+
+    /// Builds a one-way route through the given keys: hops[i] tells keys[i]
+    /// to forward to keys[i+1]; the final hop names the destination itself so
+    /// it can recognize termination. Refuses routes longer than
+    /// DEFAULT_MAX_HOPS; no relay would forward them anyway.
+    pub fn one_way(
+        cryptde: &dyn CryptDE,
+        keys: &[&crate::sub_lib::cryptde::PublicKey],
+    ) -> Result<Route, RouteError> {
+        Self::one_way_with_limit(cryptde, keys, DEFAULT_MAX_HOPS)
+    }
+
+    /// `one_way` with an explicit hop limit, for operators running a
+    /// tightened configuration.
+    pub fn one_way_with_limit(
+        cryptde: &dyn CryptDE,
+        keys: &[&crate::sub_lib::cryptde::PublicKey],
+        max_hops: usize,
+    ) -> Result<Route, RouteError> {
+        if keys.len() > max_hops {
+            return Err(RouteError::TooManyHops {
+                hops: keys.len(),
+                max: max_hops,
+            });
+        }
+        let mut hops = vec![];
+        for i in 0..keys.len() {
+            let next_key = if i + 1 < keys.len() { keys[i + 1] } else { keys[i] };
+            let hop = LiveHop::new(next_key);
+            let encrypted = encodex(cryptde, keys[i], &hop)
+                .expect("Route hop encryption should never fail");
+            hops.push(encrypted);
+        }
+        Ok(Route::new(hops))
+    }
+
+    /// Decrypts and removes the first hop, leaving the route ready to hand
+    /// to the named next node. Copy-on-write: other clones sharing the hop
+    /// storage are unaffected.
+    pub fn shift(&mut self, cryptde: &dyn CryptDE) -> Result<LiveHop, RouteError> {
+        let first = match self.hops.first() {
+            None => return Err(RouteError::EmptyRoute),
+            Some(first) => Self::decode_hop(cryptde, first)?,
+        };
+        self.hops = self.hops[1..].into();
+        Ok(first)
+    }
+
+    /// Peeks at the first hop without consuming it.
+    pub fn next_hop(&self, cryptde: &dyn CryptDE) -> Result<LiveHop, RouteError> {
+        match self.hops.first() {
+            None => Err(RouteError::EmptyRoute),
+            Some(first) => Self::decode_hop(cryptde, first),
+        }
+    }
+
+    pub fn hops(&self) -> &[CryptData] {
+        &self.hops
+    }
+
+    pub fn hop_count(&self) -> usize {
+        self.hops.len()
+    }
+
+    fn decode_hop(cryptde: &dyn CryptDE, hop: &CryptData) -> Result<LiveHop, RouteError> {
+        decodex::<LiveHop>(cryptde, hop).map_err(|e: CodexError| match e {
+            CodexError::DecryptionFailed(inner) => {
+                RouteError::HopDecodeFailed(format!("{:?}", inner))
+            }
+            other => RouteError::HopDecodeFailed(format!("{:?}", other)),
+        })
+    }
+}
+
+// Shadow struct pinning the wire format to the historical plain-Vec layout.
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Route")]
+struct RouteOnTheWire {
+    hops: Vec<CryptData>,
+}
+
+impl Serialize for Route {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        RouteOnTheWire {
+            hops: self.hops.to_vec(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Route {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Route, D::Error> {
+        let wire = RouteOnTheWire::deserialize(deserializer)?;
+        Ok(Route::new(wire.hops))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sub_lib::cryptde::PublicKey;
+    use crate::sub_lib::cryptde_null::CryptDENull;
+
+    #[test]
+    fn each_node_learns_only_its_next_hop() {
+        let originator = CryptDENull::from(&PublicKey::new(b"origin"));
+        let relay_key = PublicKey::new(b"relay");
+        let exit_key = PublicKey::new(b"exit");
+        let relay = CryptDENull::from(&relay_key);
+        let exit = CryptDENull::from(&exit_key);
+
+        let mut route = Route::one_way(&originator, &[&relay_key, &exit_key]).unwrap();
+
+        let first = route.shift(&relay).unwrap();
+        assert_eq!(first.public_key, exit_key);
+        let second = route.shift(&exit).unwrap();
+        assert_eq!(second.public_key, exit_key);
+        assert_eq!(route.shift(&exit), Err(RouteError::EmptyRoute));
+    }
+
+    #[test]
+    fn next_hop_peeks_without_consuming() {
+        let originator = CryptDENull::from(&PublicKey::new(b"origin"));
+        let relay_key = PublicKey::new(b"relay");
+        let relay = CryptDENull::from(&relay_key);
+        let route = Route::one_way(&originator, &[&relay_key]).unwrap();
+
+        let hop = route.next_hop(&relay).unwrap();
+
+        assert_eq!(hop.public_key, relay_key);
+        assert_eq!(route.hop_count(), 1);
+    }
+
+    #[test]
+    fn wrong_node_cannot_peel_a_hop() {
+        let originator = CryptDENull::from(&PublicKey::new(b"origin"));
+        let relay_key = PublicKey::new(b"relay");
+        let interloper = CryptDENull::from(&PublicKey::new(b"interloper"));
+        let mut route = Route::one_way(&originator, &[&relay_key]).unwrap();
+
+        let result = route.shift(&interloper);
+
+        assert!(matches!(result, Err(RouteError::HopDecodeFailed(_))));
+    }
+
+    #[test]
+    fn wire_format_matches_the_plain_vec_layout() {
+        let originator = CryptDENull::from(&PublicKey::new(b"origin"));
+        let relay_key = PublicKey::new(b"relay");
+        let route = Route::one_way(&originator, &[&relay_key]).unwrap();
+
+        let interned_bytes = serde_cbor::ser::to_vec(&route).unwrap();
+        let plain_bytes = serde_cbor::ser::to_vec(&RouteOnTheWire {
+            hops: route.hops().to_vec(),
+        })
+        .unwrap();
+
+        assert_eq!(interned_bytes, plain_bytes);
+        let round_tripped: Route = serde_cbor::de::from_slice(&interned_bytes).unwrap();
+        assert_eq!(round_tripped, route);
+    }
+
+    #[test]
+    fn cloning_a_route_shares_hop_storage() {
+        let originator = CryptDENull::from(&PublicKey::new(b"origin"));
+        let relay_key = PublicKey::new(b"relay");
+        let route = Route::one_way(&originator, &[&relay_key]).unwrap();
+
+        let clones: Vec<Route> = (0..10_000).map(|_| route.clone()).collect();
+
+        assert_eq!(Arc::strong_count(&route.hops), 10_001);
+        assert!(clones
+            .iter()
+            .all(|clone| Arc::ptr_eq(&clone.hops, &route.hops)));
+    }
+
+    #[test]
+    fn shift_copies_on_write_without_touching_clones() {
+        let originator = CryptDENull::from(&PublicKey::new(b"origin"));
+        let relay_key = PublicKey::new(b"relay");
+        let exit_key = PublicKey::new(b"exit");
+        let relay = CryptDENull::from(&relay_key);
+        let mut route = Route::one_way(&originator, &[&relay_key, &exit_key]).unwrap();
+        let snapshot = route.clone();
+
+        route.shift(&relay).unwrap();
+
+        assert_eq!(route.hop_count(), 1);
+        assert_eq!(snapshot.hop_count(), 2);
+    }
+
+    fn make_keys(count: usize) -> Vec<PublicKey> {
+        (0..count)
+            .map(|n| PublicKey::new(format!("key{}", n).as_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn a_route_at_the_hop_limit_still_builds() {
+        let originator = CryptDENull::from(&PublicKey::new(b"origin"));
+        let keys = make_keys(DEFAULT_MAX_HOPS);
+        let key_refs: Vec<&PublicKey> = keys.iter().collect();
+
+        let route = Route::one_way(&originator, &key_refs).unwrap();
+
+        assert_eq!(route.hop_count(), DEFAULT_MAX_HOPS);
+    }
+
+    #[test]
+    fn a_route_one_past_the_hop_limit_is_refused() {
+        let originator = CryptDENull::from(&PublicKey::new(b"origin"));
+        let keys = make_keys(DEFAULT_MAX_HOPS + 1);
+        let key_refs: Vec<&PublicKey> = keys.iter().collect();
+
+        let result = Route::one_way(&originator, &key_refs);
+
+        assert_eq!(
+            result,
+            Err(RouteError::TooManyHops {
+                hops: DEFAULT_MAX_HOPS + 1,
+                max: DEFAULT_MAX_HOPS,
+            })
+        );
+    }
+
+    #[test]
+    fn the_hop_limit_is_configurable() {
+        let originator = CryptDENull::from(&PublicKey::new(b"origin"));
+        let keys = make_keys(3);
+        let key_refs: Vec<&PublicKey> = keys.iter().collect();
+
+        assert!(Route::one_way_with_limit(&originator, &key_refs, 3).is_ok());
+        assert_eq!(
+            Route::one_way_with_limit(&originator, &key_refs, 2),
+            Err(RouteError::TooManyHops { hops: 3, max: 2 })
+        );
+    }
+}
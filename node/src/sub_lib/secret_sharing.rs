@@ -0,0 +1,188 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Shamir secret sharing for extra-sensitive routes: the session key is
+//! split K-of-N across distinct nodes, so no single node (or any K-1 of
+//! them together) learns anything about it, while any K cooperating
+//! holders can reconstruct it. The polynomial arithmetic comes from the
+//! `sharks` crate; this module owns the wire shape of a share and the
+//! collection bookkeeping.
+
+use crate::sub_lib::cryptde::CryptData;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// One share of a split session key, as it travels in route metadata or a
+/// gossip message. `share_id` is the share's x-coordinate and doubles as
+/// its identity during collection.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecretShare {
+    pub share_id: u8,
+    pub share_data: CryptData,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SecretSharingError {
+    /// threshold must be at least 2 and no greater than the share count.
+    InvalidParameters { threshold: u8, total: u8 },
+    /// A share's bytes did not parse as a Shamir share.
+    MalformedShare(u8),
+    /// Fewer than `threshold` distinct shares were supplied.
+    TooFewShares { have: usize, need: u8 },
+}
+
+/// Splits `secret` into `total` shares, any `threshold` of which suffice
+/// to reconstruct it.
+pub fn split_secret(
+    secret: &[u8],
+    threshold: u8,
+    total: u8,
+) -> Result<Vec<SecretShare>, SecretSharingError> {
+    if threshold < 2 || total < threshold {
+        return Err(SecretSharingError::InvalidParameters { threshold, total });
+    }
+    let sharks = sharks::Sharks(threshold);
+    let dealer = sharks.dealer(secret);
+    Ok(dealer
+        .take(total as usize)
+        .map(|share| {
+            let bytes = Vec::from(&share);
+            SecretShare {
+                share_id: bytes[0],
+                share_data: CryptData::from(bytes),
+            }
+        })
+        .collect())
+}
+
+/// Reconstructs the secret from at least `threshold` distinct shares.
+pub fn reconstruct_secret(
+    shares: &[SecretShare],
+    threshold: u8,
+) -> Result<Vec<u8>, SecretSharingError> {
+    if shares.len() < threshold as usize {
+        return Err(SecretSharingError::TooFewShares {
+            have: shares.len(),
+            need: threshold,
+        });
+    }
+    let parsed: Vec<sharks::Share> = shares
+        .iter()
+        .map(|share| {
+            sharks::Share::try_from(share.share_data.as_slice())
+                .map_err(|_| SecretSharingError::MalformedShare(share.share_id))
+        })
+        .collect::<Result<Vec<sharks::Share>, SecretSharingError>>()?;
+    sharks::Sharks(threshold)
+        .recover(&parsed)
+        .map_err(|_| SecretSharingError::TooFewShares {
+            have: shares.len(),
+            need: threshold,
+        })
+}
+
+/// Accumulates shares as they arrive via gossip or direct messages and
+/// hands back the secret the moment the threshold is met. Duplicate
+/// share_ids are idempotent, not double-counted.
+pub struct ShareCollector {
+    threshold: u8,
+    shares: HashMap<u8, SecretShare>,
+}
+
+impl ShareCollector {
+    pub fn new(threshold: u8) -> ShareCollector {
+        ShareCollector {
+            threshold,
+            shares: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, share: SecretShare) -> Option<Result<Vec<u8>, SecretSharingError>> {
+        self.shares.insert(share.share_id, share);
+        if self.shares.len() >= self.threshold as usize {
+            let collected: Vec<SecretShare> = self.shares.values().cloned().collect();
+            Some(reconstruct_secret(&collected, self.threshold))
+        } else {
+            None
+        }
+    }
+
+    pub fn share_count(&self) -> usize {
+        self.shares.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"thirty-two bytes of session key!";
+
+    #[test]
+    fn any_k_of_n_shares_reconstruct_the_secret() {
+        let shares = split_secret(SECRET, 3, 5).unwrap();
+
+        let from_first_three = reconstruct_secret(&shares[0..3], 3).unwrap();
+        let from_last_three = reconstruct_secret(&shares[2..5], 3).unwrap();
+
+        assert_eq!(from_first_three, SECRET.to_vec());
+        assert_eq!(from_last_three, SECRET.to_vec());
+    }
+
+    #[test]
+    fn k_minus_one_shares_reconstruct_nothing() {
+        let shares = split_secret(SECRET, 3, 5).unwrap();
+
+        let result = reconstruct_secret(&shares[0..2], 3);
+
+        assert_eq!(
+            result,
+            Err(SecretSharingError::TooFewShares { have: 2, need: 3 })
+        );
+    }
+
+    #[test]
+    fn degenerate_parameters_are_rejected() {
+        assert_eq!(
+            split_secret(SECRET, 1, 5),
+            Err(SecretSharingError::InvalidParameters {
+                threshold: 1,
+                total: 5
+            })
+        );
+        assert_eq!(
+            split_secret(SECRET, 4, 3),
+            Err(SecretSharingError::InvalidParameters {
+                threshold: 4,
+                total: 3
+            })
+        );
+    }
+
+    #[test]
+    fn collector_releases_the_secret_exactly_at_threshold() {
+        let shares = split_secret(SECRET, 3, 5).unwrap();
+        let mut collector = ShareCollector::new(3);
+
+        assert!(collector.add(shares[0].clone()).is_none());
+        assert!(collector.add(shares[1].clone()).is_none());
+        // A duplicate does not count toward the threshold.
+        assert!(collector.add(shares[1].clone()).is_none());
+        let result = collector.add(shares[4].clone()).unwrap().unwrap();
+
+        assert_eq!(result, SECRET.to_vec());
+    }
+
+    #[test]
+    fn a_corrupted_share_is_reported_by_id() {
+        let mut shares = split_secret(SECRET, 2, 3).unwrap();
+        shares[1].share_data = CryptData::new(&[shares[1].share_id]);
+
+        let result = reconstruct_secret(&shares[0..2], 2);
+
+        assert_eq!(
+            result,
+            Err(SecretSharingError::MalformedShare(shares[1].share_id))
+        );
+    }
+}
@@ -0,0 +1,50 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A chunk of stream data plus enough ordering metadata to reassemble the
+/// stream on the far side of the network.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SequencedPacket {
+    pub data: Vec<u8>,
+    pub sequence_number: u64,
+    pub last_data: bool,
+}
+
+impl SequencedPacket {
+    pub fn new(data: Vec<u8>, sequence_number: u64, last_data: bool) -> SequencedPacket {
+        SequencedPacket {
+            data,
+            sequence_number,
+            last_data,
+        }
+    }
+}
+
+impl fmt::Debug for SequencedPacket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SequencedPacket {{ #{}, {} bytes{} }}",
+            self.sequence_number,
+            self.data.len(),
+            if self.last_data { ", last" } else { "" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_is_compact() {
+        let subject = SequencedPacket::new(vec![1, 2, 3], 7, true);
+
+        assert_eq!(
+            format!("{:?}", subject),
+            "SequencedPacket { #7, 3 bytes, last }"
+        );
+    }
+}
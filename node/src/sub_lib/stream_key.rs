@@ -0,0 +1,63 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+
+/// Identifies one client stream end-to-end across the route, without
+/// revealing the client socket address to relays.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord)]
+pub struct StreamKey {
+    hash: u64,
+}
+
+impl StreamKey {
+    pub fn new(salt: &[u8], peer_addr: SocketAddr) -> StreamKey {
+        let mut hasher = DefaultHasher::new();
+        salt.hash(&mut hasher);
+        peer_addr.hash(&mut hasher);
+        StreamKey {
+            hash: hasher.finish(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn make_meaningless(n: u64) -> StreamKey {
+        StreamKey { hash: n }
+    }
+}
+
+impl fmt::Debug for StreamKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StreamKey({:016x})", self.hash)
+    }
+}
+
+impl fmt::Display for StreamKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn same_inputs_produce_same_key() {
+        let addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+
+        assert_eq!(StreamKey::new(b"salt", addr), StreamKey::new(b"salt", addr));
+    }
+
+    #[test]
+    fn different_peers_produce_different_keys() {
+        let one = StreamKey::new(b"salt", SocketAddr::from_str("1.2.3.4:5678").unwrap());
+        let other = StreamKey::new(b"salt", SocketAddr::from_str("1.2.3.4:5679").unwrap());
+
+        assert_ne!(one, other);
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+use serde::{Deserialize, Serialize};
+
+/// A blockchain wallet, named by its address.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Wallet {
+    pub address: String,
+}
+
+impl Wallet {
+    pub fn new(address: &str) -> Wallet {
+        Wallet {
+            address: address.to_string(),
+        }
+    }
+}
+
+/// The paid services a node renders to others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ServiceType {
+    Routing,
+    Exit,
+}
+
+/// Where each service's income goes. Operators who want routing and exit
+/// receipts in separate books configure two addresses; everyone else
+/// configures one and both services collapse onto it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EarningWallets {
+    pub routing: Wallet,
+    pub exit: Wallet,
+}
+
+impl EarningWallets {
+    /// The single-wallet default: both services paid to one address.
+    pub fn single(wallet: Wallet) -> EarningWallets {
+        EarningWallets {
+            routing: wallet.clone(),
+            exit: wallet,
+        }
+    }
+
+    pub fn split(routing: Wallet, exit: Wallet) -> EarningWallets {
+        EarningWallets { routing, exit }
+    }
+
+    pub fn for_service(&self, service: ServiceType) -> &Wallet {
+        match service {
+            ServiceType::Routing => &self.routing,
+            ServiceType::Exit => &self.exit,
+        }
+    }
+}
+
+/// An anonymous payment credential: a token blind-signed by the earning
+/// side, verifiable without revealing which wallet it was issued to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlindCredential {
+    pub token: Vec<u8>,
+}
+
+/// What a consuming node presents to pay for service: either its wallet
+/// outright, or an anonymous credential that unlinks the payment from the
+/// wallet identity.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConsumingCredential {
+    Wallet(Wallet),
+    Blind(BlindCredential),
+}
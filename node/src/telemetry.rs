@@ -0,0 +1,297 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Distributed tracing for the relay path. When an operator configures
+//! `otel_endpoint`, the ProxyClient opens a span as it handles an
+//! ExpiredCoresPackage and closes it when the response goes back to the
+//! hopper, exporting to the OTLP collector; the TraceContext rides the
+//! LiveCoresPackage so spans from different nodes join one trace. With no
+//! endpoint configured, span calls return None immediately — no ids are
+//! generated, nothing is recorded.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// W3C-style trace identity carried in CORES packages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct TelemetryConfig {
+    /// OTLP collector endpoint; None disables tracing entirely.
+    pub otel_endpoint: Option<String>,
+}
+
+/// A completed span, ready for export.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FinishedSpan {
+    pub context: TraceContext,
+    pub parent_span_id: Option<u64>,
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub duration: Duration,
+}
+
+/// Where finished spans go. The real exporter ships OTLP; tests record
+/// in-process.
+pub trait SpanExporter: Send {
+    fn export(&self, span: FinishedSpan);
+}
+
+/// A span in progress, returned only when tracing is enabled.
+pub struct ActiveSpan {
+    context: TraceContext,
+    parent_span_id: Option<u64>,
+    name: String,
+    started_at: Instant,
+}
+
+impl ActiveSpan {
+    pub fn context(&self) -> TraceContext {
+        self.context
+    }
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn next_id() -> u64 {
+    // splitmix64 over a counter: unique and cheap, not security-relevant.
+    let mut z = NEXT_ID
+        .fetch_add(1, Ordering::Relaxed)
+        .wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+pub struct Tracer {
+    exporter: Option<Box<dyn SpanExporter>>,
+}
+
+impl Tracer {
+    /// Builds the tracer from config: an endpoint enables export, its
+    /// absence disables tracing with zero ongoing cost.
+    pub fn from_config(config: &TelemetryConfig) -> Tracer {
+        Tracer {
+            exporter: config
+                .otel_endpoint
+                .as_ref()
+                .map(|endpoint| Box::new(OtlpExporter::new(endpoint.clone())) as Box<dyn SpanExporter>),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_exporter(exporter: Box<dyn SpanExporter>) -> Tracer {
+        Tracer {
+            exporter: Some(exporter),
+        }
+    }
+
+    pub fn disabled() -> Tracer {
+        Tracer { exporter: None }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.exporter.is_some()
+    }
+
+    /// Starts a span, continuing `parent`'s trace when present or starting
+    /// a fresh trace otherwise. None when tracing is disabled.
+    pub fn start_span(&self, name: &str, parent: Option<TraceContext>) -> Option<ActiveSpan> {
+        self.exporter.as_ref()?;
+        let trace_id = match parent {
+            Some(context) => context.trace_id,
+            None => (u128::from(next_id()) << 64) | u128::from(next_id()),
+        };
+        Some(ActiveSpan {
+            context: TraceContext {
+                trace_id,
+                span_id: next_id(),
+            },
+            parent_span_id: parent.map(|context| context.span_id),
+            name: name.to_string(),
+            started_at: Instant::now(),
+        })
+    }
+
+    /// Ends the span and hands it to the exporter.
+    pub fn end_span(&self, span: ActiveSpan, attributes: Vec<(String, String)>) {
+        if let Some(exporter) = &self.exporter {
+            exporter.export(FinishedSpan {
+                context: span.context,
+                parent_span_id: span.parent_span_id,
+                name: span.name,
+                attributes,
+                duration: span.started_at.elapsed(),
+            });
+        }
+    }
+}
+
+/// Ships spans to the configured OTLP collector over HTTP.
+pub struct OtlpExporter {
+    endpoint: String,
+    client: reqwest::blocking::Client,
+}
+
+impl OtlpExporter {
+    pub fn new(endpoint: String) -> OtlpExporter {
+        OtlpExporter {
+            endpoint,
+            client: reqwest::blocking::Client::new(),
+        }
+    }
+}
+
+impl SpanExporter for OtlpExporter {
+    fn export(&self, span: FinishedSpan) {
+        let attributes = span
+            .attributes
+            .iter()
+            .map(|(key, value)| {
+                format!(
+                    "{{\"key\":\"{}\",\"value\":{{\"stringValue\":\"{}\"}}}}",
+                    key, value
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+        let body = format!(
+            "{{\"spans\":[{{\"traceId\":\"{:032x}\",\"spanId\":\"{:016x}\",\
+             \"name\":\"{}\",\"durationNanos\":{},\"attributes\":[{}]}}]}}",
+            span.context.trace_id,
+            span.context.span_id,
+            span.name,
+            span.duration.as_nanos(),
+            attributes
+        );
+        // Telemetry is best-effort; a down collector must not affect relay.
+        let _ = self
+            .client
+            .post(format!("{}/v1/traces", self.endpoint))
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct SpanExporterMock {
+        export_params: Arc<Mutex<Vec<FinishedSpan>>>,
+    }
+
+    impl SpanExporterMock {
+        fn new(params: &Arc<Mutex<Vec<FinishedSpan>>>) -> SpanExporterMock {
+            SpanExporterMock {
+                export_params: params.clone(),
+            }
+        }
+    }
+
+    impl SpanExporter for SpanExporterMock {
+        fn export(&self, span: FinishedSpan) {
+            self.export_params.lock().unwrap().push(span);
+        }
+    }
+
+    #[test]
+    fn a_handled_package_produces_a_span_with_its_attributes() {
+        let export_params = Arc::new(Mutex::new(vec![]));
+        let tracer = Tracer::with_exporter(Box::new(SpanExporterMock::new(&export_params)));
+
+        // ProxyClient::handle(ExpiredCoresPackage) opens the span...
+        let span = tracer
+            .start_span("proxy_client.handle_expired_cores_package", None)
+            .unwrap();
+        // ...and send_response_to_hopper closes it.
+        tracer.end_span(
+            span,
+            vec![
+                ("stream_key".to_string(), "a1b2c3".to_string()),
+                ("target_hostname".to_string(), "example.com".to_string()),
+            ],
+        );
+
+        let spans = export_params.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].name, "proxy_client.handle_expired_cores_package");
+        assert_eq!(spans[0].parent_span_id, None);
+        assert_eq!(
+            spans[0].attributes,
+            vec![
+                ("stream_key".to_string(), "a1b2c3".to_string()),
+                ("target_hostname".to_string(), "example.com".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_child_span_joins_the_parent_trace() {
+        let export_params = Arc::new(Mutex::new(vec![]));
+        let tracer = Tracer::with_exporter(Box::new(SpanExporterMock::new(&export_params)));
+        let parent = tracer.start_span("hopper.route", None).unwrap();
+        let parent_context = parent.context();
+
+        let child = tracer
+            .start_span("proxy_client.handle_expired_cores_package", Some(parent_context))
+            .unwrap();
+        tracer.end_span(child, vec![]);
+        tracer.end_span(parent, vec![]);
+
+        let spans = export_params.lock().unwrap();
+        assert_eq!(spans[0].context.trace_id, parent_context.trace_id);
+        assert_eq!(spans[0].parent_span_id, Some(parent_context.span_id));
+        assert_ne!(spans[0].context.span_id, parent_context.span_id);
+    }
+
+    #[test]
+    fn disabled_tracing_creates_nothing() {
+        let tracer = Tracer::disabled();
+
+        assert!(!tracer.is_enabled());
+        assert!(tracer.start_span("anything", None).is_none());
+    }
+
+    #[test]
+    fn from_config_enables_only_with_an_endpoint() {
+        let enabled = Tracer::from_config(&TelemetryConfig {
+            otel_endpoint: Some("http://collector:4318".to_string()),
+        });
+        let disabled = Tracer::from_config(&TelemetryConfig::default());
+
+        assert!(enabled.is_enabled());
+        assert!(!disabled.is_enabled());
+    }
+
+    #[test]
+    fn trace_context_rides_a_live_cores_package() {
+        use crate::hopper::live_cores_package::LiveCoresPackage;
+        use crate::sub_lib::cryptde::CryptData;
+        use crate::sub_lib::route::Route;
+
+        let mut package = LiveCoresPackage::new(Route::new(vec![]), CryptData::new(b"payload"));
+        assert_eq!(package.trace_context, None);
+
+        package.trace_context = Some(TraceContext {
+            trace_id: 7,
+            span_id: 9,
+        });
+        let serialized = serde_cbor::ser::to_vec(&package).unwrap();
+        let deserialized: LiveCoresPackage = serde_cbor::de::from_slice(&serialized).unwrap();
+
+        assert_eq!(
+            deserialized.trace_context,
+            Some(TraceContext {
+                trace_id: 7,
+                span_id: 9,
+            })
+        );
+    }
+}
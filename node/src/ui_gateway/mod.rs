@@ -0,0 +1,7 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+pub mod node_status;
+pub mod run_registry;
+pub mod set_mode;
+pub mod setup_space;
+pub mod tls_listener;
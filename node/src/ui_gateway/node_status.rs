@@ -0,0 +1,272 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! The "what is this node doing right now" message. A status aggregator
+//! asks each interested component for its fragment of the answer and
+//! assembles the response. No single slow component may stall the whole
+//! query: each is given a short deadline, and whatever failed to answer in
+//! time is reported as missing rather than waited for.
+
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// How long the aggregator waits for any one component's fragment.
+pub const PER_ACTOR_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeStatusRequest {}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeStatusResponse {
+    pub running: bool,
+    pub uptime_secs: u64,
+    pub neighborhood_mode: String,
+    pub originate_enabled: bool,
+    pub relay_enabled: bool,
+    pub exit_enabled: bool,
+    pub active_client_streams: usize,
+    pub active_exit_streams: usize,
+    pub neighbor_count: usize,
+    pub descriptor_available: bool,
+    /// True when at least one component missed its deadline; the fields it
+    /// would have filled hold defaults.
+    pub partial: bool,
+    /// Names of the components that missed their deadline.
+    pub unresponsive: Vec<String>,
+}
+
+impl NodeStatusResponse {
+    /// The shape the daemon answers with when the node is not running at
+    /// all; everything except `running` is meaningless and zeroed.
+    pub fn not_running() -> NodeStatusResponse {
+        NodeStatusResponse {
+            running: false,
+            uptime_secs: 0,
+            neighborhood_mode: "not-running".to_string(),
+            originate_enabled: false,
+            relay_enabled: false,
+            exit_enabled: false,
+            active_client_streams: 0,
+            active_exit_streams: 0,
+            neighbor_count: 0,
+            descriptor_available: false,
+            partial: false,
+            unresponsive: vec![],
+        }
+    }
+}
+
+/// One component's contribution to the response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StatusFragment {
+    Neighborhood {
+        mode: String,
+        neighbor_count: usize,
+        descriptor_available: bool,
+    },
+    Streams {
+        client_streams: usize,
+        exit_streams: usize,
+    },
+    Capabilities {
+        originate: bool,
+        relay: bool,
+        exit: bool,
+    },
+}
+
+/// A component that can be asked for its status fragment. The aggregator
+/// calls `report` on a throwaway thread, so an implementation that blocks
+/// forever costs a thread, not the query.
+pub trait StatusSource: Send {
+    fn name(&self) -> &'static str;
+    fn report(&self) -> StatusFragment;
+}
+
+pub struct StatusAggregator {
+    sources: Vec<Box<dyn StatusSource + 'static>>,
+    per_actor_timeout: Duration,
+}
+
+impl StatusAggregator {
+    pub fn new(sources: Vec<Box<dyn StatusSource>>) -> StatusAggregator {
+        StatusAggregator {
+            sources,
+            per_actor_timeout: PER_ACTOR_TIMEOUT,
+        }
+    }
+
+    #[cfg(test)]
+    fn with_timeout(mut self, timeout: Duration) -> StatusAggregator {
+        self.per_actor_timeout = timeout;
+        self
+    }
+
+    /// Collects fragments from every source, giving each its own deadline,
+    /// and folds them into a response. Sources that miss the deadline are
+    /// listed in `unresponsive` and their fields keep defaults.
+    pub fn collect(self, uptime_secs: u64) -> NodeStatusResponse {
+        let mut response = NodeStatusResponse {
+            running: true,
+            uptime_secs,
+            neighborhood_mode: "unknown".to_string(),
+            ..NodeStatusResponse::not_running()
+        };
+        let timeout = self.per_actor_timeout;
+        for source in self.sources {
+            let name = source.name();
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                let _ = tx.send(source.report());
+            });
+            match rx.recv_timeout(timeout) {
+                Ok(fragment) => apply_fragment(&mut response, fragment),
+                Err(_) => {
+                    response.partial = true;
+                    response.unresponsive.push(name.to_string());
+                }
+            }
+        }
+        response
+    }
+}
+
+fn apply_fragment(response: &mut NodeStatusResponse, fragment: StatusFragment) {
+    match fragment {
+        StatusFragment::Neighborhood {
+            mode,
+            neighbor_count,
+            descriptor_available,
+        } => {
+            response.neighborhood_mode = mode;
+            response.neighbor_count = neighbor_count;
+            response.descriptor_available = descriptor_available;
+        }
+        StatusFragment::Streams {
+            client_streams,
+            exit_streams,
+        } => {
+            response.active_client_streams = client_streams;
+            response.active_exit_streams = exit_streams;
+        }
+        StatusFragment::Capabilities {
+            originate,
+            relay,
+            exit,
+        } => {
+            response.originate_enabled = originate;
+            response.relay_enabled = relay;
+            response.exit_enabled = exit;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct PromptSource {
+        name: &'static str,
+        fragment: StatusFragment,
+    }
+
+    impl StatusSource for PromptSource {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn report(&self) -> StatusFragment {
+            self.fragment.clone()
+        }
+    }
+
+    struct StuckSource;
+
+    impl StatusSource for StuckSource {
+        fn name(&self) -> &'static str {
+            "Neighborhood"
+        }
+
+        fn report(&self) -> StatusFragment {
+            thread::sleep(Duration::from_secs(60));
+            unreachable!("the aggregator should have given up long ago")
+        }
+    }
+
+    fn streams_source() -> Box<dyn StatusSource> {
+        Box::new(PromptSource {
+            name: "StreamHandlerPool",
+            fragment: StatusFragment::Streams {
+                client_streams: 3,
+                exit_streams: 1,
+            },
+        })
+    }
+
+    fn neighborhood_source() -> Box<dyn StatusSource> {
+        Box::new(PromptSource {
+            name: "Neighborhood",
+            fragment: StatusFragment::Neighborhood {
+                mode: "standard".to_string(),
+                neighbor_count: 5,
+                descriptor_available: true,
+            },
+        })
+    }
+
+    #[test]
+    fn all_fragments_fold_into_a_complete_response() {
+        let aggregator = StatusAggregator::new(vec![
+            neighborhood_source(),
+            streams_source(),
+            Box::new(PromptSource {
+                name: "Dispatcher",
+                fragment: StatusFragment::Capabilities {
+                    originate: true,
+                    relay: true,
+                    exit: false,
+                },
+            }),
+        ]);
+
+        let response = aggregator.collect(1234);
+
+        assert!(response.running);
+        assert_eq!(response.uptime_secs, 1234);
+        assert_eq!(response.neighborhood_mode, "standard");
+        assert_eq!(response.neighbor_count, 5);
+        assert!(response.descriptor_available);
+        assert_eq!(response.active_client_streams, 3);
+        assert_eq!(response.active_exit_streams, 1);
+        assert!(response.originate_enabled);
+        assert!(!response.exit_enabled);
+        assert!(!response.partial);
+        assert!(response.unresponsive.is_empty());
+    }
+
+    #[test]
+    fn an_unresponsive_source_yields_partial_results_not_a_hang() {
+        let aggregator = StatusAggregator::new(vec![Box::new(StuckSource), streams_source()])
+            .with_timeout(Duration::from_millis(50));
+
+        let response = aggregator.collect(10);
+
+        assert!(response.partial);
+        assert_eq!(response.unresponsive, vec!["Neighborhood".to_string()]);
+        // The stuck source's fields keep defaults...
+        assert_eq!(response.neighborhood_mode, "unknown");
+        assert_eq!(response.neighbor_count, 0);
+        // ...while the responsive source's fields are filled in.
+        assert_eq!(response.active_client_streams, 3);
+    }
+
+    #[test]
+    fn the_daemon_shape_for_a_stopped_node_is_explicit() {
+        let response = NodeStatusResponse::not_running();
+
+        assert!(!response.running);
+        assert_eq!(response.neighborhood_mode, "not-running");
+        assert!(!response.partial);
+    }
+}
@@ -0,0 +1,362 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! Per-run log bookkeeping for the daemon. Every spawn of the node gets
+//! its own timestamped log file instead of appending to one shared one,
+//! so a crash loop reads as a sequence of small complete logs rather
+//! than an interleaved pile. The daemon records run → log file → exit
+//! status in a small line-oriented state file next to the logs, answers
+//! the UI's "recent runs" query from it, and prunes old runs (records
+//! and their log files together) by count and age.
+
+use crate::sub_lib::logger::Logger;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How many finished runs are retained before the oldest is pruned.
+pub const DEFAULT_MAX_RUNS: usize = 20;
+
+/// Runs older than this are pruned regardless of count.
+pub const DEFAULT_MAX_RUN_AGE: Duration = Duration::from_secs(7 * 24 * 3600);
+
+const STATE_FILE_NAME: &str = "runs.dat";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RunOutcome {
+    /// The node is (or was, at the last daemon shutdown) still running.
+    Running,
+    Exited(i32),
+    /// Terminated without an exit code — killed by a signal.
+    Signaled,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunRecord {
+    pub run_number: u64,
+    pub log_path: PathBuf,
+    pub started_at_unix: u64,
+    pub outcome: RunOutcome,
+}
+
+/// The UI-facing query and its answer; `masq runs` renders the response.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunsRequest {}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunsResponse {
+    pub runs: Vec<RunView>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunView {
+    pub run_number: u64,
+    pub log_path: String,
+    pub started_at_unix: u64,
+    pub outcome: RunOutcome,
+}
+
+pub struct RunRegistry {
+    log_dir: PathBuf,
+    records: Vec<RunRecord>,
+    max_runs: usize,
+    max_age: Duration,
+    logger: Logger,
+}
+
+impl RunRegistry {
+    /// Loads the registry from `log_dir`, tolerating a missing or damaged
+    /// state file — lost bookkeeping must never stop the daemon from
+    /// spawning the node.
+    pub fn load(log_dir: &Path, max_runs: usize, max_age: Duration) -> RunRegistry {
+        let logger = Logger::new("Daemon");
+        let records = match fs::read_to_string(log_dir.join(STATE_FILE_NAME)) {
+            Ok(contents) => contents.lines().filter_map(parse_record).collect(),
+            Err(_) => vec![],
+        };
+        RunRegistry {
+            log_dir: log_dir.to_path_buf(),
+            records,
+            max_runs,
+            max_age,
+            logger,
+        }
+    }
+
+    /// Allocates the next run: a fresh number and a timestamped log file
+    /// the daemon passes to the spawned node. Prunes before persisting so
+    /// the state file never grows past the retention settings.
+    pub fn begin_run(&mut self, now: SystemTime) -> RunRecord {
+        let run_number = self.records.iter().map(|r| r.run_number).max().unwrap_or(0) + 1;
+        let started_at_unix = unix_secs(now);
+        let record = RunRecord {
+            run_number,
+            log_path: self
+                .log_dir
+                .join(format!("node-run-{}-{}.log", run_number, started_at_unix)),
+            started_at_unix,
+            outcome: RunOutcome::Running,
+        };
+        self.records.push(record.clone());
+        self.prune(now);
+        self.persist();
+        record
+    }
+
+    /// Records how a run ended. `exit_code` is None when the node died to
+    /// a signal.
+    pub fn complete_run(&mut self, run_number: u64, exit_code: Option<i32>) {
+        if let Some(record) = self
+            .records
+            .iter_mut()
+            .find(|r| r.run_number == run_number)
+        {
+            record.outcome = match exit_code {
+                Some(code) => RunOutcome::Exited(code),
+                None => RunOutcome::Signaled,
+            };
+        }
+        self.persist();
+    }
+
+    pub fn recent_runs(&self) -> &[RunRecord] {
+        &self.records
+    }
+
+    /// Answers the UI query, newest run first.
+    pub fn runs_response(&self) -> RunsResponse {
+        let mut runs: Vec<RunView> = self
+            .records
+            .iter()
+            .map(|record| RunView {
+                run_number: record.run_number,
+                log_path: record.log_path.to_string_lossy().to_string(),
+                started_at_unix: record.started_at_unix,
+                outcome: record.outcome.clone(),
+            })
+            .collect();
+        runs.sort_by(|a, b| b.run_number.cmp(&a.run_number));
+        RunsResponse { runs }
+    }
+
+    fn prune(&mut self, now: SystemTime) {
+        let cutoff = unix_secs(now).saturating_sub(self.max_age.as_secs());
+        let max_runs = self.max_runs;
+        let mut pruned: Vec<RunRecord> = vec![];
+        self.records.retain(|record| {
+            if record.started_at_unix < cutoff {
+                pruned.push(record.clone());
+                false
+            } else {
+                true
+            }
+        });
+        while self.records.len() > max_runs {
+            pruned.push(self.records.remove(0));
+        }
+        for record in pruned {
+            if fs::remove_file(&record.log_path).is_err() {
+                self.logger.debug(format!(
+                    "Pruned run {} had no log file at {:?}",
+                    record.run_number, record.log_path
+                ));
+            }
+        }
+    }
+
+    fn persist(&self) {
+        let contents: String = self.records.iter().map(render_record).collect();
+        if let Err(error) = fs::write(self.log_dir.join(STATE_FILE_NAME), contents) {
+            self.logger.warning(format!(
+                "Could not persist the run registry: {}; run history will not survive a daemon restart",
+                error
+            ));
+        }
+    }
+}
+
+/// Spawns one node run under the registry's bookkeeping: the per-run log
+/// path goes to the node as `--log-file`, and the exit status is recorded
+/// when the process ends. Returns the completed record.
+pub fn supervise_run(
+    registry: &mut RunRegistry,
+    command: &mut Command,
+    now: SystemTime,
+) -> RunRecord {
+    let record = registry.begin_run(now);
+    command.arg("--log-file").arg(&record.log_path);
+    let exit_code = match command.status() {
+        Ok(status) => status.code(),
+        Err(_) => Some(-1),
+    };
+    registry.complete_run(record.run_number, exit_code);
+    registry
+        .recent_runs()
+        .iter()
+        .find(|r| r.run_number == record.run_number)
+        .cloned()
+        .unwrap_or(record)
+}
+
+// One record per line: run \t unix-start \t outcome \t path. The path
+// comes last because it is the only field that may contain anything.
+fn render_record(record: &RunRecord) -> String {
+    let outcome = match &record.outcome {
+        RunOutcome::Running => "running".to_string(),
+        RunOutcome::Exited(code) => format!("exited:{}", code),
+        RunOutcome::Signaled => "signaled".to_string(),
+    };
+    format!(
+        "{}\t{}\t{}\t{}\n",
+        record.run_number,
+        record.started_at_unix,
+        outcome,
+        record.log_path.to_string_lossy()
+    )
+}
+
+fn parse_record(line: &str) -> Option<RunRecord> {
+    let mut fields = line.splitn(4, '\t');
+    let run_number = fields.next()?.parse().ok()?;
+    let started_at_unix = fields.next()?.parse().ok()?;
+    let outcome = match fields.next()? {
+        "running" => RunOutcome::Running,
+        "signaled" => RunOutcome::Signaled,
+        exited => RunOutcome::Exited(exited.strip_prefix("exited:")?.parse().ok()?),
+    };
+    Some(RunRecord {
+        run_number,
+        log_path: PathBuf::from(fields.next()?),
+        started_at_unix,
+        outcome,
+    })
+}
+
+fn unix_secs(now: SystemTime) -> u64 {
+    now.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn temp_log_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("run_registry_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn registry(dir: &Path) -> RunRegistry {
+        RunRegistry::load(dir, DEFAULT_MAX_RUNS, DEFAULT_MAX_RUN_AGE)
+    }
+
+    #[test]
+    fn several_short_runs_get_distinct_logs_and_recorded_outcomes() {
+        let dir = temp_log_dir("short_runs");
+        let mut subject = registry(&dir);
+        let now = SystemTime::now();
+
+        // A stub node: writes to the per-run log file the daemon passes
+        // as `--log-file` ($1 is the flag, $2 the path) and exits with
+        // the status the scenario asks for.
+        for exit_code in [0, 3, 0] {
+            let record = supervise_run(
+                &mut subject,
+                Command::new("sh")
+                    .arg("-c")
+                    .arg(format!("echo \"run body\" > \"$2\"; exit {}", exit_code))
+                    .arg("stub-node"),
+                now,
+            );
+            assert_eq!(record.outcome, RunOutcome::Exited(exit_code));
+            assert_eq!(
+                fs::read_to_string(&record.log_path).unwrap(),
+                "run body\n"
+            );
+        }
+
+        let paths: Vec<&PathBuf> = subject.recent_runs().iter().map(|r| &r.log_path).collect();
+        assert_eq!(paths.len(), 3);
+        assert_ne!(paths[0], paths[1]);
+        assert_ne!(paths[1], paths[2]);
+    }
+
+    #[test]
+    fn the_registry_survives_a_daemon_restart() {
+        let dir = temp_log_dir("restart");
+        let mut subject = registry(&dir);
+        let now = SystemTime::now();
+        let record = subject.begin_run(now);
+        subject.complete_run(record.run_number, Some(101));
+
+        let reloaded = registry(&dir);
+
+        assert_eq!(reloaded.recent_runs(), subject.recent_runs());
+        assert_eq!(
+            reloaded.recent_runs()[0].outcome,
+            RunOutcome::Exited(101)
+        );
+    }
+
+    #[test]
+    fn runs_beyond_the_count_limit_are_pruned_with_their_log_files() {
+        let dir = temp_log_dir("count_prune");
+        let mut subject = RunRegistry::load(&dir, 2, DEFAULT_MAX_RUN_AGE);
+        let now = SystemTime::now();
+        let first = subject.begin_run(now);
+        fs::write(&first.log_path, b"oldest").unwrap();
+        subject.begin_run(now);
+
+        subject.begin_run(now);
+
+        let numbers: Vec<u64> = subject.recent_runs().iter().map(|r| r.run_number).collect();
+        assert_eq!(numbers, vec![2, 3]);
+        assert!(!first.log_path.exists());
+    }
+
+    #[test]
+    fn runs_past_the_age_limit_are_pruned() {
+        let dir = temp_log_dir("age_prune");
+        let mut subject = RunRegistry::load(&dir, 100, Duration::from_secs(3600));
+        let old = subject.begin_run(SystemTime::now() - Duration::from_secs(7200));
+        fs::write(&old.log_path, b"ancient").unwrap();
+
+        let fresh = subject.begin_run(SystemTime::now());
+
+        let numbers: Vec<u64> = subject.recent_runs().iter().map(|r| r.run_number).collect();
+        assert_eq!(numbers, vec![fresh.run_number]);
+        assert!(!old.log_path.exists());
+    }
+
+    #[test]
+    fn the_ui_response_lists_newest_first() {
+        let dir = temp_log_dir("ui_response");
+        let mut subject = registry(&dir);
+        let now = SystemTime::now();
+        let first = subject.begin_run(now);
+        subject.complete_run(first.run_number, None);
+        subject.begin_run(now);
+
+        let response = subject.runs_response();
+
+        assert_eq!(response.runs.len(), 2);
+        assert_eq!(response.runs[0].run_number, 2);
+        assert_eq!(response.runs[0].outcome, RunOutcome::Running);
+        assert_eq!(response.runs[1].outcome, RunOutcome::Signaled);
+    }
+
+    #[test]
+    fn a_damaged_state_file_is_tolerated() {
+        let dir = temp_log_dir("damaged");
+        fs::write(dir.join("runs.dat"), b"not\tvalid\nnonsense\n").unwrap();
+
+        let subject = registry(&dir);
+
+        assert!(subject.recent_runs().is_empty());
+    }
+}
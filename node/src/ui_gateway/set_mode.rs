@@ -0,0 +1,135 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! UI messages behind `masq set-mode`: requests a runtime neighborhood mode
+//! change and reports whether the transition was applied or why it was not.
+
+use crate::neighborhood::mode_transitions::{
+    ModeManager, ModeTransitionError, NeighborhoodMode,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetModeRequest {
+    /// One of "standard", "originate-only", "consume-only", "zero-hop".
+    pub mode: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetModeResponse {
+    pub accepted: bool,
+    pub new_mode: String,
+    /// Human-readable explanation when the transition was refused.
+    pub error: Option<String>,
+}
+
+/// Applies a SetModeRequest to the mode manager and renders the outcome for
+/// the UI. The actor-system side effects travel separately as the ModeChange
+/// the manager produced.
+pub fn handle_set_mode(manager: &mut ModeManager, request: &SetModeRequest) -> SetModeResponse {
+    let target = match parse_mode(&request.mode) {
+        Some(mode) => mode,
+        None => {
+            return SetModeResponse {
+                accepted: false,
+                new_mode: manager.mode().label().to_string(),
+                error: Some(format!("Unrecognized mode '{}'", request.mode)),
+            }
+        }
+    };
+    match manager.transition(target) {
+        Ok(change) => SetModeResponse {
+            accepted: true,
+            new_mode: change.new_mode.label().to_string(),
+            error: None,
+        },
+        Err(e) => SetModeResponse {
+            accepted: false,
+            new_mode: manager.mode().label().to_string(),
+            error: Some(explain(&e)),
+        },
+    }
+}
+
+fn parse_mode(label: &str) -> Option<NeighborhoodMode> {
+    match label {
+        "standard" => Some(NeighborhoodMode::Standard),
+        "originate-only" => Some(NeighborhoodMode::OriginateOnly),
+        "consume-only" => Some(NeighborhoodMode::ConsumeOnly),
+        "zero-hop" => Some(NeighborhoodMode::ZeroHop),
+        _ => None,
+    }
+}
+
+fn explain(error: &ModeTransitionError) -> String {
+    match error {
+        ModeTransitionError::RequiresRestart { from, to } => format!(
+            "Changing from {} to {} requires a restart",
+            from.label(),
+            to.label()
+        ),
+        ModeTransitionError::AlreadyInMode(mode) => {
+            format!("Node is already in {} mode", mode.label())
+        }
+        ModeTransitionError::NotReachableAtRuntime { from, to } => format!(
+            "Cannot change from {} to {} at runtime; restart with the desired mode",
+            from.label(),
+            to.label()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepted_transition_reports_the_new_mode() {
+        let mut manager = ModeManager::new(NeighborhoodMode::Standard, 1);
+        let request = SetModeRequest {
+            mode: "originate-only".to_string(),
+        };
+
+        let response = handle_set_mode(&mut manager, &request);
+
+        assert_eq!(
+            response,
+            SetModeResponse {
+                accepted: true,
+                new_mode: "originate-only".to_string(),
+                error: None,
+            }
+        );
+        assert_eq!(manager.mode(), NeighborhoodMode::OriginateOnly);
+    }
+
+    #[test]
+    fn refused_transition_keeps_the_old_mode_and_explains() {
+        let mut manager = ModeManager::new(NeighborhoodMode::Standard, 1);
+        let request = SetModeRequest {
+            mode: "zero-hop".to_string(),
+        };
+
+        let response = handle_set_mode(&mut manager, &request);
+
+        assert!(!response.accepted);
+        assert_eq!(response.new_mode, "standard");
+        assert_eq!(
+            response.error,
+            Some("Changing from standard to zero-hop requires a restart".to_string())
+        );
+    }
+
+    #[test]
+    fn unrecognized_mode_string_is_rejected() {
+        let mut manager = ModeManager::new(NeighborhoodMode::Standard, 1);
+        let request = SetModeRequest {
+            mode: "turbo".to_string(),
+        };
+
+        let response = handle_set_mode(&mut manager, &request);
+
+        assert!(!response.accepted);
+        assert_eq!(response.error, Some("Unrecognized mode 'turbo'".to_string()));
+        assert_eq!(manager.mode(), NeighborhoodMode::Standard);
+    }
+}
@@ -0,0 +1,246 @@
+// Copyright (c) 2023, ClandestiNet. All rights reserved.
+
+//! The daemon's setup space: the parameter values a node will be started
+//! with, merged from three layers in precedence order — values set over
+//! the UI beat the config file, which beats the built-in defaults. Unset
+//! removes the UI-set value so the lower layer shows through again;
+//! reset clears the whole UI-set layer. The config file on disk is never
+//! touched here; it reasserts itself on the next daemon restart.
+
+use std::collections::BTreeMap;
+
+/// Where an effective value came from, lowest precedence first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ValueSource {
+    Default,
+    ConfigFile,
+    Set,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SetupValue {
+    pub value: String,
+    pub source: ValueSource,
+}
+
+/// One requested change to the setup space.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SetupChange {
+    Set { name: String, value: String },
+    Unset { name: String },
+    ResetAll,
+}
+
+pub struct SetupSpace {
+    defaults: BTreeMap<String, String>,
+    config_file: BTreeMap<String, String>,
+    set: BTreeMap<String, String>,
+}
+
+impl SetupSpace {
+    pub fn new(defaults: Vec<(&str, &str)>) -> SetupSpace {
+        SetupSpace {
+            defaults: defaults
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            config_file: BTreeMap::new(),
+            set: BTreeMap::new(),
+        }
+    }
+
+    /// Loaded once at daemon startup from the parsed config file.
+    pub fn config_file_value(&mut self, name: &str, value: &str) {
+        self.config_file.insert(name.to_string(), value.to_string());
+    }
+
+    /// Applies one change and reports which parameters' effective values
+    /// actually changed, so the response can mark them for the client.
+    /// Unsetting something that was never set (or setting a value to what
+    /// it already shows) affects nothing.
+    pub fn apply(&mut self, change: SetupChange) -> Vec<String> {
+        let before = self.snapshot();
+        match change {
+            SetupChange::Set { name, value } => {
+                self.set.insert(name, value);
+            }
+            SetupChange::Unset { name } => {
+                self.set.remove(&name);
+            }
+            SetupChange::ResetAll => {
+                self.set.clear();
+            }
+        }
+        let after = self.snapshot();
+        after
+            .iter()
+            .filter(|(name, value)| before.get(name.as_str()) != Some(value))
+            .map(|(name, _)| name.clone())
+            .chain(
+                before
+                    .keys()
+                    .filter(|name| !after.contains_key(name.as_str()))
+                    .cloned(),
+            )
+            .collect()
+    }
+
+    /// The value the node would start with right now, highest-precedence
+    /// layer first.
+    pub fn effective(&self, name: &str) -> Option<SetupValue> {
+        if let Some(value) = self.set.get(name) {
+            return Some(SetupValue {
+                value: value.clone(),
+                source: ValueSource::Set,
+            });
+        }
+        if let Some(value) = self.config_file.get(name) {
+            return Some(SetupValue {
+                value: value.clone(),
+                source: ValueSource::ConfigFile,
+            });
+        }
+        self.defaults.get(name).map(|value| SetupValue {
+            value: value.clone(),
+            source: ValueSource::Default,
+        })
+    }
+
+    fn snapshot(&self) -> BTreeMap<String, SetupValue> {
+        self.defaults
+            .keys()
+            .chain(self.config_file.keys())
+            .chain(self.set.keys())
+            .filter_map(|name| {
+                self.effective(name)
+                    .map(|value| (name.clone(), value))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn space_with_layers() -> SetupSpace {
+        let mut space = SetupSpace::new(vec![
+            ("neighborhood-mode", "standard"),
+            ("clandestine-port", "randomized"),
+        ]);
+        space.config_file_value("clandestine-port", "4455");
+        space
+    }
+
+    #[test]
+    fn unset_falls_through_to_the_config_file() {
+        let mut subject = space_with_layers();
+        subject.apply(SetupChange::Set {
+            name: "clandestine-port".to_string(),
+            value: "9999".to_string(),
+        });
+        assert_eq!(
+            subject.effective("clandestine-port"),
+            Some(SetupValue {
+                value: "9999".to_string(),
+                source: ValueSource::Set,
+            })
+        );
+
+        let affected = subject.apply(SetupChange::Unset {
+            name: "clandestine-port".to_string(),
+        });
+
+        assert_eq!(affected, vec!["clandestine-port"]);
+        assert_eq!(
+            subject.effective("clandestine-port"),
+            Some(SetupValue {
+                value: "4455".to_string(),
+                source: ValueSource::ConfigFile,
+            })
+        );
+    }
+
+    #[test]
+    fn unset_falls_through_to_the_default_when_no_config_file_value_exists() {
+        let mut subject = space_with_layers();
+        subject.apply(SetupChange::Set {
+            name: "neighborhood-mode".to_string(),
+            value: "consume-only".to_string(),
+        });
+
+        subject.apply(SetupChange::Unset {
+            name: "neighborhood-mode".to_string(),
+        });
+
+        assert_eq!(
+            subject.effective("neighborhood-mode"),
+            Some(SetupValue {
+                value: "standard".to_string(),
+                source: ValueSource::Default,
+            })
+        );
+    }
+
+    #[test]
+    fn reset_clears_every_ui_set_value_at_once() {
+        let mut subject = space_with_layers();
+        subject.apply(SetupChange::Set {
+            name: "neighborhood-mode".to_string(),
+            value: "consume-only".to_string(),
+        });
+        subject.apply(SetupChange::Set {
+            name: "clandestine-port".to_string(),
+            value: "9999".to_string(),
+        });
+
+        let mut affected = subject.apply(SetupChange::ResetAll);
+
+        affected.sort();
+        assert_eq!(affected, vec!["clandestine-port", "neighborhood-mode"]);
+        assert_eq!(
+            subject.effective("neighborhood-mode").unwrap().source,
+            ValueSource::Default
+        );
+        assert_eq!(
+            subject.effective("clandestine-port").unwrap().source,
+            ValueSource::ConfigFile
+        );
+    }
+
+    #[test]
+    fn changes_that_change_nothing_report_nothing() {
+        let mut subject = space_with_layers();
+
+        let unset_never_set = subject.apply(SetupChange::Unset {
+            name: "neighborhood-mode".to_string(),
+        });
+        let reset_nothing = subject.apply(SetupChange::ResetAll);
+
+        assert_eq!(unset_never_set, Vec::<String>::new());
+        assert_eq!(reset_nothing, Vec::<String>::new());
+    }
+
+    #[test]
+    fn a_set_value_that_vanishes_entirely_is_still_reported() {
+        let mut subject = SetupSpace::new(vec![]);
+        subject.apply(SetupChange::Set {
+            name: "ip".to_string(),
+            value: "1.2.3.4".to_string(),
+        });
+
+        let affected = subject.apply(SetupChange::Unset {
+            name: "ip".to_string(),
+        });
+
+        assert_eq!(affected, vec!["ip"]);
+        assert_eq!(subject.effective("ip"), None);
+    }
+
+    #[test]
+    fn an_unknown_parameter_has_no_effective_value() {
+        let subject = space_with_layers();
+
+        assert_eq!(subject.effective("no-such-parameter"), None);
+    }
+}